@@ -1,3 +1,4 @@
+use crate::errors::BucketError;
 use crate::utils::checks::validate_path;
 use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
@@ -8,14 +9,30 @@ pub enum Command {
     Init(InitCommand),
     Create(CreateCommand),
     Commit(CommitCommand),
+    Uncommit(UncommitCommand),
     Revert(RestoreCommand),
     Rollback(RollbackCommand),
+    Restore(RestoreVersionCommand),
+    Export(ExportCommand),
+    Archive(ArchiveCommand),
+    Prefetch(PrefetchCommand),
     Stash(StashCommand),
+    Lock(LockCommand),
+    Unlock(UnlockCommand),
+    Undo(UndoCommand),
+    Storage(StorageCommand),
+    Key(KeyCommand),
+    Db(DbCommand),
     // Information commands
     Status(StatusCommand),
+    #[command(alias = "log")]
     History(HistoryCommand),
     List(ListCommand),
     Stats(StatsCommand),
+    Fsck(FsckCommand),
+    Locks(LocksCommand),
+    CheckIgnore(CheckIgnoreCommand),
+    Oplog(OplogCommand),
     // Expectation commands
     Expect(ExpectCommand),
     Check(CheckCommand),
@@ -24,6 +41,81 @@ pub enum Command {
     Schema(SchemaCommand),
 }
 
+impl Command {
+    /// Name of the subcommand as typed on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Init(_) => "init",
+            Command::Create(_) => "create",
+            Command::Commit(_) => "commit",
+            Command::Uncommit(_) => "uncommit",
+            Command::Revert(_) => "revert",
+            Command::Rollback(_) => "rollback",
+            Command::Restore(_) => "restore",
+            Command::Export(_) => "export",
+            Command::Archive(_) => "archive",
+            Command::Prefetch(_) => "prefetch",
+            Command::Stash(_) => "stash",
+            Command::Lock(_) => "lock",
+            Command::Unlock(_) => "unlock",
+            Command::Undo(_) => "undo",
+            Command::Storage(_) => "storage",
+            Command::Key(_) => "key",
+            Command::Db(_) => "db",
+            Command::Status(_) => "status",
+            Command::History(_) => "history",
+            Command::List(_) => "list",
+            Command::Stats(_) => "stats",
+            Command::Fsck(_) => "fsck",
+            Command::Locks(_) => "locks",
+            Command::CheckIgnore(_) => "check-ignore",
+            Command::Oplog(_) => "oplog",
+            Command::Expect(_) => "expect",
+            Command::Check(_) => "check",
+            Command::Link(_) => "link",
+            Command::Finalize(_) => "finalize",
+            Command::Schema(_) => "schema",
+        }
+    }
+
+    /// Whether the command changes the repository and needs the repository lock.
+    /// Informational commands and dry runs run without the lock and can run concurrently.
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Command::Commit(command) => !command.dry_run,
+            Command::Revert(command) => !command.dry_run,
+            Command::Rollback(command) => !command.dry_run,
+            Command::Stash(command) => !command.dry_run,
+            Command::Finalize(command) => !command.dry_run,
+            Command::Init(_)
+            | Command::Create(_)
+            | Command::Uncommit(_)
+            | Command::Lock(_)
+            | Command::Unlock(_)
+            | Command::Undo(_)
+            | Command::Storage(_)
+            | Command::Prefetch(_)
+            | Command::Key(_)
+            | Command::Db(_)
+            | Command::Expect(_)
+            | Command::Link(_) => true,
+            Command::Status(_)
+            | Command::History(_)
+            | Command::List(_)
+            | Command::Stats(_)
+            | Command::Fsck(_)
+            | Command::Locks(_)
+            | Command::Restore(_)
+            | Command::Export(_)
+            | Command::Archive(_)
+            | Command::CheckIgnore(_)
+            | Command::Oplog(_)
+            | Command::Check(_)
+            | Command::Schema(_) => false,
+        }
+    }
+}
+
 #[derive(Parser)]
 #[clap(
     name = "buckets",
@@ -51,18 +143,39 @@ pub struct InitCommand {
 
     #[clap(long, default_value = "duckdb", value_parser = validate_database_type)]
     pub database: String,
+
+    /// Keep the metadata on an existing PostgreSQL server, such as
+    /// `postgres://buckets@db.studio.lan/assets`, instead of an embedded one
+    #[clap(long, value_parser = validate_database_url)]
+    pub database_url: Option<String>,
+
+    /// Schema on the server holding the tables of the repository, `buckets_<repo name>`
+    /// by default
+    #[clap(long, requires = "database_url")]
+    pub database_schema: Option<String>,
 }
 
 fn validate_database_type(s: &str) -> Result<String, String> {
     match s.to_lowercase().as_str() {
-        "duckdb" | "postgresql" | "postgres" => Ok(s.to_string()),
+        "duckdb" | "postgresql" | "postgres" | "sqlite" => Ok(s.to_string()),
         _ => Err(format!(
-            "Invalid database type '{}'. Valid options are: duckdb, postgresql",
+            "Invalid database type '{}'. Valid options are: duckdb, postgresql, sqlite",
             s
         )),
     }
 }
 
+fn validate_database_url(s: &str) -> Result<String, String> {
+    if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "Invalid database URL '{}'. Expected postgres://user@host/database",
+            s
+        ))
+    }
+}
+
 #[derive(Args, Clone)]
 pub struct CreateCommand {
     #[clap(flatten)]
@@ -77,8 +190,45 @@ pub struct CommitCommand {
     #[clap(flatten)]
     pub shared: SharedArguments,
 
-    #[clap(required = true)]
-    pub message: String,
+    /// Commit message
+    #[clap(short, long)]
+    pub message: Option<String>,
+
+    /// Replace the last commit instead of adding a new one, keeping its message unless
+    /// `-m` is given
+    #[clap(long)]
+    pub amend: bool,
+
+    /// Files, directories or glob patterns to commit, everything else is kept as it
+    /// was in the previous commit. Without `-m` the first value is the commit message.
+    pub paths: Vec<String>,
+
+    /// Show what would change without touching the database, storage or working files
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+impl CommitCommand {
+    /// Splits the arguments into the commit message and the paths to commit.
+    /// An empty list of paths commits the whole bucket. The message is only
+    /// optional when amending.
+    pub fn message_and_paths(&self) -> Result<(Option<String>, Vec<String>), BucketError> {
+        if self.message.is_some() || self.amend {
+            return Ok((self.message.clone(), self.paths.clone()));
+        }
+        match self.paths.split_first() {
+            Some((message, paths)) => Ok((Some(message.clone()), paths.to_vec())),
+            None => Err(BucketError::InvalidData(
+                "a commit message is required".to_string(),
+            )),
+        }
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct UncommitCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
 }
 
 #[derive(Args, Clone)]
@@ -86,8 +236,70 @@ pub struct RestoreCommand {
     #[clap(flatten)]
     pub shared: SharedArguments,
 
+    /// Files, directories or glob patterns to revert, or `all` for every file in the bucket
     #[clap(required = true)]
-    pub file: String,
+    pub paths: Vec<String>,
+
+    /// Also delete matching files that are not in the last commit
+    #[clap(long)]
+    pub remove_new: bool,
+
+    /// Show what would change without touching the database, storage or working files
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct RestoreVersionCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    /// Files, directories or glob patterns to restore
+    #[clap(required = true)]
+    pub paths: Vec<String>,
+
+    /// Commit to restore from, or a unique prefix of its id. Defaults to the last commit
+    #[clap(long)]
+    pub commit: Option<String>,
+
+    /// Directory to restore into, files keep their path within the bucket below it
+    #[clap(long, required = true)]
+    pub to: PathBuf,
+}
+
+#[derive(Args, Clone)]
+pub struct ExportCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    /// Commit to export, or a unique prefix of its id
+    pub commit: String,
+
+    /// Empty or new directory to write the files of the commit to
+    pub dir: PathBuf,
+}
+
+#[derive(Args, Clone)]
+pub struct PrefetchCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    /// Commit to fetch the objects of, or a unique prefix of its id
+    pub commit: String,
+}
+
+#[derive(Args, Clone)]
+pub struct ArchiveCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    /// Bucket and version to archive as `<bucket>@<commit>`, where the commit is a unique
+    /// prefix of its id or `latest`. Without a version the latest commit is archived.
+    pub target: String,
+
+    /// Archive to create, a `.tar.zst` or `.zip` file
+    #[clap(short, long)]
+    pub output: PathBuf,
 }
 
 #[derive(Args, Clone)]
@@ -97,12 +309,42 @@ pub struct RollbackCommand {
 
     #[clap(flatten)]
     pub shared: SharedArguments,
+
+    /// Show what would change without touching the database, storage or working files
+    #[clap(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Args, Clone)]
 pub struct StashCommand {
     #[clap(flatten)]
     pub shared: SharedArguments,
+
+    /// Show what would change without touching the database, storage or working files
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct LockCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    #[clap(required = true)]
+    pub file: String,
+}
+
+#[derive(Args, Clone)]
+pub struct UnlockCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    #[clap(required = true)]
+    pub file: String,
+
+    /// Release a lock held by another user, for the admins in the repository config
+    #[clap(long)]
+    pub force: bool,
 }
 
 #[derive(Args, Clone)]
@@ -129,6 +371,43 @@ pub struct StatsCommand {
     pub shared: SharedArguments,
 }
 
+#[derive(Args, Clone)]
+pub struct FsckCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+}
+
+#[derive(Args, Clone)]
+pub struct LocksCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+}
+
+#[derive(Args, Clone)]
+pub struct UndoCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+}
+
+#[derive(Args, Clone)]
+pub struct OplogCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    /// Number of operations to show
+    #[clap(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Args, Clone)]
+pub struct CheckIgnoreCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    #[clap(required = true)]
+    pub paths: Vec<String>,
+}
+
 #[derive(Args, Clone)]
 pub struct ExpectCommand {
     #[clap(flatten)]
@@ -151,6 +430,10 @@ pub struct LinkCommand {
 pub struct FinalizeCommand {
     #[clap(flatten)]
     pub shared: SharedArguments,
+
+    /// Show what would change without touching the database, storage or working files
+    #[clap(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Args, Clone)]
@@ -158,3 +441,101 @@ pub struct SchemaCommand {
     #[clap(flatten)]
     pub shared: SharedArguments,
 }
+
+#[derive(Args, Clone)]
+pub struct StorageCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    #[command(subcommand)]
+    pub command: StorageSubcommand,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum StorageSubcommand {
+    /// Train zstd dictionaries for file types with many small, similar files
+    TrainDict(TrainDictCommand),
+    /// Move small loose objects into pack files
+    Pack(PackCommand),
+}
+
+#[derive(Args, Clone)]
+pub struct TrainDictCommand {
+    /// File extensions to train dictionaries for, all types with enough files by default
+    #[clap(long = "type", value_name = "EXTENSION")]
+    pub types: Vec<String>,
+
+    /// Size of each dictionary in bytes
+    #[clap(long, default_value_t = 65536)]
+    pub size: usize,
+
+    /// File types with fewer small files get no dictionary
+    #[clap(long, default_value_t = 16)]
+    pub min_samples: usize,
+
+    /// Most files to train a single dictionary on
+    #[clap(long, default_value_t = 2000)]
+    pub max_samples: usize,
+}
+
+#[derive(Args, Clone)]
+pub struct KeyCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    #[command(subcommand)]
+    pub command: KeySubcommand,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum KeySubcommand {
+    /// Encrypt the objects of the repository
+    Init(KeySourceCommand),
+    /// Re-encrypt the objects of the repository with a new key
+    Rotate(KeySourceCommand),
+}
+
+#[derive(Args, Clone)]
+pub struct KeySourceCommand {
+    /// Derive the key from this file instead of a passphrase
+    #[clap(long)]
+    pub key_file: Option<PathBuf>,
+}
+
+#[derive(Args, Clone)]
+pub struct DbCommand {
+    #[clap(flatten)]
+    pub shared: SharedArguments,
+
+    #[command(subcommand)]
+    pub command: DbSubcommand,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum DbSubcommand {
+    /// Move the metadata of the repository to another database backend
+    Migrate(MigrateCommand),
+}
+
+#[derive(Args, Clone)]
+pub struct MigrateCommand {
+    /// The backend to move to
+    #[clap(long, value_parser = validate_database_type)]
+    pub to: String,
+
+    /// Move to an existing PostgreSQL server instead of an embedded one
+    #[clap(long, value_parser = validate_database_url)]
+    pub database_url: Option<String>,
+
+    /// Schema on the server for the tables of the repository, `buckets_<repo name>` by
+    /// default
+    #[clap(long, requires = "database_url")]
+    pub database_schema: Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct PackCommand {
+    /// Loose objects up to this many bytes are packed, larger objects stay loose
+    #[clap(long, default_value_t = 1024 * 1024)]
+    pub max_size: u64,
+}
@@ -0,0 +1,113 @@
+use crate::args::ArchiveCommand;
+use crate::commands::commit::{find_commit, load_commit_files, load_head_commits};
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::metadata::MetadataStore;
+use crate::utils::archive::{write_archive, ArchiveEntry, ArchiveFormat, Manifest};
+use crate::utils::identity::Identity;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+use uuid::Uuid;
+
+/// Version that selects the newest commit of a bucket
+const LATEST: &str = "latest";
+
+/// Write a version of a bucket to a `.tar.zst` or `.zip` archive
+pub struct Archive {
+    args: ArchiveCommand,
+}
+
+impl BucketCommand for Archive {
+    type Args = ArchiveCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let output = world.work_dir.join(&self.args.output);
+        let format = ArchiveFormat::from_path(&output)?;
+        if output.exists() {
+            return Err(BucketError::InvalidData(format!(
+                "{} already exists",
+                output.display()
+            )));
+        }
+
+        let (bucket_name, version) = match self.args.target.split_once('@') {
+            Some((name, version)) => (name, version),
+            None => (self.args.target.as_str(), LATEST),
+        };
+
+        let (commit, files) = with_db_connection(|connection| {
+            let bucket_id = find_bucket_id(connection, bucket_name)?;
+            let commit_id = if version == LATEST {
+                load_head_commits(connection, bucket_id, 1)?
+                    .pop()
+                    .map(|head| head.id)
+                    .ok_or_else(|| {
+                        BucketError::NotFound(format!("commits in bucket {}", bucket_name))
+                    })?
+            } else {
+                version.to_string()
+            };
+            let commit = find_commit(connection, Some(bucket_id), &commit_id)?;
+            let files = load_commit_files(connection, &commit.id)?;
+            Ok((commit, files))
+        })?;
+
+        let repository_dir = world.repo_root.parent().ok_or(BucketError::NotInRepo)?;
+        let storage_path = repository_dir
+            .join(&commit.bucket_path)
+            .join(".b")
+            .join("storage");
+        let entries: Vec<ArchiveEntry> = files
+            .into_iter()
+            .map(|(path, hash)| ArchiveEntry {
+                object_path: storage_path.join(hash.to_string()),
+                path,
+                hash,
+            })
+            .collect();
+
+        let manifest = Manifest {
+            bucket: commit.bucket_name.clone(),
+            version: commit.id.clone(),
+            message: commit.message,
+            author: commit.author,
+            committed_at: commit.created_at,
+            archived_by: Identity::resolve(&world.work_dir).to_string(),
+            archived_at: chrono::Utc::now().to_rfc3339(),
+            files: Vec::new(),
+        };
+        write_archive(&output, format, manifest, &entries)?;
+
+        println!(
+            "Archived {} files of {} commit {} to {}",
+            entries.len(),
+            commit.bucket_name,
+            commit.id,
+            output.display()
+        );
+        Ok(())
+    }
+}
+
+/// Looks up a bucket of the repository by its name
+fn find_bucket_id(connection: &dyn MetadataStore, name: &str) -> Result<Uuid, BucketError> {
+    let ids = connection
+        .query("SELECT id FROM buckets WHERE name = ?1", &[name.into()])?
+        .iter()
+        .map(|row| row.get::<String>(0))
+        .collect::<Result<Vec<_>, _>>()?;
+    match ids.as_slice() {
+        [] => Err(BucketError::NotFound(format!("bucket {}", name))),
+        [id] => Uuid::parse_str(id)
+            .map_err(|e| BucketError::InvalidData(format!("invalid bucket id {}: {}", id, e))),
+        _ => Err(BucketError::InvalidData(format!(
+            "more than one bucket is named {}",
+            name
+        ))),
+    }
+}
@@ -0,0 +1,45 @@
+use crate::args::CheckIgnoreCommand;
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::ignore_rules::IgnoreRules;
+use crate::utils::utils::{bucket_relative_path, find_bucket_path};
+use crate::world::World;
+
+/// Show whether paths are ignored and which pattern decides it
+pub struct CheckIgnore {
+    args: CheckIgnoreCommand,
+}
+
+impl BucketCommand for CheckIgnore {
+    type Args = CheckIgnoreCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        if world.bucket.is_none() {
+            return Err(BucketError::NotInBucket);
+        }
+        let bucket_path = find_bucket_path(&world.work_dir).ok_or(BucketError::NotInBucket)?;
+        let rules = IgnoreRules::for_bucket(&bucket_path)?;
+
+        for path in &self.args.paths {
+            let relative = bucket_relative_path(&bucket_path, &world.work_dir, path)?;
+            let full_path = bucket_path.join(&relative);
+
+            match rules.explain(&full_path, full_path.is_dir()) {
+                Some(matched) if matched.ignored => {
+                    println!("{}:{}    {}", matched.source, matched.pattern, path)
+                }
+                Some(matched) => println!(
+                    "{}:{}    {} (not ignored)",
+                    matched.source, matched.pattern, path
+                ),
+                None => println!("{} is not ignored", path),
+            }
+        }
+        Ok(())
+    }
+}
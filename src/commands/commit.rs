@@ -1,17 +1,26 @@
 use crate::args::CommitCommand;
 use crate::commands::BucketCommand;
 use crate::data::commit::{Commit as CommitData, CommitStatus, CommittedFile};
+use crate::data::file_lock::check_not_locked_by_others;
+use crate::data::operation::{Operation, OperationKind};
 use crate::errors::BucketError;
+use crate::metadata::MetadataStore;
+use crate::utils::cache::trim_cache;
+use crate::utils::compression::{object_exists, CompressionPolicy};
+use crate::utils::identity::Identity;
+use crate::utils::recovery::PendingCommit;
+use crate::utils::selection::PathSelection;
 use crate::utils::utils::{
-    connect_to_db, find_files_excluding_top_level_b, hash_file, with_db_connection,
+    connect_to_db, find_bucket_files, find_bucket_path, find_bucket_repo, hash_file,
+    with_db_connection,
 };
 use crate::world::World;
 use blake3::Hash;
-use duckdb::params;
 use log::{debug, error};
+use std::collections::HashSet;
 use std::io;
 use std::io::{Error, ErrorKind};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use uuid::Uuid;
 
@@ -44,10 +53,32 @@ impl BucketCommand for Commit {
             bucket.name
         );
 
+        let (message, paths) = self.args.message_and_paths()?;
+
+        // Amending replaces the head commit, and keeps its message unless a new one is given
+        let amended = if self.args.amend {
+            let head =
+                with_db_connection(|connection| load_head_commits(connection, bucket.id, 1))?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| BucketError::NotFound("commit to amend".to_string()))?;
+            Some(head)
+        } else {
+            None
+        };
+        let message = match (message, &amended) {
+            (Some(message), _) => message,
+            (None, Some(head)) => head.message.clone(),
+            (None, None) => {
+                return Err(BucketError::InvalidData(
+                    "a commit message is required".to_string(),
+                ))
+            }
+        };
+
         // create a list of each file in the bucket directory, recursively
         // and create a blake3 hash for each file and add to current_commit
-        let current_commit =
-            self.list_files_with_metadata_in_bucket(world.work_dir.clone())?;
+        let current_commit = self.list_files_with_metadata_in_bucket(world.work_dir.clone())?;
         if current_commit.files.is_empty() {
             return Err(
                 Error::new(ErrorKind::NotFound, "No commitable files found in bucket.").into(),
@@ -57,29 +88,17 @@ impl BucketCommand for Commit {
         println!("Current commit: ########################################################## ");
 
         // Load the previous commit, if it exists
-        match Commit::load_last_commit(bucket.name.clone()) {
+        let files = match Commit::load_last_commit(bucket.name.clone()) {
             Ok(None) => {
                 // There is no previous commit; Process all files in the current commit
                 println!("No previous commit found. Processing all files. ########################################################## ");
-                self.process_files(
-                    bucket.id,
-                    &world.work_dir,
-                    &current_commit.files,
-                    &self.args.message,
-                )?;
+                current_commit.files
             }
             Ok(Some(previous_commit)) => {
                 // Compare the current commit with the previous commit
                 println!("Previous commit found. Comparing with current commit. ########################################################## ");
                 if let Some(changes) = current_commit.compare(&previous_commit) {
-                    // Process the files that have changed
-                    println!("Processing files that have changed. ########################################################## ");
-                    self.process_files(
-                        bucket.id,
-                        &world.work_dir,
-                        &changes,
-                        &self.args.message,
-                    )?;
+                    changes
                 } else {
                     // if there are no difference with previous commit cancel commit
                     println!("No changes detected. Commit cancelled. ########################################################## ");
@@ -95,7 +114,55 @@ impl BucketCommand for Commit {
                     "Failed to load previous commit.",
                 )));
             }
+        };
+
+        // A partial commit only takes the selected files from the working directory
+        let files = if paths.is_empty() {
+            files
+        } else {
+            let bucket_path = find_bucket_path(&world.work_dir).ok_or(BucketError::NotInBucket)?;
+            let selection = PathSelection::new(&bucket_path, &world.work_dir, &paths)?;
+            let unmatched = selection.unmatched(files.iter().map(|file| file.name.as_str()));
+            if !unmatched.is_empty() {
+                return Err(BucketError::FileNotFound(format!(
+                    "{} did not match any files",
+                    unmatched.join(", ")
+                )));
+            }
+
+            let selected = select_files(files, &selection);
+            if amended.is_none()
+                && selected
+                    .iter()
+                    .all(|file| file.status == CommitStatus::Committed)
+            {
+                println!("No changes detected in the selected files. Commit cancelled.");
+                return Ok(());
+            }
+            selected
+        };
+
+        if self.args.dry_run {
+            return self.preview(
+                bucket.id,
+                &world.work_dir,
+                &files,
+                amended.as_ref().map(|head| head.id.as_str()),
+            );
         }
+
+        println!("Processing files that have changed. ########################################################## ");
+        let commit_id = self.process_files(
+            bucket.id,
+            &world.work_dir,
+            &files,
+            &message,
+            amended.as_ref().map(|head| head.id.as_str()),
+        )?;
+        if let Some(head) = amended {
+            println!("Amended commit {} as {}", head.id, commit_id);
+        }
+
         println!("Commit completed. ########################################################## ");
 
         Ok(())
@@ -106,47 +173,168 @@ impl Commit {
     pub fn process_files(
         &self,
         bucket_id: Uuid,
-        bucket_path: &PathBuf,
+        bucket_path: &Path,
         files: &[CommittedFile],
         message: &String,
-    ) -> Result<(), BucketError> {
-        // Use a single connection for all database operations
+        amends: Option<&str>,
+    ) -> Result<Uuid, BucketError> {
+        let buckets_dir = find_bucket_repo(bucket_path).ok_or(BucketError::NotInRepo)?;
+        let commit_id = Uuid::new_v4();
+
+        // Changes to files locked by another user cannot be committed
+        let changed_files = files
+            .iter()
+            .filter(|file| file.status != CommitStatus::Committed)
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        let author = Identity::resolve(bucket_path);
+        with_db_connection(|connection| {
+            check_not_locked_by_others(connection, bucket_id, &changed_files, &author.name)
+        })?;
+
+        // A commit lists every file it contains, so deleted files are left out
+        let files = files
+            .iter()
+            .filter(|file| file.status != CommitStatus::Deleted)
+            .collect::<Vec<_>>();
+
+        // Record which objects this commit is about to create, so they can be
+        // removed again if the commit is interrupted
+        let storage_path = bucket_path.join(".b").join("storage");
+        let mut new_objects = Vec::new();
+        for file in &files {
+            let hash = file.hash.to_string();
+            if !object_exists(&storage_path.join(&hash))? && !new_objects.contains(&hash) {
+                new_objects.push(hash);
+            }
+        }
+        let pending = PendingCommit::new(commit_id, bucket_id, bucket_path, new_objects);
+        pending.write(&buckets_dir)?;
+
+        // Write all objects before the commit becomes visible in the database. Objects
+        // are named by their content, so an existing object does not need to be written.
+        let policy = CompressionPolicy::load(bucket_path)?;
+        for file in &files {
+            if object_exists(&storage_path.join(file.hash.to_string()))? {
+                continue;
+            }
+            file.compress_and_store(bucket_path, &policy).map_err(|e| {
+                error!("Error compressing and storing file: {}", e);
+                e
+            })?;
+        }
+
+        let current_tree = files
+            .iter()
+            .map(|file| file.hash.to_string())
+            .collect::<HashSet<_>>();
+
+        // Insert the commit and its files in a single transaction
         with_db_connection(|connection| {
-            // Insert the commit into the database
-            let commit_id =
-                self.insert_commit_into_db_with_connection(connection, bucket_id, message)?;
-
-            // Process each file in the commit using the same connection
-            for file in files {
-                // Insert the file into the database
-                self.insert_file_into_db_with_connection(
-                    connection,
+            connection.transaction(|transaction| {
+                let commit_id = self.insert_commit_into_db_with_connection(
+                    transaction,
                     &commit_id,
-                    &file.name,
-                    &file.hash.to_string(),
+                    bucket_id,
+                    message,
+                    &author,
                 )?;
 
-                // Compress and store the file (no database operation)
-                file.compress_and_store(&bucket_path).map_err(|e| {
-                    error!("Error compressing and storing file: {}", e);
-                    e
-                })?;
+                for file in &files {
+                    self.insert_file_into_db_with_connection(
+                        transaction,
+                        &commit_id,
+                        &file.name,
+                        &file.hash.to_string(),
+                    )?;
+                }
+
+                // The amended commit is replaced by the new one
+                if let Some(amended_id) = amends {
+                    transaction.execute(
+                        "UPDATE commits SET abandoned = TRUE WHERE id = ?1",
+                        &[amended_id.into()],
+                    )?;
+                    Operation::new(
+                        bucket_id,
+                        OperationKind::Amend,
+                        Some(amended_id.to_string()),
+                        Some(commit_id.clone()),
+                        format!("amend {}: {}", amended_id, message),
+                    )
+                    .record(transaction, &author)?;
+                }
+                Ok(())
+            })
+        })?;
+
+        pending.remove(&buckets_dir)?;
+
+        // With a remote store older objects only stay in the bucket storage as a cache
+        let trimmed = trim_cache(&storage_path, &current_tree)?;
+        if !trimmed.removed.is_empty() {
+            debug!("Removed {} objects from the cache", trimmed.removed.len());
+        }
+        Ok(commit_id)
+    }
+
+    /// Prints the changes a commit would make after running the same checks, without
+    /// writing objects or touching the database.
+    fn preview(
+        &self,
+        bucket_id: Uuid,
+        bucket_path: &Path,
+        files: &[CommittedFile],
+        amends: Option<&str>,
+    ) -> Result<(), BucketError> {
+        let changed_files = files
+            .iter()
+            .filter(|file| file.status != CommitStatus::Committed)
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        let author = Identity::resolve(bucket_path);
+        with_db_connection(|connection| {
+            check_not_locked_by_others(connection, bucket_id, &changed_files, &author.name)
+        })?;
+
+        let storage_path = bucket_path.join(".b").join("storage");
+        let mut new_objects = Vec::new();
+        for file in files {
+            match file.status {
+                CommitStatus::New | CommitStatus::Modified => {
+                    let hash = file.hash.to_string();
+                    if !object_exists(&storage_path.join(&hash))? && !new_objects.contains(&hash) {
+                        new_objects.push(hash);
+                    }
+                    println!("{}:    {}", file.status, file.name);
+                }
+                CommitStatus::Deleted => println!("{}:    {}", file.status, file.name),
+                CommitStatus::Committed | CommitStatus::Unknown => {}
             }
-            Ok(())
-        })
+        }
+        if let Some(amended_id) = amends {
+            println!("Would replace commit {}", amended_id);
+        }
+        println!(
+            "Dry run: {} changed files would be committed by {}, storing {} new objects. Nothing was changed.",
+            changed_files.len(),
+            author,
+            new_objects.len()
+        );
+        Ok(())
     }
 
     // New methods that accept database connections to avoid repeated connection creation
     fn insert_file_into_db_with_connection(
         &self,
-        connection: &duckdb::Connection,
+        connection: &dyn MetadataStore,
         commit_id: &str,
         file_path: &str,
         hash: &str,
     ) -> Result<(), BucketError> {
         connection.execute(
         "INSERT INTO files (id, commit_id, file_path, hash) VALUES (gen_random_uuid(), ?1, ?2, ?3)",
-        [commit_id, file_path, hash],
+        &[commit_id.into(), file_path.into(), hash.into()],
     )
         .map_err(|e| {
             BucketError::from(Error::new(
@@ -159,32 +347,28 @@ impl Commit {
 
     fn insert_commit_into_db_with_connection(
         &self,
-        connection: &duckdb::Connection,
+        connection: &dyn MetadataStore,
+        commit_id: &Uuid,
         bucket_id: Uuid,
         message: &String,
+        author: &Identity,
     ) -> Result<String, BucketError> {
         debug!(
-            "CommitCommand: path to database {}",
-            connection
-                .path()
-                .ok_or_else(|| BucketError::from(Error::new(
-                    ErrorKind::Other,
-                    "Invalid database connection path".to_string()
-                )))?
-                .display()
+            "CommitCommand: inserting commit into the {} database",
+            connection.database_type().as_str()
         );
         // Now query back the `id` using the `rowid`
-        let stmt = &mut connection.prepare("INSERT INTO commits (id, bucket_id, message) VALUES (gen_random_uuid(), ?1, ?2) RETURNING id")?;
-        let rows = &mut stmt.query(params![
-            bucket_id.to_string().to_uppercase(),
-            message.clone()
-        ])?;
-
-        if let Some(row) = rows.next()? {
-            Ok(row.get(0)?)
-        } else {
-            Err(BucketError::from(duckdb::Error::QueryReturnedNoRows))
-        }
+        connection.query_value(
+            "INSERT INTO commits (id, bucket_id, message, author_name, author_email)
+             VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+            &[
+                (*commit_id).into(),
+                bucket_id.to_string().into(),
+                message.into(),
+                (&author.name).into(),
+                author.email.as_deref().into(),
+            ],
+        )
     }
 
     fn list_files_with_metadata_in_bucket(&self, bucket_path: PathBuf) -> io::Result<CommitData> {
@@ -197,7 +381,9 @@ impl Commit {
             .unwrap_or("")
             .to_string();
 
-        for entry in find_files_excluding_top_level_b(bucket_path.as_path()) {
+        let bucket_files = find_bucket_files(bucket_path.as_path())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for entry in bucket_files {
             let full_path = bucket_path.join(&entry);
 
             if full_path.is_file() || full_path.is_symlink() {
@@ -242,17 +428,22 @@ impl Commit {
     pub fn load_last_commit(bucket_name: String) -> Result<Option<CommitData>, BucketError> {
         let connection = connect_to_db()?;
 
-        let mut stmt = connection.prepare(
+        let rows = connection.query(
             "SELECT f.id, f.file_path, f.hash
-                                               FROM files f
-                                               JOIN commits c ON f.commit_id = c.id
-                                WHERE c.created_at = (SELECT MAX(created_at) FROM commits)",
+             FROM files f
+             WHERE f.commit_id = (
+                 SELECT c.id
+                 FROM commits c
+                 JOIN buckets b ON c.bucket_id = b.id
+                 WHERE b.name = ?1 AND NOT c.abandoned
+                 ORDER BY c.created_at DESC
+                 LIMIT 1
+             )",
+            &[(&bucket_name).into()],
         )?;
 
-        let mut rows = stmt.query([])?;
-
         let mut files = Vec::new();
-        while let Some(row) = rows.next()? {
+        for row in rows {
             let uuid_string: String = row.get(0)?;
             let hex_string: String = row.get(2)?;
 
@@ -283,12 +474,7 @@ impl Commit {
             });
         }
 
-        if let Err((_conn, e)) = connection.close() {
-            return Err(BucketError::from(Error::new(
-                ErrorKind::Other,
-                format!("Failed to close database connection: {}", e),
-            )));
-        }
+        connection.close()?;
 
         if files.is_empty() {
             Ok(None)
@@ -304,12 +490,185 @@ impl Commit {
     }
 }
 
+/// A commit that is part of the history of a bucket
+#[derive(Debug)]
+pub struct HeadCommit {
+    pub id: String,
+    pub message: String,
+}
+
+/// Returns up to `count` commits of a bucket, newest first, skipping abandoned commits.
+/// The first is the head of the bucket, the second its parent.
+pub fn load_head_commits(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+    count: usize,
+) -> Result<Vec<HeadCommit>, BucketError> {
+    let rows = connection.query(
+        "SELECT id, message
+         FROM commits
+         WHERE bucket_id = ?1 AND NOT abandoned
+         ORDER BY created_at DESC
+         LIMIT ?2",
+        &[bucket_id.into(), (count as i64).into()],
+    )?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(HeadCommit {
+                id: row.get(0)?,
+                message: row.get(1)?,
+            })
+        })
+        .collect()
+}
+
+/// A commit looked up by its id, with the bucket it belongs to
+#[derive(Debug)]
+pub struct CommitInfo {
+    pub id: String,
+    pub bucket_id: Uuid,
+    pub bucket_name: String,
+    /// Path of the bucket relative to the repository
+    pub bucket_path: String,
+    pub message: String,
+    pub author: Option<String>,
+    pub created_at: String,
+}
+
+/// Finds the commit whose id starts with `id_prefix`, in the bucket `bucket_id` or
+/// in any bucket when it is `None`. Fails when the prefix matches several commits.
+pub fn find_commit(
+    connection: &dyn MetadataStore,
+    bucket_id: Option<Uuid>,
+    id_prefix: &str,
+) -> Result<CommitInfo, BucketError> {
+    // Passed to LIKE, where anything but hex digits and dashes could act as a wildcard
+    if id_prefix.is_empty() || !id_prefix.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err(BucketError::InvalidData(format!(
+            "{} is not a commit id",
+            id_prefix
+        )));
+    }
+
+    let rows = connection.query(
+        "SELECT CAST(c.id AS TEXT), CAST(c.bucket_id AS TEXT), b.name, b.path, c.message,
+                c.author_name, c.author_email, CAST(c.created_at AS TEXT)
+         FROM commits c
+         JOIN buckets b ON c.bucket_id = b.id
+         WHERE CAST(c.id AS TEXT) LIKE ?1 || '%'
+         ORDER BY c.created_at DESC",
+        &[id_prefix.to_lowercase().into()],
+    )?;
+
+    let mut commits = Vec::new();
+    for row in rows {
+        let author_name: Option<String> = row.get(5)?;
+        let author_email: Option<String> = row.get(6)?;
+        let commit_bucket_id: String = row.get(1)?;
+        let commit = CommitInfo {
+            id: row.get(0)?,
+            bucket_id: Uuid::parse_str(&commit_bucket_id)
+                .map_err(|e| BucketError::InvalidData(format!("Invalid UUID: {}", e)))?,
+            bucket_name: row.get(2)?,
+            bucket_path: row.get(3)?,
+            message: row.get(4)?,
+            author: author_name.map(|name| match author_email {
+                Some(email) => format!("{} <{}>", name, email),
+                None => name,
+            }),
+            created_at: row.get(7)?,
+        };
+        if bucket_id.is_none_or(|bucket_id| bucket_id == commit.bucket_id) {
+            commits.push(commit);
+        }
+    }
+
+    match commits.len() {
+        0 => Err(BucketError::NotFound(format!("commit {}", id_prefix))),
+        1 => Ok(commits.remove(0)),
+        _ => Err(BucketError::InvalidData(format!(
+            "commit id {} is ambiguous, use more characters",
+            id_prefix
+        ))),
+    }
+}
+
+/// Returns the files of a commit with the hash of their content, sorted by path.
+pub fn load_commit_files(
+    connection: &dyn MetadataStore,
+    commit_id: &str,
+) -> Result<Vec<(String, Hash)>, BucketError> {
+    let rows = connection.query(
+        "SELECT file_path, hash FROM files WHERE commit_id = ?1 ORDER BY file_path",
+        &[commit_id.into()],
+    )?;
+
+    let mut files = Vec::new();
+    for row in rows {
+        let (file_path, hash) = (row.get::<String>(0)?, row.get::<String>(1)?);
+        let hash = Hash::from_hex(&hash)
+            .map_err(|e| BucketError::InvalidData(format!("Invalid hash {}: {}", hash, e)))?;
+        files.push((file_path, hash));
+    }
+    Ok(files)
+}
+
+/// Returns the objects of the files in the head commit of a bucket, the objects its
+/// working files are restored from.
+pub fn load_current_tree(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+) -> Result<HashSet<String>, BucketError> {
+    let mut objects = HashSet::new();
+    if let Some(head) = load_head_commits(connection, bucket_id, 1)?
+        .into_iter()
+        .next()
+    {
+        objects.extend(
+            load_commit_files(connection, &head.id)?
+                .into_iter()
+                .map(|(_, hash)| hash.to_string()),
+        );
+    }
+    Ok(objects)
+}
+
+/// Restricts a commit to the selected files.
+///
+/// Selected files are committed as they are in the working directory. Every other
+/// file is carried over from the previous commit unchanged, and files that were not
+/// committed before are left out.
+fn select_files(files: Vec<CommittedFile>, selection: &PathSelection) -> Vec<CommittedFile> {
+    files
+        .into_iter()
+        .filter_map(|file| {
+            if selection.matches(&file.name) {
+                return Some(file);
+            }
+            match file.status {
+                CommitStatus::New => None,
+                CommitStatus::Modified => Some(CommittedFile {
+                    hash: file.previous_hash,
+                    status: CommitStatus::Committed,
+                    ..file
+                }),
+                _ => Some(CommittedFile {
+                    status: CommitStatus::Committed,
+                    ..file
+                }),
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::commands::commit::Commit;
+    use crate::commands::commit::{select_files, Commit};
     use crate::commands::BucketCommand;
     use crate::data::bucket::read_bucket_info;
     use crate::data::commit::{CommitStatus, CommittedFile};
+    use crate::utils::selection::PathSelection;
     use blake3::Hash;
     use log::error;
     use serial_test::serial;
@@ -377,10 +736,19 @@ mod tests {
 
         let commit_cmd = Commit::new(&crate::args::CommitCommand {
             shared: crate::args::SharedArguments::default(),
-            message: commit_message.clone(),
+            message: Some(commit_message.clone()),
+            amend: false,
+            paths: Vec::new(),
+            dry_run: false,
         });
         let result = commit_cmd
-            .process_files(bucket.id, &bucket_dir, &[committed_file], &commit_message)
+            .process_files(
+                bucket.id,
+                &bucket_dir,
+                &[committed_file],
+                &commit_message,
+                None,
+            )
             .map_err(|e| {
                 error!("Error processing files: {}", e);
                 e
@@ -398,7 +766,10 @@ mod tests {
     fn create_test_commit_command(message: &str) -> Commit {
         let args = crate::args::CommitCommand {
             shared: crate::args::SharedArguments::default(),
-            message: message.to_string(),
+            message: Some(message.to_string()),
+            amend: false,
+            paths: Vec::new(),
+            dry_run: false,
         };
         Commit::new(&args)
     }
@@ -431,7 +802,87 @@ mod tests {
     #[test]
     fn test_commit_new() {
         let commit = create_test_commit_command("test message");
-        assert_eq!(commit.args.message, "test message");
+        assert_eq!(commit.args.message.as_deref(), Some("test message"));
+    }
+
+    #[test]
+    fn test_commit_message_and_paths() {
+        let args = crate::args::CommitCommand {
+            shared: crate::args::SharedArguments::default(),
+            message: None,
+            amend: false,
+            paths: vec!["test message".to_string(), "textures".to_string()],
+            dry_run: false,
+        };
+        let (message, paths) = args.message_and_paths().expect("Failed to split arguments");
+        assert_eq!(message.as_deref(), Some("test message"));
+        assert_eq!(paths, vec!["textures".to_string()]);
+
+        // When amending every argument is a path
+        let args = crate::args::CommitCommand {
+            shared: crate::args::SharedArguments::default(),
+            message: None,
+            amend: true,
+            paths: vec!["textures".to_string()],
+            dry_run: false,
+        };
+        let (message, paths) = args.message_and_paths().expect("Failed to split arguments");
+        assert_eq!(message, None);
+        assert_eq!(paths, vec!["textures".to_string()]);
+
+        let args = crate::args::CommitCommand {
+            shared: crate::args::SharedArguments::default(),
+            message: None,
+            amend: false,
+            paths: Vec::new(),
+            dry_run: false,
+        };
+        assert!(args.message_and_paths().is_err());
+    }
+
+    #[test]
+    fn test_select_files() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let selection =
+            PathSelection::new(temp_dir.path(), temp_dir.path(), &["textures".to_string()])
+                .expect("Failed to create selection");
+
+        let file = |name: &str, hash: u8, previous_hash: u8, status: CommitStatus| {
+            CommittedFile::new(
+                name.to_string(),
+                Hash::from([hash; 32]),
+                Hash::from([previous_hash; 32]),
+                status,
+            )
+        };
+        let files = vec![
+            file("textures/wood.png", 2, 1, CommitStatus::Modified),
+            file("textures/new.png", 3, 0, CommitStatus::New),
+            file("model.blend", 5, 4, CommitStatus::Modified),
+            file("sketch.psd", 6, 0, CommitStatus::New),
+            file("notes.txt", 7, 7, CommitStatus::Committed),
+        ];
+
+        let selected = select_files(files, &selection);
+        let names = selected
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "textures/wood.png",
+                "textures/new.png",
+                "model.blend",
+                "notes.txt"
+            ]
+        );
+
+        // The unselected model keeps its committed version
+        assert_eq!(selected[2].hash, Hash::from([4u8; 32]));
+        assert_eq!(selected[2].status, CommitStatus::Committed);
+        assert_eq!(selected[0].hash, Hash::from([2u8; 32]));
+        assert_eq!(selected[0].status, CommitStatus::Modified);
     }
 
     #[test]
@@ -1,12 +1,12 @@
 use crate::args::CreateCommand;
 use crate::commands::BucketCommand;
 use crate::data::bucket::{Bucket, BucketTrait};
+use crate::database::open_metadata_store;
 use crate::errors::BucketError;
 use crate::utils::checks;
 use crate::utils::checks::{find_directory_in_parents, is_valid_bucket};
 use crate::CURRENT_DIR;
 use chrono::Utc;
-use duckdb::Connection;
 use log::error;
 use uuid::Uuid;
 
@@ -47,15 +47,14 @@ impl BucketCommand for Create {
         }
         .to_path_buf();
 
-        let db_path = buckets_repo_path.join("buckets.db");
-        let connection = Connection::open(db_path)?;
-        let timestamp = Utc::now().to_rfc3339();
+        let connection = open_metadata_store(&buckets_repo_path)?;
+        let timestamp = Utc::now();
 
         match connection
         .execute(
             "INSERT INTO buckets (id, name, path, created_at) VALUES (gen_random_uuid(), ?1, ?2, ?3)",
-            [bucket_name, relative_path.to_str().ok_or_else(||
-                std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid path string"))?, &timestamp],
+            &[bucket_name.into(), relative_path.to_str().ok_or_else(||
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid path string"))?.into(), timestamp.into()],
         )
         .map_err(|e| {
             std::io::Error::new(
@@ -70,24 +69,21 @@ impl BucketCommand for Create {
         }
     }
 
-        let mut stmt = connection
-            .prepare("SELECT id FROM buckets WHERE name = ?1 AND path = ?2")
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Error preparing statement: {}", e),
-                )
-            })?;
-
-        let bucket_id_str: String = stmt
-            .query_row(
-                [
-                    bucket_name,
-                    relative_path.to_str().ok_or_else(|| {
-                        std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid path string")
-                    })?,
+        let bucket_id_str: String = connection
+            .query_value(
+                "SELECT id FROM buckets WHERE name = ?1 AND path = ?2",
+                &[
+                    bucket_name.into(),
+                    relative_path
+                        .to_str()
+                        .ok_or_else(|| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                "Invalid path string",
+                            )
+                        })?
+                        .into(),
                 ],
-                |row| row.get(0),
             )
             .map_err(|e| {
                 std::io::Error::new(
@@ -102,6 +98,7 @@ impl BucketCommand for Create {
                 format!("Error parsing UUID: {}", e),
             )
         })?;
+        connection.close()?;
         let bucket = Bucket::default(bucket_id, bucket_name, &relative_path);
         bucket
             .write_bucket_info()
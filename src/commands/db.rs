@@ -0,0 +1,233 @@
+use crate::args::{DbCommand, DbSubcommand, MigrateCommand};
+use crate::commands::init::default_schema;
+use crate::commands::BucketCommand;
+use crate::database::{
+    create_tables, database_path, open_metadata_store, open_postgres, write_database_type,
+    DatabaseType,
+};
+use crate::errors::BucketError;
+use crate::metadata::copy::{copy_tables, table_digest, TABLES};
+use crate::metadata::duckdb_store::DuckDbStore;
+use crate::metadata::sqlite_store::SqliteStore;
+use crate::metadata::MetadataStore;
+use crate::utils::config::{set_database_config, DatabaseConfig, RepositoryConfig};
+use crate::world::World;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maintenance of the metadata database of a repository
+pub struct Db {
+    args: DbCommand,
+}
+
+impl BucketCommand for Db {
+    type Args = DbCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        match &self.args.command {
+            DbSubcommand::Migrate(command) => migrate_database(&world, command),
+        }
+    }
+}
+
+/// Copies the metadata of the repository to a database of another backend and switches
+/// the repository to it. The copy is of a single snapshot of the old database and is
+/// checked against it before the switch, the old database is left where it is.
+fn migrate_database(world: &World, args: &MigrateCommand) -> Result<(), BucketError> {
+    let buckets_dir = &world.repo_root;
+    let from = world.database_type;
+    let to = DatabaseType::from_str(&args.to)?;
+    let current = RepositoryConfig::from_file(buckets_dir.to_path_buf())?.database;
+    let config = target_config(buckets_dir, to, &current, args);
+    if from == to && config == current {
+        return Err(BucketError::InvalidData(format!(
+            "the repository is on {} already",
+            describe(to, &config)
+        )));
+    }
+
+    let source = open_metadata_store(buckets_dir)?;
+    let target = open_target(buckets_dir, to, &config)?;
+    let counts = copy_snapshot(source.as_ref(), target.as_ref())?;
+    source.close()?;
+    target.close()?;
+
+    // The config is only read by PostgreSQL, so it changes while the other backend is
+    // still in use and the repository works whenever the switch is interrupted
+    let config = (config != DatabaseConfig::default()).then_some(&config);
+    if to != DatabaseType::PostgreSQL {
+        replace_database_file(buckets_dir, to)?;
+        write_database_type(buckets_dir, to)?;
+        set_database_config(buckets_dir, config)?;
+    } else {
+        set_database_config(buckets_dir, config)?;
+        write_database_type(buckets_dir, to)?;
+    }
+
+    for (table, count) in TABLES.iter().zip(counts) {
+        println!("{:>8} {}", count, table);
+    }
+    println!(
+        "Migrated the repository from {} to {}",
+        describe(from, &current),
+        describe(to, config.unwrap_or(&DatabaseConfig::default()))
+    );
+    if from != DatabaseType::PostgreSQL || current.url.is_none() {
+        println!(
+            "The old database is kept in {}",
+            database_path(buckets_dir, from).display()
+        );
+    }
+    Ok(())
+}
+
+/// The `[database]` table of the repository once it is on `to`
+fn target_config(
+    buckets_dir: &Path,
+    to: DatabaseType,
+    current: &DatabaseConfig,
+    args: &MigrateCommand,
+) -> DatabaseConfig {
+    let (url, schema) = match (to, &args.database_url) {
+        (DatabaseType::PostgreSQL, Some(url)) => {
+            let repo_name = buckets_dir
+                .parent()
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let schema = args
+                .database_schema
+                .clone()
+                .unwrap_or_else(|| default_schema(&repo_name));
+            (Some(url.clone()), Some(schema))
+        }
+        _ => (None, None),
+    };
+    DatabaseConfig {
+        url,
+        schema,
+        ..current.clone()
+    }
+}
+
+/// An empty database of the new backend. A DuckDB or SQLite database is created next to
+/// the one it replaces and only moved in place once the copy is complete.
+fn open_target(
+    buckets_dir: &Path,
+    to: DatabaseType,
+    config: &DatabaseConfig,
+) -> Result<Box<dyn MetadataStore>, BucketError> {
+    let target: Box<dyn MetadataStore> = match to {
+        DatabaseType::DuckDB => Box::new(DuckDbStore::open(&new_database_file(buckets_dir, to)?)?),
+        DatabaseType::SQLite => Box::new(SqliteStore::open(&new_database_file(buckets_dir, to)?)?),
+        DatabaseType::PostgreSQL => open_postgres(buckets_dir, config)?,
+    };
+    if target.query("SELECT id FROM buckets LIMIT 1", &[]).is_ok() {
+        return Err(BucketError::InvalidData(format!(
+            "{} holds the tables of a repository already",
+            describe(to, config)
+        )));
+    }
+    Ok(target)
+}
+
+/// Copies the tables in one transaction on each side and compares the row counts and
+/// hashes of both before committing the copy.
+fn copy_snapshot(
+    source: &dyn MetadataStore,
+    target: &dyn MetadataStore,
+) -> Result<Vec<usize>, BucketError> {
+    source.transaction(|source| {
+        if source.database_type() == DatabaseType::PostgreSQL {
+            // Every query of the transaction sees the same snapshot
+            source.execute_batch("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")?;
+        }
+        target.transaction(|target| {
+            create_tables(target)?;
+            let counts = copy_tables(source, target)?;
+            for table in TABLES {
+                let expected = table_digest(source, table)?;
+                let copied = table_digest(target, table)?;
+                if copied != expected {
+                    return Err(BucketError::DatabaseError(format!(
+                        "The copy of table {} does not match: {} rows with hash {}, expected \
+                         {} rows with hash {}",
+                        table, copied.rows, copied.hash, expected.rows, expected.hash
+                    )));
+                }
+            }
+            Ok(counts)
+        })
+    })
+}
+
+/// The path of the database the migration creates, without what an interrupted migration
+/// left there.
+fn new_database_file(buckets_dir: &Path, db_type: DatabaseType) -> Result<PathBuf, BucketError> {
+    let path = migrating_path(buckets_dir, db_type);
+    for stale in [vec![path.clone()], journal_files(&path, db_type)].concat() {
+        if stale.exists() {
+            fs::remove_file(stale)?;
+        }
+    }
+    Ok(path)
+}
+
+/// Moves the database made by the migration in place, replacing the one an earlier
+/// migration away from the backend left behind.
+fn replace_database_file(buckets_dir: &Path, db_type: DatabaseType) -> Result<(), BucketError> {
+    let migrating = migrating_path(buckets_dir, db_type);
+    let path = database_path(buckets_dir, db_type);
+    // The journals of the old database must not be applied to the new one
+    for (from, to) in journal_files(&migrating, db_type)
+        .into_iter()
+        .zip(journal_files(&path, db_type))
+    {
+        if from.exists() {
+            fs::rename(from, to)?;
+        } else if to.exists() {
+            fs::remove_file(to)?;
+        }
+    }
+    fs::rename(migrating, path)?;
+    Ok(())
+}
+
+fn migrating_path(buckets_dir: &Path, db_type: DatabaseType) -> PathBuf {
+    with_suffix(&database_path(buckets_dir, db_type), ".migrating")
+}
+
+/// The files the backend keeps next to the database file at `path`
+fn journal_files(path: &Path, db_type: DatabaseType) -> Vec<PathBuf> {
+    let suffixes: &[&str] = match db_type {
+        DatabaseType::DuckDB => &[".wal"],
+        DatabaseType::SQLite => &["-wal", "-shm"],
+        DatabaseType::PostgreSQL => &[],
+    };
+    suffixes
+        .iter()
+        .map(|suffix| with_suffix(path, suffix))
+        .collect()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn describe(db_type: DatabaseType, config: &DatabaseConfig) -> String {
+    match (db_type, &config.url, &config.schema) {
+        (DatabaseType::PostgreSQL, Some(_), Some(schema)) => {
+            format!("PostgreSQL schema {}", schema)
+        }
+        (DatabaseType::PostgreSQL, _, _) => "the embedded PostgreSQL server".to_string(),
+        (DatabaseType::DuckDB, _, _) => "DuckDB".to_string(),
+        (DatabaseType::SQLite, _, _) => "SQLite".to_string(),
+    }
+}
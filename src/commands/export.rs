@@ -0,0 +1,73 @@
+use crate::args::ExportCommand;
+use crate::commands::commit::{find_commit, load_commit_files};
+use crate::commands::restore::decompress_and_restore_file;
+use crate::commands::restore_version::bucket_containing;
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+
+/// Write every file of a commit into a directory
+pub struct Export {
+    args: ExportCommand,
+}
+
+impl BucketCommand for Export {
+    type Args = ExportCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket_id = world.bucket.as_ref().map(|bucket| bucket.id);
+
+        let (commit, files) = with_db_connection(|connection| {
+            let commit = find_commit(connection, bucket_id, &self.args.commit)?;
+            let files = load_commit_files(connection, &commit.id)?;
+            Ok((commit, files))
+        })?;
+
+        // Never mix an export with other files. Export takes no repository lock, so it
+        // never writes into a bucket either.
+        let destination = world.work_dir.join(&self.args.dir);
+        if let Some(bucket_dir) = bucket_containing(&destination)? {
+            return Err(BucketError::InvalidData(format!(
+                "{} is inside the bucket at {}, export to a directory outside the buckets",
+                destination.display(),
+                bucket_dir.display()
+            )));
+        }
+        if destination.is_file()
+            || (destination.is_dir() && destination.read_dir()?.next().is_some())
+        {
+            return Err(BucketError::InvalidData(format!(
+                "{} is not an empty directory",
+                destination.display()
+            )));
+        }
+
+        let repository_dir = world.repo_root.parent().ok_or(BucketError::NotInRepo)?;
+        let storage_path = repository_dir
+            .join(&commit.bucket_path)
+            .join(".b")
+            .join("storage");
+        for (name, hash) in &files {
+            decompress_and_restore_file(
+                &storage_path.join(hash.to_string()),
+                &destination.join(name),
+                hash,
+            )?;
+        }
+
+        println!(
+            "Exported {} files of {} commit {} to {}",
+            files.len(),
+            commit.bucket_name,
+            commit.id,
+            destination.display()
+        );
+        Ok(())
+    }
+}
@@ -4,7 +4,6 @@ use crate::errors::BucketError;
 
 /// Finalize command placeholder
 pub struct Finalize {
-    #[allow(dead_code)]
     args: FinalizeCommand,
 }
 
@@ -16,6 +15,10 @@ impl BucketCommand for Finalize {
     }
 
     fn execute(&self) -> Result<(), BucketError> {
+        if self.args.dry_run {
+            println!("Dry run: finalize would change nothing. Nothing was changed.");
+            return Ok(());
+        }
         println!("finalize command");
         Ok(())
     }
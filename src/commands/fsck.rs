@@ -0,0 +1,281 @@
+use crate::args::FsckCommand;
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::compression::hash_compressed_file;
+use crate::utils::object_store::stored_objects;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+use log::debug;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Verify the integrity of the object storage and the database
+pub struct Fsck {
+    args: FsckCommand,
+}
+
+/// A problem found with a single stored object
+#[derive(Debug, PartialEq)]
+pub enum ObjectProblem {
+    /// Referenced by the database but not present in storage
+    Missing,
+    /// Present in storage but its content does not match its name
+    Corrupt,
+    /// Present in storage but not referenced by the database
+    Orphan,
+}
+
+impl Display for ObjectProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectProblem::Missing => write!(f, "missing"),
+            ObjectProblem::Corrupt => write!(f, "corrupt"),
+            ObjectProblem::Orphan => write!(f, "orphan"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StorageReport {
+    /// Number of objects that were decompressed and verified
+    pub checked: usize,
+    /// Problems found, as (hash, problem) pairs
+    pub problems: Vec<(String, ObjectProblem)>,
+}
+
+impl BucketCommand for Fsck {
+    type Args = FsckCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let repo_dir = world
+            .repo_root
+            .parent()
+            .ok_or(BucketError::NotInRepo)?
+            .to_path_buf();
+
+        let buckets = query_buckets()?;
+        let mut checked = 0;
+        let mut problems = 0;
+
+        for (bucket_id, bucket_name, bucket_path) in buckets {
+            let storage_path = repo_dir.join(&bucket_path).join(".b").join("storage");
+            let referenced = query_referenced_hashes(&bucket_id)?;
+            let report = check_storage(&storage_path, &referenced)?;
+
+            for (hash, problem) in &report.problems {
+                println!("{}:    {}/{}", problem, bucket_name, hash);
+            }
+            if self.args.shared.verbose {
+                println!(
+                    "Bucket {}: {} objects checked, {} problems",
+                    bucket_name,
+                    report.checked,
+                    report.problems.len()
+                );
+            }
+
+            checked += report.checked;
+            problems += report.problems.len();
+        }
+
+        println!("Checked {} objects, found {} problems.", checked, problems);
+
+        if problems > 0 {
+            return Err(BucketError::IntegrityError(format!(
+                "{} problems found",
+                problems
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the id, name and relative path of every bucket in the repository.
+fn query_buckets() -> Result<Vec<(String, String, PathBuf)>, BucketError> {
+    with_db_connection(|connection| {
+        let rows = connection.query("SELECT id, name, path FROM buckets ORDER BY name", &[])?;
+
+        let mut buckets = Vec::new();
+        for row in rows {
+            buckets.push((
+                row.get::<String>(0)?,
+                row.get::<String>(1)?,
+                PathBuf::from(row.get::<String>(2)?),
+            ));
+        }
+        Ok(buckets)
+    })
+}
+
+/// Returns every object hash referenced by the commits of a bucket and by the
+/// snapshots in its operation log.
+fn query_referenced_hashes(bucket_id: &str) -> Result<HashSet<String>, BucketError> {
+    with_db_connection(|connection| {
+        let rows = connection.query(
+            "SELECT f.hash
+             FROM files f
+             JOIN commits c ON f.commit_id = c.id
+             WHERE c.bucket_id = ?1
+             UNION
+             SELECT s.hash
+             FROM operation_files s
+             JOIN operations o ON s.operation_id = o.id
+             WHERE o.bucket_id = ?1 AND s.hash IS NOT NULL",
+            &[bucket_id.into()],
+        )?;
+
+        let mut hashes = HashSet::new();
+        for row in rows {
+            hashes.insert(row.get(0)?);
+        }
+        Ok(hashes)
+    })
+}
+
+/// Verifies every object in the store of a bucket, loose or packed, against its name and
+/// compares the stored objects with the set of hashes referenced by the database.
+pub fn check_storage(
+    storage_path: &Path,
+    referenced: &HashSet<String>,
+) -> Result<StorageReport, BucketError> {
+    let mut report = StorageReport::default();
+    let mut present = HashSet::new();
+
+    for name in stored_objects(storage_path)? {
+        report.checked += 1;
+
+        match hash_compressed_file(&storage_path.join(&name)) {
+            Ok(hash) if hash.to_hex().as_str() == name => {
+                debug!("Object {} verified", name);
+            }
+            Ok(hash) => {
+                debug!("Object {} has content hash {}", name, hash);
+                report.problems.push((name.clone(), ObjectProblem::Corrupt));
+            }
+            Err(e) => {
+                debug!("Object {} could not be decompressed: {}", name, e);
+                report.problems.push((name.clone(), ObjectProblem::Corrupt));
+            }
+        }
+
+        if !referenced.contains(&name) {
+            report.problems.push((name.clone(), ObjectProblem::Orphan));
+        }
+        present.insert(name);
+    }
+
+    let mut missing = referenced
+        .iter()
+        .filter(|hash| !present.contains(*hash))
+        .cloned()
+        .collect::<Vec<_>>();
+    missing.sort();
+    for hash in missing {
+        report.problems.push((hash, ObjectProblem::Missing));
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::compress_and_store_file;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn store_object(storage_path: &Path, content: &[u8]) -> String {
+        let source = storage_path.parent().expect("no parent").join("source");
+        fs::write(&source, content).expect("Failed to write source file");
+        let hash = blake3::hash(content).to_hex().to_string();
+        compress_and_store_file(&source, &storage_path.join(&hash), 0)
+            .expect("Failed to compress file");
+        hash
+    }
+
+    #[test]
+    fn test_check_storage_healthy() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = temp_dir.path().join("storage");
+        fs::create_dir_all(&storage_path).expect("Failed to create storage");
+
+        let hash = store_object(&storage_path, b"healthy content");
+        let referenced = HashSet::from([hash]);
+
+        let report = check_storage(&storage_path, &referenced).expect("check failed");
+        assert_eq!(report.checked, 1);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn test_check_storage_detects_problems() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = temp_dir.path().join("storage");
+        fs::create_dir_all(&storage_path).expect("Failed to create storage");
+
+        // Object whose content no longer matches its name
+        let corrupt = store_object(&storage_path, b"original content");
+        fs::write(storage_path.join(&corrupt), b"garbage").expect("Failed to corrupt object");
+
+        // Object nobody references
+        let orphan = store_object(&storage_path, b"orphaned content");
+
+        // Reference without an object
+        let missing = blake3::hash(b"missing content").to_hex().to_string();
+
+        let referenced = HashSet::from([corrupt.clone(), missing.clone()]);
+        let report = check_storage(&storage_path, &referenced).expect("check failed");
+
+        assert_eq!(report.checked, 2);
+        assert!(report.problems.contains(&(corrupt, ObjectProblem::Corrupt)));
+        assert!(report.problems.contains(&(orphan, ObjectProblem::Orphan)));
+        assert!(report.problems.contains(&(missing, ObjectProblem::Missing)));
+        assert_eq!(report.problems.len(), 3);
+    }
+
+    #[test]
+    fn test_check_storage_packed_objects() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = temp_dir.path().join("storage");
+        fs::create_dir_all(&storage_path).expect("Failed to create storage");
+
+        let packed = store_object(&storage_path, b"packed content");
+        let loose = store_object(&storage_path, b"loose content");
+        let packed_path = storage_path.join(&packed);
+        let hash = blake3::Hash::from_hex(&packed).expect("Invalid hash");
+        crate::utils::pack::write_pack(&storage_path, &[(hash, packed_path.clone())])
+            .expect("Failed to write pack");
+        fs::remove_file(&packed_path).expect("Failed to remove loose object");
+
+        let referenced = HashSet::from([packed, loose]);
+        let report = check_storage(&storage_path, &referenced).expect("check failed");
+        assert_eq!(report.checked, 2);
+        assert!(report.problems.is_empty());
+    }
+
+    #[test]
+    fn test_check_storage_missing_directory() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = temp_dir.path().join("does_not_exist");
+        let missing = blake3::hash(b"content").to_hex().to_string();
+
+        let report =
+            check_storage(&storage_path, &HashSet::from([missing.clone()])).expect("check failed");
+        assert_eq!(report.checked, 0);
+        assert_eq!(report.problems, vec![(missing, ObjectProblem::Missing)]);
+    }
+
+    #[test]
+    fn test_object_problem_display() {
+        assert_eq!(format!("{}", ObjectProblem::Missing), "missing");
+        assert_eq!(format!("{}", ObjectProblem::Corrupt), "corrupt");
+        assert_eq!(format!("{}", ObjectProblem::Orphan), "orphan");
+    }
+}
@@ -1,9 +1,9 @@
 use std::path::PathBuf;
 
 use crate::args::HistoryCommand;
+use crate::database::open_metadata_store;
 use crate::errors::BucketError;
 use crate::utils::utils::find_bucket_repo;
-use duckdb::Connection;
 
 #[derive(Debug)]
 pub struct CommitRecord {
@@ -11,21 +11,30 @@ pub struct CommitRecord {
     message: String,
     created_at: String,
     bucket_name: String,
+    author: Option<String>,
 }
 
 impl CommitRecord {
-    pub fn new(id: String, message: String, created_at: String, bucket_name: String) -> Self {
+    pub fn new(
+        id: String,
+        message: String,
+        created_at: String,
+        bucket_name: String,
+        author: Option<String>,
+    ) -> Self {
         Self {
             id,
             message,
             created_at,
             bucket_name,
+            author,
         }
     }
 
     pub fn display(&self) {
         println!("Commit ID: {}", self.id);
         println!("Message: {}", self.message);
+        println!("Author: {}", self.author.as_deref().unwrap_or("unknown"));
         println!("Created At: {}", self.created_at);
         println!("Bucket: {}", self.bucket_name);
         println!("----------------------------------------");
@@ -45,20 +54,20 @@ pub fn execute(command: HistoryCommand) -> Result<(), BucketError> {
 
 fn fetch_commit_history(bucket_dir: &PathBuf) -> Result<Vec<CommitRecord>, BucketError> {
     let repo_root = find_bucket_repo(&bucket_dir).ok_or(BucketError::NotInRepo)?;
-    let db_path = repo_root.join("buckets.db");
 
-    let conn = Connection::open(&db_path)?;
-    let mut stmt = conn.prepare(
-        "SELECT c.id, c.message, CAST(c.created_at AS TEXT), b.name as bucket_name 
+    let conn = open_metadata_store(&repo_root)?;
+    let rows = conn.query(
+        "SELECT c.id, c.message, CAST(c.created_at AS TEXT), b.name as bucket_name,
+                c.author_name, c.author_email
          FROM commits c 
          JOIN buckets b ON c.bucket_id = b.id 
+         WHERE NOT c.abandoned
          ORDER BY c.created_at DESC",
+        &[],
     )?;
 
     let mut commits = Vec::new();
-    let mut rows = stmt.query([])?;
-
-    while let Some(row) = rows.next()? {
+    for row in rows {
         let id: String = row.get(0)?;
         let message: String = row.get(1)?;
         let created_at: String = match row.get(2) {
@@ -80,7 +89,20 @@ fn fetch_commit_history(bucket_dir: &PathBuf) -> Result<Vec<CommitRecord>, Bucke
             }
         };
 
-        commits.push(CommitRecord::new(id, message, created_at, bucket_name));
+        let author_name: Option<String> = row.get(4)?;
+        let author_email: Option<String> = row.get(5)?;
+        let author = author_name.map(|name| match author_email {
+            Some(email) => format!("{} <{}>", name, email),
+            None => name,
+        });
+
+        commits.push(CommitRecord::new(
+            id,
+            message,
+            created_at,
+            bucket_name,
+            author,
+        ));
     }
 
     Ok(commits)
@@ -112,6 +134,7 @@ mod tests {
             "Test commit".to_string(),
             "2023-01-01 12:00:00".to_string(),
             "test_bucket".to_string(),
+            Some("Alice <alice@example.com>".to_string()),
         );
 
         // This is a simple test that just ensures the display method doesn't panic
@@ -4,6 +4,7 @@ use crate::config::Config;
 use crate::database::{initialize_database, DatabaseType};
 use crate::errors::BucketError;
 use crate::utils::checks;
+use crate::utils::config::DatabaseConfig;
 use crate::CURRENT_DIR;
 use log::debug;
 use std::io::Write;
@@ -45,7 +46,11 @@ impl Init {
         fs::create_dir_all(&repo_buckets_path)?;
         self.create_config_file(&repo_buckets_path)?;
 
-        let db_type = DatabaseType::from_str(&self.args.database)?;
+        // A database URL always points at a PostgreSQL server
+        let db_type = match self.args.database_url {
+            Some(_) => DatabaseType::PostgreSQL,
+            None => DatabaseType::from_str(&self.args.database)?,
+        };
         initialize_database(&repo_buckets_path, db_type)?;
 
         Ok(())
@@ -57,6 +62,7 @@ impl Init {
             ntp_server: "pool.ntp.org".to_string(),
             ip_check: "8.8.8.8".to_string(),
             url_check: "api.ipify.org".to_string(),
+            database: self.database_config(),
         };
 
         // Serialize the configuration to TOML format
@@ -78,6 +84,20 @@ impl Init {
         Ok(())
     }
 
+    /// The `[database]` table of a repository on an external PostgreSQL server
+    fn database_config(&self) -> Option<DatabaseConfig> {
+        let url = self.args.database_url.clone()?;
+        let schema = match &self.args.database_schema {
+            Some(schema) => schema.clone(),
+            None => default_schema(&self.args.repo_name),
+        };
+        Some(DatabaseConfig {
+            url: Some(url),
+            schema: Some(schema),
+            ..DatabaseConfig::default()
+        })
+    }
+
     fn checks(&self, repo_name: &str) -> Result<(), BucketError> {
         let repo_path = CURRENT_DIR.with(|dir| dir.join(repo_name));
 
@@ -101,6 +121,18 @@ impl Init {
     }
 }
 
+/// The schema of a repository on a shared server, named after the repository in the
+/// characters PostgreSQL allows in an identifier without quoting it.
+pub(crate) fn default_schema(repo_name: &str) -> String {
+    let name = repo_name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    // PostgreSQL keeps the first 63 bytes of an identifier
+    format!("buckets_{}", name).chars().take(63).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,6 +145,8 @@ mod tests {
             shared: SharedArguments::default(),
             repo_name: repo_name.to_string(),
             database: database.to_string(),
+            database_url: None,
+            database_schema: None,
         };
         Init::new(&args)
     }
@@ -123,6 +157,8 @@ mod tests {
             shared: SharedArguments::default(),
             repo_name: "test_repo".to_string(),
             database: "duckdb".to_string(),
+            database_url: None,
+            database_schema: None,
         };
         let init = Init::new(&args);
         assert_eq!(init.args.repo_name, "test_repo");
@@ -394,15 +430,20 @@ mod tests {
         assert!(DatabaseType::from_str("duckdb").is_ok());
         assert!(DatabaseType::from_str("postgresql").is_ok());
         assert!(DatabaseType::from_str("postgres").is_ok());
+        assert_eq!(
+            DatabaseType::from_str("sqlite").expect("sqlite is a database type"),
+            DatabaseType::SQLite
+        );
 
         // Test case insensitivity
         assert!(DatabaseType::from_str("DUCKDB").is_ok());
         assert!(DatabaseType::from_str("PostgreSQL").is_ok());
         assert!(DatabaseType::from_str("POSTGRES").is_ok());
+        assert!(DatabaseType::from_str("SQLite").is_ok());
 
         // Test invalid database types
         assert!(DatabaseType::from_str("mysql").is_err());
-        assert!(DatabaseType::from_str("sqlite").is_err());
+        assert!(DatabaseType::from_str("sqlite3").is_err());
         assert!(DatabaseType::from_str("invalid").is_err());
         assert!(DatabaseType::from_str("").is_err());
     }
@@ -433,4 +474,44 @@ mod tests {
         assert_eq!(parsed["ip_check"].as_str(), Some("8.8.8.8"));
         assert_eq!(parsed["url_check"].as_str(), Some("api.ipify.org"));
     }
+
+    #[test]
+    fn test_config_file_database_table() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let config_dir = temp_dir.path().join("test_config");
+
+        // Repositories on an embedded server or DuckDB have no [database] table
+        let init = create_test_init_command("test_repo", "postgresql");
+        init.create_config_file(&config_dir)
+            .expect("Failed to create config file");
+        let content = fs::read_to_string(config_dir.join("config")).expect("Failed to read config");
+        assert!(!content.contains("[database]"));
+
+        let init = Init::new(&InitCommand {
+            shared: SharedArguments::default(),
+            repo_name: "Shot 010".to_string(),
+            database: "duckdb".to_string(),
+            database_url: Some("postgres://buckets@db.studio.lan/assets".to_string()),
+            database_schema: None,
+        });
+        init.create_config_file(&config_dir)
+            .expect("Failed to create config file");
+        let content = fs::read_to_string(config_dir.join("config")).expect("Failed to read config");
+        let parsed: toml::Value = toml::from_str(&content).expect("Config file is not valid TOML");
+        assert_eq!(
+            parsed["database"]["url"].as_str(),
+            Some("postgres://buckets@db.studio.lan/assets")
+        );
+        assert_eq!(
+            parsed["database"]["schema"].as_str(),
+            Some("buckets_shot_010")
+        );
+    }
+
+    #[test]
+    fn test_default_schema() {
+        assert_eq!(default_schema("assets"), "buckets_assets");
+        assert_eq!(default_schema("Shot-010.v2"), "buckets_shot_010_v2");
+        assert_eq!(default_schema(&"a".repeat(100)).len(), 63);
+    }
 }
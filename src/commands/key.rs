@@ -0,0 +1,189 @@
+use crate::args::{KeyCommand, KeySourceCommand, KeySubcommand};
+use crate::commands::commit::load_current_tree;
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::cache::trim_cache;
+use crate::utils::compression::{encryption_key, reencrypt_object};
+use crate::utils::dictionary::reencrypt_dictionaries;
+use crate::utils::encryption::{
+    master_key, remember_key, KeyEntry, KeyRing, NEW_PASSPHRASE_ENV, PASSPHRASE_ENV,
+};
+use crate::utils::object_store::{publish_object, remote_store, LocalStore, ObjectStore};
+use crate::utils::pack::{packed_objects, remove_packs};
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Encryption of the object storage of a repository
+pub struct Key {
+    args: KeyCommand,
+}
+
+impl BucketCommand for Key {
+    type Args = KeyCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        match &self.args.command {
+            KeySubcommand::Init(command) => init_key(&world, command),
+            KeySubcommand::Rotate(command) => rotate_key(&world, command),
+        }
+    }
+}
+
+/// Creates the first key of the repository and encrypts every stored object with it.
+fn init_key(world: &World, args: &KeySourceCommand) -> Result<(), BucketError> {
+    let buckets_dir = &world.repo_root;
+    if KeyRing::load(buckets_dir)?.is_some() {
+        return Err(BucketError::InvalidData(
+            "the repository is encrypted already, use `bucket key rotate` to change its key"
+                .to_string(),
+        ));
+    }
+
+    let (entry, key) = KeyEntry::create(args.key_file.as_deref(), PASSPHRASE_ENV)?;
+    remember_key(entry.id, key);
+    let ring = KeyRing {
+        current: entry.id,
+        keys: vec![entry],
+    };
+    ring.save(buckets_dir)?;
+
+    let count = reencrypt_storage(world, ring.current)?;
+    println!("Encrypted {} objects with key {:08x}", count, ring.current);
+    Ok(())
+}
+
+/// Adds a new key and re-encrypts every stored object with it. The old key is removed
+/// once all objects are re-encrypted, an interrupted rotation is resumed by running it
+/// again.
+fn rotate_key(world: &World, args: &KeySourceCommand) -> Result<(), BucketError> {
+    let buckets_dir = &world.repo_root;
+    let mut ring = KeyRing::load(buckets_dir)?.ok_or_else(|| {
+        BucketError::InvalidData(
+            "the repository is not encrypted, use `bucket key init` first".to_string(),
+        )
+    })?;
+
+    if ring.keys.len() > 1 {
+        println!("Resuming the rotation to key {:08x}", ring.current);
+    } else {
+        // The current key is needed to decrypt the objects
+        master_key(buckets_dir, ring.current)?;
+        let (entry, key) = KeyEntry::create(args.key_file.as_deref(), NEW_PASSPHRASE_ENV)?;
+        remember_key(entry.id, key);
+        ring.current = entry.id;
+        ring.keys.push(entry);
+        ring.save(buckets_dir)?;
+    }
+
+    let count = reencrypt_storage(world, ring.current)?;
+    let current = ring.current;
+    ring.keys.retain(|entry| entry.id == current);
+    ring.save(buckets_dir)?;
+    println!(
+        "Re-encrypted {} objects with key {:08x}",
+        count, ring.current
+    );
+    if args.key_file.is_none() {
+        println!("Use the new passphrase in {} from now on.", PASSPHRASE_ENV);
+    }
+    Ok(())
+}
+
+/// Encrypts every object of every bucket with the current key `key_id` and returns how
+/// many objects were rewritten. Packed objects are rewritten as loose objects and their
+/// packs removed, objects in a remote store are written back to it. The dictionaries of
+/// the repository are encrypted with the same key.
+fn reencrypt_storage(world: &World, key_id: u32) -> Result<usize, BucketError> {
+    let repo_dir = world.repo_root.parent().ok_or(BucketError::NotInRepo)?;
+    let buckets = with_db_connection(|connection| {
+        let rows = connection.query("SELECT id, path FROM buckets ORDER BY name", &[])?;
+        let mut buckets = Vec::new();
+        for row in rows {
+            let (id, path) = (row.get::<String>(0)?, row.get::<String>(1)?);
+            let id = Uuid::parse_str(&id)
+                .map_err(|e| BucketError::InvalidData(format!("Invalid UUID: {}", e)))?;
+            buckets.push((path, load_current_tree(connection, id)?));
+        }
+        Ok(buckets)
+    })?;
+
+    let mut count = 0;
+    for (bucket_path, current_tree) in buckets {
+        let storage_path = repo_dir.join(&bucket_path).join(".b").join("storage");
+        count += reencrypt_objects(&storage_path, key_id)?;
+        // Objects of a remote store were fetched to be re-encrypted
+        trim_cache(&storage_path, &current_tree)?;
+    }
+
+    let dictionaries = reencrypt_dictionaries(&world.repo_root, key_id)?;
+    if dictionaries > 0 {
+        println!(
+            "Encrypted {} dictionaries with key {:08x}",
+            dictionaries, key_id
+        );
+    }
+    Ok(count)
+}
+
+/// Re-encrypts the loose, packed and remote objects of a bucket. Objects are written back
+/// to the remote store only when it holds them, objects kept in the bucket stay there.
+fn reencrypt_objects(storage_path: &Path, key_id: u32) -> Result<usize, BucketError> {
+    let remote = remote_store(storage_path)?;
+    let remote_hashes = match &remote {
+        Some(remote) => remote.list()?,
+        None => Vec::new(),
+    };
+    let mut hashes = LocalStore::new(storage_path).list()?;
+    hashes.extend(remote_hashes.iter().cloned());
+    hashes.sort();
+    hashes.dedup();
+    let packed = packed_objects(storage_path)?;
+
+    let mut count = 0;
+    for hash in hashes {
+        let object_path = storage_path.join(&hash);
+        let reencrypted = reencrypt_object(&object_path)?;
+        if reencrypted {
+            count += 1;
+        }
+        let Some(remote) = &remote else {
+            continue;
+        };
+        // An interrupted rotation may have re-encrypted the object without writing it
+        // back to the remote store
+        if remote_hashes.binary_search(&hash).is_ok()
+            && (reencrypted || encryption_key(&mut remote.get(&hash)?)? != Some(key_id))
+        {
+            publish_object(&object_path)?;
+        }
+    }
+
+    if packed.is_empty() {
+        return Ok(count);
+    }
+    for (hash, _) in &packed {
+        let loose_key = match File::open(storage_path.join(hash)) {
+            Ok(mut file) => encryption_key(&mut file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if loose_key != Some(key_id) {
+            return Err(BucketError::InvalidData(format!(
+                "packed object {} in {} was not re-encrypted, its pack is kept",
+                hash,
+                storage_path.display()
+            )));
+        }
+    }
+    remove_packs(storage_path)?;
+    Ok(count)
+}
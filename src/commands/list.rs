@@ -1,13 +1,21 @@
 use crate::args::ListCommand;
 use crate::commands::BucketCommand;
 use crate::errors::BucketError;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
 
-/// List command placeholder
+/// List all buckets in the repository with their latest commit
 pub struct List {
-    #[allow(dead_code)]
     args: ListCommand,
 }
 
+/// A bucket and the author and date of its latest commit, if any
+struct BucketSummary {
+    name: String,
+    path: String,
+    last_commit: Option<(String, Option<String>)>,
+}
+
 impl BucketCommand for List {
     type Args = ListCommand;
 
@@ -16,7 +24,60 @@ impl BucketCommand for List {
     }
 
     fn execute(&self) -> Result<(), BucketError> {
-        println!("list command");
+        let _world = World::new(&self.args.shared)?;
+        let buckets = query_buckets()?;
+
+        if buckets.is_empty() {
+            println!("No buckets found.");
+            return Ok(());
+        }
+
+        for bucket in buckets {
+            match bucket.last_commit {
+                Some((created_at, author)) => println!(
+                    "{}    {}    last commit {} by {}",
+                    bucket.name,
+                    bucket.path,
+                    created_at,
+                    author.as_deref().unwrap_or("unknown")
+                ),
+                None => println!("{}    {}    no commits", bucket.name, bucket.path),
+            }
+        }
         Ok(())
     }
 }
+
+fn query_buckets() -> Result<Vec<BucketSummary>, BucketError> {
+    with_db_connection(|connection| {
+        let rows = connection.query(
+            "SELECT b.name, b.path, CAST(c.created_at AS TEXT), c.author_name, c.author_email
+             FROM buckets b
+             LEFT JOIN (
+                 SELECT *, ROW_NUMBER() OVER (PARTITION BY bucket_id ORDER BY created_at DESC) AS n
+                 FROM commits
+                 WHERE NOT abandoned
+             ) c ON c.bucket_id = b.id AND c.n = 1
+             ORDER BY b.name",
+            &[],
+        )?;
+
+        let mut buckets = Vec::new();
+        for row in rows {
+            let created_at: Option<String> = row.get(2)?;
+            let author_name: Option<String> = row.get(3)?;
+            let author_email: Option<String> = row.get(4)?;
+            let author = author_name.map(|name| match author_email {
+                Some(email) => format!("{} <{}>", name, email),
+                None => name,
+            });
+
+            buckets.push(BucketSummary {
+                name: row.get(0)?,
+                path: row.get(1)?,
+                last_commit: created_at.map(|created_at| (created_at, author)),
+            });
+        }
+        Ok(buckets)
+    })
+}
@@ -0,0 +1,38 @@
+use crate::args::LockCommand;
+use crate::commands::BucketCommand;
+use crate::data::file_lock::lock_file;
+use crate::errors::BucketError;
+use crate::utils::identity::Identity;
+use crate::utils::utils::{bucket_relative_path, find_bucket_path, with_db_connection};
+use crate::world::World;
+
+/// Take an exclusive lock on a file so that no one else can commit it
+pub struct Lock {
+    args: LockCommand,
+}
+
+impl BucketCommand for Lock {
+    type Args = LockCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket = world.bucket.as_ref().ok_or(BucketError::NotInBucket)?;
+        let bucket_path = find_bucket_path(&world.work_dir).ok_or(BucketError::NotInBucket)?;
+
+        let file_path = bucket_relative_path(&bucket_path, &world.work_dir, &self.args.file)?;
+        if !bucket_path.join(&file_path).is_file() {
+            return Err(BucketError::FileNotFound(self.args.file.clone()));
+        }
+
+        let owner = Identity::resolve(&world.work_dir).name;
+        let lock =
+            with_db_connection(|connection| lock_file(connection, bucket.id, &file_path, &owner))?;
+
+        println!("Locked {} for {}", lock.file_path, lock.owner);
+        Ok(())
+    }
+}
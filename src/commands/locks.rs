@@ -0,0 +1,55 @@
+use crate::args::LocksCommand;
+use crate::commands::BucketCommand;
+use crate::data::file_lock::list_locks;
+use crate::errors::BucketError;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+use std::collections::HashMap;
+
+/// List the file locks of the current bucket, or of all buckets when run
+/// outside a bucket
+pub struct Locks {
+    args: LocksCommand,
+}
+
+impl BucketCommand for Locks {
+    type Args = LocksCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket_id = world.bucket.as_ref().map(|bucket| bucket.id);
+
+        let (locks, bucket_names) = with_db_connection(|connection| {
+            let locks = list_locks(connection, bucket_id)?;
+
+            let rows = connection.query("SELECT id, name FROM buckets", &[])?;
+            let mut bucket_names = HashMap::new();
+            for row in rows {
+                let (id, name) = (row.get::<String>(0)?, row.get::<String>(1)?);
+                bucket_names.insert(id.to_lowercase(), name);
+            }
+            Ok((locks, bucket_names))
+        })?;
+
+        if locks.is_empty() {
+            println!("No files are locked.");
+            return Ok(());
+        }
+
+        for lock in locks {
+            let bucket_name = bucket_names
+                .get(&lock.bucket_id.to_string())
+                .map(String::as_str)
+                .unwrap_or("?");
+            println!(
+                "{}/{}    {}    {}",
+                bucket_name, lock.file_path, lock.owner, lock.created_at
+            );
+        }
+        Ok(())
+    }
+}
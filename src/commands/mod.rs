@@ -117,18 +117,33 @@ impl CommandDispatcher {
     }
 }
 
+pub(crate) mod archive;
 pub(crate) mod check;
+pub(crate) mod check_ignore;
 pub(crate) mod commit;
 pub(crate) mod create;
+pub(crate) mod db;
 pub(crate) mod expect;
+pub(crate) mod export;
 pub(crate) mod finalize;
+pub(crate) mod fsck;
 pub(crate) mod history;
 pub(crate) mod init;
+pub(crate) mod key;
 pub(crate) mod link;
 pub(crate) mod list;
+pub(crate) mod lock;
+pub(crate) mod locks;
+pub(crate) mod oplog;
+pub(crate) mod prefetch;
 pub(crate) mod restore;
+pub(crate) mod restore_version;
 pub(crate) mod rollback;
 pub mod schema;
 pub(crate) mod stash;
 pub(crate) mod stats;
 pub(crate) mod status;
+pub(crate) mod storage;
+pub(crate) mod uncommit;
+pub(crate) mod undo;
+pub(crate) mod unlock;
@@ -0,0 +1,60 @@
+use crate::args::OplogCommand;
+use crate::commands::BucketCommand;
+use crate::data::operation::list_operations;
+use crate::errors::BucketError;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+use std::collections::HashMap;
+
+/// List recent operations of the current bucket, or of all buckets when run
+/// outside a bucket
+pub struct Oplog {
+    args: OplogCommand,
+}
+
+impl BucketCommand for Oplog {
+    type Args = OplogCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket_id = world.bucket.as_ref().map(|bucket| bucket.id);
+
+        let (operations, bucket_names) = with_db_connection(|connection| {
+            let operations = list_operations(connection, bucket_id, self.args.limit)?;
+
+            let rows = connection.query("SELECT id, name FROM buckets", &[])?;
+            let mut bucket_names = HashMap::new();
+            for row in rows {
+                let (id, name) = (row.get::<String>(0)?, row.get::<String>(1)?);
+                bucket_names.insert(id.to_lowercase(), name);
+            }
+            Ok((operations, bucket_names))
+        })?;
+
+        if operations.is_empty() {
+            println!("No operations recorded.");
+            return Ok(());
+        }
+
+        for operation in operations {
+            let bucket_name = bucket_names
+                .get(&operation.bucket_id.to_string())
+                .map(String::as_str)
+                .unwrap_or("?");
+            println!(
+                "{}    {}    {}    {}    {}{}",
+                operation.created_at,
+                bucket_name,
+                operation.kind,
+                operation.description,
+                operation.author_name.as_deref().unwrap_or("unknown"),
+                if operation.undone { "    (undone)" } else { "" }
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use crate::args::PrefetchCommand;
+use crate::commands::commit::{find_commit, load_commit_files, load_current_tree};
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::cache::{prefetch_objects, trim_cache};
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+
+/// Fetch the objects of a commit from the remote store, to restore it while offline
+pub struct Prefetch {
+    args: PrefetchCommand,
+}
+
+impl BucketCommand for Prefetch {
+    type Args = PrefetchCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket_id = world.bucket.as_ref().map(|bucket| bucket.id);
+
+        let (commit, files, current_tree) = with_db_connection(|connection| {
+            let commit = find_commit(connection, bucket_id, &self.args.commit)?;
+            let files = load_commit_files(connection, &commit.id)?;
+            let current_tree = load_current_tree(connection, commit.bucket_id)?;
+            Ok((commit, files, current_tree))
+        })?;
+
+        let repository_dir = world.repo_root.parent().ok_or(BucketError::NotInRepo)?;
+        let storage_path = repository_dir
+            .join(&commit.bucket_path)
+            .join(".b")
+            .join("storage");
+        let hashes = files
+            .iter()
+            .map(|(_, hash)| hash.to_string())
+            .collect::<Vec<_>>();
+        let prefetched = prefetch_objects(&storage_path, &hashes)?;
+
+        // The prefetched commit is kept like the current tree, even beyond the cache size
+        let mut keep = current_tree;
+        keep.extend(hashes);
+        trim_cache(&storage_path, &keep)?;
+
+        println!(
+            "Fetched {} objects ({} bytes) of {} commit {}, {} were cached already",
+            prefetched.fetched, prefetched.size, commit.bucket_name, commit.id, prefetched.cached
+        );
+        Ok(())
+    }
+}
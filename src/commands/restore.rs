@@ -1,20 +1,38 @@
 use crate::args::RestoreCommand;
+use crate::commands::commit::Commit;
 use crate::commands::BucketCommand;
 use crate::data::bucket::{Bucket, BucketTrait};
+use crate::data::operation::{record_before_overwrite, OperationKind};
 use crate::errors::BucketError;
 use crate::utils::checks;
-use crate::utils::utils::{find_bucket_path, with_db_connection};
+use crate::utils::compression::restore_file;
+use crate::utils::selection::PathSelection;
+use crate::utils::utils::{find_bucket_files, find_bucket_path, hash_file};
 use crate::CURRENT_DIR;
+use blake3::Hash;
 use log::{debug, error};
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
 
-/// Restore a file from the last commit
+/// Argument of `revert` selecting every file of the bucket
+const ALL_FILES: &str = "all";
+
+/// Discard changes to files, restoring them from the last commit
 pub struct Restore {
     args: RestoreCommand,
 }
 
+/// What reverting does to a single file
+#[derive(Debug, PartialEq)]
+enum RevertAction {
+    /// Replace a modified or deleted file with its committed content
+    Restore(Hash),
+    /// Delete a file that is not in the last commit
+    Remove,
+    /// Keep a file that is not in the last commit, without `--remove-new`
+    KeepNew,
+}
+
 impl BucketCommand for Restore {
     type Args = RestoreCommand;
 
@@ -36,97 +54,169 @@ impl BucketCommand for Restore {
 
         let bucket = Bucket::from_meta_data(&current_dir)?;
 
-        let file_path = self.args.file.clone();
-
-        // Get the file's hash from the last commit using a shared connection
-        let hash = with_db_connection(|connection| {
-            let mut stmt = connection.prepare(
-                "SELECT f.hash 
-            FROM files f
-            JOIN commits c ON f.commit_id = c.id
-            WHERE f.file_path = ?1
-            AND c.created_at = (
-                SELECT MAX(created_at) 
-                FROM commits 
-                WHERE bucket_id = ?2
-            )",
-            )?;
-            let relative_path = PathBuf::from(&file_path)
-                .strip_prefix(&bucket_path)
-                .unwrap_or(&PathBuf::from(&file_path))
-                .to_string_lossy()
-                .to_string();
-            let rows = stmt.query_map([&relative_path, &bucket.id.to_string()], |row| {
-                row.get::<_, String>(0)
-            })?;
-
-            match rows.last() {
-                Some(Ok(hash)) => Ok(hash),
-                _ => Err(BucketError::FileNotFound(file_path.clone())),
+        let committed = match Commit::load_last_commit(bucket.name.clone())? {
+            Some(commit) => commit
+                .files
+                .into_iter()
+                .map(|file| (file.name, file.hash))
+                .collect::<BTreeMap<_, _>>(),
+            None => return Err(BucketError::NotFound("previous commit".to_string())),
+        };
+        let working = find_bucket_files(&bucket_path)?
+            .into_iter()
+            .map(|file| file.to_string_lossy().to_string())
+            .collect::<BTreeSet<_>>();
+
+        // `revert all` selects every file, otherwise files, directories and glob patterns
+        let selection = if self.args.paths.iter().any(|path| path == ALL_FILES) {
+            None
+        } else {
+            let selection = PathSelection::new(&bucket_path, &current_dir, &self.args.paths)?;
+            let known = committed
+                .keys()
+                .chain(working.iter())
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            let unmatched = selection.unmatched(known.iter().copied());
+            if !unmatched.is_empty() {
+                return Err(BucketError::FileNotFound(format!(
+                    "{} did not match any files",
+                    unmatched.join(", ")
+                )));
             }
-        })?;
-
-        // Construct paths
-        let storage_path = bucket_path.join(".b").join("storage").join(&hash);
-        let target_path = PathBuf::from(&file_path);
-
-        debug!(
-            "Restoring {} from {}",
-            target_path.display(),
-            storage_path.display()
-        );
-
-        // Decompress and copy the file from storage
-        self.decompress_and_restore_file(&storage_path, &target_path)
-            .map_err(|e| {
-                error!("Failed to restore file: {}", e);
-                BucketError::from(e)
-            })?;
-
-        println!("Restored {}", file_path);
-        Ok(())
-    }
-}
+            Some(selection)
+        };
+        let selected = |file: &str| match &selection {
+            Some(selection) => selection.matches(file),
+            None => true,
+        };
 
-impl Restore {
-    fn decompress_and_restore_file(
-        &self,
-        storage_path: &PathBuf,
-        target_path: &PathBuf,
-    ) -> std::io::Result<()> {
-        // Create parent directories if they don't exist
-        if let Some(parent) = target_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let mut actions = Vec::new();
+        for (name, hash) in &committed {
+            if !selected(name) {
+                continue;
+            }
+            let working_file = bucket_path.join(name);
+            if working_file.is_file() && hash_file(&working_file)? == *hash {
+                continue;
+            }
+            actions.push((name.clone(), RevertAction::Restore(*hash)));
+        }
+        for name in working.iter().filter(|name| !committed.contains_key(*name)) {
+            if !selected(name) {
+                continue;
+            }
+            let action = if self.args.remove_new {
+                RevertAction::Remove
+            } else {
+                RevertAction::KeepNew
+            };
+            actions.push((name.clone(), action));
         }
 
-        // Open the compressed file
-        let input_file = File::open(storage_path)?;
-        let reader = BufReader::new(input_file);
+        if actions.is_empty() {
+            println!("No changes to revert.");
+            return Ok(());
+        }
 
-        // Delete the target file if it exists
-        if target_path.exists() {
-            std::fs::remove_file(target_path)?;
+        let changed = actions
+            .iter()
+            .filter(|(_, action)| *action != RevertAction::KeepNew)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+
+        if self.args.dry_run {
+            for (name, action) in &actions {
+                match action {
+                    RevertAction::Restore(_) => println!("restore:    {}", name),
+                    RevertAction::Remove => println!("remove:    {}", name),
+                    RevertAction::KeepNew => println!("keep new:    {}", name),
+                }
+            }
+            println!(
+                "Dry run: {} files would be reverted. Nothing was changed.",
+                changed.len()
+            );
+            return Ok(());
         }
 
-        // Create the output file
-        let output_file = File::create(target_path)?;
-        let writer = BufWriter::new(output_file);
+        // Keep the discarded changes, so the revert can be undone
+        if !changed.is_empty() {
+            record_before_overwrite(
+                bucket.id,
+                &bucket_path,
+                OperationKind::Revert,
+                format!("revert {}", self.args.paths.join(" ")),
+                &changed,
+            )?;
+        }
 
-        // Create a zstd decoder
-        let mut decoder = zstd::Decoder::new(reader)?;
+        let storage_path = bucket_path.join(".b").join("storage");
+        let mut failed = Vec::new();
+        for (name, action) in &actions {
+            let target_path = bucket_path.join(name);
+            let result = match action {
+                RevertAction::Restore(hash) => {
+                    debug!("Restoring {} from {}", name, hash);
+                    decompress_and_restore_file(
+                        &storage_path.join(hash.to_string()),
+                        &target_path,
+                        hash,
+                    )
+                }
+                RevertAction::Remove => std::fs::remove_file(&target_path),
+                RevertAction::KeepNew => {
+                    println!("kept new:    {} (use --remove-new to delete it)", name);
+                    continue;
+                }
+            };
+            match (result, action) {
+                (Ok(()), RevertAction::Remove) => println!("removed:    {}", name),
+                (Ok(()), _) => println!("restored:    {}", name),
+                (Err(e), _) => {
+                    error!("Failed to revert {}: {}", name, e);
+                    println!("failed:    {} ({})", name, e);
+                    failed.push(name.clone());
+                }
+            }
+        }
 
-        // Copy data from decoder to output
-        std::io::copy(&mut decoder, &mut BufWriter::new(writer))?;
+        if !failed.is_empty() {
+            return Err(BucketError::InvalidData(format!(
+                "failed to revert {}",
+                failed.join(", ")
+            )));
+        }
         Ok(())
     }
 }
 
+/// Decompresses a stored object to `target_path`, which may be anywhere on disk.
+///
+/// Used by `revert` for working files and by `restore --to` and `export` to
+/// materialize committed versions elsewhere.
+pub(crate) fn decompress_and_restore_file(
+    storage_path: &Path,
+    target_path: &Path,
+    expected_hash: &Hash,
+) -> std::io::Result<()> {
+    // Create parent directories if they don't exist
+    if let Some(parent) = target_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Decompress into a temporary file and only replace the target when the
+    // content matches the committed hash
+    restore_file(storage_path, target_path, expected_hash)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::compression::compress_and_store_file;
     use serial_test::serial;
 
     use super::*;
+    use std::fs::File;
     use std::io::Write;
     use std::{env, fs};
     use tempfile::tempdir;
@@ -175,8 +265,10 @@ mod tests {
 
         // Restore the file
         let restore_cmd = RestoreCommand {
-            file: file_path.to_str().unwrap().to_string(),
+            paths: vec![file_path.to_str().unwrap().to_string()],
             shared: Default::default(),
+            remove_new: false,
+            dry_run: false,
         };
         let cmd = Restore::new(&restore_cmd);
         cmd.execute().unwrap();
@@ -211,13 +303,12 @@ mod tests {
         let restored_path = temp_dir.path().join("restored.txt");
 
         // Call the function we're testing
-        let restore_cmd = Restore::new(&RestoreCommand {
-            shared: crate::args::SharedArguments::default(),
-            file: "test".to_string(),
-        });
-        restore_cmd
-            .decompress_and_restore_file(&compressed_path, &restored_path)
-            .expect("Failed to decompress and restore file");
+        decompress_and_restore_file(
+            &compressed_path,
+            &restored_path,
+            &blake3::hash(original_content),
+        )
+        .expect("Failed to decompress and restore file");
 
         // Read the restored content
         let restored_content = std::fs::read(&restored_path).expect("Failed to read restored file");
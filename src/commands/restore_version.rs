@@ -0,0 +1,90 @@
+use crate::args::RestoreVersionCommand;
+use crate::commands::commit::{find_commit, load_commit_files, load_head_commits};
+use crate::commands::restore::decompress_and_restore_file;
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::selection::PathSelection;
+use crate::utils::utils::{find_bucket_path, with_db_connection};
+use crate::world::World;
+use std::path::{Component, Path, PathBuf};
+
+/// Restore files as they were in a commit into another directory, leaving the
+/// working files alone
+pub struct RestoreVersion {
+    args: RestoreVersionCommand,
+}
+
+impl BucketCommand for RestoreVersion {
+    type Args = RestoreVersionCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket = world.bucket.as_ref().ok_or(BucketError::NotInBucket)?;
+        let bucket_path = find_bucket_path(&world.work_dir).ok_or(BucketError::NotInBucket)?;
+
+        // Without --commit the files come from the head of the bucket
+        let (commit_id, files) = with_db_connection(|connection| {
+            let commit_id = match &self.args.commit {
+                Some(id) => find_commit(connection, Some(bucket.id), id)?.id,
+                None => load_head_commits(connection, bucket.id, 1)?
+                    .into_iter()
+                    .next()
+                    .map(|head| head.id)
+                    .ok_or_else(|| BucketError::NotFound("previous commit".to_string()))?,
+            };
+            let files = load_commit_files(connection, &commit_id)?;
+            Ok((commit_id, files))
+        })?;
+
+        let selection = PathSelection::new(&bucket_path, &world.work_dir, &self.args.paths)?;
+        let unmatched = selection.unmatched(files.iter().map(|(name, _)| name.as_str()));
+        if !unmatched.is_empty() {
+            return Err(BucketError::FileNotFound(format!(
+                "{} did not match any files in commit {}",
+                unmatched.join(", "),
+                commit_id
+            )));
+        }
+
+        // Files keep their path within the bucket below the destination. Working files
+        // are restored by revert, which takes the repository lock and can be undone.
+        let destination = world.work_dir.join(&self.args.to);
+        if let Some(bucket_dir) = bucket_containing(&destination)? {
+            return Err(BucketError::InvalidData(format!(
+                "{} is inside the bucket at {}, use revert to restore working files",
+                destination.display(),
+                bucket_dir.display()
+            )));
+        }
+        let storage_path = bucket_path.join(".b").join("storage");
+        for (name, hash) in files.iter().filter(|(name, _)| selection.matches(name)) {
+            let target_path = destination.join(name);
+            decompress_and_restore_file(&storage_path.join(hash.to_string()), &target_path, hash)?;
+            println!("restored:    {} -> {}", name, target_path.display());
+        }
+        Ok(())
+    }
+}
+
+/// The bucket `path` is in, if any. `path` does not need to exist yet, the part of it
+/// that does is resolved with its symbolic links.
+pub(crate) fn bucket_containing(path: &Path) -> Result<Option<PathBuf>, BucketError> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    match normalized.ancestors().find(|ancestor| ancestor.exists()) {
+        Some(existing) => Ok(find_bucket_path(&existing.canonicalize()?)),
+        None => Ok(None),
+    }
+}
@@ -7,6 +7,7 @@ use crate::commands::commit::Commit;
 use crate::commands::BucketCommand;
 use crate::data::bucket::{Bucket, BucketTrait};
 use crate::data::commit::CommitStatus;
+use crate::data::operation::{record_before_overwrite, OperationKind};
 use crate::errors::BucketError;
 use crate::utils::checks;
 use crate::utils::utils::{find_bucket_path, hash_file};
@@ -38,13 +39,17 @@ impl BucketCommand for Rollback {
         };
 
         match &self.args.path {
-            None => rollback_all(&current_dir),
-            Some(path) => rollback_single_file(&current_dir, &path),
+            None => rollback_all(&current_dir, self.args.dry_run),
+            Some(path) => rollback_single_file(&current_dir, path, self.args.dry_run),
         }
     }
 }
 
-fn rollback_single_file(bucket_path: &PathBuf, file: &PathBuf) -> Result<(), BucketError> {
+fn rollback_single_file(
+    bucket_path: &PathBuf,
+    file: &PathBuf,
+    dry_run: bool,
+) -> Result<(), BucketError> {
     if !file.exists() {
         return Err(BucketError::from(Error::new(
             ErrorKind::NotFound,
@@ -75,7 +80,19 @@ fn rollback_single_file(bucket_path: &PathBuf, file: &PathBuf) -> Result<(), Buc
                     ErrorKind::NotFound,
                     "File not found in previous commit.",
                 ))),
+                Some(file_to_restore) if dry_run => {
+                    println!("restore:    {}", file_to_restore.name);
+                    println!("Dry run: 1 file would be restored. Nothing was changed.");
+                    Ok(())
+                }
                 Some(file_to_restore) => {
+                    record_before_overwrite(
+                        bucket.id,
+                        bucket_path,
+                        OperationKind::Rollback,
+                        format!("rollback {}", file_to_restore.name),
+                        &[file_to_restore.name.as_str()],
+                    )?;
                     file_to_restore.restore(bucket_path)?; // Propagate any error from restore_file
                     Ok(())
                 }
@@ -91,7 +108,7 @@ fn rollback_single_file(bucket_path: &PathBuf, file: &PathBuf) -> Result<(), Buc
     }
 }
 
-fn rollback_all(bucket_path: &PathBuf) -> Result<(), BucketError> {
+fn rollback_all(bucket_path: &PathBuf, dry_run: bool) -> Result<(), BucketError> {
     // Read the bucket's metadata
     let bucket = Bucket::from_meta_data(&bucket_path)?;
     let bucket_files = bucket.list_files_with_metadata_in_bucket()?;
@@ -122,6 +139,30 @@ fn rollback_all(bucket_path: &PathBuf) -> Result<(), BucketError> {
                 return Ok(());
             }
 
+            // Keep the working files that are about to be replaced, so the rollback can be undone
+            let modified = changes
+                .iter()
+                .filter(|change| change.status == CommitStatus::Modified)
+                .map(|change| change.name.as_str())
+                .collect::<Vec<_>>();
+            if dry_run {
+                modified
+                    .iter()
+                    .for_each(|name| println!("restore:    {}", name));
+                println!(
+                    "Dry run: {} files would be restored. Nothing was changed.",
+                    modified.len()
+                );
+                return Ok(());
+            }
+            record_before_overwrite(
+                bucket.id,
+                bucket_path,
+                OperationKind::Rollback,
+                format!("rollback {} files", modified.len()),
+                &modified,
+            )?;
+
             changes
                 .iter()
                 .filter(|change| change.status == CommitStatus::Modified)
@@ -149,11 +190,12 @@ mod tests {
     use crate::args::SharedArguments;
     use crate::data::bucket::Bucket;
     use crate::data::commit::{Commit as CommitData, CommittedFile};
+    use crate::utils::compression::CompressionPolicy;
     use crate::utils::utils::hash_file;
     use blake3::Hash;
     use serial_test::serial;
     use std::fs;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use std::str::FromStr;
     use tempfile::tempdir;
     use uuid::Uuid;
@@ -163,6 +205,7 @@ mod tests {
         let args = RollbackCommand {
             path,
             shared: SharedArguments::default(),
+            dry_run: false,
         };
         Rollback::new(&args)
     }
@@ -237,7 +280,7 @@ url_check = "api.ipify.org"
 
     // Helper function to create a test file and compress it to storage
     fn create_test_file_and_compress(
-        bucket_path: &PathBuf,
+        bucket_path: &Path,
         file_name: &str,
         content: &str,
     ) -> Result<(PathBuf, Hash), Box<dyn std::error::Error>> {
@@ -269,7 +312,7 @@ url_check = "api.ipify.org"
             status: crate::data::commit::CommitStatus::New,
         };
 
-        committed_file.compress_and_store(&bucket_path)?;
+        committed_file.compress_and_store(bucket_path, &CompressionPolicy::default())?;
 
         Ok((file_path, hash))
     }
@@ -290,7 +333,7 @@ url_check = "api.ipify.org"
             create_test_bucket_structure().expect("Failed to create test bucket structure");
 
         let nonexistent_file = PathBuf::from("nonexistent.txt");
-        let result = rollback_single_file(&bucket_path, &nonexistent_file);
+        let result = rollback_single_file(&bucket_path, &nonexistent_file, false);
 
         assert!(result.is_err());
         match result.unwrap_err() {
@@ -315,7 +358,7 @@ url_check = "api.ipify.org"
         let original_dir = std::env::current_dir().expect("Failed to get current directory");
         std::env::set_current_dir(&bucket_path).expect("Failed to change directory");
 
-        let result = rollback_single_file(&std::env::current_dir().unwrap(), &file_path);
+        let result = rollback_single_file(&std::env::current_dir().unwrap(), &file_path, false);
 
         // Restore original directory
         std::env::set_current_dir(original_dir).expect("Failed to restore directory");
@@ -353,7 +396,8 @@ url_check = "api.ipify.org"
             // Create the file
             fs::write(&invalid_path, "test content").expect("Failed to write test file");
 
-            let result = rollback_single_file(&std::env::current_dir().unwrap(), &invalid_path);
+            let result =
+                rollback_single_file(&std::env::current_dir().unwrap(), &invalid_path, false);
 
             assert!(result.is_err());
             // The UTF-8 conversion may succeed in some environments,
@@ -380,7 +424,7 @@ url_check = "api.ipify.org"
             let file_path = bucket_path.join("test_file.txt");
             fs::write(&file_path, "test content").expect("Failed to write test file");
 
-            let result = rollback_single_file(&std::env::current_dir().unwrap(), &file_path);
+            let result = rollback_single_file(&std::env::current_dir().unwrap(), &file_path, false);
 
             // Should fail with no previous commit, not UTF-8 error
             assert!(result.is_err());
@@ -477,7 +521,7 @@ url_check = "api.ipify.org"
         let original_dir = std::env::current_dir().expect("Failed to get current directory");
         std::env::set_current_dir(&bucket_path).expect("Failed to change directory");
 
-        let result = rollback_all(&std::env::current_dir().unwrap());
+        let result = rollback_all(&std::env::current_dir().unwrap(), false);
 
         // Restore original directory
         std::env::set_current_dir(original_dir).expect("Failed to restore directory");
@@ -501,7 +545,7 @@ url_check = "api.ipify.org"
         fs::write(&file_path, "test content").expect("Failed to write test file");
 
         // Call rollback_all with the current directory (bucket directory)
-        let result = rollback_all(&std::env::current_dir().unwrap());
+        let result = rollback_all(&std::env::current_dir().unwrap(), false);
 
         // Restore original directory
         std::env::set_current_dir(original_dir).expect("Failed to restore directory");
@@ -789,7 +833,7 @@ url_check = "api.ipify.org"
         };
 
         committed_file
-            .compress_and_store(&bucket_path)
+            .compress_and_store(&bucket_path, &CompressionPolicy::default())
             .expect("Failed to compress binary file");
 
         // Modify the binary file
@@ -840,7 +884,7 @@ url_check = "api.ipify.org"
         };
 
         committed_file
-            .compress_and_store(&bucket_path)
+            .compress_and_store(&bucket_path, &CompressionPolicy::default())
             .expect("Failed to compress empty file");
 
         // Add content to the empty file
@@ -891,7 +935,7 @@ url_check = "api.ipify.org"
         };
 
         committed_file
-            .compress_and_store(&bucket_path)
+            .compress_and_store(&bucket_path, &CompressionPolicy::default())
             .expect("Failed to compress subdir file");
 
         // Remove the subdirectory
@@ -4,7 +4,6 @@ use crate::errors::BucketError;
 
 /// Stash command placeholder
 pub struct Stash {
-    #[allow(dead_code)]
     args: StashCommand,
 }
 
@@ -16,6 +15,10 @@ impl BucketCommand for Stash {
     }
 
     fn execute(&self) -> Result<(), BucketError> {
+        if self.args.dry_run {
+            println!("Dry run: stash would change nothing. Nothing was changed.");
+            return Ok(());
+        }
         println!("stash command");
         Ok(())
     }
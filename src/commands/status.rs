@@ -108,23 +108,18 @@ impl Status {
 
     fn query_buckets(&self) -> Result<Vec<Bucket>, BucketError> {
         with_db_connection(|connection| {
-            let mut stmt = connection.prepare("SELECT id, name, path FROM buckets")?;
-            let bucket_iter = stmt
-                .query_map([], |row| {
-                    let uuid_str: String = row.get(0)?;
-                    let path_str: String = row.get(2)?;
-                    let uuid = uuid::Uuid::parse_str(&uuid_str)
-                        .map_err(|e| BucketError::InvalidData(e.to_string()))?;
-                    Ok(Bucket {
-                        id: uuid,
-                        name: row.get(1)?,
-                        relative_bucket_path: std::path::PathBuf::from(path_str),
-                    })
-                })
-                .map_err(BucketError::from)?;
+            let rows = connection.query("SELECT id, name, path FROM buckets", &[])?;
             let mut buckets = Vec::new();
-            for bucket in bucket_iter {
-                buckets.push(bucket.map_err(BucketError::from)?); // Ensure all errors are converted properly
+            for row in rows {
+                let uuid_str: String = row.get(0)?;
+                let path_str: String = row.get(2)?;
+                let uuid = uuid::Uuid::parse_str(&uuid_str)
+                    .map_err(|e| BucketError::InvalidData(e.to_string()))?;
+                buckets.push(Bucket {
+                    id: uuid,
+                    name: row.get(1)?,
+                    relative_bucket_path: std::path::PathBuf::from(path_str),
+                });
             }
 
             Ok(buckets)
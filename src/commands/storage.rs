@@ -0,0 +1,310 @@
+use crate::args::{PackCommand, StorageCommand, StorageSubcommand, TrainDictCommand};
+use crate::commands::BucketCommand;
+use crate::errors::BucketError;
+use crate::utils::compression::{open_object, verify_object};
+use crate::utils::config::{CompressionConfig, RepositoryConfig};
+use crate::utils::dictionary::{
+    file_extension, write_dictionary, DictionaryEntry, DictionaryIndex,
+};
+use crate::utils::pack::{find_packed, remove_incomplete_packs, write_pack};
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+use blake3::Hash;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Maintenance of the object storage of a repository
+pub struct Storage {
+    args: StorageCommand,
+}
+
+impl BucketCommand for Storage {
+    type Args = StorageCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        match &self.args.command {
+            StorageSubcommand::TrainDict(command) => train_dictionaries(&world, command),
+            StorageSubcommand::Pack(command) => pack_objects(&world, command),
+        }
+    }
+}
+
+/// A committed file whose object can serve as a training sample
+struct Sample {
+    hash: String,
+    object_path: PathBuf,
+}
+
+/// Trains a zstd dictionary per file type from the small files committed so far.
+/// New objects of that type are compressed with it, existing objects are left as they are.
+fn train_dictionaries(world: &World, args: &TrainDictCommand) -> Result<(), BucketError> {
+    let repo_dir = world.repo_root.parent().ok_or(BucketError::NotInRepo)?;
+    let max_sample_size = RepositoryConfig::from_file(world.work_dir.clone())
+        .map(|config| config.compression)
+        .unwrap_or_else(|_| CompressionConfig::default())
+        .dictionary_max_size;
+
+    let files = with_db_connection(|connection| {
+        connection
+            .query(
+                "SELECT DISTINCT f.file_path, f.hash, b.path
+                 FROM files f
+                 JOIN commits c ON c.id = f.commit_id
+                 JOIN buckets b ON b.id = c.bucket_id
+                 ORDER BY f.hash",
+                &[],
+            )?
+            .iter()
+            .map(|row| {
+                Ok((
+                    row.get::<String>(0)?,
+                    row.get::<String>(1)?,
+                    row.get::<String>(2)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, BucketError>>()
+    })?;
+
+    // Ordered by hash, so the samples of a type are an arbitrary but stable selection
+    let types: HashSet<String> = args.types.iter().map(|t| t.to_lowercase()).collect();
+    let mut samples_by_type: BTreeMap<String, Vec<Sample>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for (file_path, hash, bucket_path) in files {
+        let Some(extension) = file_extension(&file_path) else {
+            continue;
+        };
+        if (!types.is_empty() && !types.contains(&extension)) || !seen.insert(hash.clone()) {
+            continue;
+        }
+        samples_by_type.entry(extension).or_default().push(Sample {
+            object_path: repo_dir
+                .join(&bucket_path)
+                .join(".b")
+                .join("storage")
+                .join(&hash),
+            hash,
+        });
+    }
+
+    let buckets_dir = &world.repo_root;
+    let mut index = DictionaryIndex::load(buckets_dir)?;
+    let mut trained = 0;
+    for (extension, candidates) in samples_by_type {
+        let samples = read_samples(&candidates, max_sample_size, args.max_samples);
+        if samples.len() < args.min_samples {
+            if !types.is_empty() {
+                println!(
+                    "skipped:    .{} has {} files up to {} bytes, {} are needed",
+                    extension,
+                    samples.len(),
+                    max_sample_size,
+                    args.min_samples
+                );
+            }
+            continue;
+        }
+
+        let dictionary = match zstd::dict::from_samples(&samples, args.size) {
+            Ok(dictionary) => dictionary,
+            Err(e) => {
+                println!("skipped:    .{} ({})", extension, e);
+                continue;
+            }
+        };
+        let id = write_dictionary(buckets_dir, &dictionary)?;
+        index.insert(DictionaryEntry {
+            extension: extension.clone(),
+            id,
+            samples: samples.len(),
+            size: dictionary.len(),
+        });
+        trained += 1;
+        println!(
+            "Trained dictionary {:08x} for .{} from {} files ({} bytes)",
+            id,
+            extension,
+            samples.len(),
+            dictionary.len()
+        );
+    }
+
+    if trained == 0 {
+        println!("No file type has enough small files to train a dictionary.");
+        return Ok(());
+    }
+    index.save(buckets_dir)?;
+    Ok(())
+}
+
+/// Moves the small loose objects of every bucket into a new pack per bucket. Objects are
+/// verified before they are packed, and loose files are only removed once the pack and
+/// its index are written. New commits keep writing loose objects until the next pack.
+fn pack_objects(world: &World, args: &PackCommand) -> Result<(), BucketError> {
+    let repo_dir = world.repo_root.parent().ok_or(BucketError::NotInRepo)?;
+    let buckets = with_db_connection(|connection| {
+        connection
+            .query("SELECT name, path FROM buckets ORDER BY name", &[])?
+            .iter()
+            .map(|row| Ok((row.get::<String>(0)?, row.get::<String>(1)?)))
+            .collect::<Result<Vec<_>, BucketError>>()
+    })?;
+
+    let mut packed = 0;
+    for (bucket_name, bucket_path) in buckets {
+        let storage_path = repo_dir.join(&bucket_path).join(".b").join("storage");
+        if !storage_path.is_dir() {
+            continue;
+        }
+        remove_incomplete_packs(&storage_path)?;
+
+        let objects = loose_objects(&storage_path, args.max_size)?;
+        if objects.is_empty() {
+            continue;
+        }
+        let pack_path = write_pack(&storage_path, &objects)?;
+        for (_, object_path) in &objects {
+            fs::remove_file(object_path)?;
+        }
+
+        let pack_size = fs::metadata(&pack_path)?.len();
+        println!(
+            "Packed {} objects of {} into {} ({} bytes)",
+            objects.len(),
+            bucket_name,
+            pack_path.file_name().unwrap_or_default().to_string_lossy(),
+            pack_size
+        );
+        packed += objects.len();
+    }
+
+    if packed == 0 {
+        println!("No loose objects to pack.");
+    }
+    Ok(())
+}
+
+/// The loose objects of a storage directory up to `max_size` bytes that can be packed.
+/// Objects that fail verification stay loose for `bucket fsck` to report, and loose
+/// copies of objects that are packed already are removed.
+fn loose_objects(storage_path: &Path, max_size: u64) -> Result<Vec<(Hash, PathBuf)>, BucketError> {
+    let mut objects = Vec::new();
+    for entry in fs::read_dir(storage_path)?.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Objects still being written and the packs directory are not named by a hash
+        let Ok(hash) = Hash::from_hex(&name) else {
+            continue;
+        };
+        if !path.is_file() {
+            continue;
+        }
+        if find_packed(storage_path, &name)?.is_some() {
+            fs::remove_file(&path)?;
+            continue;
+        }
+        if entry.metadata()?.len() > max_size {
+            continue;
+        }
+        match verify_object(&path, &hash) {
+            Ok(_) => objects.push((hash, path)),
+            Err(e) => println!("skipped:    {} ({})", name, e),
+        }
+    }
+    Ok(objects)
+}
+
+/// Reads the content of up to `max_samples` objects of at most `max_size` bytes.
+/// Objects that are missing or damaged are left out.
+fn read_samples(candidates: &[Sample], max_size: u64, max_samples: usize) -> Vec<Vec<u8>> {
+    candidates
+        .iter()
+        .filter_map(
+            |sample| match read_small_object(&sample.object_path, max_size) {
+                Ok(content) => content,
+                Err(e) => {
+                    log::debug!("Skipping sample {}: {}", sample.hash, e);
+                    None
+                }
+            },
+        )
+        .take(max_samples)
+        .collect()
+}
+
+/// The content of the object at `path`, or `None` when it is larger than `max_size`.
+fn read_small_object(path: &Path, max_size: u64) -> std::io::Result<Option<Vec<u8>>> {
+    let mut content = Vec::new();
+    open_object(path)?
+        .take(max_size + 1)
+        .read_to_end(&mut content)?;
+    if content.len() as u64 > max_size || content.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::compress_and_store_file;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_loose_objects() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let mut hashes = Vec::new();
+        for content in ["small", "large large large", "packed", "corrupt"] {
+            let source = dir.path().join("source");
+            fs::write(&source, content).expect("Failed to write file");
+            let hash = blake3::hash(content.as_bytes());
+            compress_and_store_file(&source, &dir.path().join(hash.to_hex().as_str()), 0)
+                .expect("Failed to store object");
+            hashes.push(hash);
+        }
+        fs::remove_file(dir.path().join("source")).expect("Failed to remove file");
+        let path = |hash: &Hash| dir.path().join(hash.to_hex().as_str());
+        write_pack(dir.path(), &[(hashes[2], path(&hashes[2]))]).expect("Failed to pack");
+        fs::write(path(&hashes[3]), b"garbage").expect("Failed to corrupt object");
+
+        let max_size = fs::metadata(path(&hashes[0]))
+            .expect("Failed to read metadata")
+            .len();
+        let objects = loose_objects(dir.path(), max_size).expect("Failed to list objects");
+        assert_eq!(objects, vec![(hashes[0], path(&hashes[0]))]);
+        // The loose copy of a packed object is removed
+        assert!(!path(&hashes[2]).exists());
+        assert!(path(&hashes[1]).exists());
+    }
+
+    #[test]
+    fn test_read_samples_skips_large_and_missing_objects() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let mut candidates = Vec::new();
+        for (name, content) in [("small", "a".repeat(10)), ("large", "b".repeat(100))] {
+            let source = dir.path().join(name);
+            fs::write(&source, &content).expect("Failed to write file");
+            let object_path = dir.path().join(format!("{}.object", name));
+            compress_and_store_file(&source, &object_path, 0).expect("Failed to store object");
+            candidates.push(Sample {
+                hash: name.to_string(),
+                object_path,
+            });
+        }
+        candidates.push(Sample {
+            hash: "missing".to_string(),
+            object_path: dir.path().join("missing.object"),
+        });
+
+        let samples = read_samples(&candidates, 50, 10);
+        assert_eq!(samples, vec!["a".repeat(10).into_bytes()]);
+        assert!(read_samples(&candidates, 500, 1).len() == 1);
+    }
+}
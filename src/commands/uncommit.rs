@@ -0,0 +1,62 @@
+use crate::args::UncommitCommand;
+use crate::commands::commit::load_head_commits;
+use crate::commands::BucketCommand;
+use crate::data::operation::{Operation, OperationKind};
+use crate::errors::BucketError;
+use crate::utils::identity::Identity;
+use crate::utils::utils::with_db_connection;
+use crate::world::World;
+
+/// Move the head of a bucket back to its parent commit, keeping the working files
+pub struct Uncommit {
+    args: UncommitCommand,
+}
+
+impl BucketCommand for Uncommit {
+    type Args = UncommitCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket = world.bucket.as_ref().ok_or(BucketError::NotInBucket)?;
+        let author = Identity::resolve(&world.work_dir);
+
+        let heads = with_db_connection(|connection| load_head_commits(connection, bucket.id, 2))?;
+        let (head, parent) = match heads.as_slice() {
+            [] => {
+                println!("Bucket {} has no commits.", bucket.name);
+                return Ok(());
+            }
+            [head] => (head, None),
+            [head, parent, ..] => (head, Some(parent)),
+        };
+
+        // The commit is kept in the database, so the operation can be traced in the log
+        with_db_connection(|connection| {
+            connection.transaction(|transaction| {
+                transaction.execute(
+                    "UPDATE commits SET abandoned = TRUE WHERE id = ?1",
+                    &[(&head.id).into()],
+                )?;
+                Operation::new(
+                    bucket.id,
+                    OperationKind::Uncommit,
+                    Some(head.id.clone()),
+                    parent.map(|parent| parent.id.clone()),
+                    format!("uncommit {}: {}", head.id, head.message),
+                )
+                .record(transaction, &author)
+            })
+        })?;
+
+        println!("Uncommitted {} \"{}\"", head.id, head.message);
+        match parent {
+            Some(parent) => println!("Head is now {} \"{}\"", parent.id, parent.message),
+            None => println!("Bucket {} has no commits left.", bucket.name),
+        }
+        Ok(())
+    }
+}
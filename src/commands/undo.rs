@@ -0,0 +1,85 @@
+use crate::args::UndoCommand;
+use crate::commands::BucketCommand;
+use crate::data::operation::{find_last_undoable, Operation, OperationKind};
+use crate::errors::BucketError;
+use crate::utils::compression::restore_file;
+use crate::utils::identity::Identity;
+use crate::utils::utils::{find_bucket_path, with_db_connection};
+use crate::world::World;
+use blake3::Hash;
+use std::fs;
+
+/// Reverse the last rollback, revert, stash or finalize of a bucket by restoring
+/// the working files it overwrote or deleted
+pub struct Undo {
+    args: UndoCommand,
+}
+
+impl BucketCommand for Undo {
+    type Args = UndoCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket = world.bucket.as_ref().ok_or(BucketError::NotInBucket)?;
+        let bucket_path = find_bucket_path(&world.work_dir).ok_or(BucketError::NotInBucket)?;
+
+        let target =
+            match with_db_connection(|connection| find_last_undoable(connection, bucket.id))? {
+                Some(operation) => operation,
+                None => {
+                    println!("Nothing to undo.");
+                    return Ok(());
+                }
+            };
+
+        // The undo snapshots the files it overwrites itself, so it is logged like any
+        // other operation
+        let mut undo = Operation::new(
+            bucket.id,
+            OperationKind::Undo,
+            None,
+            None,
+            format!("undo {}", target.description),
+        );
+        undo.undoes = Some(target.id);
+        for snapshot in &target.snapshots {
+            undo.snapshot(&bucket_path, &snapshot.file_path)?;
+        }
+
+        println!("Undoing {} ({})", target.description, target.id);
+        for snapshot in &target.snapshots {
+            let working_file = bucket_path.join(&snapshot.file_path);
+            match &snapshot.hash {
+                Some(hash) => {
+                    let expected_hash = Hash::from_hex(hash).map_err(|e| {
+                        BucketError::InvalidData(format!("Invalid hash {}: {}", hash, e))
+                    })?;
+                    if let Some(parent) = working_file.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let object_path = bucket_path.join(".b").join("storage").join(hash);
+                    restore_file(&object_path, &working_file, &expected_hash)?;
+                    println!("restored:    {}", snapshot.file_path);
+                }
+                None => {
+                    // The file did not exist before the operation
+                    if working_file.exists() {
+                        fs::remove_file(&working_file)?;
+                    }
+                    println!("removed:    {}", snapshot.file_path);
+                }
+            }
+        }
+
+        // Only recorded once every file is restored, so a failed undo can be run again
+        let author = Identity::resolve(&world.work_dir);
+        with_db_connection(|connection| {
+            connection.transaction(|transaction| undo.record(transaction, &author))
+        })?;
+        Ok(())
+    }
+}
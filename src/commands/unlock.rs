@@ -0,0 +1,54 @@
+use crate::args::UnlockCommand;
+use crate::commands::BucketCommand;
+use crate::data::file_lock::unlock_file;
+use crate::errors::BucketError;
+use crate::utils::config::RepositoryConfig;
+use crate::utils::identity::Identity;
+use crate::utils::utils::{bucket_relative_path, find_bucket_path, with_db_connection};
+use crate::world::World;
+
+/// Release the lock on a file
+pub struct Unlock {
+    args: UnlockCommand,
+}
+
+impl BucketCommand for Unlock {
+    type Args = UnlockCommand;
+
+    fn new(args: &Self::Args) -> Self {
+        Self { args: args.clone() }
+    }
+
+    fn execute(&self) -> Result<(), BucketError> {
+        let world = World::new(&self.args.shared)?;
+        let bucket = world.bucket.as_ref().ok_or(BucketError::NotInBucket)?;
+        let bucket_path = find_bucket_path(&world.work_dir).ok_or(BucketError::NotInBucket)?;
+
+        // The file itself may have been deleted, so it does not need to exist
+        let file_path = bucket_relative_path(&bucket_path, &world.work_dir, &self.args.file)?;
+
+        let owner = Identity::resolve(&world.work_dir).name;
+        if self.args.force {
+            let admins = RepositoryConfig::from_file(world.work_dir.clone())?
+                .locks
+                .admins;
+            if !admins.contains(&owner) {
+                return Err(BucketError::FileLocked(format!(
+                    "{}, only the admins in the [locks] table of the repository config can \
+                     use --force and {} is not one of them",
+                    file_path, owner
+                )));
+            }
+        }
+        let lock = with_db_connection(|connection| {
+            unlock_file(connection, bucket.id, &file_path, &owner, self.args.force)
+        })?;
+
+        if lock.owner == owner {
+            println!("Unlocked {}", lock.file_path);
+        } else {
+            println!("Unlocked {} (was locked by {})", lock.file_path, lock.owner);
+        }
+        Ok(())
+    }
+}
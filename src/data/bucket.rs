@@ -1,9 +1,7 @@
 use crate::data::commit::{Commit, CommitStatus, CommittedFile};
 use crate::errors::BucketError;
 use crate::utils::checks::{find_directory_in_parents, is_valid_bucket_info};
-use crate::utils::utils::{
-    connect_to_db, find_bucket_repo, find_files_excluding_top_level_b, hash_file,
-};
+use crate::utils::utils::{connect_to_db, find_bucket_files, find_bucket_repo, hash_file};
 use blake3::Hash;
 use log::debug;
 use serde::{Deserialize, Serialize};
@@ -103,11 +101,13 @@ impl BucketTrait for Bucket {
     fn list_files_with_metadata_in_bucket(&self) -> io::Result<Commit> {
         let mut files = Vec::new();
 
-        for entry in find_files_excluding_top_level_b(
+        for entry in find_bucket_files(
             self.get_full_bucket_path()
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
                 .as_path(),
-        ) {
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        {
             let path = entry.as_path();
 
             if path.is_file() {
@@ -147,17 +147,21 @@ impl BucketTrait for Bucket {
     fn load_last_commit(&self) -> Result<Option<Commit>, BucketError> {
         let connection = connect_to_db()?;
 
-        let mut stmt = connection.prepare(
+        let rows = connection.query(
             "SELECT f.id, f.file_path, f.hash
-                                               FROM files f
-                                               JOIN commits c ON f.commit_id = c.id
-                                WHERE c.created_at = (SELECT MAX(created_at) FROM commits)",
+             FROM files f
+             WHERE f.commit_id = (
+                 SELECT id
+                 FROM commits
+                 WHERE bucket_id = ?1 AND NOT abandoned
+                 ORDER BY created_at DESC
+                 LIMIT 1
+             )",
+            &[self.id.into()],
         )?;
 
-        let mut rows = stmt.query([])?;
-
         let mut files = Vec::new();
-        while let Some(row) = rows.next()? {
+        for row in rows {
             let uuid_string: String = row.get(0)?;
             let hex_string: String = row.get(2)?;
 
@@ -175,12 +179,7 @@ impl BucketTrait for Bucket {
             });
         }
 
-        if let Err((_conn, e)) = connection.close() {
-            return Err(BucketError::from(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to close database connection: {}", e),
-            )));
-        }
+        connection.close()?;
 
         Ok(Some(Commit {
             bucket: self.name.clone(),
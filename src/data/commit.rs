@@ -3,11 +3,12 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::cmp::PartialEq;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use uuid::Uuid;
 
-use crate::utils::compression::{compress_and_store_file, restore_file};
+use crate::utils::compression::{restore_file, CompressionPolicy};
+use crate::utils::object_store::publish_object;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum CommitStatus {
@@ -183,14 +184,25 @@ impl CommittedFile {
         }
     }
 
-    pub fn compress_and_store(&self, bucket_path: &PathBuf) -> io::Result<()> {
+    pub fn compress_and_store(
+        &self,
+        bucket_path: &Path,
+        policy: &CompressionPolicy,
+    ) -> io::Result<()> {
         let input_path = bucket_path.join(&self.name);
         let output_path = bucket_path
             .join(".b")
             .join("storage")
             .join(&self.hash.to_string());
 
-        compress_and_store_file(&input_path, &output_path, 0)
+        // Modified files can be stored as a delta against their previous version
+        let previous = match self.status {
+            CommitStatus::Modified => Some(&self.previous_hash),
+            _ => None,
+        };
+        policy.store(&input_path, &self.name, &output_path, previous)?;
+        // With a remote store the object is only staged in the bucket storage
+        publish_object(&output_path)
     }
 
     pub fn restore(&self, bucket_path: &PathBuf) -> io::Result<()> {
@@ -205,7 +217,8 @@ impl CommittedFile {
             std::fs::create_dir_all(parent)?;
         }
 
-        restore_file(&input_path, &output_path)
+        // Objects that are not in the bucket storage are read from the remote store
+        restore_file(&input_path, &output_path, &self.previous_hash)
     }
 }
 
@@ -411,7 +424,7 @@ mod tests {
             status: CommitStatus::New,
         };
 
-        let result = file.compress_and_store(&bucket_path);
+        let result = file.compress_and_store(&bucket_path, &CompressionPolicy::default());
         assert!(result.is_ok());
 
         // Check that compressed file exists
@@ -439,7 +452,7 @@ mod tests {
             status: CommitStatus::New,
         };
 
-        let result = file.compress_and_store(&bucket_path);
+        let result = file.compress_and_store(&bucket_path, &CompressionPolicy::default());
         assert!(result.is_err());
         Ok(())
     }
@@ -457,8 +470,7 @@ mod tests {
         let file_content = "test restore content";
         fs::write(bucket_path.join("original.txt"), file_content)?;
 
-        let hash_string = "test_hash_restore";
-        let hash = Hash::from_str(hash_string).unwrap_or_else(|_| Hash::from([0u8; 32]));
+        let hash = blake3::hash(file_content.as_bytes());
         let compressed_path = bucket_path
             .join(".b")
             .join("storage")
@@ -475,7 +487,7 @@ mod tests {
             id: Uuid::new_v4(),
             name: "restored.txt".to_string(),
             hash: Hash::from([1u8; 32]),
-            previous_hash: Hash::from_str(hash_string).unwrap_or_else(|_| Hash::from([0u8; 32])),
+            previous_hash: hash,
             status: CommitStatus::Modified,
         };
 
@@ -510,6 +522,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_committed_file_restore_corrupt_object_keeps_file() -> std::io::Result<()> {
+        let temp_dir = tempdir()?;
+        let bucket_path = temp_dir.path().to_path_buf();
+        fs::create_dir_all(bucket_path.join(".b").join("storage"))?;
+
+        // Store an object whose content does not match its name
+        fs::write(bucket_path.join("other.txt"), "other content")?;
+        let hash = blake3::hash(b"committed content");
+        let storage_path = bucket_path
+            .join(".b")
+            .join("storage")
+            .join(hash.to_string());
+        crate::utils::compression::compress_and_store_file(
+            &bucket_path.join("other.txt"),
+            &storage_path,
+            0,
+        )?;
+
+        fs::write(bucket_path.join("work.txt"), "work in progress")?;
+        let file = CommittedFile {
+            id: Uuid::new_v4(),
+            name: "work.txt".to_string(),
+            hash: blake3::hash(b"work in progress"),
+            previous_hash: hash,
+            status: CommitStatus::Modified,
+        };
+
+        assert!(file.restore(&bucket_path).is_err());
+        assert_eq!(
+            fs::read_to_string(bucket_path.join("work.txt"))?,
+            "work in progress"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_hash_serialization() {
         let hash = Hash::from([42u8; 32]);
@@ -0,0 +1,266 @@
+use crate::errors::BucketError;
+use crate::metadata::{MetadataStore, Row};
+use uuid::Uuid;
+
+/// An exclusive lock on a file in a bucket.
+///
+/// Files that cannot be merged, such as `.blend` or `.psd` files, are locked before
+/// editing so that only the lock owner can commit changes to them.
+#[derive(Debug, PartialEq)]
+pub struct FileLock {
+    pub bucket_id: Uuid,
+    pub file_path: String,
+    pub owner: String,
+    pub created_at: String,
+}
+
+/// Takes the lock on a file for `owner`.
+///
+/// Locking a file that `owner` already holds is not an error and keeps the
+/// original lock. Fails with `BucketError::FileLocked` when someone else holds it.
+pub fn lock_file(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+    file_path: &str,
+    owner: &str,
+) -> Result<FileLock, BucketError> {
+    if let Some(existing) = find_lock(connection, bucket_id, file_path)? {
+        if existing.owner != owner {
+            return Err(locked_by_other(&existing));
+        }
+        return Ok(existing);
+    }
+
+    connection.execute(
+        "INSERT INTO file_locks (bucket_id, file_path, owner) VALUES (?1, ?2, ?3)",
+        &[bucket_id.into(), file_path.into(), owner.into()],
+    )?;
+
+    find_lock(connection, bucket_id, file_path)?
+        .ok_or_else(|| BucketError::NotFound(format!("lock on {}", file_path)))
+}
+
+/// Releases the lock on a file.
+///
+/// Only the owner can release a lock unless `force` is set. Returns the released lock.
+pub fn unlock_file(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+    file_path: &str,
+    owner: &str,
+    force: bool,
+) -> Result<FileLock, BucketError> {
+    let existing = find_lock(connection, bucket_id, file_path)?
+        .ok_or_else(|| BucketError::NotFound(format!("lock on {}", file_path)))?;
+
+    if existing.owner != owner && !force {
+        return Err(BucketError::FileLocked(format!(
+            "{} is locked by {}, use --force to release another user's lock",
+            existing.file_path, existing.owner
+        )));
+    }
+
+    connection.execute(
+        "DELETE FROM file_locks WHERE bucket_id = ?1 AND file_path = ?2",
+        &[bucket_id.into(), file_path.into()],
+    )?;
+    Ok(existing)
+}
+
+pub fn find_lock(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+    file_path: &str,
+) -> Result<Option<FileLock>, BucketError> {
+    connection
+        .query_row(
+            "SELECT bucket_id, file_path, owner, CAST(created_at AS TEXT)
+             FROM file_locks
+             WHERE bucket_id = ?1 AND file_path = ?2",
+            &[bucket_id.into(), file_path.into()],
+        )?
+        .map(|row| row_to_lock(&row))
+        .transpose()
+}
+
+/// Returns every lock of a bucket, or of all buckets when `bucket_id` is `None`.
+pub fn list_locks(
+    connection: &dyn MetadataStore,
+    bucket_id: Option<Uuid>,
+) -> Result<Vec<FileLock>, BucketError> {
+    let rows = match bucket_id {
+        Some(bucket_id) => connection.query(
+            "SELECT bucket_id, file_path, owner, CAST(created_at AS TEXT)
+             FROM file_locks
+             WHERE bucket_id = ?1
+             ORDER BY file_path",
+            &[bucket_id.into()],
+        )?,
+        None => connection.query(
+            "SELECT bucket_id, file_path, owner, CAST(created_at AS TEXT)
+             FROM file_locks
+             ORDER BY bucket_id, file_path",
+            &[],
+        )?,
+    };
+    rows.iter().map(row_to_lock).collect()
+}
+
+/// Fails with `BucketError::FileLocked` if any of `file_paths` is locked by
+/// someone other than `owner`.
+pub fn check_not_locked_by_others(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+    file_paths: &[&str],
+    owner: &str,
+) -> Result<(), BucketError> {
+    let blocking = list_locks(connection, Some(bucket_id))?
+        .into_iter()
+        .filter(|lock| lock.owner != owner && file_paths.contains(&lock.file_path.as_str()))
+        .collect::<Vec<_>>();
+
+    match blocking.as_slice() {
+        [] => Ok(()),
+        [lock] => Err(locked_by_other(lock)),
+        locks => Err(BucketError::FileLocked(
+            locks
+                .iter()
+                .map(|lock| format!("{} (locked by {})", lock.file_path, lock.owner))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )),
+    }
+}
+
+fn locked_by_other(lock: &FileLock) -> BucketError {
+    BucketError::FileLocked(format!(
+        "{} (locked by {} since {})",
+        lock.file_path, lock.owner, lock.created_at
+    ))
+}
+
+fn row_to_lock(row: &Row) -> Result<FileLock, BucketError> {
+    Ok(FileLock {
+        bucket_id: row.get(0)?,
+        file_path: row.get(1)?,
+        owner: row.get(2)?,
+        created_at: row.get(3)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{initialize_database, open_metadata_store, DatabaseType};
+    use tempfile::tempdir;
+
+    fn setup() -> (tempfile::TempDir, Box<dyn MetadataStore>, Uuid) {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        initialize_database(temp_dir.path(), DatabaseType::DuckDB)
+            .expect("Failed to initialize database");
+        let connection = open_metadata_store(temp_dir.path()).expect("Failed to open database");
+
+        let bucket_id = Uuid::new_v4();
+        connection
+            .execute(
+                "INSERT INTO buckets (id, name, path) VALUES (?1, 'art', 'art')",
+                &[bucket_id.into()],
+            )
+            .expect("Failed to insert bucket");
+        (temp_dir, connection, bucket_id)
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let (_temp_dir, connection, bucket_id) = setup();
+
+        let lock =
+            lock_file(connection.as_ref(), bucket_id, "scene.blend", "alice").expect("lock failed");
+        assert_eq!(lock.owner, "alice");
+        assert_eq!(lock.file_path, "scene.blend");
+
+        // Locking again as the owner keeps the lock
+        lock_file(connection.as_ref(), bucket_id, "scene.blend", "alice").expect("relock failed");
+        assert_eq!(
+            list_locks(connection.as_ref(), Some(bucket_id))
+                .expect("list failed")
+                .len(),
+            1
+        );
+
+        unlock_file(
+            connection.as_ref(),
+            bucket_id,
+            "scene.blend",
+            "alice",
+            false,
+        )
+        .expect("unlock failed");
+        assert!(find_lock(connection.as_ref(), bucket_id, "scene.blend")
+            .expect("find failed")
+            .is_none());
+    }
+
+    #[test]
+    fn test_lock_held_by_other_user() {
+        let (_temp_dir, connection, bucket_id) = setup();
+        lock_file(connection.as_ref(), bucket_id, "poster.psd", "alice").expect("lock failed");
+
+        assert!(matches!(
+            lock_file(connection.as_ref(), bucket_id, "poster.psd", "bob"),
+            Err(BucketError::FileLocked(_))
+        ));
+        assert!(matches!(
+            unlock_file(connection.as_ref(), bucket_id, "poster.psd", "bob", false),
+            Err(BucketError::FileLocked(_))
+        ));
+
+        let released = unlock_file(connection.as_ref(), bucket_id, "poster.psd", "bob", true)
+            .expect("force failed");
+        assert_eq!(released.owner, "alice");
+    }
+
+    #[test]
+    fn test_unlock_without_lock() {
+        let (_temp_dir, connection, bucket_id) = setup();
+        assert!(matches!(
+            unlock_file(
+                connection.as_ref(),
+                bucket_id,
+                "scene.blend",
+                "alice",
+                false
+            ),
+            Err(BucketError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_not_locked_by_others() {
+        let (_temp_dir, connection, bucket_id) = setup();
+        lock_file(connection.as_ref(), bucket_id, "a.blend", "alice").expect("lock failed");
+        lock_file(connection.as_ref(), bucket_id, "b.blend", "bob").expect("lock failed");
+
+        check_not_locked_by_others(
+            connection.as_ref(),
+            bucket_id,
+            &["a.blend", "c.txt"],
+            "alice",
+        )
+        .expect("own lock should not block");
+
+        match check_not_locked_by_others(
+            connection.as_ref(),
+            bucket_id,
+            &["a.blend", "b.blend"],
+            "alice",
+        ) {
+            Err(BucketError::FileLocked(msg)) => {
+                assert!(msg.contains("b.blend"));
+                assert!(msg.contains("bob"));
+                assert!(!msg.contains("a.blend"));
+            }
+            _ => panic!("Expected FileLocked error"),
+        }
+    }
+}
@@ -1,2 +1,4 @@
 pub mod bucket;
 pub mod commit;
+pub mod file_lock;
+pub mod operation;
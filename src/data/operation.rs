@@ -0,0 +1,446 @@
+use crate::errors::BucketError;
+use crate::metadata::{MetadataStore, Row};
+use crate::utils::compression::{object_exists, CompressionPolicy};
+use crate::utils::identity::Identity;
+use crate::utils::utils::{hash_file, with_db_connection};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Kinds of operations recorded in the operation log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperationKind {
+    /// The head commit was replaced by a new commit with the same parent
+    Amend,
+    /// The head of the bucket was moved back to the parent commit
+    Uncommit,
+    /// Working files were replaced by the versions of an earlier commit
+    Rollback,
+    /// Changes to working files were discarded
+    Revert,
+    /// Working files were stashed away
+    Stash,
+    /// Finalized files were promoted into other buckets
+    Finalize,
+    /// An earlier operation was reversed
+    Undo,
+}
+
+impl OperationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationKind::Amend => "amend",
+            OperationKind::Uncommit => "uncommit",
+            OperationKind::Rollback => "rollback",
+            OperationKind::Revert => "revert",
+            OperationKind::Stash => "stash",
+            OperationKind::Finalize => "finalize",
+            OperationKind::Undo => "undo",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "amend" => Some(OperationKind::Amend),
+            "uncommit" => Some(OperationKind::Uncommit),
+            "rollback" => Some(OperationKind::Rollback),
+            "revert" => Some(OperationKind::Revert),
+            "stash" => Some(OperationKind::Stash),
+            "finalize" => Some(OperationKind::Finalize),
+            "undo" => Some(OperationKind::Undo),
+            _ => None,
+        }
+    }
+}
+
+impl Display for OperationKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The content a working file had before an operation overwrote or deleted it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSnapshot {
+    /// Path relative to the bucket
+    pub file_path: String,
+    /// Object in the bucket storage holding the previous content, `None` if the
+    /// file did not exist before the operation
+    pub hash: Option<String>,
+}
+
+/// An entry of the operation log.
+///
+/// Operations that change the history of a bucket record the head commit before
+/// and after the operation. Operations that overwrite or delete working files
+/// snapshot their previous content, so the operation can be undone.
+#[derive(Debug)]
+pub struct Operation {
+    pub id: Uuid,
+    pub bucket_id: Uuid,
+    pub kind: OperationKind,
+    /// Head commit before the operation
+    pub old_head: Option<String>,
+    /// Head commit after the operation
+    pub new_head: Option<String>,
+    pub description: String,
+    /// The operation reversed by an undo
+    pub undoes: Option<Uuid>,
+    pub snapshots: Vec<FileSnapshot>,
+}
+
+/// An operation as listed by `bucket oplog`
+#[derive(Debug)]
+pub struct LoggedOperation {
+    pub bucket_id: Uuid,
+    pub kind: String,
+    pub description: String,
+    pub author_name: Option<String>,
+    pub created_at: String,
+    /// Whether a later undo reversed this operation
+    pub undone: bool,
+}
+
+impl Operation {
+    pub fn new(
+        bucket_id: Uuid,
+        kind: OperationKind,
+        old_head: Option<String>,
+        new_head: Option<String>,
+        description: String,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            bucket_id,
+            kind,
+            old_head,
+            new_head,
+            description,
+            undoes: None,
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Stores the current content of `file_path` in the bucket storage before the
+    /// operation overwrites or deletes it.
+    ///
+    /// A file that does not exist yet is recorded as such, so undo removes it again.
+    /// Taking a snapshot of the same file twice keeps the first one.
+    pub fn snapshot(&mut self, bucket_path: &Path, file_path: &str) -> Result<(), BucketError> {
+        if self
+            .snapshots
+            .iter()
+            .any(|snapshot| snapshot.file_path == file_path)
+        {
+            return Ok(());
+        }
+
+        let working_file = bucket_path.join(file_path);
+        let hash = if working_file.is_file() {
+            let hash = hash_file(&working_file)?.to_string();
+            let object_path = bucket_path.join(".b").join("storage").join(&hash);
+            if !object_exists(&object_path)? {
+                CompressionPolicy::load(bucket_path)?.store(
+                    &working_file,
+                    file_path,
+                    &object_path,
+                    None,
+                )?;
+            }
+            Some(hash)
+        } else {
+            None
+        };
+
+        self.snapshots.push(FileSnapshot {
+            file_path: file_path.to_string(),
+            hash,
+        });
+        Ok(())
+    }
+
+    /// Adds the operation and its snapshots to the log, as part of the transaction
+    /// of `connection` if any.
+    pub fn record(
+        &self,
+        connection: &dyn MetadataStore,
+        author: &Identity,
+    ) -> Result<(), BucketError> {
+        connection.execute(
+            "INSERT INTO operations (id, bucket_id, kind, old_head, new_head, description, author_name, undoes)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            &[
+                self.id.into(),
+                self.bucket_id.into(),
+                self.kind.as_str().into(),
+                self.old_head.as_deref().into(),
+                self.new_head.as_deref().into(),
+                (&self.description).into(),
+                (&author.name).into(),
+                self.undoes.into(),
+            ],
+        )?;
+        for snapshot in &self.snapshots {
+            connection.execute(
+                "INSERT INTO operation_files (operation_id, file_path, hash) VALUES (?1, ?2, ?3)",
+                &[
+                    self.id.into(),
+                    (&snapshot.file_path).into(),
+                    snapshot.hash.as_deref().into(),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Snapshots `file_paths` and records the operation in the log.
+///
+/// Called before an operation overwrites or deletes working files, so the log
+/// always holds the previous content of every file the operation touches.
+pub fn record_before_overwrite(
+    bucket_id: Uuid,
+    bucket_path: &Path,
+    kind: OperationKind,
+    description: String,
+    file_paths: &[&str],
+) -> Result<Operation, BucketError> {
+    let mut operation = Operation::new(bucket_id, kind, None, None, description);
+    for file_path in file_paths {
+        operation.snapshot(bucket_path, file_path)?;
+    }
+    let author = Identity::resolve(bucket_path);
+    with_db_connection(|connection| {
+        connection.transaction(|transaction| operation.record(transaction, &author))
+    })?;
+    Ok(operation)
+}
+
+/// Returns the latest operation of a bucket that can be undone and was not undone
+/// yet, with its snapshots.
+pub fn find_last_undoable(
+    connection: &dyn MetadataStore,
+    bucket_id: Uuid,
+) -> Result<Option<Operation>, BucketError> {
+    let row = connection.query_row(
+        "SELECT o.id, o.kind, o.description
+             FROM operations o
+             WHERE o.bucket_id = ?1
+               AND o.kind IN ('rollback', 'revert', 'stash', 'finalize')
+               AND NOT EXISTS (SELECT 1 FROM operations u WHERE u.undoes = o.id)
+             ORDER BY o.created_at DESC
+             LIMIT 1",
+        &[bucket_id.into()],
+    )?;
+
+    let (id, kind, description) = match row {
+        Some(row) => (
+            row.get::<String>(0)?,
+            row.get::<String>(1)?,
+            row.get::<String>(2)?,
+        ),
+        None => return Ok(None),
+    };
+    let kind = OperationKind::parse(&kind)
+        .ok_or_else(|| BucketError::InvalidData(format!("unknown operation kind {}", kind)))?;
+
+    let snapshots = connection
+        .query(
+            "SELECT file_path, hash FROM operation_files WHERE operation_id = ?1 ORDER BY file_path",
+            &[(&id).into()],
+        )?
+        .iter()
+        .map(|row| {
+            Ok(FileSnapshot {
+                file_path: row.get(0)?,
+                hash: row.get(1)?,
+            })
+        })
+        .collect::<Result<Vec<_>, BucketError>>()?;
+
+    let mut operation = Operation::new(bucket_id, kind, None, None, description);
+    operation.id = parse_uuid(&id)?;
+    operation.snapshots = snapshots;
+    Ok(Some(operation))
+}
+
+/// Returns the latest `limit` operations, newest first, of a bucket or of all
+/// buckets when `bucket_id` is `None`.
+pub fn list_operations(
+    connection: &dyn MetadataStore,
+    bucket_id: Option<Uuid>,
+    limit: usize,
+) -> Result<Vec<LoggedOperation>, BucketError> {
+    let filter = match bucket_id {
+        Some(_) => "WHERE o.bucket_id = ?1",
+        None => "",
+    };
+    let sql = format!(
+        "SELECT o.bucket_id, o.kind, o.description, o.author_name,
+                CAST(o.created_at AS TEXT),
+                EXISTS (SELECT 1 FROM operations u WHERE u.undoes = o.id)
+         FROM operations o
+         {}
+         ORDER BY o.created_at DESC
+         LIMIT {}",
+        filter, limit
+    );
+    let bucket_ids = bucket_id.map(Into::into).into_iter().collect::<Vec<_>>();
+    connection
+        .query(&sql, &bucket_ids)?
+        .iter()
+        .map(row_to_operation)
+        .collect()
+}
+
+fn row_to_operation(row: &Row) -> Result<LoggedOperation, BucketError> {
+    Ok(LoggedOperation {
+        bucket_id: row.get(0)?,
+        kind: row.get(1)?,
+        description: row.get(2)?,
+        author_name: row.get(3)?,
+        created_at: row.get(4)?,
+        undone: row.get(5)?,
+    })
+}
+
+fn parse_uuid(value: &str) -> Result<Uuid, BucketError> {
+    Uuid::parse_str(value).map_err(|e| BucketError::InvalidData(format!("Invalid UUID: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{initialize_database, open_metadata_store, DatabaseType};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn setup() -> (
+        tempfile::TempDir,
+        Box<dyn MetadataStore>,
+        Uuid,
+        std::path::PathBuf,
+    ) {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        initialize_database(temp_dir.path(), DatabaseType::DuckDB)
+            .expect("Failed to initialize database");
+        let connection = open_metadata_store(temp_dir.path()).expect("Failed to open database");
+
+        let bucket_id = Uuid::new_v4();
+        connection
+            .execute(
+                "INSERT INTO buckets (id, name, path) VALUES (?1, 'art', 'art')",
+                &[bucket_id.into()],
+            )
+            .expect("Failed to insert bucket");
+
+        let bucket_path = temp_dir.path().join("art");
+        fs::create_dir_all(bucket_path.join(".b").join("storage"))
+            .expect("Failed to create storage");
+        (temp_dir, connection, bucket_id, bucket_path)
+    }
+
+    fn identity() -> Identity {
+        Identity {
+            name: "alice".to_string(),
+            email: None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_stores_previous_content() {
+        let (_temp_dir, _connection, bucket_id, bucket_path) = setup();
+        fs::write(bucket_path.join("scene.blend"), b"scene").expect("Failed to write file");
+
+        let mut operation = Operation::new(
+            bucket_id,
+            OperationKind::Revert,
+            None,
+            None,
+            "revert".to_string(),
+        );
+        operation
+            .snapshot(&bucket_path, "scene.blend")
+            .expect("Failed to snapshot");
+        operation
+            .snapshot(&bucket_path, "new.png")
+            .expect("Failed to snapshot");
+
+        let hash = blake3::hash(b"scene").to_string();
+        assert_eq!(
+            operation.snapshots,
+            vec![
+                FileSnapshot {
+                    file_path: "scene.blend".to_string(),
+                    hash: Some(hash.clone()),
+                },
+                FileSnapshot {
+                    file_path: "new.png".to_string(),
+                    hash: None,
+                },
+            ]
+        );
+        assert!(bucket_path.join(".b").join("storage").join(hash).exists());
+    }
+
+    #[test]
+    fn test_find_last_undoable() {
+        let (_temp_dir, connection, bucket_id, bucket_path) = setup();
+        fs::write(bucket_path.join("scene.blend"), b"scene").expect("Failed to write file");
+
+        let mut revert = Operation::new(
+            bucket_id,
+            OperationKind::Revert,
+            None,
+            None,
+            "revert scene.blend".to_string(),
+        );
+        revert
+            .snapshot(&bucket_path, "scene.blend")
+            .expect("Failed to snapshot");
+        revert
+            .record(connection.as_ref(), &identity())
+            .expect("Failed to record");
+
+        // History operations are not undone by undo
+        Operation::new(
+            bucket_id,
+            OperationKind::Uncommit,
+            None,
+            None,
+            "uncommit".to_string(),
+        )
+        .record(connection.as_ref(), &identity())
+        .expect("Failed to record");
+
+        let found = find_last_undoable(connection.as_ref(), bucket_id)
+            .expect("Failed to query")
+            .expect("Expected an operation");
+        assert_eq!(found.id, revert.id);
+        assert_eq!(found.kind, OperationKind::Revert);
+        assert_eq!(found.snapshots, revert.snapshots);
+
+        let mut undo = Operation::new(
+            bucket_id,
+            OperationKind::Undo,
+            None,
+            None,
+            "undo".to_string(),
+        );
+        undo.undoes = Some(revert.id);
+        undo.record(connection.as_ref(), &identity())
+            .expect("Failed to record");
+
+        assert!(find_last_undoable(connection.as_ref(), bucket_id)
+            .expect("Failed to query")
+            .is_none());
+
+        let logged =
+            list_operations(connection.as_ref(), Some(bucket_id), 10).expect("Failed to list");
+        assert_eq!(logged.len(), 3);
+        assert_eq!(logged[0].kind, "undo");
+        assert!(logged
+            .iter()
+            .any(|operation| operation.kind == "revert" && operation.undone));
+    }
+}
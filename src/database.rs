@@ -1,16 +1,38 @@
 use crate::errors::BucketError;
-use std::path::Path;
+use crate::metadata::duckdb_store::DuckDbStore;
+#[cfg(feature = "postgres")]
+use crate::metadata::postgres_store::PostgresStore;
+use crate::metadata::sqlite_store::SqliteStore;
+use crate::metadata::MetadataStore;
+use crate::utils::compression::write_object;
+use crate::utils::config::{DatabaseConfig, RepositoryConfig};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
-#[cfg(feature = "postgres")]
-use postgres;
-#[cfg(feature = "postgres")]
-use postgresql_embedded;
+/// Changes to the tables of repositories created before them, in the order they were
+/// made. `schema.sql` has all of them, and the number of migrations a database has had
+/// is kept in its `schema_version` table.
+const MIGRATIONS: &[&str] = &[
+    include_str!("sql/migrations/001_file_locks.sql"),
+    include_str!("sql/migrations/002_commit_authors.sql"),
+    include_str!("sql/migrations/003_operations.sql"),
+    include_str!("sql/migrations/004_operation_snapshots.sql"),
+];
+
+const SCHEMA_VERSION_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS schema_version (version BIGINT NOT NULL)";
+
+/// The version of the tables `schema.sql` creates
+fn schema_version() -> i64 {
+    MIGRATIONS.len() as i64
+}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DatabaseType {
     DuckDB,
     PostgreSQL,
+    SQLite,
 }
 
 impl DatabaseType {
@@ -18,6 +40,7 @@ impl DatabaseType {
         match s.to_lowercase().as_str() {
             "duckdb" => Ok(DatabaseType::DuckDB),
             "postgresql" | "postgres" => Ok(DatabaseType::PostgreSQL),
+            "sqlite" => Ok(DatabaseType::SQLite),
             _ => Err(BucketError::InvalidData(format!(
                 "Unsupported database type: {}",
                 s
@@ -29,6 +52,7 @@ impl DatabaseType {
         match self {
             DatabaseType::DuckDB => "duckdb",
             DatabaseType::PostgreSQL => "postgresql",
+            DatabaseType::SQLite => "sqlite",
         }
     }
 }
@@ -38,7 +62,11 @@ pub fn get_database_type() -> Result<DatabaseType, BucketError> {
     let current_dir = env::current_dir()?;
     let buckets_dir = crate::utils::utils::find_directory_in_parents(&current_dir, ".buckets")
         .ok_or(BucketError::NotInRepo)?;
+    read_database_type(&buckets_dir)
+}
 
+/// Reads the database type of the repository with `.buckets` directory `buckets_dir`.
+pub fn read_database_type(buckets_dir: &Path) -> Result<DatabaseType, BucketError> {
     let db_type_file = buckets_dir.join("database_type");
     if db_type_file.exists() {
         let content = fs::read_to_string(db_type_file)?;
@@ -56,103 +84,240 @@ pub fn get_database_path() -> Result<std::path::PathBuf, BucketError> {
         .ok_or(BucketError::NotInRepo)?;
 
     let db_type = get_database_type()?;
+    Ok(database_path(&buckets_dir, db_type))
+}
+
+/// The file or directory in `buckets_dir` holding a database of type `db_type`. A
+/// repository on an external PostgreSQL server has no local database.
+pub fn database_path(buckets_dir: &Path, db_type: DatabaseType) -> PathBuf {
     match db_type {
-        DatabaseType::DuckDB => Ok(buckets_dir.join("buckets.db")),
-        DatabaseType::PostgreSQL => Ok(buckets_dir.join("postgres_data")),
+        DatabaseType::DuckDB => buckets_dir.join("buckets.db"),
+        DatabaseType::PostgreSQL => buckets_dir.join("postgres_data"),
+        DatabaseType::SQLite => buckets_dir.join("buckets.sqlite"),
     }
 }
 
-pub fn create_duckdb_connection(path: &Path) -> Result<duckdb::Connection, BucketError> {
-    duckdb::Connection::open(path).map_err(BucketError::DuckDB)
+/// Opens the metadata database of the repository with `.buckets` directory
+/// `buckets_dir`, with the backend its `database_type` file names, and runs the
+/// migrations it has not had yet.
+pub fn open_metadata_store(buckets_dir: &Path) -> Result<Box<dyn MetadataStore>, BucketError> {
+    let store = open_store(buckets_dir, read_database_type(buckets_dir)?)?;
+    migrate(store.as_ref())?;
+    Ok(store)
 }
 
-#[cfg(feature = "postgres")]
-pub fn create_postgres_connection_and_execute_schema(
-    data_dir: &Path,
-    schema: &str,
-) -> Result<(), BucketError> {
-    use postgresql_embedded::{PostgreSQL, Settings};
+fn open_store(
+    buckets_dir: &Path,
+    db_type: DatabaseType,
+) -> Result<Box<dyn MetadataStore>, BucketError> {
+    match db_type {
+        DatabaseType::DuckDB => Ok(Box::new(DuckDbStore::open(&database_path(
+            buckets_dir,
+            db_type,
+        ))?)),
+        DatabaseType::PostgreSQL => {
+            let config = RepositoryConfig::from_file(buckets_dir.to_path_buf())?.database;
+            open_postgres(buckets_dir, &config)
+        }
+        DatabaseType::SQLite => Ok(Box::new(SqliteStore::open(&database_path(
+            buckets_dir,
+            db_type,
+        ))?)),
+    }
+}
 
-    let settings = Settings {
-        data_dir: data_dir.to_path_buf(),
-        ..Default::default()
-    };
+/// Opens a PostgreSQL database for the repository with `.buckets` directory `buckets_dir`,
+/// on the external server of `config` when it has a URL and on the embedded server of the
+/// repository otherwise.
+#[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+pub fn open_postgres(
+    buckets_dir: &Path,
+    config: &DatabaseConfig,
+) -> Result<Box<dyn MetadataStore>, BucketError> {
+    #[cfg(feature = "postgres")]
+    {
+        match config.url {
+            Some(_) => Ok(Box::new(PostgresStore::connect(config)?)),
+            None => Ok(Box::new(PostgresStore::open_embedded(buckets_dir, config)?)),
+        }
+    }
+    #[cfg(not(feature = "postgres"))]
+    {
+        Err(BucketError::DatabaseError(
+            "PostgreSQL support not compiled in. Build with --features postgres to enable."
+                .to_string(),
+        ))
+    }
+}
 
-    let mut server = PostgreSQL::new(settings);
+/// The statements creating the tables of a new repository, which need none of the
+/// migrations.
+fn new_repository_schema() -> String {
+    format!(
+        "{}\n{};\nINSERT INTO schema_version (version) VALUES ({});\n",
+        include_str!("sql/schema.sql"),
+        SCHEMA_VERSION_TABLE,
+        schema_version()
+    )
+}
 
-    // Use a simple runtime for async operations
-    let rt = tokio::runtime::Runtime::new()
-        .map_err(|e| BucketError::DatabaseError(format!("Failed to create runtime: {}", e)))?;
+/// Runs the migrations the database has not had yet. Databases without a
+/// `schema_version` table have the tables of the first release.
+pub fn migrate(connection: &dyn MetadataStore) -> Result<(), BucketError> {
+    // Most databases are up to date, which is checked without taking a write lock
+    if let Ok(Some(version)) = stored_schema_version(connection) {
+        if version == schema_version() {
+            return Ok(());
+        }
+    }
+    // A database without the tables of a repository has nothing to migrate
+    if connection
+        .query("SELECT id FROM buckets LIMIT 1", &[])
+        .is_err()
+    {
+        return Ok(());
+    }
 
-    rt.block_on(async {
-        server.setup().await.map_err(|e| {
-            BucketError::DatabaseError(format!("Failed to setup PostgreSQL: {}", e))
-        })?;
+    connection.transaction(|transaction| {
+        transaction.execute_batch(SCHEMA_VERSION_TABLE)?;
+        let version = match stored_schema_version(transaction)? {
+            Some(version) => version,
+            None => {
+                transaction.execute("INSERT INTO schema_version (version) VALUES (0)", &[])?;
+                0
+            }
+        };
+        if version > schema_version() {
+            return Err(BucketError::DatabaseError(format!(
+                "The database has schema version {}, this version of buckets supports up to {}",
+                version,
+                schema_version()
+            )));
+        }
+        for migration in &MIGRATIONS[version as usize..] {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.execute(
+            "UPDATE schema_version SET version = ?1",
+            &[schema_version().into()],
+        )?;
+        Ok(())
+    })
+}
 
-        server.start().await.map_err(|e| {
-            BucketError::DatabaseError(format!("Failed to start PostgreSQL: {}", e))
-        })?;
+fn stored_schema_version(connection: &dyn MetadataStore) -> Result<Option<i64>, BucketError> {
+    connection
+        .query_row("SELECT version FROM schema_version", &[])?
+        .map(|row| row.get(0))
+        .transpose()
+}
 
-        // Wait a bit for the server to be ready
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+pub fn initialize_database(location: &Path, db_type: DatabaseType) -> Result<(), BucketError> {
+    let store = open_store(location, db_type)?;
+    create_tables(store.as_ref())?;
+    store.close()?;
 
-        Ok::<(), BucketError>(())
-    })?;
+    write_database_type(location, db_type)
+}
 
-    let url = server.settings().url("postgres");
+/// Creates the tables of a new repository in an empty database.
+pub fn create_tables(store: &dyn MetadataStore) -> Result<(), BucketError> {
+    store.execute_batch(&new_repository_schema())
+}
 
-    // Connect and execute schema while server is alive
-    let mut client = postgres::Client::connect(&url, postgres::NoTls).map_err(|e| {
-        BucketError::DatabaseError(format!("Failed to connect to PostgreSQL: {}", e))
+/// Records the backend of the repository with `.buckets` directory `buckets_dir`. The file
+/// is replaced in one step once it is on disk, so commands see either the old or the new
+/// backend, also after a crash.
+pub fn write_database_type(buckets_dir: &Path, db_type: DatabaseType) -> Result<(), BucketError> {
+    write_object(&buckets_dir.join("database_type"), |writer| {
+        writer.write_all(db_type.as_str().as_bytes())
     })?;
-
-    client
-        .batch_execute(schema)
-        .map_err(|e| BucketError::DatabaseError(format!("Failed to execute schema: {}", e)))?;
-
     Ok(())
 }
 
-#[cfg(not(feature = "postgres"))]
-#[allow(dead_code)]
-pub fn create_postgres_connection_and_execute_schema(
-    _data_dir: &Path,
-    _schema: &str,
-) -> Result<(), BucketError> {
-    Err(BucketError::DatabaseError(
-        "PostgreSQL support not compiled in".to_string(),
-    ))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
 
-pub fn initialize_database(location: &Path, db_type: DatabaseType) -> Result<(), BucketError> {
-    let schema = include_str!("sql/schema.sql");
+    /// The tables of the first release, before there were migrations
+    const FIRST_SCHEMA: &str = "
+        CREATE TABLE buckets (
+            id UUID PRIMARY KEY,
+            name TEXT NOT NULL,
+            path TEXT NOT NULL,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE commits (
+            id UUID PRIMARY KEY,
+            bucket_id UUID NOT NULL,
+            message TEXT NOT NULL,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (bucket_id) REFERENCES buckets (id)
+        );
+        CREATE TABLE files (
+            id UUID PRIMARY KEY,
+            commit_id UUID NOT NULL,
+            file_path TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            FOREIGN KEY (commit_id) REFERENCES commits (id),
+            UNIQUE (commit_id, file_path, hash)
+        );
+        INSERT INTO buckets (id, name, path)
+            VALUES ('5f0c6d2e-3a1b-4c8d-9e7f-0a1b2c3d4e5f', 'props', 'props');
+        INSERT INTO commits (id, bucket_id, message)
+            VALUES ('6a1d7e3f-4b2c-4d9e-8f01-1b2c3d4e5f60',
+                    '5f0c6d2e-3a1b-4c8d-9e7f-0a1b2c3d4e5f', 'first');
+    ";
 
-    match db_type {
-        DatabaseType::DuckDB => {
-            let db_path = location.join("buckets.db");
-            let connection = create_duckdb_connection(&db_path)?;
-            connection.execute_batch(schema)?;
-        }
-        DatabaseType::PostgreSQL => {
-            #[cfg(feature = "postgres")]
-            {
-                let data_dir = location.join("postgres_data");
-                fs::create_dir_all(&data_dir)?;
-                create_postgres_connection_and_execute_schema(&data_dir, schema)?;
-            }
-            #[cfg(not(feature = "postgres"))]
-            {
-                return Err(BucketError::DatabaseError(
-                    "PostgreSQL support not compiled in. Build with --features postgres to enable."
-                        .to_string(),
-                ));
-            }
+    #[test]
+    fn test_first_schema_is_migrated() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let store = DuckDbStore::open(&temp_dir.path().join("buckets.db"))
+            .expect("Failed to open database");
+        let connection: &dyn MetadataStore = &store;
+        connection
+            .execute_batch(FIRST_SCHEMA)
+            .expect("Failed to create tables");
+
+        migrate(connection).expect("Failed to migrate");
+        let locks: i64 = connection
+            .query_value("SELECT COUNT(*) FROM file_locks", &[])
+            .expect("Failed to count locks");
+        assert_eq!(locks, 0);
+        for table in ["operations", "operation_files"] {
+            let count: i64 = connection
+                .query_value(&format!("SELECT COUNT(*) FROM {}", table), &[])
+                .expect("Failed to count");
+            assert_eq!(count, 0);
         }
-    }
+        let authors = connection
+            .query("SELECT author_name FROM commits WHERE NOT abandoned", &[])
+            .and_then(|rows| {
+                rows.iter()
+                    .map(|row| row.get::<Option<String>>(0))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .expect("Failed to query commits");
+        assert_eq!(authors, [None]);
+        assert_eq!(
+            stored_schema_version(connection).expect("Failed to read version"),
+            Some(schema_version())
+        );
 
-    // Write database type to config
-    let config_path = location.join("database_type");
-    fs::write(config_path, db_type.as_str())?;
+        // Migrating again finds it up to date
+        migrate(connection).expect("Failed to migrate");
+    }
 
-    Ok(())
+    #[test]
+    fn test_new_database_needs_no_migrations() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        initialize_database(temp_dir.path(), DatabaseType::DuckDB)
+            .expect("Failed to initialize database");
+        let connection = open_metadata_store(temp_dir.path()).expect("Failed to open database");
+        assert_eq!(
+            stored_schema_version(connection.as_ref()).expect("Failed to read version"),
+            Some(schema_version())
+        );
+    }
 }
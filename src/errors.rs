@@ -31,6 +31,12 @@ pub enum BucketError {
     SecurityError(String),
     #[error("Path validation error: {0}")]
     PathValidationError(String),
+    #[error("Integrity check failed: {0}")]
+    IntegrityError(String),
+    #[error("Repository is locked: {0}")]
+    RepositoryLocked(String),
+    #[error("File is locked: {0}")]
+    FileLocked(String),
 }
 
 impl From<&str> for BucketError {
@@ -91,6 +97,13 @@ mod tests {
             format!("{}", BucketError::FileNotFound("missing.txt".to_string())),
             "File not found missing.txt"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                BucketError::IntegrityError("2 problems found".to_string())
+            ),
+            "Integrity check failed: 2 problems found"
+        );
     }
 
     #[test]
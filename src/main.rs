@@ -14,6 +14,7 @@ mod config;
 mod data;
 mod database;
 mod errors;
+mod metadata;
 mod utils;
 mod world;
 
@@ -53,19 +54,50 @@ fn main() -> ExitCode {
 }
 
 fn dispatch() -> Result<(), BucketError> {
+    let current_dir = CURRENT_DIR.with(|dir| dir.clone());
+
+    // Commands that modify the repository hold its lock until they finish
+    let lock = if ARGS.command.is_mutating() {
+        utils::lock::lock_repository(&current_dir, ARGS.command.name())?
+    } else {
+        None
+    };
+
+    // Clean up after commits that were interrupted before they completed
+    utils::recovery::recover_repository(&current_dir, lock.is_some())?;
+
     match &ARGS.command {
         // Commands that modify the repository
         Command::Init(command) => commands::init::Init::new(command).execute()?,
         Command::Create(command) => commands::create::Create::new(command).execute()?,
         Command::Commit(command) => commands::commit::Commit::new(command).execute()?,
+        Command::Uncommit(command) => commands::uncommit::Uncommit::new(command).execute()?,
         Command::Revert(command) => commands::restore::Restore::new(command).execute()?,
         Command::Rollback(command) => commands::rollback::Rollback::new(command).execute()?,
+        Command::Restore(command) => {
+            commands::restore_version::RestoreVersion::new(command).execute()?
+        }
+        Command::Export(command) => commands::export::Export::new(command).execute()?,
+        Command::Archive(command) => commands::archive::Archive::new(command).execute()?,
+        Command::Prefetch(command) => commands::prefetch::Prefetch::new(command).execute()?,
         Command::Stash(command) => commands::stash::Stash::new(command).execute()?,
+        Command::Lock(command) => commands::lock::Lock::new(command).execute()?,
+        Command::Unlock(command) => commands::unlock::Unlock::new(command).execute()?,
+        Command::Undo(command) => commands::undo::Undo::new(command).execute()?,
+        Command::Storage(command) => commands::storage::Storage::new(command).execute()?,
+        Command::Key(command) => commands::key::Key::new(command).execute()?,
+        Command::Db(command) => commands::db::Db::new(command).execute()?,
         // Informational commands
         Command::Status(command) => commands::status::Status::new(command).execute()?,
         Command::History(command) => commands::history::execute(command.clone())?,
         Command::List(command) => commands::list::List::new(command).execute()?,
         Command::Stats(command) => commands::stats::Stats::new(command).execute()?,
+        Command::Fsck(command) => commands::fsck::Fsck::new(command).execute()?,
+        Command::Locks(command) => commands::locks::Locks::new(command).execute()?,
+        Command::CheckIgnore(command) => {
+            commands::check_ignore::CheckIgnore::new(command).execute()?
+        }
+        Command::Oplog(command) => commands::oplog::Oplog::new(command).execute()?,
         // Expectation commands
         Command::Expect(command) => commands::expect::Expect::new(command).execute()?,
         Command::Check(command) => commands::check::Check::new(command).execute()?,
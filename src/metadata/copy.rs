@@ -0,0 +1,249 @@
+use crate::database::DatabaseType;
+use crate::errors::BucketError;
+use crate::metadata::{MetadataStore, Value};
+use chrono::NaiveDateTime;
+
+/// The tables of a repository besides `schema_version`, ordered so the rows a row refers to
+/// are copied before it
+pub const TABLES: &[&str] = &[
+    "buckets",
+    "commits",
+    "files",
+    "file_locks",
+    "operations",
+    "operation_files",
+];
+
+/// Most parameters of one insert, PostgreSQL allows 65535
+const MAX_PARAMS: usize = 1000;
+
+/// The rows of a table, hashed independently of their order and of how the backend
+/// formats its values, so the digests of two copies of a table match
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDigest {
+    pub table: &'static str,
+    pub rows: usize,
+    pub hash: String,
+}
+
+/// Copies the rows of every table of `source` into the empty tables of `target`. Both
+/// should be in a transaction, so the copy is of a single snapshot and is all or nothing.
+pub fn copy_tables(
+    source: &dyn MetadataStore,
+    target: &dyn MetadataStore,
+) -> Result<Vec<usize>, BucketError> {
+    TABLES
+        .iter()
+        .map(|table| copy_table(source, target, table))
+        .collect()
+}
+
+fn copy_table(
+    source: &dyn MetadataStore,
+    target: &dyn MetadataStore,
+    table: &str,
+) -> Result<usize, BucketError> {
+    // Columns added by migrations are last in a migrated table, so they go by name
+    let columns = column_names(source, table)?;
+    let rows = source.query(
+        &format!("SELECT {} FROM {}", columns.join(", "), table),
+        &[],
+    )?;
+
+    for chunk in rows.chunks((MAX_PARAMS / columns.len()).max(1)) {
+        let values = (0..chunk.len())
+            .map(|row| {
+                let params = (1..=columns.len())
+                    .map(|column| format!("?{}", row * columns.len() + column))
+                    .collect::<Vec<_>>();
+                format!("({})", params.join(", "))
+            })
+            .collect::<Vec<_>>();
+        let params = chunk
+            .iter()
+            .flat_map(|row| row.values().iter().cloned())
+            .collect::<Vec<_>>();
+        target.execute(
+            &format!(
+                "INSERT INTO {} ({}) VALUES {}",
+                table,
+                columns.join(", "),
+                values.join(", ")
+            ),
+            &params,
+        )?;
+    }
+    Ok(rows.len())
+}
+
+/// Counts and hashes the rows of `table`.
+pub fn table_digest(
+    store: &dyn MetadataStore,
+    table: &'static str,
+) -> Result<TableDigest, BucketError> {
+    let mut columns = column_names(store, table)?;
+    columns.sort();
+    let rows = store.query(
+        &format!("SELECT {} FROM {}", columns.join(", "), table),
+        &[],
+    )?;
+
+    let mut lines = rows
+        .iter()
+        .map(|row| {
+            row.values()
+                .iter()
+                .map(canonical)
+                .collect::<Vec<_>>()
+                .join("\x1f")
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    let mut hasher = blake3::Hasher::new();
+    for line in &lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\x1e");
+    }
+    Ok(TableDigest {
+        table,
+        rows: rows.len(),
+        hash: hasher.finalize().to_hex().to_string(),
+    })
+}
+
+fn column_names(store: &dyn MetadataStore, table: &str) -> Result<Vec<String>, BucketError> {
+    // SQLite has no information schema
+    let sql = match store.database_type() {
+        DatabaseType::SQLite => "SELECT name FROM pragma_table_info(?1) ORDER BY cid",
+        DatabaseType::DuckDB | DatabaseType::PostgreSQL => {
+            "SELECT CAST(column_name AS TEXT) FROM information_schema.columns \
+             WHERE table_schema = current_schema() AND table_name = ?1 \
+             ORDER BY ordinal_position"
+        }
+    };
+    let columns = store
+        .query(sql, &[table.into()])?
+        .iter()
+        .map(|row| row.get(0))
+        .collect::<Result<Vec<String>, _>>()?;
+    if columns.is_empty() {
+        return Err(BucketError::DatabaseError(format!(
+            "The database has no table {}",
+            table
+        )));
+    }
+    Ok(columns)
+}
+
+/// A value as text that is the same on every backend. Timestamps are the only values
+/// the backends format differently.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Null => "\0".to_string(),
+        Value::Bool(bool) => bool.to_string(),
+        Value::Int(int) => int.to_string(),
+        Value::Text(text) => match NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f") {
+            Ok(timestamp) => canonical(&Value::Timestamp(timestamp)),
+            Err(_) => text.clone(),
+        },
+        Value::Timestamp(timestamp) => timestamp.format("%Y-%m-%d %H:%M:%S%.6f").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::create_tables;
+    use crate::metadata::duckdb_store::DuckDbStore;
+    use crate::metadata::sqlite_store::SqliteStore;
+    use tempfile::tempdir;
+
+    const BUCKET_ID: &str = "5f0c6d2e-3a1b-4c8d-9e7f-0a1b2c3d4e5f";
+    const COMMIT_ID: &str = "6a1d7e3f-4b2c-4d9e-8f01-1b2c3d4e5f60";
+
+    fn open(path: &std::path::Path) -> Box<dyn MetadataStore> {
+        let store = DuckDbStore::open(path).expect("Failed to open database");
+        create_tables(&store).expect("Failed to create tables");
+        Box::new(store)
+    }
+
+    #[test]
+    fn test_copy_tables() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let source = open(&temp_dir.path().join("source.db"));
+        source
+            .execute(
+                "INSERT INTO buckets (id, name, path) VALUES (?1, 'props', 'props')",
+                &[BUCKET_ID.into()],
+            )
+            .expect("Failed to insert bucket");
+        source
+            .execute(
+                "INSERT INTO commits (id, bucket_id, message, author_name) \
+                 VALUES (?1, ?2, 'first', NULL)",
+                &[COMMIT_ID.into(), BUCKET_ID.into()],
+            )
+            .expect("Failed to insert commit");
+        // More files than fit in one insert
+        for index in 0..300 {
+            source
+                .execute(
+                    "INSERT INTO files (id, commit_id, file_path, hash) VALUES (?1, ?2, ?3, ?4)",
+                    &[
+                        uuid::Uuid::new_v4().into(),
+                        COMMIT_ID.into(),
+                        format!("models/{}.blend", index).into(),
+                        blake3::hash(&[index as u8]).to_hex().to_string().into(),
+                    ],
+                )
+                .expect("Failed to insert file");
+        }
+
+        let target = open(&temp_dir.path().join("target.db"));
+        let counts = copy_tables(source.as_ref(), target.as_ref()).expect("Failed to copy");
+        assert_eq!(counts, [1, 1, 300, 0, 0, 0]);
+        for table in TABLES {
+            let expected = table_digest(source.as_ref(), table).expect("Failed to hash");
+            let copied = table_digest(target.as_ref(), table).expect("Failed to hash");
+            assert_eq!(copied, expected);
+        }
+
+        // SQLite keeps timestamps and booleans differently, the digests still match
+        let sqlite = SqliteStore::open(&temp_dir.path().join("target.sqlite"))
+            .expect("Failed to open database");
+        create_tables(&sqlite).expect("Failed to create tables");
+        let counts = copy_tables(source.as_ref(), &sqlite).expect("Failed to copy");
+        assert_eq!(counts, [1, 1, 300, 0, 0, 0]);
+        for table in TABLES {
+            let expected = table_digest(source.as_ref(), table).expect("Failed to hash");
+            let copied = table_digest(&sqlite, table).expect("Failed to hash");
+            assert_eq!(copied, expected);
+        }
+
+        // A changed value changes the hash
+        target
+            .execute("UPDATE commits SET abandoned = TRUE", &[])
+            .expect("Failed to update commit");
+        let expected = table_digest(source.as_ref(), "commits").expect("Failed to hash");
+        let copied = table_digest(target.as_ref(), "commits").expect("Failed to hash");
+        assert_eq!(copied.rows, expected.rows);
+        assert_ne!(copied.hash, expected.hash);
+    }
+
+    #[test]
+    fn test_canonical_timestamps() {
+        assert_eq!(
+            canonical(&Value::Text("2024-05-01 12:30:00".to_string())),
+            "2024-05-01 12:30:00.000000"
+        );
+        assert_eq!(
+            canonical(&Value::Text("2024-05-01 12:30:00.25".to_string())),
+            "2024-05-01 12:30:00.250000"
+        );
+        assert_eq!(canonical(&Value::Text("props".to_string())), "props");
+        assert_ne!(
+            canonical(&Value::Null),
+            canonical(&Value::Text(String::new()))
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use crate::database::DatabaseType;
+use crate::errors::BucketError;
+use crate::metadata::{MetadataStore, Row, Value};
+use duckdb::types::{TimeUnit, ToSqlOutput, ValueRef};
+use duckdb::{params_from_iter, Connection, ToSql};
+use std::path::Path;
+
+/// Metadata in the `.buckets/buckets.db` DuckDB file of a repository
+pub struct DuckDbStore {
+    connection: Connection,
+}
+
+impl DuckDbStore {
+    pub fn open(path: &Path) -> Result<Self, BucketError> {
+        Ok(Self::new(Connection::open(path)?))
+    }
+
+    pub fn new(connection: Connection) -> Self {
+        Self { connection }
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Null => ToSqlOutput::Owned(duckdb::types::Value::Null),
+            Value::Bool(value) => ToSqlOutput::Owned(duckdb::types::Value::Boolean(*value)),
+            Value::Int(value) => ToSqlOutput::Owned(duckdb::types::Value::BigInt(*value)),
+            Value::Text(value) => ToSqlOutput::Borrowed(ValueRef::Text(value.as_bytes())),
+            Value::Timestamp(value) => ToSqlOutput::Owned(duckdb::types::Value::Timestamp(
+                TimeUnit::Microsecond,
+                value.and_utc().timestamp_micros(),
+            )),
+        })
+    }
+}
+
+impl MetadataStore for DuckDbStore {
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::DuckDB
+    }
+
+    fn execute(&self, sql: &str, params: &[Value]) -> Result<usize, BucketError> {
+        Ok(self.connection.execute(sql, params_from_iter(params))?)
+    }
+
+    fn query(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>, BucketError> {
+        let mut statement = self.connection.prepare(sql)?;
+        let mut rows = statement.query(params_from_iter(params))?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            let columns = row.as_ref().column_count();
+            let mut values = Vec::with_capacity(columns);
+            for index in 0..columns {
+                values.push(match row.get_ref(index)? {
+                    ValueRef::Null => Value::Null,
+                    ValueRef::Boolean(value) => Value::Bool(value),
+                    ValueRef::TinyInt(value) => Value::Int(value.into()),
+                    ValueRef::SmallInt(value) => Value::Int(value.into()),
+                    ValueRef::Int(value) => Value::Int(value.into()),
+                    ValueRef::BigInt(value) => Value::Int(value),
+                    ValueRef::UTinyInt(value) => Value::Int(value.into()),
+                    ValueRef::USmallInt(value) => Value::Int(value.into()),
+                    ValueRef::UInt(value) => Value::Int(value.into()),
+                    // Written the way PostgreSQL timestamps are read
+                    ValueRef::Timestamp(unit, value) => {
+                        let timestamp =
+                            chrono::DateTime::from_timestamp_micros(unit.to_micros(value))
+                                .ok_or_else(|| {
+                                    BucketError::DatabaseError(format!(
+                                        "Timestamp {} of column {} is out of range",
+                                        value, index
+                                    ))
+                                })?;
+                        Value::Text(timestamp.naive_utc().to_string())
+                    }
+                    // UUIDs and the like are read as text
+                    _ => Value::Text(row.get::<_, String>(index)?),
+                });
+            }
+            result.push(Row::new(values));
+        }
+        Ok(result)
+    }
+
+    fn execute_batch(&self, sql: &str) -> Result<(), BucketError> {
+        Ok(self.connection.execute_batch(sql)?)
+    }
+
+    fn close(self: Box<Self>) -> Result<(), BucketError> {
+        self.connection.close().map_err(|(_, e)| {
+            BucketError::DatabaseError(format!("Failed to close database connection: {}", e))
+        })
+    }
+}
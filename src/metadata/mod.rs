@@ -0,0 +1,284 @@
+use crate::database::DatabaseType;
+use crate::errors::BucketError;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+pub mod copy;
+pub mod duckdb_store;
+#[cfg(feature = "postgres")]
+pub mod postgres_store;
+pub mod sqlite_store;
+
+/// A parameter of a statement or a column of a row read from the metadata database
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Text(String),
+    /// A time in UTC, for a `TIMESTAMP` column
+    Timestamp(NaiveDateTime),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<&String> for Value {
+    fn from(value: &String) -> Self {
+        Value::Text(value.clone())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<Uuid> for Value {
+    fn from(value: Uuid) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<DateTime<Utc>> for Value {
+    fn from(value: DateTime<Utc>) -> Self {
+        Value::Timestamp(value.naive_utc())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Types a column of a row can be read as
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Text(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(bool) => Some(*bool),
+            // Conditions computed by SQLite, such as `EXISTS`, are integers
+            Value::Int(0) => Some(false),
+            Value::Int(1) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for Uuid {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Text(text) => Uuid::parse_str(text).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A row returned by a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub fn new(values: Vec<Value>) -> Self {
+        Self { values }
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// Reads column `index` as a `T`, failing when the column is missing or holds
+    /// another type.
+    pub fn get<T: FromValue>(&self, index: usize) -> Result<T, BucketError> {
+        let value = self.values.get(index).ok_or_else(|| {
+            BucketError::DatabaseError(format!("Column {} is not in the row", index))
+        })?;
+        T::from_value(value).ok_or_else(|| {
+            BucketError::DatabaseError(format!(
+                "Column {} cannot be read as {}: {:?}",
+                index,
+                std::any::type_name::<T>(),
+                value
+            ))
+        })
+    }
+}
+
+/// The database holding the buckets, commits, files and the other metadata of a
+/// repository. Statements are written once, in the SQL DuckDB, PostgreSQL and SQLite
+/// all understand, with `?1`, `?2`, ... as parameters.
+pub trait MetadataStore {
+    fn database_type(&self) -> DatabaseType;
+
+    /// Runs a statement and returns the number of rows it changed.
+    fn execute(&self, sql: &str, params: &[Value]) -> Result<usize, BucketError>;
+
+    /// Runs a query and returns all of its rows.
+    fn query(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>, BucketError>;
+
+    /// Runs statements separated by semicolons, without parameters.
+    fn execute_batch(&self, sql: &str) -> Result<(), BucketError>;
+
+    /// Starts a transaction, which is ended with `COMMIT` or `ROLLBACK`.
+    fn begin(&self) -> Result<(), BucketError> {
+        self.execute_batch("BEGIN TRANSACTION")
+    }
+
+    /// Closes the database, reporting the errors dropping it would hide.
+    fn close(self: Box<Self>) -> Result<(), BucketError>;
+}
+
+impl dyn MetadataStore + '_ {
+    /// Runs a query and returns its first row, if there is one.
+    pub fn query_row(&self, sql: &str, params: &[Value]) -> Result<Option<Row>, BucketError> {
+        Ok(self.query(sql, params)?.into_iter().next())
+    }
+
+    /// Runs a query returning a single value, such as a count.
+    pub fn query_value<T: FromValue>(&self, sql: &str, params: &[Value]) -> Result<T, BucketError> {
+        self.query_row(sql, params)?
+            .ok_or_else(|| BucketError::DatabaseError("The query returned no rows".to_string()))?
+            .get(0)
+    }
+
+    /// Runs `f` in a transaction, which is committed when `f` succeeds and rolled
+    /// back when it fails.
+    pub fn transaction<R>(
+        &self,
+        f: impl FnOnce(&dyn MetadataStore) -> Result<R, BucketError>,
+    ) -> Result<R, BucketError> {
+        self.begin()?;
+        match f(self) {
+            Ok(result) => {
+                self.execute_batch("COMMIT")?;
+                Ok(result)
+            }
+            Err(e) => {
+                // The error of the transaction is the one worth reporting
+                let _ = self.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::duckdb_store::DuckDbStore;
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_row_get() {
+        let id = Uuid::new_v4();
+        let row = Row::new(vec![
+            Value::from(id),
+            Value::from(Some(3_i64)),
+            Value::from(None::<String>),
+        ]);
+        assert_eq!(row.get::<Uuid>(0).expect("Failed to read uuid"), id);
+        assert_eq!(row.get::<i64>(1).expect("Failed to read int"), 3);
+        assert_eq!(
+            row.get::<Option<String>>(2).expect("Failed to read null"),
+            None
+        );
+        assert!(row.get::<String>(2).is_err());
+        assert!(row.get::<bool>(1).is_err());
+        assert!(Row::new(vec![Value::Int(1)])
+            .get::<bool>(0)
+            .expect("Failed to read bool"));
+        assert!(row.get::<i64>(3).is_err());
+    }
+
+    #[test]
+    fn test_transaction_is_rolled_back_on_error() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let store = DuckDbStore::open(&temp_dir.path().join("buckets.db")).expect("Failed to open");
+        let connection: &dyn MetadataStore = &store;
+        connection
+            .execute_batch("CREATE TABLE t (id UUID PRIMARY KEY, name TEXT, n BIGINT)")
+            .expect("Failed to create table");
+
+        let id = Uuid::new_v4();
+        let result = connection.transaction(|transaction| {
+            transaction.execute(
+                "INSERT INTO t (id, name, n) VALUES (?1, ?2, ?3)",
+                &[id.into(), "kept".into(), 1_i64.into()],
+            )?;
+            Err::<(), _>(BucketError::NotInRepo)
+        });
+        assert!(matches!(result, Err(BucketError::NotInRepo)));
+        let count: i64 = connection
+            .query_value("SELECT COUNT(*) FROM t", &[])
+            .expect("Failed to count");
+        assert_eq!(count, 0);
+
+        connection
+            .transaction(|transaction| {
+                transaction.execute(
+                    "INSERT INTO t (id, name, n) VALUES (?1, ?2, ?3)",
+                    &[id.into(), None::<String>.into(), 2_i64.into()],
+                )
+            })
+            .expect("Failed to insert");
+        let row = connection
+            .query_row("SELECT id, name, n FROM t WHERE id = ?1", &[id.into()])
+            .expect("Failed to query")
+            .expect("Row is missing");
+        assert_eq!(row.get::<Uuid>(0).expect("Failed to read id"), id);
+        assert_eq!(
+            row.get::<Option<String>>(1).expect("Failed to read name"),
+            None
+        );
+        assert_eq!(row.get::<i64>(2).expect("Failed to read n"), 2);
+    }
+}
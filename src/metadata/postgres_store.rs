@@ -0,0 +1,423 @@
+use crate::database::{database_path, DatabaseType};
+use crate::errors::BucketError;
+use crate::metadata::{MetadataStore, Row, Value};
+use crate::utils::config::DatabaseConfig;
+use log::debug;
+use once_cell::sync::Lazy;
+use postgres::error::SqlState;
+use postgres::types::{ToSql, Type};
+use postgres::{Client, NoTls};
+use postgresql_embedded::{PostgreSQL, Settings};
+use r2d2::{CustomizeConnection, Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// File in `.buckets` holding the password of the embedded PostgreSQL server
+const PASSWORD_FILE: &str = "postgres_password";
+
+type Manager = PostgresConnectionManager<NoTls>;
+/// URL and schema of a repository on an external server
+type PoolKey = (String, Option<String>);
+
+/// Connections to the external servers this process has used
+static POOLS: Lazy<Mutex<HashMap<PoolKey, Pool<Manager>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Metadata in a PostgreSQL database, on an external server or on the embedded server
+/// of the repository. The embedded server runs while the store is open and is stopped
+/// when it is dropped.
+pub struct PostgresStore {
+    client: RefCell<PooledConnection<Manager>>,
+    // Dropped after the client, which stops the server
+    _server: Option<EmbeddedServer>,
+}
+
+/// The PostgreSQL server in `.buckets/postgres_data`, with the runtime it was started on
+struct EmbeddedServer {
+    server: PostgreSQL,
+    _runtime: tokio::runtime::Runtime,
+}
+
+impl PostgresStore {
+    /// Starts the embedded server of the repository with `.buckets` directory
+    /// `buckets_dir` and connects to it, creating the server on first use.
+    pub fn open_embedded(buckets_dir: &Path, config: &DatabaseConfig) -> Result<Self, BucketError> {
+        let server = EmbeddedServer::start(buckets_dir)?;
+        // The server stops with the store, so its connections are not shared
+        let pool = create_pool(&server.server.settings().url("postgres"), None, config)?;
+        Ok(Self {
+            client: RefCell::new(get_connection(&pool)?),
+            _server: Some(server),
+        })
+    }
+
+    /// Connects to the external server of `config`, with the tables of the repository in
+    /// its schema. The schema is created when it does not exist yet.
+    pub fn connect(config: &DatabaseConfig) -> Result<Self, BucketError> {
+        let url = config.url.clone().ok_or_else(|| {
+            BucketError::DatabaseError("No database URL in the [database] config".to_string())
+        })?;
+        let key = (url, config.schema.clone());
+        let pool = {
+            let mut pools = POOLS
+                .lock()
+                .map_err(|_| BucketError::DatabaseError("Connection pool poisoned".to_string()))?;
+            match pools.get(&key) {
+                Some(pool) => pool.clone(),
+                None => {
+                    let pool = create_pool(&key.0, key.1.as_deref(), config)?;
+                    pools.insert(key, pool.clone());
+                    pool
+                }
+            }
+        };
+        Ok(Self {
+            client: RefCell::new(get_connection(&pool)?),
+            _server: None,
+        })
+    }
+}
+
+impl EmbeddedServer {
+    fn start(buckets_dir: &Path) -> Result<Self, BucketError> {
+        let data_dir = database_path(buckets_dir, DatabaseType::PostgreSQL);
+        let password_file = buckets_dir.join(PASSWORD_FILE);
+        // The password is chosen when the server is created and kept for later commands
+        let password = match fs::read_to_string(&password_file) {
+            Ok(password) => password.trim().to_string(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let password = Uuid::new_v4().simple().to_string();
+                fs::write(&password_file, &password)?;
+                password
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let settings = Settings {
+            data_dir,
+            password_file,
+            password,
+            temporary: false,
+            ..Default::default()
+        };
+        let mut server = PostgreSQL::new(settings);
+
+        let runtime = tokio::runtime::Runtime::new()
+            .map_err(|e| BucketError::DatabaseError(format!("Failed to create runtime: {}", e)))?;
+        runtime.block_on(async {
+            server.setup().await.map_err(|e| {
+                BucketError::DatabaseError(format!("Failed to setup PostgreSQL: {}", e))
+            })?;
+            server.start().await.map_err(|e| {
+                BucketError::DatabaseError(format!("Failed to start PostgreSQL: {}", e))
+            })
+        })?;
+
+        Ok(Self {
+            server,
+            _runtime: runtime,
+        })
+    }
+}
+
+/// Points the unqualified table names of the statements at the schema of a repository
+#[derive(Debug)]
+struct SearchPath(String);
+
+impl CustomizeConnection<Client, postgres::Error> for SearchPath {
+    fn on_acquire(&self, client: &mut Client) -> Result<(), postgres::Error> {
+        client.batch_execute(&format!("SET search_path TO {}", quote_identifier(&self.0)))
+    }
+}
+
+/// Creates a pool of up to `pool_size` connections to the server at `url`, once it
+/// accepts connections, creating `schema` on it.
+fn create_pool(
+    url: &str,
+    schema: Option<&str>,
+    config: &DatabaseConfig,
+) -> Result<Pool<Manager>, BucketError> {
+    let mut pg_config = url
+        .parse::<postgres::Config>()
+        .map_err(|e| BucketError::DatabaseError(format!("Invalid database URL: {}", e)))?;
+    let timeout = Duration::from_secs(config.connect_timeout);
+    if pg_config.get_connect_timeout().is_none() {
+        pg_config.connect_timeout(timeout);
+    }
+
+    let mut client = connect_when_ready(&pg_config, timeout)?;
+    if let Some(schema) = schema {
+        client
+            .batch_execute(&format!(
+                "CREATE SCHEMA IF NOT EXISTS {}",
+                quote_identifier(schema)
+            ))
+            .map_err(database_error)?;
+    }
+    client.close().map_err(database_error)?;
+
+    let mut builder = Pool::builder()
+        .max_size(config.pool_size.max(1))
+        .min_idle(Some(0))
+        .connection_timeout(timeout);
+    if let Some(schema) = schema {
+        builder = builder.connection_customizer(Box::new(SearchPath(schema.to_string())));
+    }
+    Ok(builder.build_unchecked(PostgresConnectionManager::new(pg_config, NoTls)))
+}
+
+fn get_connection(pool: &Pool<Manager>) -> Result<PooledConnection<Manager>, BucketError> {
+    pool.get()
+        .map_err(|e| BucketError::DatabaseError(format!("Failed to connect to PostgreSQL: {}", e)))
+}
+
+/// Connects to a server, retrying while it starts up or cannot be reached until
+/// `timeout` has passed.
+fn connect_when_ready(config: &postgres::Config, timeout: Duration) -> Result<Client, BucketError> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(50);
+    loop {
+        match config.connect(NoTls) {
+            Ok(client) => return Ok(client),
+            Err(e) if is_transient(&e) && Instant::now() + delay < deadline => {
+                debug!("PostgreSQL is not ready, retrying in {:?}: {}", delay, e);
+                thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(2));
+            }
+            Err(e) => {
+                return Err(BucketError::DatabaseError(format!(
+                    "Failed to connect to PostgreSQL: {}",
+                    e
+                )))
+            }
+        }
+    }
+}
+
+/// Whether connecting failed because the server is not up yet, rather than because of
+/// a wrong password or database name
+fn is_transient(e: &postgres::Error) -> bool {
+    match e.as_db_error() {
+        Some(db_error) => db_error.code() == &SqlState::CANNOT_CONNECT_NOW,
+        None => e
+            .source()
+            .is_some_and(|source| source.is::<std::io::Error>()),
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn database_error(e: postgres::Error) -> BucketError {
+    BucketError::DatabaseError(e.to_string())
+}
+
+/// Rewrites the `?1` parameters of a statement to the `$1` PostgreSQL expects.
+fn placeholders(sql: &str) -> String {
+    let mut result = String::with_capacity(sql.len());
+    let mut in_string = false;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => in_string = !in_string,
+            '?' if !in_string && chars.peek().is_some_and(char::is_ascii_digit) => {
+                result.push('$');
+                continue;
+            }
+            _ => {}
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Converts a parameter to the type PostgreSQL inferred for it.
+fn to_sql(value: &Value, ty: &Type) -> Result<Box<dyn ToSql + Sync>, BucketError> {
+    let mismatch =
+        || BucketError::DatabaseError(format!("Cannot pass {:?} as a {} parameter", value, ty));
+    Ok(match (value, ty) {
+        (Value::Null, &Type::UUID) => Box::new(None::<Uuid>),
+        (Value::Null, &Type::INT8) => Box::new(None::<i64>),
+        (Value::Null, &Type::BOOL) => Box::new(None::<bool>),
+        (Value::Null, _) => Box::new(None::<String>),
+        (Value::Text(text), &Type::UUID) => {
+            Box::new(Uuid::parse_str(text).map_err(|_| mismatch())?)
+        }
+        (Value::Text(text), &Type::TIMESTAMP) => {
+            Box::new(parse_timestamp(text).ok_or_else(mismatch)?)
+        }
+        (Value::Timestamp(timestamp), &Type::TIMESTAMP) => Box::new(*timestamp),
+        (Value::Text(text), _) => Box::new(text.clone()),
+        (Value::Int(int), &Type::INT8) => Box::new(*int),
+        (Value::Int(int), &Type::INT4) => Box::new(i32::try_from(*int).map_err(|_| mismatch())?),
+        (Value::Int(int), &Type::INT2) => Box::new(i16::try_from(*int).map_err(|_| mismatch())?),
+        (Value::Bool(bool), &Type::BOOL) => Box::new(*bool),
+        _ => return Err(mismatch()),
+    })
+}
+
+/// Parses RFC 3339 timestamps and the timestamps other backends read from a `TIMESTAMP`
+/// column, such as `2024-05-01 12:30:00.123456`.
+fn parse_timestamp(text: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::DateTime::parse_from_rfc3339(text)
+        .map(|timestamp| timestamp.naive_utc())
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+}
+
+/// Reads a column as one of the values the statements of buckets use.
+fn from_sql(row: &postgres::Row, index: usize) -> Result<Value, BucketError> {
+    let ty = row.columns()[index].type_();
+    let value = match *ty {
+        Type::BOOL => row
+            .try_get::<_, Option<bool>>(index)
+            .map(|v| v.map(Value::Bool)),
+        Type::INT2 => row
+            .try_get::<_, Option<i16>>(index)
+            .map(|v| v.map(|v| Value::Int(v.into()))),
+        Type::INT4 => row
+            .try_get::<_, Option<i32>>(index)
+            .map(|v| v.map(|v| Value::Int(v.into()))),
+        Type::INT8 => row
+            .try_get::<_, Option<i64>>(index)
+            .map(|v| v.map(Value::Int)),
+        Type::UUID => row
+            .try_get::<_, Option<Uuid>>(index)
+            .map(|v| v.map(|v| Value::Text(v.to_string()))),
+        Type::TIMESTAMP => row
+            .try_get::<_, Option<chrono::NaiveDateTime>>(index)
+            .map(|v| v.map(|v| Value::Text(v.to_string()))),
+        _ => row
+            .try_get::<_, Option<String>>(index)
+            .map(|v| v.map(Value::Text)),
+    };
+    Ok(value.map_err(database_error)?.unwrap_or(Value::Null))
+}
+
+impl PostgresStore {
+    /// Prepares a statement and converts its parameters to the types it expects.
+    fn prepare(
+        &self,
+        sql: &str,
+        params: &[Value],
+    ) -> Result<(postgres::Statement, Vec<Box<dyn ToSql + Sync>>), BucketError> {
+        let statement = self
+            .client
+            .borrow_mut()
+            .prepare(&placeholders(sql))
+            .map_err(database_error)?;
+        let params = params
+            .iter()
+            .zip(statement.params())
+            .map(|(value, ty)| to_sql(value, ty))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((statement, params))
+    }
+}
+
+impl MetadataStore for PostgresStore {
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::PostgreSQL
+    }
+
+    fn execute(&self, sql: &str, params: &[Value]) -> Result<usize, BucketError> {
+        let (statement, params) = self.prepare(sql, params)?;
+        let params = params
+            .iter()
+            .map(|param| param.as_ref())
+            .collect::<Vec<_>>();
+        let count = self
+            .client
+            .borrow_mut()
+            .execute(&statement, &params)
+            .map_err(database_error)?;
+        Ok(count as usize)
+    }
+
+    fn query(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>, BucketError> {
+        let (statement, params) = self.prepare(sql, params)?;
+        let params = params
+            .iter()
+            .map(|param| param.as_ref())
+            .collect::<Vec<_>>();
+        let rows = self
+            .client
+            .borrow_mut()
+            .query(&statement, &params)
+            .map_err(database_error)?;
+        rows.iter()
+            .map(|row| {
+                (0..row.len())
+                    .map(|index| from_sql(row, index))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Row::new)
+            })
+            .collect()
+    }
+
+    fn execute_batch(&self, sql: &str) -> Result<(), BucketError> {
+        self.client
+            .borrow_mut()
+            .batch_execute(sql)
+            .map_err(database_error)
+    }
+
+    fn close(self: Box<Self>) -> Result<(), BucketError> {
+        // The connection goes back to its pool, for the next store this process opens
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            placeholders("SELECT id FROM commits WHERE id LIKE ?1 || '%' AND bucket_id = ?2"),
+            "SELECT id FROM commits WHERE id LIKE $1 || '%' AND bucket_id = $2"
+        );
+        assert_eq!(
+            placeholders("SELECT '?1' WHERE x = ?1"),
+            "SELECT '?1' WHERE x = $1"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2024, 5, 1)
+            .and_then(|date| date.and_hms_micro_opt(12, 30, 0, 123456));
+        assert_eq!(
+            parse_timestamp("2024-05-01T12:30:00.123456+00:00"),
+            expected
+        );
+        assert_eq!(parse_timestamp("2024-05-01 12:30:00.123456"), expected);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(quote_identifier("buckets_assets"), "\"buckets_assets\"");
+        assert_eq!(quote_identifier("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn test_connect_when_ready_gives_up() {
+        // Nothing listens on port 1, connecting is retried until the timeout passes
+        let mut config = postgres::Config::new();
+        config.host("127.0.0.1").port(1).user("buckets");
+        let start = Instant::now();
+        assert!(connect_when_ready(&config, Duration::from_millis(500)).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+}
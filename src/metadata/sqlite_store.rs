@@ -0,0 +1,324 @@
+use crate::database::DatabaseType;
+use crate::errors::BucketError;
+use crate::metadata::{MetadataStore, Row, Value};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{params_from_iter, Connection, ToSql};
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How long a statement waits for another process writing to the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Metadata in the `.buckets/buckets.sqlite` SQLite file of a repository. The database is
+/// in WAL mode, so commands can read while another one writes.
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self, BucketError> {
+        let connection = Connection::open(path).map_err(database_error)?;
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .map_err(database_error)?;
+        let journal_mode = connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .map_err(database_error)?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            return Err(BucketError::DatabaseError(format!(
+                "Failed to put the database in WAL mode, it is in {} mode",
+                journal_mode
+            )));
+        }
+        // Fully synchronous is only needed without WAL, and SQLite leaves foreign keys
+        // unchecked unless asked to
+        connection
+            .execute_batch("PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON;")
+            .map_err(database_error)?;
+        // Built into DuckDB and PostgreSQL
+        connection
+            .create_scalar_function("gen_random_uuid", 0, FunctionFlags::SQLITE_UTF8, |_| {
+                Ok(Uuid::new_v4().to_string())
+            })
+            .map_err(database_error)?;
+        Ok(Self { connection })
+    }
+}
+
+fn database_error(e: rusqlite::Error) -> BucketError {
+    BucketError::DatabaseError(e.to_string())
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Null => ToSqlOutput::Owned(rusqlite::types::Value::Null),
+            Value::Bool(value) => {
+                ToSqlOutput::Owned(rusqlite::types::Value::Integer(*value as i64))
+            }
+            Value::Int(value) => ToSqlOutput::Owned(rusqlite::types::Value::Integer(*value)),
+            Value::Text(value) => ToSqlOutput::Borrowed(ValueRef::Text(value.as_bytes())),
+            // SQLite has no timestamp type, so they are kept as text in the format of
+            // `CURRENT_TIMESTAMP`, which sorts with the timestamps it fills in
+            Value::Timestamp(value) => ToSqlOutput::Owned(rusqlite::types::Value::Text(
+                value.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+            )),
+        })
+    }
+}
+
+/// `CURRENT_TIMESTAMP` is in whole seconds in SQLite, too coarse to order the commits of a
+/// bucket, so the tables it creates default to the current time in milliseconds.
+fn precise_defaults(sql: &str) -> String {
+    sql.replace(
+        "DEFAULT CURRENT_TIMESTAMP",
+        "DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))",
+    )
+}
+
+/// Reads a column as one of the values the statements of buckets use. SQLite keeps
+/// booleans as integers, which are told apart by the type the column is declared with.
+fn from_sql(value: ValueRef, decl_type: Option<&str>, index: usize) -> Result<Value, BucketError> {
+    Ok(match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(value)
+            if decl_type.is_some_and(|ty| ty.eq_ignore_ascii_case("BOOLEAN")) =>
+        {
+            Value::Bool(value != 0)
+        }
+        ValueRef::Integer(value) => Value::Int(value),
+        ValueRef::Text(text) => Value::Text(String::from_utf8_lossy(text).to_string()),
+        ValueRef::Real(_) | ValueRef::Blob(_) => {
+            return Err(BucketError::DatabaseError(format!(
+                "Column {} holds a {} value",
+                index,
+                value.data_type()
+            )))
+        }
+    })
+}
+
+impl MetadataStore for SqliteStore {
+    fn database_type(&self) -> DatabaseType {
+        DatabaseType::SQLite
+    }
+
+    fn execute(&self, sql: &str, params: &[Value]) -> Result<usize, BucketError> {
+        self.connection
+            .execute(sql, params_from_iter(params))
+            .map_err(database_error)
+    }
+
+    fn query(&self, sql: &str, params: &[Value]) -> Result<Vec<Row>, BucketError> {
+        let mut statement = self.connection.prepare(sql).map_err(database_error)?;
+        let decl_types = statement
+            .columns()
+            .iter()
+            .map(|column| column.decl_type().map(str::to_string))
+            .collect::<Vec<_>>();
+        let mut rows = statement
+            .query(params_from_iter(params))
+            .map_err(database_error)?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next().map_err(database_error)? {
+            let values = decl_types
+                .iter()
+                .enumerate()
+                .map(|(index, decl_type)| {
+                    let value = row.get_ref(index).map_err(database_error)?;
+                    from_sql(value, decl_type.as_deref(), index)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            result.push(Row::new(values));
+        }
+        Ok(result)
+    }
+
+    fn execute_batch(&self, sql: &str) -> Result<(), BucketError> {
+        self.connection
+            .execute_batch(&precise_defaults(sql))
+            .map_err(database_error)
+    }
+
+    /// Takes the write lock when the transaction starts. A transaction that reads before
+    /// it writes would otherwise fail at its first write, without waiting, when another
+    /// command wrote in the meantime.
+    fn begin(&self) -> Result<(), BucketError> {
+        self.execute_batch("BEGIN IMMEDIATE")
+    }
+
+    fn close(self: Box<Self>) -> Result<(), BucketError> {
+        self.connection.close().map_err(|(_, e)| {
+            BucketError::DatabaseError(format!("Failed to close database connection: {}", e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_open_in_wal_mode() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("buckets.sqlite");
+        let store = SqliteStore::open(&path).expect("Failed to open database");
+        let connection: &dyn MetadataStore = &store;
+        let journal_mode: String = connection
+            .query_value("PRAGMA journal_mode", &[])
+            .expect("Failed to read journal mode");
+        assert_eq!(journal_mode, "wal");
+
+        // Another connection reads while the first one is writing
+        connection
+            .execute_batch("CREATE TABLE t (id UUID PRIMARY KEY, n BIGINT)")
+            .expect("Failed to create table");
+        connection
+            .execute_batch("BEGIN TRANSACTION; INSERT INTO t VALUES ('a', 1);")
+            .expect("Failed to insert");
+        let reader = SqliteStore::open(&path).expect("Failed to open database");
+        let reader: &dyn MetadataStore = &reader;
+        let count: i64 = reader
+            .query_value("SELECT COUNT(*) FROM t", &[])
+            .expect("Failed to count");
+        assert_eq!(count, 0);
+        connection
+            .execute_batch("COMMIT")
+            .expect("Failed to commit");
+        let count: i64 = reader
+            .query_value("SELECT COUNT(*) FROM t", &[])
+            .expect("Failed to count");
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_transaction_takes_write_lock() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("buckets.sqlite");
+        let store = SqliteStore::open(&path).expect("Failed to open database");
+        let connection: &dyn MetadataStore = &store;
+        connection
+            .execute_batch("CREATE TABLE t (id UUID PRIMARY KEY, n BIGINT)")
+            .expect("Failed to create table");
+
+        let other = Connection::open(&path).expect("Failed to open database");
+        connection
+            .transaction(|_| {
+                // Before the transaction has written anything
+                assert!(other.execute_batch("BEGIN IMMEDIATE").is_err());
+                Ok(())
+            })
+            .expect("Failed to run transaction");
+        other
+            .execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+            .expect("Failed to take write lock");
+    }
+
+    #[test]
+    fn test_values() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let store = SqliteStore::open(&temp_dir.path().join("buckets.sqlite"))
+            .expect("Failed to open database");
+        let connection: &dyn MetadataStore = &store;
+        connection
+            .execute_batch(
+                "CREATE TABLE t (
+                    id UUID PRIMARY KEY,
+                    name TEXT,
+                    done BOOLEAN NOT NULL DEFAULT FALSE,
+                    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+                )",
+            )
+            .expect("Failed to create table");
+
+        let id = Uuid::new_v4();
+        connection
+            .execute(
+                "INSERT INTO t (id, name, done) VALUES (?1, ?2, ?3)",
+                &[id.into(), None::<String>.into(), true.into()],
+            )
+            .expect("Failed to insert");
+        let row = connection
+            .query_row(
+                "SELECT id, name, done, created_at, COUNT(*) FROM t WHERE done",
+                &[],
+            )
+            .expect("Failed to query")
+            .expect("Row is missing");
+        assert_eq!(row.get::<Uuid>(0).expect("Failed to read id"), id);
+        assert_eq!(
+            row.get::<Option<String>>(1).expect("Failed to read name"),
+            None
+        );
+        assert!(row.get::<bool>(2).expect("Failed to read done"));
+        let created_at = row.get::<String>(3).expect("Failed to read created_at");
+        assert!(
+            chrono::NaiveDateTime::parse_from_str(&created_at, "%Y-%m-%d %H:%M:%S%.3f").is_ok()
+        );
+        assert_eq!(row.get::<i64>(4).expect("Failed to read count"), 1);
+    }
+
+    #[test]
+    fn test_timestamps_and_uuids() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let store = SqliteStore::open(&temp_dir.path().join("buckets.sqlite"))
+            .expect("Failed to open database");
+        let connection: &dyn MetadataStore = &store;
+        connection
+            .execute_batch("CREATE TABLE t (id UUID PRIMARY KEY, created_at TIMESTAMP)")
+            .expect("Failed to create table");
+        connection
+            .execute(
+                "INSERT INTO t (id, created_at) VALUES (gen_random_uuid(), ?1)",
+                &[
+                    chrono::DateTime::parse_from_rfc3339("2024-05-01T14:30:00.25+02:00")
+                        .expect("Failed to parse timestamp")
+                        .to_utc()
+                        .into(),
+                ],
+            )
+            .expect("Failed to insert");
+        let row = connection
+            .query_row("SELECT id, created_at FROM t", &[])
+            .expect("Failed to query")
+            .expect("Row is missing");
+        assert!(row.get::<Uuid>(0).is_ok());
+        assert_eq!(
+            row.get::<String>(1).expect("Failed to read created_at"),
+            "2024-05-01 12:30:00.250"
+        );
+
+        // Text that looks like a timestamp is kept as it is
+        connection
+            .execute(
+                "UPDATE t SET created_at = ?1",
+                &["2024-05-01T14:30:00Z".into()],
+            )
+            .expect("Failed to update");
+        let created_at: String = connection
+            .query_value("SELECT created_at FROM t", &[])
+            .expect("Failed to query");
+        assert_eq!(created_at, "2024-05-01T14:30:00Z");
+    }
+
+    #[test]
+    fn test_foreign_keys_are_checked() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let store = SqliteStore::open(&temp_dir.path().join("buckets.sqlite"))
+            .expect("Failed to open database");
+        let connection: &dyn MetadataStore = &store;
+        connection
+            .execute_batch(
+                "CREATE TABLE a (id UUID PRIMARY KEY);
+                 CREATE TABLE b (a_id UUID NOT NULL, FOREIGN KEY (a_id) REFERENCES a (id));",
+            )
+            .expect("Failed to create tables");
+        assert!(connection
+            .execute("INSERT INTO b (a_id) VALUES (?1)", &["missing".into()])
+            .is_err());
+    }
+}
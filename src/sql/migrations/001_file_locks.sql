@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS file_locks (
+    bucket_id UUID NOT NULL,
+    file_path TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (bucket_id, file_path),
+    FOREIGN KEY (bucket_id) REFERENCES buckets (id)
+);
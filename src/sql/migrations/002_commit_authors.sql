@@ -0,0 +1,2 @@
+ALTER TABLE commits ADD COLUMN author_name TEXT;
+ALTER TABLE commits ADD COLUMN author_email TEXT;
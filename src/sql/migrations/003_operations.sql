@@ -0,0 +1,14 @@
+-- DuckDB cannot add a column with a constraint, the default fills in existing commits
+ALTER TABLE commits ADD COLUMN abandoned BOOLEAN DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS operations (
+    id UUID PRIMARY KEY,
+    bucket_id UUID NOT NULL,
+    kind TEXT NOT NULL,
+    old_head UUID,
+    new_head UUID,
+    description TEXT NOT NULL,
+    author_name TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (bucket_id) REFERENCES buckets (id)
+);
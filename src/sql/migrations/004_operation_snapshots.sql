@@ -0,0 +1,9 @@
+ALTER TABLE operations ADD COLUMN undoes UUID;
+
+CREATE TABLE IF NOT EXISTS operation_files (
+    operation_id UUID NOT NULL,
+    file_path TEXT NOT NULL,
+    hash TEXT,
+    PRIMARY KEY (operation_id, file_path),
+    FOREIGN KEY (operation_id) REFERENCES operations (id)
+);
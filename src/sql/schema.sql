@@ -9,6 +9,9 @@ CREATE TABLE commits (
     id UUID PRIMARY KEY,
     bucket_id UUID NOT NULL,
     message TEXT NOT NULL,
+    author_name TEXT,
+    author_email TEXT,
+    abandoned BOOLEAN NOT NULL DEFAULT FALSE,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     FOREIGN KEY (bucket_id) REFERENCES buckets (id)
 );
@@ -20,4 +23,33 @@ CREATE TABLE files (
     hash TEXT NOT NULL,
     FOREIGN KEY (commit_id) REFERENCES commits (id),
     UNIQUE (commit_id, file_path, hash)
-); 
\ No newline at end of file
+); 
+CREATE TABLE file_locks (
+    bucket_id UUID NOT NULL,
+    file_path TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (bucket_id, file_path),
+    FOREIGN KEY (bucket_id) REFERENCES buckets (id)
+);
+
+CREATE TABLE operations (
+    id UUID PRIMARY KEY,
+    bucket_id UUID NOT NULL,
+    kind TEXT NOT NULL,
+    old_head UUID,
+    new_head UUID,
+    description TEXT NOT NULL,
+    author_name TEXT,
+    undoes UUID,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (bucket_id) REFERENCES buckets (id)
+);
+
+CREATE TABLE operation_files (
+    operation_id UUID NOT NULL,
+    file_path TEXT NOT NULL,
+    hash TEXT,
+    PRIMARY KEY (operation_id, file_path),
+    FOREIGN KEY (operation_id) REFERENCES operations (id)
+);
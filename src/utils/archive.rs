@@ -0,0 +1,390 @@
+use crate::errors::BucketError;
+use crate::utils::compression::{open_object, HashingWriter};
+use blake3::Hash;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Name of the manifest written as the last entry of every archive
+pub const MANIFEST_NAME: &str = "buckets-manifest.toml";
+
+const TAR_BLOCK: u64 = 512;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Magic number, frame header descriptor, content size and block header
+const RAW_FRAME_HEADER_LEN: usize = 12;
+/// Largest block of a zstd frame
+const MAX_RAW_BLOCK: usize = 128 * 1024;
+
+/// Archive formats, chosen by the extension of the output file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    /// A tar stream compressed with zstd, `.tar.zst` or `.tzst`
+    TarZstd,
+    /// A zip file with deflate compression, `.zip`
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Result<Self, BucketError> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Ok(ArchiveFormat::TarZstd)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            Err(BucketError::InvalidData(format!(
+                "unsupported archive format {}, use .tar.zst or .zip",
+                path.display()
+            )))
+        }
+    }
+}
+
+/// Describes the content of an archive and where it came from
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub bucket: String,
+    /// Id of the commit the files come from
+    pub version: String,
+    pub message: String,
+    pub author: Option<String>,
+    pub committed_at: String,
+    pub archived_by: String,
+    pub archived_at: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestFile {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+}
+
+/// A file of the archived commit and the stored object holding its content
+pub struct ArchiveEntry {
+    pub path: String,
+    pub hash: Hash,
+    pub object_path: std::path::PathBuf,
+}
+
+/// Writes the content of `entries` and the manifest to an archive at `output`.
+///
+/// Content is streamed from the stored objects into the archive, so nothing is
+/// checked out to disk. Every object is hashed while it is written and checked
+/// against its hash, and a failed archive is removed again. The manifest is the
+/// last entry, once the size of every file is known.
+pub fn write_archive(
+    output: &Path,
+    format: ArchiveFormat,
+    mut manifest: Manifest,
+    entries: &[ArchiveEntry],
+) -> Result<(), BucketError> {
+    let result = match format {
+        ArchiveFormat::TarZstd => write_tar_zstd(output, &mut manifest, entries),
+        ArchiveFormat::Zip => write_zip(output, &mut manifest, entries),
+    };
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result
+}
+
+/// Adds an archived file to the manifest once its content matches the hash it was
+/// committed with.
+fn add_to_manifest(
+    manifest: &mut Manifest,
+    entry: &ArchiveEntry,
+    size: u64,
+    hash: Hash,
+) -> io::Result<()> {
+    if hash != entry.hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Hash mismatch reading {}: expected {}, found {}",
+                entry.object_path.display(),
+                entry.hash,
+                hash
+            ),
+        ));
+    }
+    manifest.files.push(ManifestFile {
+        path: entry.path.clone(),
+        hash: entry.hash.to_string(),
+        size,
+    });
+    Ok(())
+}
+
+fn manifest_content(manifest: &Manifest) -> Result<String, BucketError> {
+    toml::to_string(manifest)
+        .map_err(|e| BucketError::InvalidData(format!("invalid manifest: {}", e)))
+}
+
+fn write_tar_zstd(
+    output: &Path,
+    manifest: &mut Manifest,
+    entries: &[ArchiveEntry],
+) -> Result<(), BucketError> {
+    let mut file = BufWriter::new(File::create(output)?);
+    for entry in entries {
+        let (size, hash) = append_tar_entry(
+            &mut file,
+            &entry.path,
+            &mut open_object(&entry.object_path)?,
+        )?;
+        add_to_manifest(manifest, entry, size, hash)?;
+    }
+    let manifest_content = manifest_content(manifest)?;
+    append_tar_entry(&mut file, MANIFEST_NAME, &mut manifest_content.as_bytes())?;
+
+    // The end of the archive
+    let mut encoder = zstd::stream::Encoder::new(&mut file, 0)?;
+    encoder.write_all(&[0; 2 * TAR_BLOCK as usize])?;
+    encoder.finish()?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Appends a file to a tar stream compressed with zstd and returns the size and hash of
+/// its content. Tar needs the size before the content, so the header is written in an
+/// uncompressed zstd frame of its own and filled in once the content is written in a
+/// compressed frame after it. Zstd decodes consecutive frames as one stream.
+fn append_tar_entry(
+    output: &mut BufWriter<File>,
+    path: &str,
+    content: &mut dyn Read,
+) -> io::Result<(u64, Hash)> {
+    // Paths too long for the header get a GNU long name entry before it
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append_data(&mut tar_header(0), path, io::empty())?;
+    let headers = builder.get_ref().clone();
+    let header_offset =
+        output.stream_position()? + RAW_FRAME_HEADER_LEN as u64 + headers.len() as u64 - TAR_BLOCK;
+    output.write_all(&raw_frame_header(headers.len())?)?;
+    output.write_all(&headers)?;
+
+    let mut encoder = zstd::stream::Encoder::new(&mut *output, 0)?;
+    let mut writer = HashingWriter::new(&mut encoder);
+    let size = io::copy(content, &mut writer)?;
+    let hash = writer.finish()?;
+    // Content fills whole blocks
+    let padding = (TAR_BLOCK - size % TAR_BLOCK) % TAR_BLOCK;
+    io::copy(&mut io::repeat(0).take(padding), &mut encoder)?;
+    encoder.finish()?;
+
+    let mut header =
+        tar::Header::from_byte_slice(&headers[headers.len() - TAR_BLOCK as usize..]).clone();
+    header.set_size(size);
+    header.set_cksum();
+    let end = output.stream_position()?;
+    output.seek(SeekFrom::Start(header_offset))?;
+    output.write_all(header.as_bytes())?;
+    output.seek(SeekFrom::Start(end))?;
+    Ok((size, hash))
+}
+
+/// The start of a zstd frame holding `len` bytes as they are, in a single raw block.
+fn raw_frame_header(len: usize) -> io::Result<[u8; RAW_FRAME_HEADER_LEN]> {
+    if len > MAX_RAW_BLOCK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes of tar headers do not fit in a zstd block", len),
+        ));
+    }
+    let len = len as u32;
+    let mut header = [0; RAW_FRAME_HEADER_LEN];
+    header[..4].copy_from_slice(&ZSTD_MAGIC);
+    // A single segment, with the content size in 4 bytes
+    header[4] = 0xa0;
+    header[5..9].copy_from_slice(&len.to_le_bytes());
+    // The last block of the frame, of the raw type
+    header[9..].copy_from_slice(&((len << 3) | 1).to_le_bytes()[..3]);
+    Ok(header)
+}
+
+fn tar_header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header
+}
+
+fn write_zip(
+    output: &Path,
+    manifest: &mut Manifest,
+    entries: &[ArchiveEntry],
+) -> Result<(), BucketError> {
+    let mut writer = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(true);
+
+    for entry in entries {
+        writer
+            .start_file(entry.path.as_str(), options)
+            .map_err(zip_error)?;
+        let mut hashing = HashingWriter::new(&mut writer);
+        let size = io::copy(&mut open_object(&entry.object_path)?, &mut hashing)?;
+        add_to_manifest(manifest, entry, size, hashing.finish()?)?;
+    }
+
+    writer
+        .start_file(MANIFEST_NAME, options)
+        .map_err(zip_error)?;
+    writer.write_all(manifest_content(manifest)?.as_bytes())?;
+    writer.finish().map_err(zip_error)?.flush()?;
+    Ok(())
+}
+
+fn zip_error(error: zip::result::ZipError) -> BucketError {
+    BucketError::IoError(io::Error::other(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::compress_and_store_file;
+    use std::fs;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    fn manifest() -> Manifest {
+        Manifest {
+            bucket: "art".to_string(),
+            version: "1234".to_string(),
+            message: "first version".to_string(),
+            author: Some("alice".to_string()),
+            committed_at: "2025-01-01 00:00:00".to_string(),
+            archived_by: "bob".to_string(),
+            archived_at: "2025-01-02T00:00:00Z".to_string(),
+            files: Vec::new(),
+        }
+    }
+
+    fn store(dir: &Path, content: &[u8]) -> (Hash, std::path::PathBuf) {
+        let source = dir.join("source");
+        fs::write(&source, content).expect("Failed to write file");
+        let hash = blake3::hash(content);
+        let object_path = dir.join(hash.to_string());
+        compress_and_store_file(&source, &object_path, 0).expect("Failed to store object");
+        (hash, object_path)
+    }
+
+    #[test]
+    fn test_archive_format_from_path() {
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("out.tar.zst")).expect("tar.zst"),
+            ArchiveFormat::TarZstd
+        );
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("OUT.ZIP")).expect("zip"),
+            ArchiveFormat::Zip
+        );
+        assert!(ArchiveFormat::from_path(Path::new("out.rar")).is_err());
+    }
+
+    #[test]
+    fn test_write_tar_zstd() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let long_path = format!("{}/model.blend", "nested".repeat(30));
+        // Content of a whole block needs no padding
+        let contents = [b"model".to_vec(), vec![7; 512], vec![3; 70_000]];
+        let mut entries = Vec::new();
+        for (path, content) in ["models/model.blend", "block.bin", &long_path]
+            .into_iter()
+            .zip(&contents)
+        {
+            let (hash, object_path) = store(dir.path(), content);
+            entries.push(ArchiveEntry {
+                path: path.to_string(),
+                hash,
+                object_path,
+            });
+        }
+        let output = dir.path().join("out.tar.zst");
+
+        write_archive(&output, ArchiveFormat::TarZstd, manifest(), &entries)
+            .expect("Failed to write archive");
+
+        let decoder = zstd::stream::Decoder::new(File::open(&output).expect("Failed to open"))
+            .expect("Failed to create decoder");
+        let mut archive = tar::Archive::new(decoder);
+        let mut files = Vec::new();
+        for entry in archive.entries().expect("Failed to read entries") {
+            let mut entry = entry.expect("Failed to read entry");
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .expect("Failed to read content");
+            files.push((entry.path().expect("path").display().to_string(), content));
+        }
+
+        assert_eq!(files.len(), 4);
+        for ((path, content), entry) in files.iter().zip(&entries) {
+            assert_eq!(path, &entry.path);
+            assert_eq!(blake3::hash(content), entry.hash);
+        }
+        let manifest = String::from_utf8(files[3].1.clone()).expect("Manifest is not text");
+        assert_eq!(files[3].0, MANIFEST_NAME);
+        assert!(manifest.contains("version = \"1234\""));
+        assert!(manifest.contains(&entries[0].hash.to_string()));
+        assert!(manifest.contains("size = 70000"));
+    }
+
+    #[test]
+    fn test_write_zip() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let (hash, object_path) = store(dir.path(), b"texture");
+        let output = dir.path().join("out.zip");
+        let entries = vec![ArchiveEntry {
+            path: "wood.png".to_string(),
+            hash,
+            object_path,
+        }];
+
+        write_archive(&output, ArchiveFormat::Zip, manifest(), &entries)
+            .expect("Failed to write archive");
+
+        let mut archive =
+            zip::ZipArchive::new(File::open(&output).expect("Failed to open")).expect("zip");
+        let mut content = String::new();
+        archive
+            .by_name("wood.png")
+            .expect("Missing file")
+            .read_to_string(&mut content)
+            .expect("Failed to read content");
+        assert_eq!(content, "texture");
+        assert!(archive.by_name(MANIFEST_NAME).is_ok());
+    }
+
+    #[test]
+    fn test_corrupt_object_removes_archive() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let (_hash, object_path) = store(dir.path(), b"model");
+        let entries = vec![ArchiveEntry {
+            path: "model.blend".to_string(),
+            hash: blake3::hash(b"other"),
+            object_path,
+        }];
+
+        for (name, format) in [
+            ("out.zip", ArchiveFormat::Zip),
+            ("out.tar.zst", ArchiveFormat::TarZstd),
+        ] {
+            let output = dir.path().join(name);
+            let error = write_archive(&output, format, manifest(), &entries)
+                .expect_err("the hash should not match");
+            assert!(error.to_string().contains("Hash mismatch"));
+            assert!(!output.exists());
+        }
+    }
+}
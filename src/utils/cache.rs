@@ -0,0 +1,258 @@
+use crate::utils::compression::delta_base;
+use crate::utils::config::RepositoryConfig;
+use crate::utils::object_store::{
+    fetch_object, object_files, remote_store, LocalStore, ObjectStore,
+};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Objects fetched from the remote store by `prefetch_objects`
+#[derive(Debug, Default, PartialEq)]
+pub struct Prefetched {
+    /// Number of objects that were fetched
+    pub fetched: usize,
+    /// Bytes of the fetched objects, as stored
+    pub size: u64,
+    /// Number of objects that were in the storage directory already
+    pub cached: usize,
+}
+
+/// Objects removed from the storage directory by `trim_cache`
+#[derive(Debug, Default, PartialEq)]
+pub struct TrimmedCache {
+    /// Objects that were removed, they are fetched again when they are needed
+    pub removed: Vec<String>,
+    /// Bytes of the cached objects outside the current tree that were kept
+    pub kept_size: u64,
+}
+
+/// Makes sure the objects `hashes`, and the objects their deltas are restored from, are in
+/// the storage directory of a bucket, fetching the missing ones from the remote store.
+pub fn prefetch_objects(storage_path: &Path, hashes: &[String]) -> io::Result<Prefetched> {
+    let store = remote_store(storage_path)?;
+    fetch_missing(storage_path, store.as_deref(), hashes)
+}
+
+fn fetch_missing(
+    storage_path: &Path,
+    store: Option<&dyn ObjectStore>,
+    hashes: &[String],
+) -> io::Result<Prefetched> {
+    let local = LocalStore::new(storage_path);
+    let mut prefetched = Prefetched::default();
+    let mut seen = HashSet::new();
+
+    for hash in hashes {
+        let mut next = Some(hash.clone());
+        while let Some(hash) = next.take() {
+            if !seen.insert(hash.clone()) {
+                break;
+            }
+            if local.exists(&hash)? {
+                prefetched.cached += 1;
+            } else {
+                let store = store.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("object {} is not stored", hash),
+                    )
+                })?;
+                prefetched.size += fetch_object(store, storage_path, &hash)?;
+                prefetched.fetched += 1;
+            }
+            next = delta_base(&storage_path.join(&hash))?.map(|base| base.to_hex().to_string());
+        }
+    }
+    Ok(prefetched)
+}
+
+/// Removes fetched and committed objects from the storage directory of a bucket with a
+/// remote store. The objects of `current_tree` and the objects their deltas are restored
+/// from stay. Of the others the most recently fetched are kept up to the `max_size` of
+/// the `[cache]` table of the repository config.
+///
+/// Only objects the remote store holds are removed, objects stored before the repository
+/// used a remote store stay where they are.
+pub fn trim_cache(storage_path: &Path, current_tree: &HashSet<String>) -> io::Result<TrimmedCache> {
+    let Some(store) = remote_store(storage_path)? else {
+        return Ok(TrimmedCache::default());
+    };
+    let max_size = RepositoryConfig::from_file(storage_path.to_path_buf())?
+        .cache
+        .max_size;
+    let keep = with_delta_bases(storage_path, current_tree)?;
+    trim_objects(storage_path, store.as_ref(), &keep, max_size)
+}
+
+/// `hashes` and the objects the deltas among them are restored from, as far as those are
+/// in the storage directory.
+fn with_delta_bases(storage_path: &Path, hashes: &HashSet<String>) -> io::Result<HashSet<String>> {
+    let local = LocalStore::new(storage_path);
+    let mut objects = HashSet::new();
+    for hash in hashes {
+        let mut next = Some(hash.clone());
+        while let Some(hash) = next.take() {
+            if !objects.insert(hash.clone()) || !local.exists(&hash)? {
+                break;
+            }
+            next = delta_base(&storage_path.join(&hash))?.map(|base| base.to_hex().to_string());
+        }
+    }
+    Ok(objects)
+}
+
+fn trim_objects(
+    storage_path: &Path,
+    store: &dyn ObjectStore,
+    keep: &HashSet<String>,
+    max_size: u64,
+) -> io::Result<TrimmedCache> {
+    let stored = store.list()?.into_iter().collect::<HashSet<_>>();
+    let mut cached = Vec::new();
+    for hash in object_files(storage_path)? {
+        if keep.contains(&hash) || !stored.contains(&hash) {
+            continue;
+        }
+        let metadata = fs::metadata(storage_path.join(&hash))?;
+        cached.push((metadata.modified()?, metadata.len(), hash));
+    }
+
+    // Newest first, everything older than the first object that does not fit goes
+    cached.sort_by(|a, b| b.cmp(a));
+    let mut trimmed = TrimmedCache::default();
+    let mut full = false;
+    for (_, size, hash) in cached {
+        if !full && trimmed.kept_size + size <= max_size {
+            trimmed.kept_size += size;
+            continue;
+        }
+        full = true;
+        fs::remove_file(storage_path.join(&hash))?;
+        trimmed.removed.push(hash);
+    }
+    trimmed.removed.sort();
+    Ok(trimmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::{store_object, Codec};
+    use crate::utils::object_store::DirectoryStore;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    /// Stores `content` in `storage_path` and in the store, fetched `age` seconds ago
+    fn cached_object(
+        storage_path: &Path,
+        store: &DirectoryStore,
+        content: &str,
+        codec: Codec,
+        age: u64,
+    ) -> String {
+        let source = storage_path.with_file_name("source.obj");
+        fs::write(&source, content).expect("Failed to write file");
+        let hash = blake3::hash(content.as_bytes()).to_hex().to_string();
+        let object_path = storage_path.join(&hash);
+        store_object(&source, &object_path, codec).expect("Failed to store object");
+        store
+            .put(&hash, &object_path)
+            .expect("Failed to put object");
+        File::options()
+            .write(true)
+            .open(&object_path)
+            .expect("Failed to open object")
+            .set_modified(SystemTime::now() - Duration::from_secs(age))
+            .expect("Failed to set modification time");
+        hash
+    }
+
+    #[test]
+    fn test_trim_objects() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = dir.path().join("storage");
+        fs::create_dir_all(&storage_path).expect("Failed to create storage");
+        let store = DirectoryStore::new(&dir.path().join("remote"));
+
+        let current = cached_object(&storage_path, &store, "current", Codec::Raw, 300);
+        let old = cached_object(&storage_path, &store, "old", Codec::Raw, 200);
+        let older = cached_object(&storage_path, &store, "older", Codec::Raw, 250);
+        let recent = cached_object(&storage_path, &store, "recent", Codec::Raw, 100);
+        // Objects the remote store does not have are never removed
+        let local_only = storage_path.join("local");
+        fs::write(&local_only, b"local").expect("Failed to write object");
+
+        let recent_size = fs::metadata(storage_path.join(&recent))
+            .expect("Failed to read metadata")
+            .len();
+        let keep = HashSet::from([current.clone()]);
+        let trimmed =
+            trim_objects(&storage_path, &store, &keep, recent_size).expect("Failed to trim");
+
+        let mut removed = vec![old.clone(), older.clone()];
+        removed.sort();
+        assert_eq!(trimmed.removed, removed);
+        assert_eq!(trimmed.kept_size, recent_size);
+        assert!(storage_path.join(&current).is_file());
+        assert!(storage_path.join(&recent).is_file());
+        assert!(!storage_path.join(&old).exists());
+        assert!(local_only.is_file());
+        assert!(store.exists(&old).expect("Failed to look up"));
+
+        let trimmed = trim_objects(&storage_path, &store, &keep, 0).expect("Failed to trim");
+        assert_eq!(trimmed.removed, vec![recent]);
+        assert!(storage_path.join(&current).is_file());
+    }
+
+    #[test]
+    fn test_delta_bases_are_kept_and_prefetched() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = dir.path().join("storage");
+        fs::create_dir_all(&storage_path).expect("Failed to create storage");
+        let store = DirectoryStore::new(&dir.path().join("remote"));
+
+        let model = "v 1.0 2.0 3.0\n".repeat(200);
+        let base = cached_object(&storage_path, &store, &model, Codec::Zstd(3), 100);
+        let changed = format!("{}v 4.0 5.0 6.0\n", model);
+        let delta = cached_object(
+            &storage_path,
+            &store,
+            &changed,
+            Codec::ZstdDelta {
+                level: 3,
+                base: blake3::hash(model.as_bytes()),
+                depth: 1,
+            },
+            100,
+        );
+
+        let keep = with_delta_bases(&storage_path, &HashSet::from([delta.clone()]))
+            .expect("Failed to follow deltas");
+        assert_eq!(keep, HashSet::from([delta.clone(), base.clone()]));
+
+        // Both objects are fetched back when only the delta is asked for
+        fs::remove_file(storage_path.join(&base)).expect("Failed to remove object");
+        fs::remove_file(storage_path.join(&delta)).expect("Failed to remove object");
+        assert!(fetch_missing(&storage_path, None, std::slice::from_ref(&delta)).is_err());
+        let prefetched = fetch_missing(&storage_path, Some(&store), std::slice::from_ref(&delta))
+            .expect("Failed to prefetch");
+        assert_eq!(prefetched.fetched, 2);
+        assert_eq!(prefetched.cached, 0);
+        assert!(storage_path.join(&base).is_file());
+        assert!(storage_path.join(&delta).is_file());
+
+        let prefetched =
+            fetch_missing(&storage_path, Some(&store), &[delta, base]).expect("Failed to prefetch");
+        assert_eq!(
+            prefetched,
+            Prefetched {
+                fetched: 0,
+                size: 0,
+                cached: 2,
+            }
+        );
+    }
+}
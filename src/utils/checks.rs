@@ -1,5 +1,8 @@
+use crate::database::{database_path, read_database_type, DatabaseType};
+use crate::utils::config::RepositoryConfig;
 use log::debug;
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 /// Searches for a directory with the given name in the parent directories.
@@ -32,7 +35,9 @@ pub fn find_directory_in_parents(start_path: &Path, target_dir_name: &str) -> Op
 }
 
 /// Checks if the given directory is a valid bucket repository.
-/// It verifies the presence of a `.buckets` directory and a valid `buckets.db` DuckDB database file.
+/// It verifies the presence of a `.buckets` directory and of the database of its database
+/// type, a valid `buckets.db` DuckDB database file, a PostgreSQL data directory or the URL
+/// of an external PostgreSQL server.
 pub fn is_valid_bucket_repo(dir_path: &Path) -> bool {
     debug!("{:?}", dir_path);
     // Find the .buckets directory
@@ -47,17 +52,44 @@ pub fn is_valid_bucket_repo(dir_path: &Path) -> bool {
                 return false;
             }
 
-            // Check if `buckets.db` exists
-            let db_path = path.join("buckets.db");
-            if !db_path.is_file() {
-                debug!("buckets.db file is missing");
-                return false;
-            }
-
-            // Validate the `buckets.db` file as a DuckDB database
-            if !is_valid_duckdb_database(&db_path) {
-                debug!("buckets.db is not a valid DuckDB database");
-                return false;
+            let db_type = match read_database_type(&path) {
+                Ok(db_type) => db_type,
+                Err(e) => {
+                    debug!("database_type is not valid: {}", e);
+                    return false;
+                }
+            };
+            let db_path = database_path(&path, db_type);
+            match db_type {
+                DatabaseType::DuckDB => {
+                    // Check if `buckets.db` exists
+                    if !db_path.is_file() {
+                        debug!("buckets.db file is missing");
+                        return false;
+                    }
+
+                    // Validate the `buckets.db` file as a DuckDB database
+                    if !is_valid_duckdb_database(&db_path) {
+                        debug!("buckets.db is not a valid DuckDB database");
+                        return false;
+                    }
+                }
+                DatabaseType::PostgreSQL => {
+                    // The embedded server is started when a command connects to it, an
+                    // external server is configured in the [database] table
+                    let external = RepositoryConfig::from_file(path.clone())
+                        .is_ok_and(|config| config.database.url.is_some());
+                    if !db_path.is_dir() && !external {
+                        debug!("postgres_data directory and database URL are missing");
+                        return false;
+                    }
+                }
+                DatabaseType::SQLite => {
+                    if !is_valid_sqlite_database(&db_path) {
+                        debug!("buckets.sqlite is missing or not a SQLite database");
+                        return false;
+                    }
+                }
             }
 
             true
@@ -87,6 +119,18 @@ fn is_valid_duckdb_database(db_path: &Path) -> bool {
     }
 }
 
+/// Checks the header of the given file, which opening it with SQLite would not do.
+fn is_valid_sqlite_database(db_path: &Path) -> bool {
+    let mut header = [0; 16];
+    match fs::File::open(db_path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => &header == b"SQLite format 3\0",
+        Err(e) => {
+            debug!("Error reading SQLite database: {}", e);
+            false
+        }
+    }
+}
+
 pub fn is_valid_bucket(path: &Path) -> bool {
     let bucket_path = find_bucket_path(path);
     match bucket_path {
@@ -268,6 +312,50 @@ mod tests {
         assert!(is_valid_bucket_repo(temp_dir.path()));
     }
 
+    #[test]
+    fn test_is_valid_bucket_repo_with_sqlite() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let buckets_dir = temp_dir.path().join(".buckets");
+        fs::create_dir_all(&buckets_dir).expect("Failed to create .buckets directory");
+        fs::File::create(buckets_dir.join("config")).expect("Failed to create config file");
+        fs::write(buckets_dir.join("database_type"), "sqlite")
+            .expect("Failed to write database type");
+        let db_path = buckets_dir.join("buckets.sqlite");
+        fs::write(&db_path, "not a database").expect("Failed to write database");
+        assert!(!is_valid_bucket_repo(temp_dir.path()));
+
+        fs::remove_file(&db_path).expect("Failed to remove database");
+        let conn = rusqlite::Connection::open(&db_path).expect("Failed to create database");
+        conn.execute("CREATE TABLE test (id INTEGER);", [])
+            .expect("error executing sql");
+        conn.close().expect("Failed to close SQLite connection");
+        assert!(is_valid_bucket_repo(temp_dir.path()));
+    }
+
+    #[test]
+    fn test_is_valid_bucket_repo_with_external_database() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let buckets_dir = temp_dir.path().join(".buckets");
+        fs::create_dir_all(&buckets_dir).expect("Failed to create .buckets directory");
+        fs::write(buckets_dir.join("database_type"), "postgresql")
+            .expect("Failed to write database type");
+        let config = r#"
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+"#;
+        fs::write(buckets_dir.join("config"), config).expect("Failed to create config file");
+        // Neither an embedded server nor a database URL
+        assert!(!is_valid_bucket_repo(temp_dir.path()));
+
+        let config = format!(
+            "{}\n[database]\nurl = \"postgres://db.studio.lan/assets\"\n",
+            config
+        );
+        fs::write(buckets_dir.join("config"), config).expect("Failed to create config file");
+        assert!(is_valid_bucket_repo(temp_dir.path()));
+    }
+
     #[test]
     fn test_invalid_bucket_repo() {
         let temp_dir = tempdir().expect("Failed to create temporary directory");
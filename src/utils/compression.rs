@@ -1,46 +1,863 @@
+use crate::utils::config::{CompressionConfig, RepositoryConfig};
+use crate::utils::dictionary::{file_extension, read_dictionary, DictionaryIndex};
+use crate::utils::encryption::{
+    current_key, object_master_key, random_nonce, DecryptingReader, EncryptingWriter, NONCE_LEN,
+};
+use crate::utils::object_store::{
+    fetch_object, object_location, remote_store, LocalStore, ObjectStore,
+};
+use crate::utils::utils::find_bucket_repo;
+use blake3::{Hash, Hasher};
+use globset::{GlobBuilder, GlobMatcher};
 use std::{
-    fs::File,
-    io::{self},
-    path::PathBuf,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Cursor, Read, Write},
+    path::Path,
 };
+use tempfile::{Builder, NamedTempFile};
+
+use zstd::stream::{copy_encode, Decoder, Encoder};
+
+/// Suffix of objects that are still being written to storage.
+pub const TEMP_OBJECT_SUFFIX: &str = ".tmp";
+
+/// Every object starts with a header naming the codec of its content. The header is a
+/// zstd skippable frame, so an object compressed with zstd is still a valid zstd stream.
+/// Objects written before the header existed are plain zstd streams.
+const HEADER_MAGIC: [u8; 4] = 0x184D_2A5Bu32.to_le_bytes();
+/// Length of the magic number and the frame size that start the header
+const FRAME_PREFIX_LEN: usize = 8;
+/// Length of the header of an object without a dictionary
+#[cfg(test)]
+const HEADER_LEN: usize = 12;
+const HEADER_TAG: &[u8; 2] = b"BK";
+const HEADER_VERSION: u8 = 1;
+/// Codec of an object encrypted with a key of the repository. The key id and nonce
+/// follow, then the codec of the encrypted content.
+const ENCRYPTED_CODEC: u8 = 4;
+/// Largest window used for delta objects, the default limit of zstd decoders
+const DELTA_WINDOW_LOG: u32 = 27;
+
+/// How the content of an object is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    /// The content as is, for files that do not get smaller when compressed
+    Raw,
+    /// Compressed with zstd at the given level, 0 selects the zstd default
+    Zstd(i32),
+    /// Compressed with zstd using a trained dictionary of the repository
+    ZstdDictionary { level: i32, id: u32 },
+    /// Compressed with zstd against the content of the object `base`, usually the previous
+    /// version of the file. `depth` counts the delta objects down to a full object.
+    ZstdDelta { level: i32, base: Hash, depth: u8 },
+}
+
+impl Codec {
+    fn id(&self) -> u8 {
+        match self {
+            Codec::Raw => 0,
+            Codec::Zstd(_) => 1,
+            Codec::ZstdDictionary { .. } => 2,
+            Codec::ZstdDelta { .. } => 3,
+        }
+    }
+
+    /// The codec id followed by what is needed to decode the content
+    fn fields(&self) -> Vec<u8> {
+        let mut fields = vec![self.id()];
+        match self {
+            Codec::ZstdDictionary { id, .. } => fields.extend_from_slice(&id.to_le_bytes()),
+            Codec::ZstdDelta { base, depth, .. } => {
+                fields.push(*depth);
+                fields.extend_from_slice(base.as_bytes());
+            }
+            Codec::Raw | Codec::Zstd(_) => {}
+        }
+        fields
+    }
+
+    fn header(&self) -> Vec<u8> {
+        object_header(&self.fields())
+    }
+}
+
+/// The header of an object whose content is stored with the codec `codec_fields`
+fn object_header(codec_fields: &[u8]) -> Vec<u8> {
+    let mut fields = HEADER_TAG.to_vec();
+    fields.push(HEADER_VERSION);
+    fields.extend_from_slice(codec_fields);
+
+    let mut header = HEADER_MAGIC.to_vec();
+    header.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    header.extend_from_slice(&fields);
+    header
+}
 
-use zstd::stream::{copy_decode, copy_encode};
+/// The header of an object encrypted with key `key_id`, whose decrypted content is
+/// stored with the codec `codec_fields`
+fn encrypted_header(key_id: u32, nonce: &[u8; NONCE_LEN], codec_fields: &[u8]) -> Vec<u8> {
+    let mut fields = vec![ENCRYPTED_CODEC];
+    fields.extend_from_slice(&key_id.to_le_bytes());
+    fields.extend_from_slice(nonce);
+    fields.extend_from_slice(codec_fields);
+    object_header(&fields)
+}
+
+/// Chooses the codec for each file from the `[compression]` table of the repository config
+#[derive(Debug)]
+pub struct CompressionPolicy {
+    level: i32,
+    store_below: u64,
+    rules: Vec<PolicyRule>,
+    /// Trained dictionaries by file extension
+    dictionaries: HashMap<String, u32>,
+    dictionary_max_size: u64,
+    delta_max_size: u64,
+    max_delta_chain: u8,
+}
+
+#[derive(Debug)]
+struct PolicyRule {
+    matcher: GlobMatcher,
+    /// Whether the pattern has a `/` and is matched against the whole path
+    match_path: bool,
+    codec: Codec,
+    /// Store new versions as a delta against the previous version
+    delta: bool,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+}
+
+impl CompressionPolicy {
+    pub fn from_config(config: &CompressionConfig) -> io::Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                let matcher = GlobBuilder::new(&rule.pattern)
+                    .case_insensitive(true)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("invalid compression pattern {}: {}", rule.pattern, e),
+                        )
+                    })?
+                    .compile_matcher();
+                let codec = if rule.store {
+                    Codec::Raw
+                } else {
+                    Codec::Zstd(rule.level.unwrap_or(config.level))
+                };
+                Ok(PolicyRule {
+                    matcher,
+                    match_path: rule.pattern.contains('/'),
+                    codec,
+                    delta: rule.delta,
+                    min_size: rule.min_size,
+                    max_size: rule.max_size,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            level: config.level,
+            store_below: config.store_below,
+            rules,
+            dictionaries: HashMap::new(),
+            dictionary_max_size: config.dictionary_max_size,
+            delta_max_size: config.delta_max_size,
+            max_delta_chain: config.max_delta_chain,
+        })
+    }
+
+    /// Reads the policy and the trained dictionaries of the repository that contains
+    /// `dir`. Without a repository config the default policy is used.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let mut policy = match RepositoryConfig::from_file(dir.to_path_buf()) {
+            Ok(config) => Self::from_config(&config.compression)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Self::from_config(&CompressionConfig::default())?
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(buckets_dir) = find_bucket_repo(dir) {
+            policy.dictionaries = DictionaryIndex::load(&buckets_dir)?.by_extension();
+        }
+        Ok(policy)
+    }
+
+    /// The codec for the file at bucket relative path `file` of `size` bytes.
+    ///
+    /// The first rule whose pattern and size limits match decides. Patterns without a `/`
+    /// match the file name in any directory. Small files compressed with zstd use the
+    /// dictionary trained for their extension, if there is one.
+    pub fn codec_for(&self, file: &str, size: u64) -> Codec {
+        if size < self.store_below {
+            return Codec::Raw;
+        }
+        let codec = self
+            .matching_rule(file, size)
+            .map(|rule| rule.codec)
+            .unwrap_or(Codec::Zstd(self.level));
+
+        match codec {
+            Codec::Zstd(level) if size <= self.dictionary_max_size => file_extension(file)
+                .and_then(|extension| self.dictionaries.get(&extension))
+                .map(|id| Codec::ZstdDictionary { level, id: *id })
+                .unwrap_or(codec),
+            _ => codec,
+        }
+    }
 
+    /// The first rule whose pattern and size limits match `file`.
+    fn matching_rule(&self, file: &str, size: u64) -> Option<&PolicyRule> {
+        let name = file.rsplit('/').next().unwrap_or(file);
+        self.rules.iter().find(|rule| {
+            let candidate = if rule.match_path { file } else { name };
+            rule.matcher.is_match(candidate)
+                && rule.min_size.is_none_or(|min| size >= min)
+                && rule.max_size.is_none_or(|max| size <= max)
+        })
+    }
+
+    /// The codec for a new version of `file` whose previous version is stored at
+    /// `previous`. Files of a rule with `delta` are stored as a delta against the previous
+    /// version, unless that would make the chain of deltas longer than `max_delta_chain`.
+    /// Both versions must be at most `delta_max_size` bytes, as reading a delta holds its
+    /// base and its content in memory.
+    pub fn codec_for_version(&self, file: &str, size: u64, previous: Option<&Path>) -> Codec {
+        let codec = self.codec_for(file, size);
+        let level = match codec {
+            Codec::Zstd(level) | Codec::ZstdDictionary { level, .. } => level,
+            _ => return codec,
+        };
+        let (Some(previous), Some(rule)) = (previous, self.matching_rule(file, size)) else {
+            return codec;
+        };
+        if !rule.delta || size > self.delta_max_size {
+            return codec;
+        }
+
+        match (base_hash(previous), delta_depth(previous)) {
+            (Some(base), Ok(depth))
+                if depth < self.max_delta_chain && content_fits(previous, self.delta_max_size) =>
+            {
+                Codec::ZstdDelta {
+                    level,
+                    base,
+                    depth: depth + 1,
+                }
+            }
+            _ => codec,
+        }
+    }
+
+    /// Stores the file at `input_path`, known in the bucket as `file`, with the codec
+    /// the policy chooses for it. `previous` is the object of the previous version of
+    /// the file, if there is one.
+    pub fn store(
+        &self,
+        input_path: &Path,
+        file: &str,
+        output_path: &Path,
+        previous: Option<&Hash>,
+    ) -> io::Result<()> {
+        let size = fs::metadata(input_path).map(|m| m.len()).unwrap_or(0);
+        let previous_path = previous.map(|hash| output_path.with_file_name(hash.to_hex().as_str()));
+        let codec = match self.codec_for_version(file, size, previous_path.as_deref()) {
+            Codec::ZstdDelta { .. } if !delta_suits(input_path) => self.codec_for(file, size),
+            codec => codec,
+        };
+        store_object(input_path, output_path, codec)
+    }
+}
+
+/// Whether a delta of the file at `path` against its previous version stays small. A
+/// few changed pixels change most of a run-length encoded TGA image, so only TGA images
+/// without compression, of image type 1 to 3, are stored as a delta.
+fn delta_suits(path: &Path) -> bool {
+    let is_tga = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("tga"));
+    if !is_tga {
+        return true;
+    }
+    let mut header = [0u8; 3];
+    match File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => matches!(header[2], 1..=3),
+        Err(_) => false,
+    }
+}
+
+/// Whether the content of the object at `object_path` is at most `max_size` bytes. No
+/// more than that is decompressed to find out.
+fn content_fits(object_path: &Path, max_size: u64) -> bool {
+    open_object(object_path)
+        .and_then(|object| {
+            io::copy(
+                &mut object.take(max_size.saturating_add(1)),
+                &mut io::sink(),
+            )
+        })
+        .is_ok_and(|size| size <= max_size)
+}
+
+/// The hash an object is named after
+fn base_hash(object_path: &Path) -> Option<Hash> {
+    let name = object_path.file_name()?.to_str()?;
+    Hash::from_hex(name).ok()
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self::from_config(&CompressionConfig::default()).unwrap_or(Self {
+            level: 0,
+            store_below: 0,
+            rules: Vec::new(),
+            dictionaries: HashMap::new(),
+            dictionary_max_size: 0,
+            delta_max_size: 0,
+            max_delta_chain: 0,
+        })
+    }
+}
+
+/// Compresses `input_path` into `output_path` with zstd at `compression_level`.
+#[cfg(test)]
 pub fn compress_and_store_file(
-    input_path: &PathBuf,
-    output_path: &PathBuf,
+    input_path: &Path,
+    output_path: &Path,
     compression_level: i32,
 ) -> io::Result<()> {
-    let input_file = File::open(input_path).map_err(|_e| {
+    store_object(input_path, output_path, Codec::Zstd(compression_level))
+}
+
+/// Stores `input_path` as an object at `output_path` using `codec`. In an encrypted
+/// repository the object is encrypted with the current key.
+pub fn store_object(input_path: &Path, output_path: &Path, codec: Codec) -> io::Result<()> {
+    let mut input_file = File::open(input_path).map_err(|_e| {
         io::Error::new(
             io::ErrorKind::NotFound,
             format!("Failed to open input file: {}", input_path.display()),
         )
     })?;
-    let mut output_file = File::create(output_path)?;
-
-    copy_encode(&input_file, &mut output_file, compression_level).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!(
+    let key = current_key(output_path)?;
+
+    write_object(output_path, |writer| {
+        let result = match key {
+            Some((key_id, key)) => {
+                let nonce = random_nonce();
+                writer.write_all(&encrypted_header(key_id, &nonce, &codec.fields()))?;
+                let mut encrypting = EncryptingWriter::new(writer, &key, output_path, &nonce);
+                encode(codec, &mut input_file, &mut encrypting, output_path)
+                    .and_then(|_| encrypting.finish().map(|_| ()))
+            }
+            None => {
+                writer.write_all(&codec.header())?;
+                encode(codec, &mut input_file, writer, output_path)
+            }
+        };
+        result.map_err(|e| {
+            io::Error::other(format!(
                 "Failed to compress file from {} to {}: {}",
                 input_path.display(),
                 output_path.display(),
                 e
-            ),
-        )
+            ))
+        })
+    })
+}
+
+/// Writes the content of `input` with `codec`.
+fn encode<W: Write>(
+    codec: Codec,
+    input: &mut File,
+    writer: &mut W,
+    output_path: &Path,
+) -> io::Result<()> {
+    match codec {
+        Codec::Raw => io::copy(input, writer).map(|_| ()),
+        Codec::Zstd(level) => copy_encode(&*input, writer, level),
+        Codec::ZstdDictionary { level, id } => {
+            let dictionary = read_dictionary(output_path, id)?;
+            let mut encoder = Encoder::with_dictionary(writer, level, &dictionary)?;
+            io::copy(input, &mut encoder)?;
+            encoder.finish().map(|_| ())
+        }
+        Codec::ZstdDelta { level, base, .. } => {
+            let base_content = object_content(&output_path.with_file_name(base.to_hex().as_str()))?;
+            let size = input.metadata()?.len();
+            let mut encoder = Encoder::with_ref_prefix(writer, level, &base_content)?;
+            encoder.window_log(delta_window_log(base_content.len() as u64 + size))?;
+            encoder.long_distance_matching(true)?;
+            io::copy(input, &mut encoder)?;
+            encoder.finish().map(|_| ())
+        }
+    }
+}
+
+/// Writes an object with `write` under a temporary name, flushes it to disk and then
+/// renames it, so `output_path` either does not exist or contains a complete object.
+pub fn write_object<F>(output_path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<&mut File>) -> io::Result<()>,
+{
+    let output_dir = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp_file = Builder::new()
+        .prefix(".")
+        .suffix(TEMP_OBJECT_SUFFIX)
+        .tempfile_in(output_dir)?;
+
+    let mut writer = BufWriter::new(temp_file.as_file_mut());
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(output_path).map_err(|e| e.error)?;
+    sync_directory(output_dir)?;
+
+    Ok(())
+}
+
+/// Rewrites the object at `object_path` as a loose object encrypted with the current key
+/// of its repository, keeping its codec. Returns false when the loose object is
+/// encrypted with that key already.
+pub fn reencrypt_object(object_path: &Path) -> io::Result<bool> {
+    let (key_id, key) = current_key(object_path)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "the repository is not encrypted")
     })?;
+    let is_loose = object_path.exists();
+    let mut input = open_stored(object_path)?;
+    let (codec_fields, mut content): (Vec<u8>, Box<dyn Read>) = match read_header(&mut input)? {
+        // Objects without a header are zstd streams
+        ObjectHeader::Missing(prefix) => (
+            Codec::Zstd(0).fields(),
+            Box::new(Cursor::new(prefix).chain(input)),
+        ),
+        ObjectHeader::Fields(fields) => {
+            let format = parse_header(&fields).ok_or_else(|| unknown_header(object_path))?;
+            match format.encryption {
+                Some((id, _)) if id == key_id && is_loose => return Ok(false),
+                Some((id, nonce)) => {
+                    let old_key = object_master_key(object_path, id)?;
+                    let decrypting = DecryptingReader::new(input, &old_key, object_path, &nonce);
+                    (format.codec.to_vec(), Box::new(decrypting))
+                }
+                None => (format.codec.to_vec(), input),
+            }
+        }
+    };
+
+    write_object(object_path, |writer| {
+        let nonce = random_nonce();
+        writer.write_all(&encrypted_header(key_id, &nonce, &codec_fields))?;
+        let mut encrypting = EncryptingWriter::new(writer, &key, object_path, &nonce);
+        io::copy(&mut content, &mut encrypting)?;
+        encrypting.finish().map(|_| ())
+    })?;
+    Ok(true)
+}
+
+/// Writes a repository file derived from committed content, such as a dictionary. In an
+/// encrypted repository it is stored like an object of codec raw, encrypted with the
+/// current key, otherwise `content` is written as is.
+pub fn write_protected_file(output_path: &Path, content: &[u8]) -> io::Result<()> {
+    let key = current_key(output_path)?;
+    write_object(output_path, |writer| match key {
+        Some((key_id, key)) => {
+            let nonce = random_nonce();
+            writer.write_all(&encrypted_header(key_id, &nonce, &Codec::Raw.fields()))?;
+            let mut encrypting = EncryptingWriter::new(writer, &key, output_path, &nonce);
+            encrypting.write_all(content)?;
+            encrypting.finish().map(|_| ())
+        }
+        None => writer.write_all(content),
+    })
+}
+
+/// Reads a file written by `write_protected_file`, decrypting it when it is encrypted.
+pub fn read_protected_file(input_path: &Path) -> io::Result<Vec<u8>> {
+    let mut input = File::open(input_path)?;
+    if let ObjectHeader::Missing(mut content) = read_header(&mut input)? {
+        input.read_to_end(&mut content)?;
+        return Ok(content);
+    }
+    let mut content = Vec::new();
+    read_object(Box::new(File::open(input_path)?), input_path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
 
+/// Flushes a directory entry to disk so a completed rename survives a crash.
+#[cfg(unix)]
+pub fn sync_directory(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+#[cfg(not(unix))]
+pub fn sync_directory(_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
-pub fn restore_file(input_path: &PathBuf, output_path: &PathBuf) -> io::Result<()> {
-    let input = File::open(input_path)?;
-    let output = File::create(output_path)?;
-    copy_decode(input, output)?;
+/// Decompresses a stored object to `output_path`.
+///
+/// The content is written to a temporary file next to the target and hashed while
+/// decompressing. Only when the hash matches `expected_hash` is the temporary file
+/// renamed over the target, so a damaged object never replaces a working file.
+pub fn restore_file(input_path: &Path, output_path: &Path, expected_hash: &Hash) -> io::Result<()> {
+    let input = open_stored(input_path)?;
+
+    let target_dir = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp_file = NamedTempFile::new_in(target_dir)?;
+
+    let mut writer = HashingWriter::new(BufWriter::new(temp_file.as_file_mut()));
+    io::copy(&mut read_object(input, input_path)?, &mut writer)?;
+    let hash = writer.finish()?;
+
+    if hash != *expected_hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Hash mismatch restoring {} from {}: expected {}, found {}",
+                output_path.display(),
+                input_path.display(),
+                expected_hash,
+                hash
+            ),
+        ));
+    }
+
+    // Keep the permissions of the file being replaced
+    if let Ok(metadata) = fs::metadata(output_path) {
+        temp_file
+            .as_file()
+            .set_permissions(metadata.permissions())?;
+    } else {
+        set_default_permissions(temp_file.as_file())?;
+    }
+
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(output_path).map_err(|e| e.error)?;
     Ok(())
 }
 
+#[cfg(unix)]
+fn set_default_permissions(file: &File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(0o644))
+}
+
+#[cfg(not(unix))]
+fn set_default_permissions(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Writer that hashes everything passing through it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new(),
+        }
+    }
+
+    /// Flushes the inner writer and returns the hash of the written data.
+    pub fn finish(mut self) -> io::Result<Hash> {
+        self.inner.flush()?;
+        Ok(self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decompresses a stored object and returns the BLAKE3 hash of its content
+/// without writing the decompressed data to disk.
+pub fn hash_compressed_file(input_path: &Path) -> io::Result<Hash> {
+    let mut hasher = Hasher::new();
+    io::copy(&mut open_object(input_path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// Opens a stored object to read its decompressed content as a stream.
+pub fn open_object(input_path: &Path) -> io::Result<Box<dyn Read>> {
+    read_object(open_stored(input_path)?, input_path)
+}
+
+/// Opens the stored bytes of the object at `input_path`. An object that is not in its
+/// loose file is looked up in the packs of its storage directory, and then fetched from
+/// the remote store of its repository into the storage directory.
+fn open_stored(input_path: &Path) -> io::Result<Box<dyn Read>> {
+    let (storage_path, hash) = object_location(input_path)?;
+    let local = LocalStore::new(storage_path);
+    match local.get(hash) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match remote_store(storage_path)? {
+            Some(store) => {
+                fetch_object(store.as_ref(), storage_path, hash)?;
+                local.get(hash)
+            }
+            None => Err(e),
+        },
+        result => result,
+    }
+}
+
+/// Whether the object at `object_path` is stored, as a loose file, in a pack or in the
+/// remote store of its repository.
+pub fn object_exists(object_path: &Path) -> io::Result<bool> {
+    let (storage_path, hash) = object_location(object_path)?;
+    if LocalStore::new(storage_path).exists(hash)? {
+        return Ok(true);
+    }
+    match remote_store(storage_path)? {
+        Some(store) => store.exists(hash),
+        None => Ok(false),
+    }
+}
+
+/// The header of an object as read from the start of its file
+enum ObjectHeader {
+    /// The object was written before headers existed, these are its first bytes
+    Missing(Vec<u8>),
+    /// The fields following the magic number and frame size
+    Fields(Vec<u8>),
+}
+
+fn read_header<R: Read>(input: &mut R) -> io::Result<ObjectHeader> {
+    let mut prefix = Vec::with_capacity(FRAME_PREFIX_LEN);
+    input
+        .by_ref()
+        .take(FRAME_PREFIX_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    if prefix.len() < FRAME_PREFIX_LEN || prefix[..4] != HEADER_MAGIC {
+        return Ok(ObjectHeader::Missing(prefix));
+    }
+
+    let mut length = [0u8; 4];
+    length.copy_from_slice(&prefix[4..8]);
+    let mut fields = Vec::new();
+    input
+        .by_ref()
+        .take(u32::from_le_bytes(length) as u64)
+        .read_to_end(&mut fields)?;
+    Ok(ObjectHeader::Fields(fields))
+}
+
+/// How an object is stored, as read from its header
+struct StoredFormat<'a> {
+    /// Key id and nonce of an encrypted object
+    encryption: Option<(u32, [u8; NONCE_LEN])>,
+    /// The codec id and its parameters
+    codec: &'a [u8],
+}
+
+fn parse_header(fields: &[u8]) -> Option<StoredFormat<'_>> {
+    match fields {
+        [b'B', b'K', HEADER_VERSION, ENCRYPTED_CODEC, rest @ ..] if rest.len() > 4 + NONCE_LEN => {
+            let key_id = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+            let mut nonce = [0u8; NONCE_LEN];
+            nonce.copy_from_slice(&rest[4..4 + NONCE_LEN]);
+            Some(StoredFormat {
+                encryption: Some((key_id, nonce)),
+                codec: &rest[4 + NONCE_LEN..],
+            })
+        }
+        [b'B', b'K', HEADER_VERSION, codec @ ..] if !codec.is_empty() => Some(StoredFormat {
+            encryption: None,
+            codec,
+        }),
+        _ => None,
+    }
+}
+
+fn unknown_header(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unknown object header in {}", path.display()),
+    )
+}
+
+/// Number of delta objects in the chain down to a full object, 0 for a full object.
+pub fn delta_depth(object_path: &Path) -> io::Result<u8> {
+    match read_header(&mut open_stored(object_path)?)? {
+        ObjectHeader::Fields(fields) => match parse_header(&fields).map(|format| format.codec) {
+            Some([3, depth, ..]) => Ok(*depth),
+            _ => Ok(0),
+        },
+        ObjectHeader::Missing(_) => Ok(0),
+    }
+}
+
+/// The object a delta object was compressed against, `None` for a full object.
+pub fn delta_base(object_path: &Path) -> io::Result<Option<Hash>> {
+    match read_header(&mut open_stored(object_path)?)? {
+        ObjectHeader::Fields(fields) => {
+            Ok(parse_header(&fields).and_then(|format| codec_delta_base(format.codec)))
+        }
+        ObjectHeader::Missing(_) => Ok(None),
+    }
+}
+
+/// Whether the object at `object_path` is encrypted
+#[cfg(test)]
+pub fn is_encrypted(object_path: &Path) -> io::Result<bool> {
+    Ok(encryption_key(&mut open_stored(object_path)?)?.is_some())
+}
+
+/// The id of the key the object read from `input` is encrypted with, `None` when it is
+/// not encrypted. Only the header is read.
+pub fn encryption_key<R: Read>(input: &mut R) -> io::Result<Option<u32>> {
+    match read_header(input)? {
+        ObjectHeader::Fields(fields) => Ok(parse_header(&fields)
+            .and_then(|format| format.encryption)
+            .map(|(id, _)| id)),
+        ObjectHeader::Missing(_) => Ok(None),
+    }
+}
+
+/// Window large enough for a delta to refer to any part of its base
+fn delta_window_log(total_size: u64) -> u32 {
+    let needed = u64::BITS - total_size.saturating_sub(1).leading_zeros();
+    needed.clamp(10, DELTA_WINDOW_LOG)
+}
+
+/// Reads the header of the object at `input_path` and returns a reader for its content.
+fn read_object(input: Box<dyn Read>, input_path: &Path) -> io::Result<Box<dyn Read>> {
+    let (codec_fields, input) = read_encoded(input, input_path)?;
+    decode(&codec_fields, input, input_path)
+}
+
+/// Reads the header of the object at `input_path` and returns its codec fields and a
+/// reader for its decrypted, still compressed content.
+fn read_encoded(
+    mut input: Box<dyn Read>,
+    input_path: &Path,
+) -> io::Result<(Vec<u8>, Box<dyn Read>)> {
+    let fields = match read_header(&mut input)? {
+        // Objects without a header are zstd streams
+        ObjectHeader::Missing(prefix) => {
+            return Ok((
+                Codec::Zstd(0).fields(),
+                Box::new(Cursor::new(prefix).chain(input)),
+            ))
+        }
+        ObjectHeader::Fields(fields) => fields,
+    };
+
+    let format = parse_header(&fields).ok_or_else(|| unknown_header(input_path))?;
+    let input: Box<dyn Read> = match format.encryption {
+        Some((key_id, nonce)) => {
+            let key = object_master_key(input_path, key_id)?;
+            Box::new(DecryptingReader::new(input, &key, input_path, &nonce))
+        }
+        None => input,
+    };
+    Ok((format.codec.to_vec(), input))
+}
+
+/// The whole content of the object at `object_path`. A delta is returned as it was
+/// decompressed, so a chain of deltas holds no more than one base and one delta at once.
+fn object_content(object_path: &Path) -> io::Result<Vec<u8>> {
+    let (codec_fields, input) = read_encoded(open_stored(object_path)?, object_path)?;
+    if let Some(base) = codec_delta_base(&codec_fields) {
+        return decode_delta(input, object_path, &base);
+    }
+    let mut content = Vec::new();
+    decode(&codec_fields, input, object_path)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Decompresses a delta in memory, as it refers back to all of its base.
+fn decode_delta(input: Box<dyn Read>, input_path: &Path, base: &Hash) -> io::Result<Vec<u8>> {
+    let base_content = object_content(&input_path.with_file_name(base.to_hex().as_str()))?;
+    let mut decoder = Decoder::with_ref_prefix(BufReader::new(input), &base_content)?;
+    decoder.window_log_max(DELTA_WINDOW_LOG)?;
+    let mut content = Vec::new();
+    decoder.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// The object a delta with the codec `codec_fields` refers to, `None` for other codecs.
+fn codec_delta_base(codec_fields: &[u8]) -> Option<Hash> {
+    match codec_fields {
+        [3, _depth, base @ ..] if base.len() == 32 => {
+            let mut base_hash = [0u8; 32];
+            base_hash.copy_from_slice(base);
+            Some(Hash::from(base_hash))
+        }
+        _ => None,
+    }
+}
+
+/// Returns a reader for the content of an object stored with the codec `codec_fields`.
+fn decode(
+    codec_fields: &[u8],
+    input: Box<dyn Read>,
+    input_path: &Path,
+) -> io::Result<Box<dyn Read>> {
+    match codec_fields {
+        [0] => Ok(Box::new(BufReader::new(input))),
+        [1] => Ok(Box::new(Decoder::new(input)?)),
+        [2, id @ ..] if id.len() == 4 => {
+            let id = u32::from_le_bytes([id[0], id[1], id[2], id[3]]);
+            let dictionary = read_dictionary(input_path, id)?;
+            Ok(Box::new(Decoder::with_dictionary(
+                BufReader::new(input),
+                &dictionary,
+            )?))
+        }
+        codec => match codec_delta_base(codec) {
+            Some(base) => Ok(Box::new(Cursor::new(decode_delta(
+                input, input_path, &base,
+            )?))),
+            None => Err(unknown_header(input_path)),
+        },
+    }
+}
+
+/// Decompresses a stored object without writing it anywhere, checks that its content
+/// matches `expected_hash` and returns the size of the content.
+pub fn verify_object(input_path: &Path, expected_hash: &Hash) -> io::Result<u64> {
+    let mut writer = HashingWriter::new(io::sink());
+    let size = io::copy(&mut open_object(input_path)?, &mut writer)?;
+    let hash = writer.finish()?;
+    if hash != *expected_hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Hash mismatch reading {}: expected {}, found {}",
+                input_path.display(),
+                expected_hash,
+                hash
+            ),
+        ));
+    }
+    Ok(size)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -79,6 +896,445 @@ mod tests {
         assert_eq!(content, "Hello, world!");
     }
 
+    #[test]
+    fn test_store_raw_object() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input.png");
+        let output_path = dir.path().join("object");
+        let restored_path = dir.path().join("restored.png");
+        let content = "not really a png ".repeat(100);
+        fs::write(&input_path, &content).expect("Failed to write test file");
+
+        store_object(&input_path, &output_path, Codec::Raw).expect("Failed to store file");
+
+        // The content follows the header uncompressed
+        let stored = fs::read(&output_path).expect("Failed to read object");
+        assert_eq!(stored.len(), HEADER_LEN + content.len());
+        assert_eq!(&stored[HEADER_LEN..], content.as_bytes());
+
+        let hash = blake3::hash(content.as_bytes());
+        assert_eq!(
+            hash_compressed_file(&output_path).expect("Failed to hash"),
+            hash
+        );
+        restore_file(&output_path, &restored_path, &hash).expect("Failed to restore file");
+        assert_eq!(
+            fs::read_to_string(&restored_path).expect("Failed to read restored file"),
+            content
+        );
+    }
+
+    #[test]
+    fn test_restore_object_without_header() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let output_path = dir.path().join("object");
+        let restored_path = dir.path().join("restored.txt");
+        let encoded = zstd::stream::encode_all(&b"old object"[..], 0).expect("Failed to encode");
+        fs::write(&output_path, encoded).expect("Failed to write object");
+
+        restore_file(&output_path, &restored_path, &blake3::hash(b"old object"))
+            .expect("Failed to restore file");
+        assert_eq!(
+            fs::read(&restored_path).expect("Failed to read restored file"),
+            b"old object"
+        );
+    }
+
+    #[test]
+    fn test_unknown_codec_is_rejected() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let output_path = dir.path().join("object");
+        let mut object = Codec::Raw.header();
+        object[11] = 9;
+        object.extend_from_slice(b"content");
+        fs::write(&output_path, object).expect("Failed to write object");
+
+        let error = hash_compressed_file(&output_path).expect_err("codec should be unknown");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_store_object_with_dictionary() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let buckets_dir = dir.path().join(".buckets");
+        let storage = dir.path().join("bucket").join(".b").join("storage");
+        fs::create_dir_all(&storage).expect("Failed to create storage");
+
+        let samples = (0..200)
+            .map(|i| {
+                format!(
+                    r#"{{"material": "wood{}", "roughness": 0.{}, "metallic": false}}"#,
+                    i, i
+                )
+            })
+            .collect::<Vec<_>>();
+        let dictionary =
+            zstd::dict::from_samples(&samples, 4096).expect("Failed to train dictionary");
+        let id = crate::utils::dictionary::write_dictionary(&buckets_dir, &dictionary)
+            .expect("Failed to write dictionary");
+
+        let input_path = dir.path().join("wood.json");
+        let output_path = storage.join("object");
+        let restored_path = dir.path().join("restored.json");
+        fs::write(&input_path, &samples[7]).expect("Failed to write test file");
+        store_object(
+            &input_path,
+            &output_path,
+            Codec::ZstdDictionary { level: 3, id },
+        )
+        .expect("Failed to store file");
+
+        restore_file(
+            &output_path,
+            &restored_path,
+            &blake3::hash(samples[7].as_bytes()),
+        )
+        .expect("Failed to restore file");
+        assert_eq!(
+            fs::read_to_string(&restored_path).expect("Failed to read restored file"),
+            samples[7]
+        );
+
+        // The object cannot be read without its dictionary
+        fs::remove_file(crate::utils::dictionary::dictionary_path(&buckets_dir, id))
+            .expect("Failed to remove dictionary");
+        assert!(hash_compressed_file(&output_path).is_err());
+    }
+
+    #[test]
+    fn test_store_delta_objects() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("mesh.obj");
+        let mut versions = Vec::new();
+        let mut content = (0..2000)
+            .map(|i| format!("v {}.0 {}.5 {}.25\n", i, i * 2, i * 3))
+            .collect::<String>();
+
+        // Each version changes a single line of the previous one
+        for version in 0..4 {
+            content = content.replacen(&format!("v {}.0", version), "v 9.9", 1);
+            fs::write(&input_path, &content).expect("Failed to write test file");
+            let hash = blake3::hash(content.as_bytes());
+            let output_path = dir.path().join(hash.to_hex().as_str());
+            let codec = match versions.last() {
+                None => Codec::Zstd(3),
+                Some((base, _)) => Codec::ZstdDelta {
+                    level: 3,
+                    base: *base,
+                    depth: version,
+                },
+            };
+            store_object(&input_path, &output_path, codec).expect("Failed to store file");
+            assert_eq!(
+                delta_depth(&output_path).expect("Failed to read header"),
+                version
+            );
+            assert_eq!(
+                delta_base(&output_path).expect("Failed to read header"),
+                versions.last().map(|(base, _)| *base)
+            );
+            versions.push((hash, content.clone()));
+        }
+
+        let full_size = fs::metadata(dir.path().join(versions[0].0.to_hex().as_str()))
+            .expect("Failed to read metadata")
+            .len();
+        for (hash, content) in &versions {
+            let object_path = dir.path().join(hash.to_hex().as_str());
+            let restored_path = dir.path().join("restored.obj");
+            restore_file(&object_path, &restored_path, hash).expect("Failed to restore");
+            assert_eq!(
+                fs::read_to_string(&restored_path).expect("Failed to read restored file"),
+                *content
+            );
+            if *hash != versions[0].0 {
+                let delta_size = fs::metadata(&object_path)
+                    .expect("Failed to read metadata")
+                    .len();
+                assert!(delta_size < full_size / 4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_packed_objects() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("mesh.obj");
+        let first = "v 1.0 2.0 3.0\n".repeat(500);
+        let second = first.replacen("v 1.0", "v 4.0", 1);
+        let first_hash = blake3::hash(first.as_bytes());
+        let second_hash = blake3::hash(second.as_bytes());
+        let first_path = dir.path().join(first_hash.to_hex().as_str());
+        let second_path = dir.path().join(second_hash.to_hex().as_str());
+
+        fs::write(&input_path, &first).expect("Failed to write test file");
+        store_object(&input_path, &first_path, Codec::Zstd(0)).expect("Failed to store");
+        fs::write(&input_path, &second).expect("Failed to write test file");
+        let delta = Codec::ZstdDelta {
+            level: 0,
+            base: first_hash,
+            depth: 1,
+        };
+        store_object(&input_path, &second_path, delta).expect("Failed to store");
+
+        // Only the base is packed, the delta stays loose
+        crate::utils::pack::write_pack(dir.path(), &[(first_hash, first_path.clone())])
+            .expect("Failed to write pack");
+        fs::remove_file(&first_path).expect("Failed to remove loose object");
+
+        assert!(object_exists(&first_path).expect("Failed to look up object"));
+        assert!(
+            !object_exists(&dir.path().join(blake3::hash(b"other").to_hex().as_str()))
+                .expect("Failed to look up object")
+        );
+        assert_eq!(delta_depth(&first_path).expect("Failed to read header"), 0);
+
+        let restored_path = dir.path().join("restored.obj");
+        restore_file(&first_path, &restored_path, &first_hash).expect("Failed to restore");
+        assert_eq!(
+            fs::read_to_string(&restored_path).expect("Failed to read file"),
+            first
+        );
+        restore_file(&second_path, &restored_path, &second_hash).expect("Failed to restore");
+        assert_eq!(
+            fs::read_to_string(&restored_path).expect("Failed to read file"),
+            second
+        );
+    }
+
+    #[test]
+    fn test_encrypted_objects() {
+        use crate::utils::encryption::{KeyEntry, KeyRing, PASSPHRASE_ENV};
+
+        let dir = tempdir().expect("Failed to create temp dir");
+        let buckets_dir = dir.path().join(".buckets");
+        let storage = dir.path().join("bucket").join(".b").join("storage");
+        fs::create_dir_all(&buckets_dir).expect("Failed to create .buckets");
+        fs::create_dir_all(&storage).expect("Failed to create storage");
+
+        // A plain object stored before the repository was encrypted
+        let input_path = dir.path().join("input");
+        let content = "secret asset ".repeat(10_000);
+        fs::write(&input_path, &content).expect("Failed to write test file");
+        let hash = blake3::hash(content.as_bytes());
+        let object_path = storage.join(hash.to_hex().as_str());
+        store_object(&input_path, &object_path, Codec::Zstd(0)).expect("Failed to store");
+        assert!(!is_encrypted(&object_path).expect("Failed to read header"));
+
+        let mut ring = None;
+        for name in ["first.key", "second.key"] {
+            let key_file = dir.path().join(name);
+            fs::write(&key_file, name).expect("Failed to write key file");
+            let (entry, _) =
+                KeyEntry::create(Some(&key_file), PASSPHRASE_ENV).expect("Failed to create key");
+            let ring = ring.get_or_insert_with(|| KeyRing {
+                current: entry.id,
+                keys: Vec::new(),
+            });
+            ring.current = entry.id;
+            ring.keys.push(entry);
+            ring.save(&buckets_dir).expect("Failed to save key ring");
+
+            assert!(reencrypt_object(&object_path).expect("Failed to re-encrypt"));
+            assert!(!reencrypt_object(&object_path).expect("Failed to re-encrypt"));
+            let object = fs::read(&object_path).expect("Failed to read object");
+            assert_eq!(object[11], ENCRYPTED_CODEC);
+            assert_eq!(object[12..16], ring.current.to_le_bytes());
+            assert!(!object.windows(13).any(|window| window == b"secret asset "));
+
+            let restored_path = dir.path().join("restored");
+            restore_file(&object_path, &restored_path, &hash).expect("Failed to restore");
+            assert_eq!(
+                fs::read_to_string(&restored_path).expect("Failed to read file"),
+                content
+            );
+        }
+
+        // New objects are encrypted, including deltas against an encrypted base
+        let changed = content.replacen("secret", "public", 1);
+        fs::write(&input_path, &changed).expect("Failed to write test file");
+        let changed_hash = blake3::hash(changed.as_bytes());
+        let delta_path = storage.join(changed_hash.to_hex().as_str());
+        let delta = Codec::ZstdDelta {
+            level: 0,
+            base: hash,
+            depth: 1,
+        };
+        store_object(&input_path, &delta_path, delta).expect("Failed to store");
+        assert!(is_encrypted(&delta_path).expect("Failed to read header"));
+        assert_eq!(delta_depth(&delta_path).expect("Failed to read header"), 1);
+        assert_eq!(
+            verify_object(&delta_path, &changed_hash).expect("Failed to verify"),
+            changed.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_delta_chain_is_bounded() {
+        let config: CompressionConfig = toml::from_str(
+            r#"
+max_delta_chain = 2
+
+[[rules]]
+pattern = "*.obj"
+delta = true
+"#,
+        )
+        .expect("Failed to parse config");
+        let policy = CompressionPolicy::from_config(&config).expect("Failed to build policy");
+
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input");
+        fs::write(&input_path, "v 1.0 2.0 3.0\n".repeat(100)).expect("Failed to write file");
+        let base = blake3::hash(b"base");
+        let base_path = dir.path().join(base.to_hex().as_str());
+        store_object(&input_path, &base_path, Codec::Zstd(0)).expect("Failed to store file");
+
+        assert_eq!(
+            policy.codec_for_version("mesh.obj", 1400, Some(&base_path)),
+            Codec::ZstdDelta {
+                level: 0,
+                base,
+                depth: 1
+            }
+        );
+        assert_eq!(
+            policy.codec_for_version("notes.txt", 1400, Some(&base_path)),
+            Codec::Zstd(0)
+        );
+        assert_eq!(
+            policy.codec_for_version("mesh.obj", 1400, None),
+            Codec::Zstd(0)
+        );
+
+        // A base that is two deltas deep already gets a full object next
+        let deep = Codec::ZstdDelta {
+            level: 0,
+            base,
+            depth: 2,
+        };
+        let deep_path = dir.path().join(blake3::hash(b"deep").to_hex().as_str());
+        store_object(&input_path, &deep_path, deep).expect("Failed to store file");
+        assert_eq!(
+            policy.codec_for_version("mesh.obj", 1400, Some(&deep_path)),
+            Codec::Zstd(0)
+        );
+    }
+
+    #[test]
+    fn test_delta_base_is_bounded() {
+        let config: CompressionConfig = toml::from_str(
+            r#"
+delta_max_size = 1000
+
+[[rules]]
+pattern = "*.obj"
+delta = true
+"#,
+        )
+        .expect("Failed to parse config");
+        let policy = CompressionPolicy::from_config(&config).expect("Failed to build policy");
+
+        // Only a base of at most delta_max_size bytes is held in memory to read a delta
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input");
+        for (size, expected_depth) in [(1000, Some(1)), (1001, None)] {
+            fs::write(&input_path, vec![b'v'; size]).expect("Failed to write file");
+            let base = blake3::hash(size.to_string().as_bytes());
+            let base_path = dir.path().join(base.to_hex().as_str());
+            store_object(&input_path, &base_path, Codec::Zstd(0)).expect("Failed to store file");
+            let expected = match expected_depth {
+                Some(depth) => Codec::ZstdDelta {
+                    level: 0,
+                    base,
+                    depth,
+                },
+                None => Codec::Zstd(0),
+            };
+            assert_eq!(
+                policy.codec_for_version("mesh.obj", 500, Some(&base_path)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_only_uncompressed_tga_is_stored_as_delta() {
+        let policy = CompressionPolicy::default();
+        let dir = tempdir().expect("Failed to create temp dir");
+        let base = blake3::hash(b"base");
+        let base_path = dir.path().join(base.to_hex().as_str());
+        fs::write(dir.path().join("base.tga"), b"base").expect("Failed to write file");
+        store_object(&dir.path().join("base.tga"), &base_path, Codec::Zstd(0))
+            .expect("Failed to store file");
+
+        // Image type 2 is uncompressed true color, 10 the same with run-length encoding
+        for (image_type, depth) in [(2u8, 1), (10u8, 0)] {
+            let mut image = vec![0u8, 0, image_type];
+            image.extend(vec![0u8; 1000]);
+            let input_path = dir.path().join("wood.tga");
+            fs::write(&input_path, &image).expect("Failed to write file");
+            let output_path = dir.path().join(blake3::hash(&image).to_hex().as_str());
+            policy
+                .store(&input_path, "wood.tga", &output_path, Some(&base))
+                .expect("Failed to store file");
+            assert_eq!(
+                delta_depth(&output_path).expect("Failed to read header"),
+                depth
+            );
+        }
+    }
+
+    #[test]
+    fn test_compression_policy() {
+        let config: CompressionConfig = toml::from_str(
+            r#"
+level = 3
+store_below = 16
+
+[[rules]]
+pattern = "*.png"
+store = true
+
+[[rules]]
+pattern = "renders/**/*.exr"
+level = 19
+min_size = 1000
+"#,
+        )
+        .expect("Failed to parse config");
+        let policy = CompressionPolicy::from_config(&config).expect("Failed to build policy");
+
+        assert_eq!(policy.codec_for("textures/Wood.PNG", 5000), Codec::Raw);
+        assert_eq!(policy.codec_for("notes.txt", 10), Codec::Raw);
+        assert_eq!(policy.codec_for("notes.txt", 5000), Codec::Zstd(3));
+        assert_eq!(
+            policy.codec_for("renders/a/frame.exr", 5000),
+            Codec::Zstd(19)
+        );
+        assert_eq!(policy.codec_for("renders/a/frame.exr", 500), Codec::Zstd(3));
+        assert_eq!(policy.codec_for("frame.exr", 5000), Codec::Zstd(3));
+
+        let mut policy = policy;
+        policy.dictionaries.insert("json".to_string(), 7);
+        assert_eq!(
+            policy.codec_for("materials/wood.json", 5000),
+            Codec::ZstdDictionary { level: 3, id: 7 }
+        );
+        assert_eq!(
+            policy.codec_for("materials/wood.json", 10_000_000),
+            Codec::Zstd(3)
+        );
+    }
+
+    #[test]
+    fn test_default_policy_stores_compressed_media_raw() {
+        let policy = CompressionPolicy::default();
+        assert_eq!(policy.codec_for("textures/wood.jpg", 5000), Codec::Raw);
+        assert_eq!(policy.codec_for("video/intro.mp4", 5000), Codec::Raw);
+        assert_eq!(policy.codec_for("model.blend", 5000), Codec::Zstd(0));
+    }
+
     #[test]
     fn test_restore_file() {
         let mut dir = tempdir().expect("Failed to create temp dir");
@@ -105,7 +1361,12 @@ mod tests {
         fs::remove_file(&restored_file_path).expect("Failed to remove original file");
 
         // Restore the file
-        restore_file(&compressed_file_path, &restored_file_path).expect("Failed to restore file");
+        restore_file(
+            &compressed_file_path,
+            &restored_file_path,
+            &blake3::hash(b"Hello, world!"),
+        )
+        .expect("Failed to restore file");
         log::info!("File restored successfully");
 
         // Check if the restored file exists and contains the expected content
@@ -115,6 +1376,49 @@ mod tests {
         assert_eq!(content, "Hello, world!");
     }
 
+    #[test]
+    fn test_compress_and_store_leaves_no_temp_files() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input.txt");
+        let storage_dir = dir.path().join("storage");
+        fs::create_dir_all(&storage_dir).expect("Failed to create storage dir");
+        let output_path = storage_dir.join("object");
+
+        fs::write(&input_path, "Hello, world!").expect("Failed to write test file");
+        compress_and_store_file(&input_path, &output_path, 3).expect("Failed to compress file");
+
+        let names = fs::read_dir(&storage_dir)
+            .expect("Failed to read storage dir")
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["object".to_string()]);
+    }
+
+    #[test]
+    fn test_hash_compressed_file() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input.txt");
+        let output_path = dir.path().join("output.zst");
+
+        fs::write(&input_path, "Hello, world!").expect("Failed to write test file");
+        compress_and_store_file(&input_path, &output_path, 3).expect("Failed to compress file");
+
+        let hash = hash_compressed_file(&output_path).expect("Failed to hash compressed file");
+        assert_eq!(hash, blake3::hash(b"Hello, world!"));
+    }
+
+    #[test]
+    fn test_hash_compressed_file_invalid_data() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let fake_compressed = dir.path().join("fake.zst");
+
+        fs::write(&fake_compressed, "this is not compressed data")
+            .expect("Failed to write fake file");
+
+        assert!(hash_compressed_file(&fake_compressed).is_err());
+    }
+
     #[test]
     fn test_compress_and_store_large_file() {
         let dir = tempdir().expect("Failed to create temp dir");
@@ -164,7 +1468,8 @@ mod tests {
             .expect("Failed to compress large file");
 
         // Restore the file
-        restore_file(&output_path, &result_path).expect("Failed to restore large file");
+        restore_file(&output_path, &result_path, &blake3::hash(&buffer))
+            .expect("Failed to restore large file");
 
         // Check if the restored file exists
         assert!(result_path.exists());
@@ -210,7 +1515,7 @@ mod tests {
         let output_path = dir.path().join("output.txt");
 
         // Test restoration of non-existent compressed file
-        let result = restore_file(&nonexistent_compressed, &output_path);
+        let result = restore_file(&nonexistent_compressed, &output_path, &blake3::hash(b""));
         assert!(result.is_err());
 
         if let Err(e) = result {
@@ -229,11 +1534,65 @@ mod tests {
             .expect("Failed to write fake file");
 
         // Test restoration of invalid compressed file
-        let result = restore_file(&fake_compressed, &output_path);
+        let result = restore_file(&fake_compressed, &output_path, &blake3::hash(b""));
         assert!(result.is_err());
         // Should fail during decompression
     }
 
+    #[test]
+    fn test_restore_hash_mismatch_keeps_target() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input.txt");
+        let compressed_path = dir.path().join("compressed.zst");
+        let output_path = dir.path().join("output.txt");
+
+        fs::write(&input_path, "stored content").expect("Failed to write input file");
+        compress_and_store_file(&input_path, &compressed_path, 3).expect("Failed to compress");
+        fs::write(&output_path, "working content").expect("Failed to write output file");
+
+        // The object does not have the expected content
+        let result = restore_file(
+            &compressed_path,
+            &output_path,
+            &blake3::hash(b"other content"),
+        );
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+
+        // The working file is untouched and no temporary files are left behind
+        let content = fs::read_to_string(&output_path).expect("Failed to read output file");
+        assert_eq!(content, "working content");
+        let entries = fs::read_dir(dir.path())
+            .expect("Failed to read dir")
+            .count();
+        assert_eq!(entries, 3);
+    }
+
+    #[test]
+    fn test_restore_corrupt_object_keeps_target() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let input_path = dir.path().join("input.txt");
+        let compressed_path = dir.path().join("compressed.zst");
+        let output_path = dir.path().join("output.txt");
+
+        fs::write(&input_path, "stored content ".repeat(1000)).expect("Failed to write input");
+        compress_and_store_file(&input_path, &compressed_path, 3).expect("Failed to compress");
+        fs::write(&output_path, "working content").expect("Failed to write output file");
+
+        // Truncate the compressed object half way
+        let compressed = fs::read(&compressed_path).expect("Failed to read compressed file");
+        fs::write(&compressed_path, &compressed[..compressed.len() / 2])
+            .expect("Failed to truncate compressed file");
+
+        let expected = blake3::hash("stored content ".repeat(1000).as_bytes());
+        assert!(restore_file(&compressed_path, &output_path, &expected).is_err());
+
+        let content = fs::read_to_string(&output_path).expect("Failed to read output file");
+        assert_eq!(content, "working content");
+    }
+
     #[test]
     fn test_restore_to_readonly_directory() {
         let dir = tempdir().expect("Failed to create temp dir");
@@ -262,7 +1621,11 @@ mod tests {
         let readonly_output = readonly_dir.join("output.txt");
 
         // Test restoration to readonly directory (may fail on some systems)
-        let result = restore_file(&compressed_path, &readonly_output);
+        let result = restore_file(
+            &compressed_path,
+            &readonly_output,
+            &blake3::hash(b"test content"),
+        );
 
         // Restore directory permissions for cleanup
         #[cfg(unix)]
@@ -302,7 +1665,8 @@ mod tests {
         assert!(output_path.exists());
 
         // Restore empty file
-        restore_file(&output_path, &restored_path).expect("Failed to restore empty file");
+        restore_file(&output_path, &restored_path, &blake3::hash(b""))
+            .expect("Failed to restore empty file");
         assert!(restored_path.exists());
 
         let restored_content = fs::read(&restored_path).expect("Failed to read restored file");
@@ -329,7 +1693,12 @@ mod tests {
             assert!(output_path.exists());
 
             // Restore and verify content
-            restore_file(&output_path, &restored_path).expect("Failed to restore");
+            restore_file(
+                &output_path,
+                &restored_path,
+                &blake3::hash(test_content.as_bytes()),
+            )
+            .expect("Failed to restore");
             let restored_content =
                 fs::read_to_string(&restored_path).expect("Failed to read restored");
             assert_eq!(restored_content, test_content);
@@ -372,7 +1741,12 @@ mod tests {
             assert!(compressed_path.exists());
 
             // Restore
-            restore_file(&compressed_path, &restored_path).expect("Failed to restore test content");
+            restore_file(
+                &compressed_path,
+                &restored_path,
+                &blake3::hash(content.as_bytes()),
+            )
+            .expect("Failed to restore test content");
             assert!(restored_path.exists());
 
             // Verify content matches
@@ -1,14 +1,211 @@
 use crate::utils::checks::find_directory_in_parents;
+use crate::utils::compression::write_object;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct RepositoryConfig {
     pub ntp_server: String,
     pub ip_check: String,
     pub url_check: String,
+    #[serde(default, skip_serializing_if = "UserConfig::is_empty")]
+    pub user: UserConfig,
+    #[serde(default, skip_serializing_if = "LocksConfig::is_empty")]
+    pub locks: LocksConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+}
+
+/// The `[user]` table, found in the repository config and the per-user config file
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub(crate) struct UserConfig {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl UserConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.name.is_none() && self.email.is_none()
+    }
+}
+
+/// The `[locks]` table, for the file locks of `bucket lock`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub(crate) struct LocksConfig {
+    /// User names allowed to release the locks of other users with `unlock --force`
+    pub admins: Vec<String>,
+}
+
+impl LocksConfig {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.admins.is_empty()
+    }
+}
+
+/// The `[compression]` table, deciding how files are stored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub(crate) struct CompressionConfig {
+    /// zstd level for files no rule matches, 0 selects the zstd default
+    pub level: i32,
+    /// Files smaller than this many bytes are stored without compression
+    pub store_below: u64,
+    /// Files up to this many bytes use the dictionary trained for their extension
+    pub dictionary_max_size: u64,
+    /// Files up to this many bytes can be stored as a delta, the previous version is
+    /// held in memory to restore them
+    pub delta_max_size: u64,
+    /// Most deltas restoring a file may need to go through before reaching a full object
+    pub max_delta_chain: u8,
+    /// Checked in order, the first matching rule decides
+    pub rules: Vec<CompressionRule>,
+}
+
+/// A `[[compression.rules]]` entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct CompressionRule {
+    /// Glob such as `*.png` or `renders/**/*.exr`. Without a `/` it matches the file
+    /// name in any directory.
+    pub pattern: String,
+    /// Store matching files without compression
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub store: bool,
+    /// Store new versions of matching files as a delta against their previous version
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delta: bool,
+    /// zstd level for matching files
+    pub level: Option<i32>,
+    /// The rule only applies to files of at least this many bytes
+    pub min_size: Option<u64>,
+    /// The rule only applies to files of at most this many bytes
+    pub max_size: Option<u64>,
+}
+
+/// The `[storage]` table, selecting where the objects of the buckets are kept
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub(crate) enum StorageConfig {
+    /// In the `.b/storage` directory of each bucket
+    #[default]
+    Local,
+    /// In a directory outside the repository, such as a NAS mount. A relative path is
+    /// relative to the repository directory.
+    Directory { path: PathBuf },
+    /// In a bucket of an S3-compatible service
+    S3(S3Config),
+}
+
+/// Where objects are kept with the `s3` storage backend
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct S3Config {
+    /// Such as `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Prepended to object keys, so several repositories can share a bucket
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// The `[cache]` table, limiting the objects a remote store leaves in `.b/storage`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub(crate) struct CacheConfig {
+    /// Bytes of cached objects kept besides the objects of the current tree of a bucket,
+    /// the least recently fetched objects are removed first
+    pub max_size: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_size: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// The `[database]` table, connecting a PostgreSQL repository to an external server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub(crate) struct DatabaseConfig {
+    /// Such as `postgres://buckets@db.studio.lan/assets`, without one the repository uses
+    /// the embedded server in `.buckets/postgres_data`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Schema holding the tables of the repository, so repositories can share a database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// Most connections a command keeps open to the server
+    pub pool_size: u32,
+    /// Seconds to keep retrying a server that does not accept connections yet
+    pub connect_timeout: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: None,
+            schema: None,
+            pool_size: 4,
+            connect_timeout: 30,
+        }
+    }
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Uncompressed file types that change a little between versions. TGA images that are
+/// run-length encoded are stored in full all the same
+const DELTA_TYPES: &[&str] = &["*.obj", "*.usda", "*.tga"];
+
+/// File types that are compressed already, where zstd only costs time
+const COMPRESSED_TYPES: &[&str] = &[
+    "*.png", "*.jpg", "*.jpeg", "*.gif", "*.webp", "*.mp3", "*.ogg", "*.mp4", "*.mov", "*.mkv",
+    "*.webm", "*.zip", "*.7z", "*.rar", "*.gz", "*.xz", "*.bz2", "*.zst",
+];
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            level: 0,
+            store_below: 0,
+            dictionary_max_size: 128 * 1024,
+            delta_max_size: 64 * 1024 * 1024,
+            max_delta_chain: 8,
+            rules: COMPRESSED_TYPES
+                .iter()
+                .map(|pattern| CompressionRule {
+                    pattern: pattern.to_string(),
+                    store: true,
+                    delta: false,
+                    level: None,
+                    min_size: None,
+                    max_size: None,
+                })
+                .chain(DELTA_TYPES.iter().map(|pattern| CompressionRule {
+                    pattern: pattern.to_string(),
+                    store: false,
+                    delta: true,
+                    level: None,
+                    min_size: None,
+                    max_size: None,
+                }))
+                .collect(),
+        }
+    }
 }
 
 impl RepositoryConfig {
@@ -28,12 +225,71 @@ impl RepositoryConfig {
     }
 }
 
+/// Replaces the settings of the `[database]` table of the config in `buckets_dir`, or
+/// removes the table when `database` is `None`. The rest of the file is kept as it is,
+/// with its comments. The file is replaced in one step once it is on disk.
+pub(crate) fn set_database_config(
+    buckets_dir: &Path,
+    database: Option<&DatabaseConfig>,
+) -> std::io::Result<()> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+    };
+    let config_path = buckets_dir.join("config");
+    let mut config: DocumentMut = fs::read_to_string(&config_path)?
+        .parse()
+        .map_err(|e| invalid(&e))?;
+    match database {
+        Some(database) => {
+            let mut table = toml::Table::new();
+            table.insert(
+                "database".to_string(),
+                toml::Value::try_from(database).map_err(|e| invalid(&e))?,
+            );
+            let settings: DocumentMut = toml::to_string(&table)
+                .map_err(|e| invalid(&e))?
+                .parse()
+                .map_err(|e| invalid(&e))?;
+            let settings = settings
+                .get("database")
+                .and_then(Item::as_table)
+                .cloned()
+                .unwrap_or_default();
+            // Keep the comments around the table, only its settings change
+            match config.get_mut("database").and_then(Item::as_table_mut) {
+                Some(existing) => {
+                    existing.clear();
+                    for (key, item) in settings.iter() {
+                        existing.insert(key, item.clone());
+                    }
+                }
+                None => {
+                    config.insert("database", Item::Table(settings));
+                }
+            }
+        }
+        None => {
+            config.remove("database");
+        }
+    }
+
+    write_object(&config_path, |writer| {
+        writer.write_all(config.to_string().as_bytes())
+    })
+}
+
 impl Default for RepositoryConfig {
     fn default() -> Self {
         RepositoryConfig {
             ntp_server: "pool.ntp.org".to_string(),
             ip_check: "8.8.8.8".to_string(),
             url_check: "api.ipify.org".to_string(),
+            user: UserConfig::default(),
+            locks: LocksConfig::default(),
+            compression: CompressionConfig::default(),
+            storage: StorageConfig::default(),
+            cache: CacheConfig::default(),
+            database: DatabaseConfig::default(),
         }
     }
 }
@@ -56,6 +312,8 @@ mod tests {
             shared: crate::args::SharedArguments::default(),
             repo_name: "test".to_string(),
             database: "duckdb".to_string(),
+            database_url: None,
+            database_schema: None,
         });
         init_cmd
             .create_config_file(&buckets_dir.as_path())
@@ -107,6 +365,179 @@ url_check = "custom.check.url"
         assert_eq!(config.url_check, "custom.check.url");
     }
 
+    #[test]
+    fn test_compression_config_deserialization() {
+        let toml_content = r#"
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+
+[compression]
+level = 9
+
+[[compression.rules]]
+pattern = "*.psd"
+level = 19
+min_size = 1048576
+"#;
+        let config: RepositoryConfig =
+            toml::from_str(toml_content).expect("Failed to deserialize config");
+
+        assert_eq!(config.compression.level, 9);
+        assert_eq!(config.compression.store_below, 0);
+        assert_eq!(config.compression.rules.len(), 1);
+        assert_eq!(config.compression.rules[0].level, Some(19));
+        assert!(!config.compression.rules[0].store);
+
+        // Without a [compression] table compressed media is stored as is
+        let config = RepositoryConfig::default();
+        assert!(config
+            .compression
+            .rules
+            .iter()
+            .any(|rule| rule.pattern == "*.png" && rule.store));
+    }
+
+    #[test]
+    fn test_storage_config_deserialization() {
+        let toml_content = r#"
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+
+[storage]
+backend = "s3"
+endpoint = "http://localhost:9000"
+bucket = "assets"
+prefix = "studio"
+"#;
+        let config: RepositoryConfig =
+            toml::from_str(toml_content).expect("Failed to deserialize config");
+        assert_eq!(
+            config.storage,
+            StorageConfig::S3(S3Config {
+                endpoint: "http://localhost:9000".to_string(),
+                bucket: "assets".to_string(),
+                region: "us-east-1".to_string(),
+                prefix: "studio".to_string(),
+            })
+        );
+
+        let toml_content = r#"
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+
+[storage]
+backend = "directory"
+path = "/mnt/nas/assets"
+
+[cache]
+max_size = 0
+"#;
+        let config: RepositoryConfig =
+            toml::from_str(toml_content).expect("Failed to deserialize config");
+        assert_eq!(
+            config.storage,
+            StorageConfig::Directory {
+                path: PathBuf::from("/mnt/nas/assets")
+            }
+        );
+        assert_eq!(config.cache.max_size, 0);
+
+        // Without a [storage] table objects stay in the buckets
+        let config = RepositoryConfig::default();
+        assert_eq!(config.storage, StorageConfig::Local);
+        assert_eq!(config.cache, CacheConfig::default());
+        let serialized = toml::to_string(&config).expect("Failed to serialize config");
+        let config: RepositoryConfig =
+            toml::from_str(&serialized).expect("Failed to deserialize config");
+        assert_eq!(config.storage, StorageConfig::Local);
+    }
+
+    #[test]
+    fn test_database_config_deserialization() {
+        let toml_content = r#"
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+
+[database]
+url = "postgres://buckets@db.studio.lan/assets"
+schema = "buckets_shots"
+"#;
+        let config: RepositoryConfig =
+            toml::from_str(toml_content).expect("Failed to deserialize config");
+        assert_eq!(
+            config.database,
+            DatabaseConfig {
+                url: Some("postgres://buckets@db.studio.lan/assets".to_string()),
+                schema: Some("buckets_shots".to_string()),
+                pool_size: 4,
+                connect_timeout: 30,
+            }
+        );
+
+        // Without a [database] table a PostgreSQL repository uses its embedded server
+        let config = RepositoryConfig::default();
+        assert_eq!(config.database.url, None);
+        let serialized = toml::to_string(&config).expect("Failed to serialize config");
+        let config: RepositoryConfig =
+            toml::from_str(&serialized).expect("Failed to deserialize config");
+        assert_eq!(config.database, DatabaseConfig::default());
+    }
+
+    #[test]
+    fn test_set_database_config() {
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let buckets_dir = temp_dir.path().join(".buckets");
+        fs::create_dir(&buckets_dir).expect("Failed to create .buckets directory");
+        let config = r#"
+# Checked before every commit
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+
+[user]
+name = "Alice" # shown in the log
+
+# Shared with the other shows
+[database]
+pool_size = 2
+"#;
+        fs::write(buckets_dir.join("config"), config).expect("Failed to write config");
+
+        let database = DatabaseConfig {
+            url: Some("postgres://buckets@db.studio.lan/assets".to_string()),
+            schema: Some("buckets_shots".to_string()),
+            ..DatabaseConfig::default()
+        };
+        set_database_config(&buckets_dir, Some(&database)).expect("Failed to set database");
+        let config = RepositoryConfig::from_file(temp_dir.path().to_path_buf())
+            .expect("Failed to read config file");
+        assert_eq!(config.database, database);
+        assert_eq!(config.user.name.as_deref(), Some("Alice"));
+        let written = fs::read_to_string(buckets_dir.join("config")).expect("Failed to read");
+        for comment in [
+            "# Checked before every commit",
+            "# shown in the log",
+            "# Shared with the other shows",
+        ] {
+            assert!(
+                written.contains(comment),
+                "lost {:?} in {}",
+                comment,
+                written
+            );
+        }
+
+        set_database_config(&buckets_dir, None).expect("Failed to remove database");
+        let config = RepositoryConfig::from_file(temp_dir.path().to_path_buf())
+            .expect("Failed to read config file");
+        assert_eq!(config.database, DatabaseConfig::default());
+        assert_eq!(config.user.name.as_deref(), Some("Alice"));
+    }
+
     #[test]
     fn test_from_file_no_buckets_directory() {
         let temp_dir = tempdir().expect("Failed to create temporary directory");
@@ -159,6 +590,8 @@ url_check = "custom.check.url"
             shared: crate::args::SharedArguments::default(),
             repo_name: "test".to_string(),
             database: "duckdb".to_string(),
+            database_url: None,
+            database_schema: None,
         });
         init_cmd
             .create_config_file(&buckets_dir.as_path())
@@ -175,6 +608,29 @@ url_check = "custom.check.url"
         Ok(())
     }
 
+    #[test]
+    fn test_config_user_section() {
+        let toml_content = r#"
+ntp_server = "pool.ntp.org"
+ip_check = "8.8.8.8"
+url_check = "api.ipify.org"
+
+[user]
+name = "Alice"
+email = "alice@example.com"
+"#;
+        let config: RepositoryConfig =
+            toml::from_str(toml_content).expect("Failed to deserialize config");
+        assert_eq!(config.user.name.as_deref(), Some("Alice"));
+        assert_eq!(config.user.email.as_deref(), Some("alice@example.com"));
+
+        // Configs written before the user section existed still load
+        let config = RepositoryConfig::default();
+        assert!(config.user.is_empty());
+        let serialized = toml::to_string(&config).expect("Failed to serialize config");
+        assert!(!serialized.contains("[user]"));
+    }
+
     #[test]
     fn test_config_debug_format() {
         let config = RepositoryConfig::default();
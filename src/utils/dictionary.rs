@@ -0,0 +1,245 @@
+use crate::utils::compression::{
+    encryption_key, read_protected_file, write_object, write_protected_file,
+};
+use crate::utils::utils::find_bucket_repo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Directory in `.buckets` holding the trained zstd dictionaries
+pub const DICTIONARY_DIR: &str = "dictionaries";
+const INDEX_FILE: &str = "index.toml";
+
+/// The dictionaries of a repository and the file types they are used for
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DictionaryIndex {
+    #[serde(default, rename = "dictionary")]
+    pub dictionaries: Vec<DictionaryEntry>,
+}
+
+/// A `[[dictionary]]` entry of the index
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DictionaryEntry {
+    /// Lowercase file extension without the dot
+    pub extension: String,
+    pub id: u32,
+    /// Number of files the dictionary was trained on
+    pub samples: usize,
+    pub size: usize,
+}
+
+impl DictionaryIndex {
+    /// Reads the index of the repository whose `.buckets` directory is `buckets_dir`.
+    /// A repository without dictionaries has an empty index.
+    pub fn load(buckets_dir: &Path) -> io::Result<Self> {
+        let path = buckets_dir.join(DICTIONARY_DIR).join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        toml::from_str(&fs::read_to_string(&path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid dictionary index {}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn save(&self, buckets_dir: &Path) -> io::Result<()> {
+        let dir = buckets_dir.join(DICTIONARY_DIR);
+        fs::create_dir_all(&dir)?;
+        let content = toml::to_string(self).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to serialize dictionary index: {}", e),
+            )
+        })?;
+        write_object(&dir.join(INDEX_FILE), |writer| {
+            writer.write_all(content.as_bytes())
+        })
+    }
+
+    /// Dictionary ids by file extension
+    pub fn by_extension(&self) -> HashMap<String, u32> {
+        self.dictionaries
+            .iter()
+            .map(|entry| (entry.extension.clone(), entry.id))
+            .collect()
+    }
+
+    /// Adds a dictionary, replacing the one used for the same extension so far.
+    /// Replaced dictionaries stay on disk, objects written with them still need them.
+    pub fn insert(&mut self, entry: DictionaryEntry) {
+        self.dictionaries
+            .retain(|existing| existing.extension != entry.extension);
+        self.dictionaries.push(entry);
+        self.dictionaries
+            .sort_by(|a, b| a.extension.cmp(&b.extension));
+    }
+}
+
+/// Lowercase extension of a bucket relative path, the key dictionaries are chosen by
+pub fn file_extension(file: &str) -> Option<String> {
+    Path::new(file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+pub fn dictionary_path(buckets_dir: &Path, id: u32) -> PathBuf {
+    buckets_dir
+        .join(DICTIONARY_DIR)
+        .join(format!("{:08x}.dict", id))
+}
+
+/// Writes a trained dictionary and returns its id, derived from its content. A
+/// dictionary is never replaced, objects written with it need it to be read. Dictionaries
+/// hold parts of committed files and are encrypted like objects in an encrypted
+/// repository.
+pub fn write_dictionary(buckets_dir: &Path, dictionary: &[u8]) -> io::Result<u32> {
+    let hash = blake3::hash(dictionary);
+    let mut id_bytes = [0u8; 4];
+    id_bytes.copy_from_slice(&hash.as_bytes()[..4]);
+    let id = u32::from_le_bytes(id_bytes);
+
+    let path = dictionary_path(buckets_dir, id);
+    match read_protected_file(&path) {
+        Ok(existing) if existing == dictionary => return Ok(id),
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                "dictionary {:08x} exists with other content, train it again with other samples",
+                id
+            ),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    fs::create_dir_all(buckets_dir.join(DICTIONARY_DIR))?;
+    write_protected_file(&path, dictionary)?;
+    Ok(id)
+}
+
+/// Encrypts every dictionary with the current key `key_id` of the repository and returns
+/// how many were rewritten.
+pub fn reencrypt_dictionaries(buckets_dir: &Path, key_id: u32) -> io::Result<usize> {
+    let dir = buckets_dir.join(DICTIONARY_DIR);
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "dict") {
+            continue;
+        }
+        if encryption_key(&mut File::open(&path)?)? == Some(key_id) {
+            continue;
+        }
+        write_protected_file(&path, &read_protected_file(&path)?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Reads dictionary `id` of the repository that stores the object at `object_path`.
+pub fn read_dictionary(object_path: &Path, id: u32) -> io::Result<Vec<u8>> {
+    let buckets_dir = object_path
+        .parent()
+        .and_then(find_bucket_repo)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "no repository found for {} to read dictionary {:08x}",
+                    object_path.display(),
+                    id
+                ),
+            )
+        })?;
+    read_protected_file(&dictionary_path(&buckets_dir, id)).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to read dictionary {:08x}: {}", id, e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_file_extension() {
+        assert_eq!(
+            file_extension("materials/Wood.JSON"),
+            Some("json".to_string())
+        );
+        assert_eq!(file_extension("Makefile"), None);
+    }
+
+    #[test]
+    fn test_index_roundtrip() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        assert!(DictionaryIndex::load(dir.path())
+            .expect("Failed to load index")
+            .dictionaries
+            .is_empty());
+
+        let mut index = DictionaryIndex::default();
+        for (extension, id) in [("json", 1), ("mat", 2), ("json", 3)] {
+            index.insert(DictionaryEntry {
+                extension: extension.to_string(),
+                id,
+                samples: 10,
+                size: 100,
+            });
+        }
+        index.save(dir.path()).expect("Failed to save index");
+
+        let index = DictionaryIndex::load(dir.path()).expect("Failed to load index");
+        let by_extension = index.by_extension();
+        assert_eq!(by_extension.len(), 2);
+        assert_eq!(by_extension["json"], 3);
+        assert_eq!(by_extension["mat"], 2);
+    }
+
+    #[test]
+    fn test_read_dictionary_of_object() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let buckets_dir = dir.path().join(".buckets");
+        let storage = dir.path().join("bucket").join(".b").join("storage");
+        fs::create_dir_all(&storage).expect("Failed to create storage");
+
+        let id = write_dictionary(&buckets_dir, b"dictionary").expect("Failed to write");
+        assert_eq!(
+            read_dictionary(&storage.join("object"), id).expect("Failed to read"),
+            b"dictionary"
+        );
+        assert!(read_dictionary(&storage.join("object"), id.wrapping_add(1)).is_err());
+
+        // Writing the same dictionary again keeps it
+        assert_eq!(
+            write_dictionary(&buckets_dir, b"dictionary").expect("Failed to write"),
+            id
+        );
+    }
+
+    #[test]
+    fn test_write_dictionary_keeps_colliding_dictionary() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let id = write_dictionary(dir.path(), b"dictionary").expect("Failed to write");
+        // Another dictionary whose id is the same
+        fs::write(dictionary_path(dir.path(), id), b"older dictionary").expect("Failed to write");
+
+        let error = write_dictionary(dir.path(), b"dictionary").expect_err("ids collide");
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            fs::read(dictionary_path(dir.path(), id)).expect("Failed to read"),
+            b"older dictionary"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use crate::utils::compression::sync_directory;
+use crate::utils::utils::find_bucket_repo;
+use argon2::Argon2;
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::XChaCha20Poly1305;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::NamedTempFile;
+
+/// File in `.buckets` listing the keys of an encrypted repository
+pub const KEYS_FILE: &str = "keys.toml";
+/// Environment variable holding the passphrase of a repository
+pub const PASSPHRASE_ENV: &str = "BUCKETS_PASSPHRASE";
+/// Environment variable holding the new passphrase while keys are rotated
+pub const NEW_PASSPHRASE_ENV: &str = "BUCKETS_NEW_PASSPHRASE";
+
+/// Length of the nonce stored in the header, the STREAM construction uses the
+/// remaining 5 bytes of the 24 byte XChaCha20 nonce for its counter
+pub const NONCE_LEN: usize = 19;
+/// Size of the plaintext chunks, each is followed by a 16 byte tag
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+
+/// Master keys derived in this process by key id, deriving from a passphrase is slow
+static MASTER_KEYS: Lazy<Mutex<HashMap<u32, [u8; 32]>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The `keys.toml` of an encrypted repository
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyRing {
+    /// Id of the key new objects are encrypted with
+    pub current: u32,
+    /// The current key and, while a rotation is not finished, the keys it replaces
+    #[serde(rename = "key")]
+    pub keys: Vec<KeyEntry>,
+}
+
+/// A `[[key]]` entry. The key itself is never stored in the repository.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyEntry {
+    pub id: u32,
+    /// Salt of a key derived from a passphrase with Argon2id, as hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    /// File a key is derived from instead of a passphrase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+}
+
+impl KeyRing {
+    /// Reads the key ring of the repository whose `.buckets` directory is `buckets_dir`.
+    /// A repository without a key ring is not encrypted.
+    pub fn load(buckets_dir: &Path) -> io::Result<Option<Self>> {
+        let path = buckets_dir.join(KEYS_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        toml::from_str(&fs::read_to_string(&path)?)
+            .map(Some)
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid key ring {}: {}", path.display(), e),
+                )
+            })
+    }
+
+    /// Replaces the key ring of the repository in one step. A crash while writing a
+    /// truncated key ring would leave every encrypted object unreadable.
+    pub fn save(&self, buckets_dir: &Path) -> io::Result<()> {
+        let content = toml::to_string(self).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to serialize key ring: {}", e),
+            )
+        })?;
+        let mut file = NamedTempFile::new_in(buckets_dir)?;
+        file.write_all(content.as_bytes())?;
+        file.as_file().sync_all()?;
+        file.persist(buckets_dir.join(KEYS_FILE))
+            .map_err(|e| e.error)?;
+        sync_directory(buckets_dir)
+    }
+
+    fn entry(&self, id: u32) -> Option<&KeyEntry> {
+        self.keys.iter().find(|entry| entry.id == id)
+    }
+}
+
+impl KeyEntry {
+    /// Creates a key from `key_file`, or from the passphrase in `passphrase_env` with a
+    /// new random salt, and returns it with its master key.
+    pub fn create(key_file: Option<&Path>, passphrase_env: &str) -> io::Result<(Self, [u8; 32])> {
+        let (salt, key_file) = match key_file {
+            Some(path) => (None, Some(fs::canonicalize(path)?)),
+            None => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                (Some(to_hex(&salt)), None)
+            }
+        };
+        let mut entry = KeyEntry {
+            id: 0,
+            salt,
+            key_file,
+        };
+        let key = entry.derive(passphrase_env)?;
+        entry.id = key_id(&key);
+        Ok((entry, key))
+    }
+
+    /// Derives the master key from the key file or the passphrase in `passphrase_env`.
+    fn derive(&self, passphrase_env: &str) -> io::Result<[u8; 32]> {
+        if let Some(path) = &self.key_file {
+            let content = fs::read(path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to read key file {}: {}", path.display(), e),
+                )
+            })?;
+            return Ok(blake3::derive_key("buckets key file", &content));
+        }
+
+        let salt = self
+            .salt
+            .as_deref()
+            .and_then(from_hex)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "key without salt"))?;
+        let passphrase = std::env::var(passphrase_env).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("the repository is encrypted, set {}", passphrase_env),
+            )
+        })?;
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| io::Error::other(format!("failed to derive key: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// Identifies a master key without revealing it
+fn key_id(key: &[u8; 32]) -> u32 {
+    let id = blake3::derive_key("buckets key id", key);
+    u32::from_le_bytes([id[0], id[1], id[2], id[3]])
+}
+
+/// The master key `id` of a repository. A key derived from a passphrase accepts the
+/// passphrase of either environment variable, so keys can be read during a rotation.
+pub fn master_key(buckets_dir: &Path, id: u32) -> io::Result<[u8; 32]> {
+    if let Some(key) = cached_key(id) {
+        return Ok(key);
+    }
+    let ring = KeyRing::load(buckets_dir)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "object is encrypted but {} has no keys",
+                buckets_dir.display()
+            ),
+        )
+    })?;
+    let entry = ring.entry(id).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("unknown key {:08x}", id))
+    })?;
+
+    let mut error = None;
+    for passphrase_env in [PASSPHRASE_ENV, NEW_PASSPHRASE_ENV] {
+        match entry.derive(passphrase_env) {
+            Ok(key) if key_id(&key) == id => {
+                remember_key(id, key);
+                return Ok(key);
+            }
+            Ok(_) => {}
+            Err(e) => error = Some(e),
+        }
+        if entry.key_file.is_some() {
+            break;
+        }
+    }
+    Err(error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("wrong passphrase or key file for key {:08x}", id),
+        )
+    }))
+}
+
+pub fn remember_key(id: u32, key: [u8; 32]) {
+    if let Ok(mut keys) = MASTER_KEYS.lock() {
+        keys.insert(id, key);
+    }
+}
+
+fn cached_key(id: u32) -> Option<[u8; 32]> {
+    MASTER_KEYS.lock().ok()?.get(&id).copied()
+}
+
+/// The id and master key new objects in the storage of `object_path` are encrypted
+/// with, or `None` when the repository is not encrypted.
+pub fn current_key(object_path: &Path) -> io::Result<Option<(u32, [u8; 32])>> {
+    let Some(buckets_dir) = object_path.parent().and_then(find_bucket_repo) else {
+        return Ok(None);
+    };
+    match KeyRing::load(&buckets_dir)? {
+        Some(ring) => Ok(Some((
+            ring.current,
+            master_key(&buckets_dir, ring.current)?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// The master key `id` of the repository storing the object at `object_path`
+pub fn object_master_key(object_path: &Path, id: u32) -> io::Result<[u8; 32]> {
+    let buckets_dir = object_path
+        .parent()
+        .and_then(find_bucket_repo)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no repository found for {}", object_path.display()),
+            )
+        })?;
+    master_key(&buckets_dir, id)
+}
+
+/// Every object has its own key, derived from the master key and the content hash the
+/// object is named after.
+fn object_cipher(master: &[u8; 32], object_path: &Path) -> XChaCha20Poly1305 {
+    let name = object_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let key = blake3::keyed_hash(master, name.as_bytes());
+    XChaCha20Poly1305::new(GenericArray::from_slice(key.as_bytes()))
+}
+
+pub fn random_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+fn crypto_error(e: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("object could not be decrypted: {}", e),
+    )
+}
+
+/// Encrypts what is written to it in chunks. `finish` must be called to write the last
+/// chunk, without it the object cannot be decrypted.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    encryptor: EncryptorBE32<XChaCha20Poly1305>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(inner: W, master: &[u8; 32], object_path: &Path, nonce: &[u8; NONCE_LEN]) -> Self {
+        Self {
+            inner,
+            encryptor: EncryptorBE32::from_aead(
+                object_cipher(master, object_path),
+                GenericArray::from_slice(nonce),
+            ),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let chunk = self
+            .encryptor
+            .encrypt_last(self.buffer.as_slice())
+            .map_err(crypto_error)?;
+        self.inner.write_all(&chunk)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is only encrypted once more data follows, the last chunk is
+        // encrypted differently
+        if self.buffer.len() == CHUNK_SIZE && !buf.is_empty() {
+            let chunk = self
+                .encryptor
+                .encrypt_next(self.buffer.as_slice())
+                .map_err(crypto_error)?;
+            self.inner.write_all(&chunk)?;
+            self.buffer.clear();
+        }
+        let length = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts an object written by [`EncryptingWriter`]. A truncated or altered object
+/// fails with `InvalidData`.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// One byte read past the current chunk, to tell whether it is the last one
+    lookahead: Option<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(inner: R, master: &[u8; 32], object_path: &Path, nonce: &[u8; NONCE_LEN]) -> Self {
+        Self {
+            inner,
+            decryptor: Some(DecryptorBE32::from_aead(
+                object_cipher(master, object_path),
+                GenericArray::from_slice(nonce),
+            )),
+            lookahead: None,
+            plaintext: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Decrypts the next chunk into `plaintext`, returns false after the last chunk.
+    fn next_chunk(&mut self) -> io::Result<bool> {
+        if self.decryptor.is_none() {
+            return Ok(false);
+        }
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_LEN + 1);
+        chunk.extend(self.lookahead.take());
+        (&mut self.inner)
+            .take((CHUNK_SIZE + TAG_LEN + 1 - chunk.len()) as u64)
+            .read_to_end(&mut chunk)?;
+
+        self.plaintext = if chunk.len() > CHUNK_SIZE + TAG_LEN {
+            self.lookahead = chunk.pop();
+            let decryptor = self
+                .decryptor
+                .as_mut()
+                .ok_or_else(|| io::Error::other("no decryptor"))?;
+            decryptor
+                .decrypt_next(chunk.as_slice())
+                .map_err(crypto_error)?
+        } else {
+            let decryptor = self
+                .decryptor
+                .take()
+                .ok_or_else(|| io::Error::other("no decryptor"))?;
+            decryptor
+                .decrypt_last(chunk.as_slice())
+                .map_err(crypto_error)?
+        };
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+        let length = buf.len().min(self.plaintext.len() - self.position);
+        buf[..length].copy_from_slice(&self.plaintext[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn encrypt(content: &[u8], key: &[u8; 32], nonce: &[u8; NONCE_LEN]) -> Vec<u8> {
+        let mut writer = EncryptingWriter::new(Vec::new(), key, Path::new("object"), nonce);
+        writer.write_all(content).expect("Failed to encrypt");
+        writer.finish().expect("Failed to finish")
+    }
+
+    fn decrypt(encrypted: &[u8], key: &[u8; 32], nonce: &[u8; NONCE_LEN]) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        DecryptingReader::new(encrypted, key, Path::new("object"), nonce)
+            .read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let key = [7u8; 32];
+        let nonce = random_nonce();
+        for size in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+        ] {
+            let content = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            let encrypted = encrypt(&content, &key, &nonce);
+            let chunks = size.div_ceil(CHUNK_SIZE).max(1);
+            assert_eq!(encrypted.len(), size + chunks * TAG_LEN);
+            assert_eq!(
+                decrypt(&encrypted, &key, &nonce).expect("Failed to decrypt"),
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn test_tampered_or_truncated_objects_fail() {
+        let key = [7u8; 32];
+        let nonce = random_nonce();
+        let content = vec![1u8; 2 * CHUNK_SIZE + 10];
+        let encrypted = encrypt(&content, &key, &nonce);
+
+        let mut tampered = encrypted.clone();
+        tampered[100] ^= 1;
+        assert!(decrypt(&tampered, &key, &nonce).is_err());
+
+        // Dropping the last chunk must not pass as a shorter object
+        let truncated = &encrypted[..2 * (CHUNK_SIZE + TAG_LEN)];
+        assert!(decrypt(truncated, &key, &nonce).is_err());
+
+        assert!(decrypt(&encrypted, &[8u8; 32], &nonce).is_err());
+    }
+
+    #[test]
+    fn test_key_file_and_ring() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let key_file = dir.path().join("repo.key");
+        fs::write(&key_file, b"secret key material").expect("Failed to write key file");
+
+        let (entry, key) =
+            KeyEntry::create(Some(&key_file), PASSPHRASE_ENV).expect("Failed to create key");
+        assert_eq!(entry.id, key_id(&key));
+        assert!(entry.salt.is_none());
+
+        let ring = KeyRing {
+            current: entry.id,
+            keys: vec![entry.clone()],
+        };
+        ring.save(dir.path()).expect("Failed to save key ring");
+        assert_eq!(
+            KeyRing::load(dir.path()).expect("Failed to load key ring"),
+            Some(ring)
+        );
+        assert_eq!(
+            master_key(dir.path(), entry.id).expect("Failed to derive key"),
+            key
+        );
+        assert!(master_key(dir.path(), entry.id.wrapping_add(1)).is_err());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+        assert_eq!(from_hex("000fff"), Some(vec![0, 15, 255]));
+        assert_eq!(from_hex("0g"), None);
+        assert_eq!(from_hex("abc"), None);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::utils::config::{RepositoryConfig, UserConfig};
+use log::debug;
+use serde::Deserialize;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Environment variables overriding the configured user name and email
+pub const USER_NAME_ENV: &str = "BUCKETS_USER_NAME";
+pub const USER_EMAIL_ENV: &str = "BUCKETS_USER_EMAIL";
+
+/// The per-user config file, shared by all repositories of a user
+#[derive(Deserialize, Debug, Default)]
+struct UserConfigFile {
+    #[serde(default)]
+    user: UserConfig,
+}
+
+/// The user recorded as author of commits and owner of file locks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub email: Option<String>,
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.email {
+            Some(email) => write!(f, "{} <{}>", self.name, email),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl Identity {
+    /// Determines the identity of the user working in `dir`.
+    ///
+    /// Each of name and email is taken from the first source that sets it:
+    /// the `BUCKETS_USER_NAME`/`BUCKETS_USER_EMAIL` environment variables, the `[user]`
+    /// table of the repository config, then the per-user config file. Without a
+    /// configured name the login name of the operating system user is used.
+    pub fn resolve(dir: &Path) -> Self {
+        let from_env = UserConfig {
+            name: env_value(USER_NAME_ENV),
+            email: env_value(USER_EMAIL_ENV),
+        };
+        let from_repo = RepositoryConfig::from_file(dir.to_path_buf())
+            .map(|config| config.user)
+            .unwrap_or_default();
+        let from_user_file = user_config_path()
+            .map(|path| read_user_config(&path))
+            .unwrap_or_default();
+        let login = env_value("USER").or_else(|| env_value("USERNAME"));
+
+        Self::from_sources(&[from_env, from_repo, from_user_file], login)
+    }
+
+    /// Combines config sources in order of precedence, falling back to `login` for the name.
+    fn from_sources(sources: &[UserConfig], login: Option<String>) -> Self {
+        let name = sources
+            .iter()
+            .find_map(|source| non_empty(source.name.as_deref()))
+            .or(login)
+            .unwrap_or_else(|| "unknown".to_string());
+        let email = sources
+            .iter()
+            .find_map(|source| non_empty(source.email.as_deref()));
+
+        Self { name, email }
+    }
+}
+
+/// Location of the per-user config file: `$XDG_CONFIG_HOME/buckets/config`,
+/// `~/.config/buckets/config`, or `%APPDATA%\buckets\config` on Windows.
+pub fn user_config_path() -> Option<PathBuf> {
+    if let Some(config_home) = env_value("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(config_home).join("buckets").join("config"));
+    }
+    if cfg!(windows) {
+        if let Some(app_data) = env_value("APPDATA") {
+            return Some(PathBuf::from(app_data).join("buckets").join("config"));
+        }
+    }
+    env_value("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".config")
+            .join("buckets")
+            .join("config")
+    })
+}
+
+fn read_user_config(path: &Path) -> UserConfig {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return UserConfig::default(),
+    };
+    match toml::from_str::<UserConfigFile>(&content) {
+        Ok(file) => file.user,
+        Err(e) => {
+            debug!("Ignoring invalid user config {}: {}", path.display(), e);
+            UserConfig::default()
+        }
+    }
+}
+
+fn env_value(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .and_then(|value| non_empty(Some(&value)))
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn user(name: Option<&str>, email: Option<&str>) -> UserConfig {
+        UserConfig {
+            name: name.map(str::to_string),
+            email: email.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_identity_precedence() {
+        let identity = Identity::from_sources(
+            &[
+                user(Some("Env Name"), None),
+                user(Some("Repo Name"), Some("repo@example.com")),
+                user(Some("User Name"), Some("user@example.com")),
+            ],
+            Some("login".to_string()),
+        );
+        assert_eq!(identity.name, "Env Name");
+        assert_eq!(identity.email.as_deref(), Some("repo@example.com"));
+    }
+
+    #[test]
+    fn test_identity_falls_back_to_login() {
+        let identity = Identity::from_sources(
+            &[user(Some("  "), None), user(None, Some("user@example.com"))],
+            Some("login".to_string()),
+        );
+        assert_eq!(identity.name, "login");
+        assert_eq!(identity.to_string(), "login <user@example.com>");
+
+        let identity = Identity::from_sources(&[UserConfig::default()], None);
+        assert_eq!(identity.to_string(), "unknown");
+    }
+
+    #[test]
+    fn test_read_user_config() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("config");
+
+        assert_eq!(read_user_config(&path), UserConfig::default());
+
+        fs::write(
+            &path,
+            "[user]\nname = \"Alice\"\nemail = \"alice@example.com\"\n",
+        )
+        .expect("Failed to write config");
+        assert_eq!(
+            read_user_config(&path),
+            user(Some("Alice"), Some("alice@example.com"))
+        );
+
+        fs::write(&path, "not toml {").expect("Failed to write config");
+        assert_eq!(read_user_config(&path), UserConfig::default());
+    }
+}
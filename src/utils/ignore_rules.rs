@@ -0,0 +1,213 @@
+use crate::errors::BucketError;
+use crate::utils::utils::find_bucket_repo;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Name of the ignore files read from the repository root and the bucket root.
+pub const IGNORE_FILE: &str = ".bucketsignore";
+
+/// Patterns ignored in every bucket: editor backups, application temp files and OS junk.
+/// An ignore file can include one of these again with a `!` pattern.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    // Blender backups
+    "*.blend[0-9]",
+    "*.blend[0-9][0-9]",
+    // Photoshop and other application temp files
+    "Photoshop Temp*",
+    "~$*",
+    "*.tmp",
+    "*.swp",
+    "*~",
+    // Operating system files
+    ".DS_Store",
+    "._*",
+    ".Spotlight-V100",
+    ".Trashes",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+];
+
+/// Where the pattern deciding whether a path is ignored comes from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum IgnoreSource {
+    BuiltIn,
+    File(PathBuf),
+}
+
+impl Display for IgnoreSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IgnoreSource::BuiltIn => write!(f, "<built-in>"),
+            IgnoreSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The pattern that decided whether a path is ignored.
+#[derive(Debug, PartialEq)]
+pub struct IgnoreMatch {
+    pub source: IgnoreSource,
+    pub pattern: String,
+    /// False when the pattern is a `!` pattern that includes the path again
+    pub ignored: bool,
+}
+
+/// The ignore rules that apply to the files of a bucket.
+///
+/// Patterns follow the gitignore syntax. The bucket `.bucketsignore` takes precedence
+/// over the repository `.bucketsignore`, which takes precedence over the built-in defaults.
+pub struct IgnoreRules {
+    /// Matchers in order of precedence
+    matchers: Vec<(IgnoreSource, Gitignore)>,
+}
+
+impl IgnoreRules {
+    /// Loads the rules for the bucket at `bucket_path`.
+    pub fn for_bucket(bucket_path: &Path) -> Result<Self, BucketError> {
+        let mut matchers = Vec::new();
+
+        let bucket_file = bucket_path.join(IGNORE_FILE);
+        if bucket_file.is_file() {
+            matchers.push(load_ignore_file(bucket_path, &bucket_file)?);
+        }
+
+        if let Some(repo_root) = find_bucket_repo(bucket_path)
+            .as_deref()
+            .and_then(Path::parent)
+        {
+            let repo_file = repo_root.join(IGNORE_FILE);
+            if repo_file.is_file() {
+                matchers.push(load_ignore_file(repo_root, &repo_file)?);
+            }
+        }
+
+        let mut builder = GitignoreBuilder::new(bucket_path);
+        for pattern in DEFAULT_PATTERNS {
+            builder.add_line(None, pattern).map_err(invalid_pattern)?;
+        }
+        matchers.push((
+            IgnoreSource::BuiltIn,
+            builder.build().map_err(invalid_pattern)?,
+        ));
+
+        Ok(Self { matchers })
+    }
+
+    /// Returns the pattern deciding whether `path` is ignored, or `None` when no
+    /// pattern matches it. A path inside an ignored directory is ignored as well.
+    pub fn explain(&self, path: &Path, is_dir: bool) -> Option<IgnoreMatch> {
+        for (source, matcher) in &self.matchers {
+            // The matcher panics on paths outside of its root
+            if path.is_absolute() && !path.starts_with(matcher.path()) {
+                continue;
+            }
+
+            let (pattern, ignored) = match matcher.matched_path_or_any_parents(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(glob) => (glob.original().to_string(), true),
+                Match::Whitelist(glob) => (glob.original().to_string(), false),
+            };
+            return Some(IgnoreMatch {
+                source: source.clone(),
+                pattern,
+                ignored,
+            });
+        }
+        None
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.explain(path, is_dir)
+            .map(|matched| matched.ignored)
+            .unwrap_or(false)
+    }
+}
+
+fn load_ignore_file(root: &Path, file: &Path) -> Result<(IgnoreSource, Gitignore), BucketError> {
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(e) = builder.add(file) {
+        return Err(invalid_pattern(e));
+    }
+    let matcher = builder.build().map_err(invalid_pattern)?;
+    Ok((IgnoreSource::File(file.to_path_buf()), matcher))
+}
+
+fn invalid_pattern(error: ignore::Error) -> BucketError {
+    BucketError::InvalidData(format!("invalid ignore pattern: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Creates a repository directory with a bucket, without a database.
+    fn setup() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let repo_path = temp_dir.path().join("repo");
+        let bucket_path = repo_path.join("art");
+        fs::create_dir_all(repo_path.join(".buckets")).expect("Failed to create .buckets");
+        fs::create_dir_all(bucket_path.join(".b")).expect("Failed to create .b");
+        (temp_dir, repo_path, bucket_path)
+    }
+
+    #[test]
+    fn test_default_patterns() {
+        let (_temp_dir, _repo_path, bucket_path) = setup();
+        let rules = IgnoreRules::for_bucket(&bucket_path).expect("Failed to load rules");
+
+        assert!(rules.is_ignored(&bucket_path.join("scene.blend1"), false));
+        assert!(rules.is_ignored(&bucket_path.join("shots").join("scene.blend12"), false));
+        assert!(rules.is_ignored(&bucket_path.join(".DS_Store"), false));
+        assert!(rules.is_ignored(&bucket_path.join("Thumbs.db"), false));
+        assert!(!rules.is_ignored(&bucket_path.join("scene.blend"), false));
+        assert!(!rules.is_ignored(&bucket_path.join("poster.psd"), false));
+
+        let matched = rules
+            .explain(&bucket_path.join("scene.blend1"), false)
+            .expect("Expected a match");
+        assert_eq!(matched.source, IgnoreSource::BuiltIn);
+        assert_eq!(matched.pattern, "*.blend[0-9]");
+    }
+
+    #[test]
+    fn test_repository_and_bucket_ignore_files() {
+        let (_temp_dir, repo_path, bucket_path) = setup();
+        fs::write(repo_path.join(IGNORE_FILE), "renders/\n*.exr\n").expect("Failed to write");
+        fs::write(bucket_path.join(IGNORE_FILE), "!final.exr\n!*.blend1\n")
+            .expect("Failed to write");
+        let rules = IgnoreRules::for_bucket(&bucket_path).expect("Failed to load rules");
+
+        // Repository patterns apply to every bucket
+        assert!(rules.is_ignored(&bucket_path.join("frame_001.exr"), false));
+        assert!(rules.is_ignored(&bucket_path.join("renders").join("frame.png"), false));
+
+        // Bucket patterns take precedence
+        let matched = rules
+            .explain(&bucket_path.join("final.exr"), false)
+            .expect("Expected a match");
+        assert!(!matched.ignored);
+        assert_eq!(matched.pattern, "!final.exr");
+        assert_eq!(
+            matched.source,
+            IgnoreSource::File(bucket_path.join(IGNORE_FILE))
+        );
+        assert!(!rules.is_ignored(&bucket_path.join("scene.blend1"), false));
+    }
+
+    #[test]
+    fn test_explain_unmatched_path() {
+        let (_temp_dir, _repo_path, bucket_path) = setup();
+        let rules = IgnoreRules::for_bucket(&bucket_path).expect("Failed to load rules");
+        assert!(rules
+            .explain(&bucket_path.join("model.fbx"), false)
+            .is_none());
+        assert!(rules
+            .explain(Path::new("/elsewhere/x.tmp"), false)
+            .is_none());
+    }
+}
@@ -0,0 +1,357 @@
+use crate::errors::BucketError;
+use crate::utils::utils::find_bucket_repo;
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Name of the lock file inside `.buckets`.
+const LOCK_FILE: &str = "lock";
+
+/// Name of the file inside `.buckets` that is locked while taking over a stale lock.
+const TAKEOVER_FILE: &str = "lock.takeover";
+
+/// Locks held by another host are considered stale after this many hours, as there
+/// is no way to check whether the process on that host is still running.
+const STALE_LOCK_HOURS: i64 = 24;
+
+/// Information about the process holding the repository lock.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub host: String,
+    pub command: String,
+    pub created_at: String,
+}
+
+impl LockInfo {
+    fn current(command: &str) -> Self {
+        Self {
+            pid: std::process::id(),
+            host: host_name(),
+            command: command.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// A lock is stale when its process is known to be gone, or when it is older
+    /// than `STALE_LOCK_HOURS` and held by another host.
+    fn is_stale(&self) -> bool {
+        if self.host == host_name() {
+            return !process_is_running(self.pid);
+        }
+
+        match DateTime::parse_from_rfc3339(&self.created_at) {
+            Ok(created_at) => {
+                Utc::now().signed_duration_since(created_at) > Duration::hours(STALE_LOCK_HOURS)
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Exclusive lock on a repository, released when dropped.
+///
+/// Commands that change the repository take this lock so that two invocations
+/// cannot write to the database and object storage at the same time.
+#[derive(Debug)]
+pub struct RepositoryLock {
+    path: PathBuf,
+}
+
+impl RepositoryLock {
+    /// Takes the lock of the repository whose `.buckets` directory is `buckets_dir`.
+    ///
+    /// The lock file is written next to the lock and linked in place, so it never exists
+    /// without its content. A stale lock left behind by a process that no longer runs is
+    /// taken over. Fails with `BucketError::RepositoryLocked` when another process holds
+    /// the lock, or when the lock file cannot be read.
+    pub fn acquire(buckets_dir: &Path, command: &str) -> Result<Self, BucketError> {
+        let path = buckets_dir.join(LOCK_FILE);
+        let info = LockInfo::current(command);
+        let serialized = toml::to_string(&info)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let mut temp_file = NamedTempFile::new_in(buckets_dir)?;
+        temp_file.write_all(serialized.as_bytes())?;
+        temp_file.as_file().sync_all()?;
+
+        // Try twice: the second attempt happens after removing a stale lock
+        for _ in 0..2 {
+            match fs::hard_link(temp_file.path(), &path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    remove_stale_lock(buckets_dir, &path)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(BucketError::RepositoryLocked(format!(
+            "could not take over the lock at {}",
+            path.display()
+        )))
+    }
+}
+
+/// Removes the lock at `path` if it is stale, and fails when it is held.
+///
+/// Two processes that both find the lock stale must not remove the lock one of them
+/// has taken in the meantime, so the lock is checked again and removed while holding
+/// an advisory lock on the `TAKEOVER_FILE`.
+fn remove_stale_lock(buckets_dir: &Path, path: &Path) -> Result<(), BucketError> {
+    let guard = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(buckets_dir.join(TAKEOVER_FILE))?;
+    guard.lock()?;
+
+    match fs::read_to_string(path) {
+        Ok(content) => match toml::from_str::<LockInfo>(&content) {
+            Ok(holder) if !holder.is_stale() => Err(BucketError::RepositoryLocked(format!(
+                "'{}' (process {} on {}) has held the lock since {}. \
+                 If that process is no longer running, remove {}",
+                holder.command,
+                holder.pid,
+                holder.host,
+                holder.created_at,
+                path.display()
+            ))),
+            Ok(_) => {
+                debug!("Removing stale repository lock {}", path.display());
+                fs::remove_file(path)?;
+                Ok(())
+            }
+            Err(_) => Err(BucketError::RepositoryLocked(format!(
+                "the lock at {} cannot be read. If no other command is running, remove it",
+                path.display()
+            ))),
+        },
+        // Released since the attempt to take it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Drop for RepositoryLock {
+    fn drop(&mut self) {
+        // Only remove the lock if it still belongs to this process
+        if let Some(info) = read_lock_info(&self.path) {
+            if info.pid == std::process::id() && info.host == host_name() {
+                if let Err(e) = fs::remove_file(&self.path) {
+                    debug!("Failed to remove repository lock: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Takes the lock of the repository containing `dir`.
+///
+/// Returns `None` when `dir` is not inside a repository.
+pub fn lock_repository(dir: &Path, command: &str) -> Result<Option<RepositoryLock>, BucketError> {
+    match find_bucket_repo(dir) {
+        Some(buckets_dir) => Ok(Some(RepositoryLock::acquire(&buckets_dir, command)?)),
+        None => Ok(None),
+    }
+}
+
+fn read_lock_info(path: &Path) -> Option<LockInfo> {
+    let content = fs::read_to_string(path).ok()?;
+    toml::from_str(&content).ok()
+}
+
+fn host_name() -> String {
+    if let Ok(host) = std::env::var("HOSTNAME") {
+        if !host.trim().is_empty() {
+            return host.trim().to_string();
+        }
+    }
+    if let Ok(host) = std::env::var("COMPUTERNAME") {
+        if !host.trim().is_empty() {
+            return host.trim().to_string();
+        }
+    }
+    fs::read_to_string("/etc/hostname")
+        .map(|host| host.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn process_is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks whether the process exists. EPERM means it exists but
+    // belongs to another user.
+    // SAFETY: kill with signal 0 sends no signal and has no other effect
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn process_is_running(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    // SAFETY: the handle is checked before use and closed afterwards
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            // Processes of other users cannot be opened, but are running
+            return io::Error::last_os_error().raw_os_error()
+                == Some(windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED as i32);
+        }
+        let mut exit_code = 0;
+        let running =
+            GetExitCodeProcess(handle, &mut exit_code) != 0 && exit_code == STILL_ACTIVE as u32;
+        CloseHandle(handle);
+        running
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_is_running(_pid: u32) -> bool {
+    // Without a way to check, assume the process is still running
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_acquire_and_release() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let lock_path = temp_dir.path().join(LOCK_FILE);
+
+        let lock = RepositoryLock::acquire(temp_dir.path(), "commit").expect("Failed to lock");
+        assert!(lock_path.exists());
+
+        let info = read_lock_info(&lock_path).expect("Failed to read lock");
+        assert_eq!(info.pid, std::process::id());
+        assert_eq!(info.command, "commit");
+
+        drop(lock);
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_acquire_held_lock_fails() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let _lock = RepositoryLock::acquire(temp_dir.path(), "commit").expect("Failed to lock");
+
+        let result = RepositoryLock::acquire(temp_dir.path(), "rollback");
+        match result {
+            Err(BucketError::RepositoryLocked(msg)) => {
+                assert!(msg.contains("'commit'"));
+                assert!(msg.contains(&std::process::id().to_string()));
+            }
+            _ => panic!("Expected RepositoryLocked error"),
+        }
+    }
+
+    #[test]
+    fn test_acquire_lock_held_by_other_host_fails() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let info = LockInfo {
+            pid: 1,
+            host: "some-other-workstation".to_string(),
+            command: "commit".to_string(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+        fs::write(
+            temp_dir.path().join(LOCK_FILE),
+            toml::to_string(&info).expect("Failed to serialize"),
+        )
+        .expect("Failed to write lock");
+
+        assert!(RepositoryLock::acquire(temp_dir.path(), "commit").is_err());
+    }
+
+    #[test]
+    fn test_stale_lock_is_taken_over() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+
+        // Lock of another host that is older than the stale limit
+        let info = LockInfo {
+            pid: 1,
+            host: "some-other-workstation".to_string(),
+            command: "commit".to_string(),
+            created_at: (Utc::now() - Duration::hours(STALE_LOCK_HOURS + 1)).to_rfc3339(),
+        };
+        fs::write(
+            temp_dir.path().join(LOCK_FILE),
+            toml::to_string(&info).expect("Failed to serialize"),
+        )
+        .expect("Failed to write lock");
+
+        let lock = RepositoryLock::acquire(temp_dir.path(), "rollback").expect("Failed to lock");
+        let info = read_lock_info(&lock.path).expect("Failed to read lock");
+        assert_eq!(info.command, "rollback");
+    }
+
+    #[test]
+    fn test_unreadable_lock_is_held() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let lock_path = temp_dir.path().join(LOCK_FILE);
+        for content in ["", "not a lock"] {
+            fs::write(&lock_path, content).expect("Failed to write lock");
+            match RepositoryLock::acquire(temp_dir.path(), "commit") {
+                Err(BucketError::RepositoryLocked(msg)) => assert!(msg.contains("cannot be read")),
+                _ => panic!("Expected RepositoryLocked error"),
+            }
+            assert_eq!(
+                fs::read_to_string(&lock_path).expect("Failed to read lock"),
+                content
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(any(unix, windows))]
+    fn test_lock_of_dead_process_is_stale() {
+        let info = LockInfo {
+            pid: u32::MAX,
+            host: host_name(),
+            command: "commit".to_string(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+        assert!(info.is_stale());
+
+        let info = LockInfo::current("commit");
+        assert!(!info.is_stale());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_lock_of_exited_child_is_stale() {
+        let mut child = std::process::Command::new("true")
+            .spawn()
+            .expect("Failed to start process");
+        let pid = child.id();
+        child.wait().expect("Failed to wait for process");
+
+        let info = LockInfo {
+            pid,
+            host: host_name(),
+            command: "commit".to_string(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+        assert!(info.is_stale());
+        assert!(process_is_running(1));
+    }
+
+    #[test]
+    fn test_lock_repository_outside_repo() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let lock = lock_repository(temp_dir.path(), "commit").expect("Failed to lock");
+        assert!(lock.is_none());
+    }
+}
@@ -1,5 +1,17 @@
+pub mod archive;
+pub mod cache;
 pub(crate) mod checks;
 pub mod compression;
 pub mod config;
+pub mod dictionary;
+pub mod encryption;
+pub mod identity;
+pub mod ignore_rules;
+pub mod lock;
+pub mod object_store;
+pub mod pack;
+pub mod recovery;
+pub mod s3;
 pub mod security;
+pub mod selection;
 pub mod utils;
@@ -0,0 +1,369 @@
+use crate::utils::compression::{sync_directory, TEMP_OBJECT_SUFFIX};
+use crate::utils::config::{RepositoryConfig, StorageConfig};
+use crate::utils::pack::{find_packed, packed_objects};
+use crate::utils::s3::S3Store;
+use crate::utils::utils::find_bucket_repo;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use tempfile::Builder;
+
+/// Stored objects by the hex hash of their content. Objects are kept exactly as they
+/// were written, header included, so compression and encryption happen before an object
+/// reaches its store.
+pub trait ObjectStore {
+    /// Stores a copy of the object file at `object_path` as `hash`.
+    fn put(&self, hash: &str, object_path: &Path) -> io::Result<()>;
+
+    /// Opens the stored bytes of an object. Fails with `NotFound` when it is not stored.
+    fn get(&self, hash: &str) -> io::Result<Box<dyn Read>>;
+
+    fn exists(&self, hash: &str) -> io::Result<bool>;
+
+    /// The hashes of all stored objects, sorted
+    fn list(&self) -> io::Result<Vec<String>>;
+
+    /// Removes an object. Removing an object that is not stored succeeds.
+    fn delete(&self, hash: &str) -> io::Result<()>;
+}
+
+/// The `.b/storage` directory of a bucket, with loose objects and packs
+pub struct LocalStore {
+    storage_path: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(storage_path: &Path) -> Self {
+        Self {
+            storage_path: storage_path.to_path_buf(),
+        }
+    }
+}
+
+impl ObjectStore for LocalStore {
+    fn put(&self, hash: &str, object_path: &Path) -> io::Result<()> {
+        let destination = self.storage_path.join(hash);
+        if destination == object_path {
+            return Ok(());
+        }
+        copy_object(object_path, &destination)
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Box<dyn Read>> {
+        match File::open(self.storage_path.join(hash)) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match find_packed(&self.storage_path, hash)? {
+                    Some(object) => Ok(Box::new(object.open()?)),
+                    None => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn exists(&self, hash: &str) -> io::Result<bool> {
+        if self.storage_path.join(hash).exists() {
+            return Ok(true);
+        }
+        Ok(find_packed(&self.storage_path, hash)?.is_some())
+    }
+
+    /// Loose objects and the objects in packs
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut hashes = object_files(&self.storage_path)?;
+        hashes.extend(
+            packed_objects(&self.storage_path)?
+                .into_iter()
+                .map(|(hash, _)| hash),
+        );
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    /// Removes a loose object. Packed objects are only removed together with their pack.
+    fn delete(&self, hash: &str) -> io::Result<()> {
+        match fs::remove_file(self.storage_path.join(hash)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if find_packed(&self.storage_path, hash)?.is_some() {
+                    return Err(io::Error::other(format!(
+                        "object {} is packed and cannot be removed on its own",
+                        hash
+                    )));
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// A directory outside the repository, such as a NAS mount
+pub struct DirectoryStore {
+    dir: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn put(&self, hash: &str, object_path: &Path) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        copy_object(object_path, &self.dir.join(hash))
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(self.dir.join(hash))?))
+    }
+
+    fn exists(&self, hash: &str) -> io::Result<bool> {
+        Ok(self.dir.join(hash).is_file())
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut hashes = object_files(&self.dir)?;
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    fn delete(&self, hash: &str) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(hash)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Names of the object files in `dir`, without objects that are still being written.
+pub fn object_files(dir: &Path) -> io::Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !is_temp_object(name))
+        .collect())
+}
+
+/// Objects still being written are skipped, they are cleaned up by commit recovery.
+fn is_temp_object(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(TEMP_OBJECT_SUFFIX)
+}
+
+/// Copies an object under a temporary name and renames it once it is on disk, so
+/// `destination` either does not exist or holds the complete object.
+fn copy_object(source: &Path, destination: &Path) -> io::Result<()> {
+    write_object(&mut File::open(source)?, destination)
+}
+
+/// Writes the object read from `source` to `destination`, the same way as `copy_object`.
+fn write_object(source: &mut dyn Read, destination: &Path) -> io::Result<()> {
+    let dir = match destination.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp_file = Builder::new()
+        .prefix(".")
+        .suffix(TEMP_OBJECT_SUFFIX)
+        .tempfile_in(dir)?;
+    io::copy(source, temp_file.as_file_mut())?;
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(destination).map_err(|e| e.error)?;
+    sync_directory(dir)
+}
+
+/// Splits the path of an object into its storage directory and its hash.
+pub fn object_location(object_path: &Path) -> io::Result<(&Path, &str)> {
+    match (
+        object_path.parent(),
+        object_path.file_name().and_then(|name| name.to_str()),
+    ) {
+        (Some(storage_path), Some(hash)) => Ok((storage_path, hash)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not an object path", object_path.display()),
+        )),
+    }
+}
+
+/// The hashes of the objects of a bucket, sorted. Objects kept in the bucket, such as objects
+/// committed before a remote store was configured, are listed with those of the remote store.
+pub fn stored_objects(storage_path: &Path) -> io::Result<Vec<String>> {
+    let mut hashes = LocalStore::new(storage_path).list()?;
+    if let Some(store) = remote_store(storage_path)? {
+        hashes.extend(store.list()?);
+        hashes.sort();
+        hashes.dedup();
+    }
+    Ok(hashes)
+}
+
+/// The store the repository config selects for the bucket with storage directory
+/// `storage_path`, when it keeps objects outside the bucket. Each bucket has its own
+/// directory or key prefix in the store, named after its path in the repository.
+pub fn remote_store(storage_path: &Path) -> io::Result<Option<Box<dyn ObjectStore>>> {
+    let Some(buckets_dir) = find_bucket_repo(storage_path) else {
+        return Ok(None);
+    };
+    let storage = match RepositoryConfig::from_file(storage_path.to_path_buf()) {
+        Ok(config) => config.storage,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let repo_dir = buckets_dir.parent().unwrap_or(Path::new("."));
+
+    let store: Box<dyn ObjectStore> = match storage {
+        StorageConfig::Local => return Ok(None),
+        StorageConfig::Directory { path } => Box::new(DirectoryStore::new(
+            &repo_dir
+                .join(path)
+                .join(bucket_in_repository(storage_path, repo_dir)?),
+        )),
+        StorageConfig::S3(config) => {
+            let bucket_key = bucket_in_repository(storage_path, repo_dir)?
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name.to_string_lossy()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            Box::new(S3Store::new(&config, &bucket_key)?)
+        }
+    };
+    Ok(Some(store))
+}
+
+/// The path of a bucket in its repository, from the storage directory of the bucket
+fn bucket_in_repository<'a>(storage_path: &'a Path, repo_dir: &Path) -> io::Result<&'a Path> {
+    storage_path
+        .parent()
+        .and_then(Path::parent)
+        .and_then(|bucket_path| bucket_path.strip_prefix(repo_dir).ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not the storage of a bucket", storage_path.display()),
+            )
+        })
+}
+
+/// Uploads an object just written to the storage directory of its bucket to the remote
+/// store of the repository. The local copy stays as part of the cache of the bucket.
+pub fn publish_object(object_path: &Path) -> io::Result<()> {
+    let (storage_path, hash) = object_location(object_path)?;
+    if let Some(store) = remote_store(storage_path)? {
+        // Without the local copy a failed upload is written again next time
+        if let Err(e) = store.put(hash, object_path) {
+            fs::remove_file(object_path)?;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Copies an object of the remote store into the storage directory `storage_path`, where
+/// it is read from until the cache is trimmed. Returns the size of the object.
+pub fn fetch_object(store: &dyn ObjectStore, storage_path: &Path, hash: &str) -> io::Result<u64> {
+    fs::create_dir_all(storage_path)?;
+    let object_path = storage_path.join(hash);
+    write_object(&mut store.get(hash)?, &object_path)?;
+    Ok(fs::metadata(&object_path)?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::compression::compress_and_store_file;
+    use crate::utils::pack::write_pack;
+    use tempfile::tempdir;
+
+    fn stored(store: &dyn ObjectStore, hash: &str) -> Vec<u8> {
+        let mut content = Vec::new();
+        store
+            .get(hash)
+            .expect("Failed to open object")
+            .read_to_end(&mut content)
+            .expect("Failed to read object");
+        content
+    }
+
+    #[test]
+    fn test_directory_store() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let store = DirectoryStore::new(&dir.path().join("nas").join("bucket"));
+        let object_path = dir.path().join("object");
+        fs::write(&object_path, b"stored bytes").expect("Failed to write object");
+
+        assert!(store.list().expect("Failed to list").is_empty());
+        assert!(!store.exists("b").expect("Failed to look up"));
+        store.put("b", &object_path).expect("Failed to put");
+        store.put("a", &object_path).expect("Failed to put");
+        assert!(store.exists("b").expect("Failed to look up"));
+        assert_eq!(stored(&store, "b"), b"stored bytes");
+        assert_eq!(store.list().expect("Failed to list"), vec!["a", "b"]);
+
+        store.delete("b").expect("Failed to delete");
+        store
+            .delete("b")
+            .expect("Failed to delete a missing object");
+        assert_eq!(
+            store.get("b").err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        assert_eq!(store.list().expect("Failed to list"), vec!["a"]);
+    }
+
+    #[test]
+    fn test_local_store_with_packs() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = dir.path().join("storage");
+        fs::create_dir_all(&storage_path).expect("Failed to create storage");
+        let source = dir.path().join("source.txt");
+
+        let mut objects = Vec::new();
+        for content in [b"packed".as_slice(), b"loose".as_slice()] {
+            fs::write(&source, content).expect("Failed to write file");
+            let hash = blake3::hash(content);
+            let object_path = storage_path.join(hash.to_hex().as_str());
+            compress_and_store_file(&source, &object_path, 0).expect("Failed to store object");
+            objects.push((hash, object_path));
+        }
+        write_pack(&storage_path, &objects[..1]).expect("Failed to write pack");
+        fs::remove_file(&objects[0].1).expect("Failed to remove loose object");
+
+        let store = LocalStore::new(&storage_path);
+        let packed = objects[0].0.to_hex().to_string();
+        let loose = objects[1].0.to_hex().to_string();
+        let mut expected = vec![packed.clone(), loose.clone()];
+        expected.sort();
+        assert_eq!(store.list().expect("Failed to list"), expected);
+        assert!(store.exists(&packed).expect("Failed to look up"));
+        assert!(!stored(&store, &packed).is_empty());
+
+        // Objects that are written in place are not copied
+        store
+            .put(&loose, &objects[1].1)
+            .expect("Failed to put object in place");
+        assert!(store.delete(&packed).is_err());
+        store.delete(&loose).expect("Failed to delete");
+        assert!(!store.exists(&loose).expect("Failed to look up"));
+    }
+
+    #[test]
+    fn test_remote_store_outside_repository() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let storage_path = dir.path().join("bucket").join(".b").join("storage");
+        assert!(remote_store(&storage_path)
+            .expect("Failed to open store")
+            .is_none());
+    }
+}
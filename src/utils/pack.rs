@@ -0,0 +1,330 @@
+use crate::utils::compression::sync_directory;
+use blake3::{Hash, Hasher};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tempfile::Builder;
+
+/// Directory in a storage directory holding the pack files
+pub const PACK_DIR: &str = "packs";
+const PACK_EXTENSION: &str = "pack";
+const INDEX_EXTENSION: &str = "idx";
+
+const PACK_MAGIC: &[u8; 4] = b"BKPK";
+const INDEX_MAGIC: &[u8; 4] = b"BKIX";
+const PACK_VERSION: u32 = 1;
+/// Magic number, version and number of records
+const INDEX_HEADER_LEN: u64 = 12;
+/// Hash, offset and length of an object
+const RECORD_LEN: u64 = 48;
+
+/// Where an object is stored in a pack
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedObject {
+    pub pack_path: PathBuf,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl PackedObject {
+    /// Opens the stored bytes of the object, exactly as its loose file held them.
+    pub fn open(&self) -> io::Result<io::Take<File>> {
+        let mut pack = File::open(&self.pack_path)?;
+        pack.seek(SeekFrom::Start(self.offset))?;
+        Ok(pack.take(self.length))
+    }
+}
+
+pub fn pack_dir(storage_path: &Path) -> PathBuf {
+    storage_path.join(PACK_DIR)
+}
+
+/// Index files of the packs in a storage directory. A pack is only used once its index
+/// is written, so a pack without an index is ignored.
+fn index_files(storage_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = pack_dir(storage_path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut indexes = fs::read_dir(&dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == INDEX_EXTENSION))
+        .collect::<Vec<_>>();
+    indexes.sort();
+    Ok(indexes)
+}
+
+/// Looks up the object named `hash` in the packs of a storage directory.
+pub fn find_packed(storage_path: &Path, hash: &str) -> io::Result<Option<PackedObject>> {
+    let Ok(hash) = Hash::from_hex(hash) else {
+        return Ok(None);
+    };
+    for index_path in index_files(storage_path)? {
+        if let Some((offset, length)) = search_index(&index_path, hash.as_bytes())? {
+            return Ok(Some(PackedObject {
+                pack_path: index_path.with_extension(PACK_EXTENSION),
+                offset,
+                length,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Every object in the packs of a storage directory, by hash
+pub fn packed_objects(storage_path: &Path) -> io::Result<Vec<(String, PackedObject)>> {
+    let mut objects = Vec::new();
+    for index_path in index_files(storage_path)? {
+        let mut index = File::open(&index_path)?;
+        let count = read_index_header(&mut index, &index_path)?;
+        let mut records = Vec::new();
+        index.read_to_end(&mut records)?;
+        if records.len() as u64 != count * RECORD_LEN {
+            return Err(invalid_index(&index_path));
+        }
+        for record in records.chunks_exact(RECORD_LEN as usize) {
+            let (hash, offset, length) = parse_record(record);
+            objects.push((
+                Hash::from(hash).to_hex().to_string(),
+                PackedObject {
+                    pack_path: index_path.with_extension(PACK_EXTENSION),
+                    offset,
+                    length,
+                },
+            ));
+        }
+    }
+    Ok(objects)
+}
+
+/// Binary search of the sorted records of an index for `hash`. Records have a fixed size,
+/// so only the records on the search path are read.
+fn search_index(index_path: &Path, hash: &[u8; 32]) -> io::Result<Option<(u64, u64)>> {
+    let mut index = File::open(index_path)?;
+    let count = read_index_header(&mut index, index_path)?;
+
+    let mut record = [0u8; RECORD_LEN as usize];
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        index.seek(SeekFrom::Start(INDEX_HEADER_LEN + middle * RECORD_LEN))?;
+        index.read_exact(&mut record)?;
+        let (candidate, offset, length) = parse_record(&record);
+        match candidate.cmp(hash) {
+            std::cmp::Ordering::Equal => return Ok(Some((offset, length))),
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+        }
+    }
+    Ok(None)
+}
+
+/// Checks the header of an index and returns its number of records.
+fn read_index_header(index: &mut File, index_path: &Path) -> io::Result<u64> {
+    let mut header = [0u8; INDEX_HEADER_LEN as usize];
+    index
+        .read_exact(&mut header)
+        .map_err(|_| invalid_index(index_path))?;
+    if &header[..4] != INDEX_MAGIC || header[4..8] != PACK_VERSION.to_le_bytes() {
+        return Err(invalid_index(index_path));
+    }
+    Ok(u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as u64)
+}
+
+fn parse_record(record: &[u8]) -> ([u8; 32], u64, u64) {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&record[..32]);
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&record[32..40]);
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&record[40..48]);
+    (hash, u64::from_le_bytes(offset), u64::from_le_bytes(length))
+}
+
+fn invalid_index(index_path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid pack index {}", index_path.display()),
+    )
+}
+
+/// Copies the loose objects at the given paths into a new pack of `storage_path` and
+/// returns the path of the pack. The loose objects are left in place.
+///
+/// The pack starts with `BKPK` and a version, followed by the objects as they were stored.
+/// The index starts with `BKIX`, the version and the number of objects, followed by a
+/// record of hash, offset and length per object, sorted by hash. Both are written to
+/// temporary files first, and the index is renamed into place last.
+pub fn write_pack(storage_path: &Path, objects: &[(Hash, PathBuf)]) -> io::Result<PathBuf> {
+    let mut objects = objects.to_vec();
+    objects.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    objects.dedup_by(|a, b| a.0 == b.0);
+
+    let dir = pack_dir(storage_path);
+    fs::create_dir_all(&dir)?;
+
+    // Named after the objects it holds
+    let mut name_hasher = Hasher::new();
+    for (hash, _) in &objects {
+        name_hasher.update(hash.as_bytes());
+    }
+    let name = format!("pack-{}", &name_hasher.finalize().to_hex()[..16]);
+    let pack_path = dir.join(format!("{}.{}", name, PACK_EXTENSION));
+    let index_path = dir.join(format!("{}.{}", name, INDEX_EXTENSION));
+
+    let mut pack = Builder::new()
+        .prefix(".pack")
+        .suffix(".tmp")
+        .tempfile_in(&dir)?;
+    let mut records = Vec::with_capacity(objects.len() * RECORD_LEN as usize);
+    {
+        let mut writer = BufWriter::new(pack.as_file_mut());
+        writer.write_all(PACK_MAGIC)?;
+        writer.write_all(&PACK_VERSION.to_le_bytes())?;
+        let mut offset = (PACK_MAGIC.len() + 4) as u64;
+        for (hash, path) in &objects {
+            let length = io::copy(&mut File::open(path)?, &mut writer)?;
+            records.extend_from_slice(hash.as_bytes());
+            records.extend_from_slice(&offset.to_le_bytes());
+            records.extend_from_slice(&length.to_le_bytes());
+            offset += length;
+        }
+        writer.flush()?;
+    }
+    pack.as_file().sync_all()?;
+    pack.persist(&pack_path).map_err(|e| e.error)?;
+
+    let count = u32::try_from(objects.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many objects for a pack"))?;
+    let mut index = Builder::new()
+        .prefix(".idx")
+        .suffix(".tmp")
+        .tempfile_in(&dir)?;
+    index.write_all(INDEX_MAGIC)?;
+    index.write_all(&PACK_VERSION.to_le_bytes())?;
+    index.write_all(&count.to_le_bytes())?;
+    index.write_all(&records)?;
+    index.as_file().sync_all()?;
+    index.persist(&index_path).map_err(|e| e.error)?;
+    sync_directory(&dir)?;
+
+    Ok(pack_path)
+}
+
+/// Removes every pack of a storage directory, once all of their objects are stored loose.
+pub fn remove_packs(storage_path: &Path) -> io::Result<()> {
+    for index_path in index_files(storage_path)? {
+        fs::remove_file(&index_path)?;
+        fs::remove_file(index_path.with_extension(PACK_EXTENSION))?;
+    }
+    sync_directory(&pack_dir(storage_path))
+}
+
+/// Removes packs whose index was never written and temporary files of interrupted packs.
+pub fn remove_incomplete_packs(storage_path: &Path) -> io::Result<()> {
+    let dir = pack_dir(storage_path);
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(&dir)?.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let unindexed = path.extension().is_some_and(|ext| ext == PACK_EXTENSION)
+            && !path.with_extension(INDEX_EXTENSION).exists();
+        if unindexed || (name.starts_with('.') && name.ends_with(".tmp")) {
+            log::debug!("Removing incomplete pack file {}", name);
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn loose_object(storage_path: &Path, content: &[u8]) -> (Hash, PathBuf) {
+        let hash = blake3::hash(content);
+        let path = storage_path.join(hash.to_hex().as_str());
+        fs::write(&path, content).expect("Failed to write object");
+        (hash, path)
+    }
+
+    fn read_packed(object: &PackedObject) -> Vec<u8> {
+        let mut content = Vec::new();
+        object
+            .open()
+            .expect("Failed to open packed object")
+            .read_to_end(&mut content)
+            .expect("Failed to read packed object");
+        content
+    }
+
+    #[test]
+    fn test_write_and_find_packed_objects() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let objects = (0..50)
+            .map(|i| loose_object(dir.path(), format!("object {}", i).as_bytes()))
+            .collect::<Vec<_>>();
+        let pack_path = write_pack(dir.path(), &objects).expect("Failed to write pack");
+        assert!(pack_path.exists());
+        assert!(pack_path.with_extension(INDEX_EXTENSION).exists());
+
+        for (i, (hash, _)) in objects.iter().enumerate() {
+            let object = find_packed(dir.path(), hash.to_hex().as_str())
+                .expect("Failed to search packs")
+                .expect("Object not found in pack");
+            assert_eq!(read_packed(&object), format!("object {}", i).into_bytes());
+        }
+
+        let missing = blake3::hash(b"missing").to_hex();
+        assert_eq!(
+            find_packed(dir.path(), missing.as_str()).expect("Failed to search packs"),
+            None
+        );
+        assert_eq!(
+            find_packed(dir.path(), "not a hash").expect("Failed to search packs"),
+            None
+        );
+        assert_eq!(
+            packed_objects(dir.path())
+                .expect("Failed to list packs")
+                .len(),
+            50
+        );
+    }
+
+    #[test]
+    fn test_objects_in_several_packs() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let first = loose_object(dir.path(), b"first");
+        let second = loose_object(dir.path(), b"second");
+        write_pack(dir.path(), std::slice::from_ref(&first)).expect("Failed to write pack");
+        write_pack(dir.path(), std::slice::from_ref(&second)).expect("Failed to write pack");
+
+        for (hash, content) in [(first.0, b"first".as_slice()), (second.0, b"second")] {
+            let object = find_packed(dir.path(), hash.to_hex().as_str())
+                .expect("Failed to search packs")
+                .expect("Object not found in pack");
+            assert_eq!(read_packed(&object), content);
+        }
+    }
+
+    #[test]
+    fn test_pack_without_index_is_ignored_and_removed() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let object = loose_object(dir.path(), b"content");
+        let pack_path =
+            write_pack(dir.path(), std::slice::from_ref(&object)).expect("Failed to write pack");
+        fs::remove_file(pack_path.with_extension(INDEX_EXTENSION)).expect("Failed to remove");
+
+        assert_eq!(
+            find_packed(dir.path(), object.0.to_hex().as_str()).expect("Failed to search"),
+            None
+        );
+        remove_incomplete_packs(dir.path()).expect("Failed to clean up packs");
+        assert!(!pack_path.exists());
+    }
+}
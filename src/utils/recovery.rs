@@ -0,0 +1,331 @@
+use crate::database::open_metadata_store;
+use crate::errors::BucketError;
+use crate::utils::compression::{sync_directory, TEMP_OBJECT_SUFFIX};
+use crate::utils::lock::RepositoryLock;
+use crate::utils::object_store::remote_store;
+use crate::utils::utils::find_bucket_repo;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Directory inside `.buckets` holding the journals of commits in progress.
+const PENDING_DIR: &str = "pending";
+
+/// Journal of a commit that has started writing objects but is not yet recorded
+/// in the database.
+///
+/// The journal is written before the first object is stored and removed after the
+/// database transaction has been committed. A journal that still exists on the next
+/// start belongs to an interrupted commit.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PendingCommit {
+    pub commit_id: Uuid,
+    pub bucket_id: Uuid,
+    pub bucket_path: PathBuf,
+    /// Objects written by this commit which did not exist in storage before
+    pub objects: Vec<String>,
+}
+
+impl PendingCommit {
+    pub fn new(commit_id: Uuid, bucket_id: Uuid, bucket_path: &Path, objects: Vec<String>) -> Self {
+        Self {
+            commit_id,
+            bucket_id,
+            bucket_path: bucket_path.to_path_buf(),
+            objects,
+        }
+    }
+
+    fn journal_path(&self, buckets_dir: &Path) -> PathBuf {
+        buckets_dir
+            .join(PENDING_DIR)
+            .join(self.commit_id.to_string())
+    }
+
+    /// Writes the journal to disk and flushes it before returning.
+    pub fn write(&self, buckets_dir: &Path) -> io::Result<()> {
+        let pending_dir = buckets_dir.join(PENDING_DIR);
+        fs::create_dir_all(&pending_dir)?;
+
+        let serialized = toml::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let mut file = fs::File::create(self.journal_path(buckets_dir))?;
+        file.write_all(serialized.as_bytes())?;
+        file.sync_all()?;
+        sync_directory(&pending_dir)
+    }
+
+    /// Removes the journal once the commit is complete.
+    pub fn remove(&self, buckets_dir: &Path) -> io::Result<()> {
+        fs::remove_file(self.journal_path(buckets_dir))
+    }
+
+    fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+/// Cleans up commits that were interrupted in the repository containing `dir`.
+///
+/// Does nothing when `dir` is not inside a repository or no commit was interrupted.
+/// A journal may also belong to a commit that is still running in another process,
+/// so without `holds_lock` recovery only happens if the repository lock is free.
+pub fn recover_repository(dir: &Path, holds_lock: bool) -> Result<(), BucketError> {
+    let buckets_dir = match find_bucket_repo(dir) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    if !has_pending_commits(&buckets_dir) {
+        return Ok(());
+    }
+
+    let _lock = if holds_lock {
+        None
+    } else {
+        match RepositoryLock::acquire(&buckets_dir, "recover") {
+            Ok(lock) => Some(lock),
+            Err(BucketError::RepositoryLocked(msg)) => {
+                debug!("Skipping commit recovery: {}", msg);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    for pending in recover_interrupted_commits(&buckets_dir)? {
+        eprintln!(
+            "Recovered interrupted commit {}: removed {} unreferenced objects",
+            pending.commit_id,
+            pending.objects.len()
+        );
+    }
+    Ok(())
+}
+
+fn has_pending_commits(buckets_dir: &Path) -> bool {
+    fs::read_dir(buckets_dir.join(PENDING_DIR))
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false)
+}
+
+/// Rolls back every commit with a journal in `buckets_dir`.
+///
+/// Commits that reached the database are kept and only their journal is removed.
+/// For the others every object they created that is not referenced by the bucket
+/// is deleted, together with any partially written objects in the bucket storage.
+/// Returns the commits that were rolled back, with only the removed objects listed.
+pub fn recover_interrupted_commits(buckets_dir: &Path) -> Result<Vec<PendingCommit>, BucketError> {
+    let pending_dir = buckets_dir.join(PENDING_DIR);
+    if !pending_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut journals = fs::read_dir(&pending_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    if journals.is_empty() {
+        return Ok(Vec::new());
+    }
+    journals.sort();
+
+    let connection = open_metadata_store(buckets_dir)?;
+    let mut recovered = Vec::new();
+
+    for journal in journals {
+        let mut pending = PendingCommit::read(&journal)?;
+
+        let committed: i64 = connection.query_value(
+            "SELECT COUNT(*) FROM commits WHERE id = ?1",
+            &[pending.commit_id.into()],
+        )?;
+
+        if committed > 0 {
+            debug!("Commit {} completed, removing journal", pending.commit_id);
+            fs::remove_file(&journal)?;
+            continue;
+        }
+
+        let storage_path = pending.bucket_path.join(".b").join("storage");
+        let remote = remote_store(&storage_path)?;
+        let mut removed = Vec::new();
+        for hash in &pending.objects {
+            let referenced: i64 = connection.query_value(
+                "SELECT COUNT(*)
+                 FROM files f
+                 JOIN commits c ON f.commit_id = c.id
+                 WHERE f.hash = ?1 AND c.bucket_id = ?2",
+                &[hash.into(), pending.bucket_id.into()],
+            )?;
+            if referenced > 0 {
+                continue;
+            }
+            // An object can be staged in the bucket storage and uploaded already
+            let object_path = storage_path.join(hash);
+            let mut was_stored = false;
+            if object_path.exists() {
+                fs::remove_file(&object_path)?;
+                was_stored = true;
+            }
+            if let Some(store) = &remote {
+                if store.exists(hash)? {
+                    store.delete(hash)?;
+                    was_stored = true;
+                }
+            }
+            if was_stored {
+                removed.push(hash.clone());
+            }
+        }
+        remove_temp_objects(&storage_path)?;
+
+        fs::remove_file(&journal)?;
+        pending.objects = removed;
+        recovered.push(pending);
+    }
+
+    Ok(recovered)
+}
+
+/// Removes objects that were still being written when a commit was interrupted.
+fn remove_temp_objects(storage_path: &Path) -> io::Result<()> {
+    if !storage_path.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(storage_path)?.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') && name.ends_with(TEMP_OBJECT_SUFFIX) {
+            debug!("Removing partially written object {}", name);
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{initialize_database, DatabaseType};
+    use tempfile::tempdir;
+
+    fn setup() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let buckets_dir = temp_dir.path().join(".buckets");
+        fs::create_dir_all(&buckets_dir).expect("Failed to create .buckets");
+        initialize_database(&buckets_dir, DatabaseType::DuckDB)
+            .expect("Failed to initialize database");
+
+        let bucket_path = temp_dir.path().join("test_bucket");
+        fs::create_dir_all(bucket_path.join(".b").join("storage"))
+            .expect("Failed to create storage");
+        (temp_dir, buckets_dir, bucket_path)
+    }
+
+    #[test]
+    fn test_pending_commit_roundtrip() {
+        let (_temp_dir, buckets_dir, bucket_path) = setup();
+        let pending = PendingCommit::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &bucket_path,
+            vec!["abc".to_string()],
+        );
+
+        pending
+            .write(&buckets_dir)
+            .expect("Failed to write journal");
+        let read = PendingCommit::read(&pending.journal_path(&buckets_dir))
+            .expect("Failed to read journal");
+        assert_eq!(read, pending);
+
+        pending
+            .remove(&buckets_dir)
+            .expect("Failed to remove journal");
+        assert!(!pending.journal_path(&buckets_dir).exists());
+    }
+
+    #[test]
+    fn test_recover_without_journals() {
+        let (_temp_dir, buckets_dir, _bucket_path) = setup();
+        let recovered = recover_interrupted_commits(&buckets_dir).expect("Recovery failed");
+        assert!(recovered.is_empty());
+    }
+
+    #[test]
+    fn test_recover_interrupted_commit() {
+        let (_temp_dir, buckets_dir, bucket_path) = setup();
+        let storage_path = bucket_path.join(".b").join("storage");
+
+        // An object written by the interrupted commit and a half written object
+        fs::write(storage_path.join("written"), b"object").expect("Failed to write object");
+        fs::write(storage_path.join(".partial.tmp"), b"obj").expect("Failed to write object");
+        // An object belonging to an earlier commit
+        fs::write(storage_path.join("existing"), b"object").expect("Failed to write object");
+
+        let pending = PendingCommit::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &bucket_path,
+            vec!["written".to_string(), "never_written".to_string()],
+        );
+        pending
+            .write(&buckets_dir)
+            .expect("Failed to write journal");
+
+        let recovered = recover_interrupted_commits(&buckets_dir).expect("Recovery failed");
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].objects, vec!["written".to_string()]);
+
+        assert!(!storage_path.join("written").exists());
+        assert!(!storage_path.join(".partial.tmp").exists());
+        assert!(storage_path.join("existing").exists());
+        assert!(!pending.journal_path(&buckets_dir).exists());
+    }
+
+    #[test]
+    fn test_recover_completed_commit_keeps_objects() {
+        let (_temp_dir, buckets_dir, bucket_path) = setup();
+        let storage_path = bucket_path.join(".b").join("storage");
+        fs::write(storage_path.join("written"), b"object").expect("Failed to write object");
+
+        let bucket_id = Uuid::new_v4();
+        let commit_id = Uuid::new_v4();
+        let connection = open_metadata_store(&buckets_dir).expect("Failed to open database");
+        connection
+            .execute(
+                "INSERT INTO buckets (id, name, path) VALUES (?1, 'test_bucket', 'test_bucket')",
+                &[bucket_id.into()],
+            )
+            .expect("Failed to insert bucket");
+        connection
+            .execute(
+                "INSERT INTO commits (id, bucket_id, message) VALUES (?1, ?2, 'message')",
+                &[commit_id.into(), bucket_id.into()],
+            )
+            .expect("Failed to insert commit");
+        connection.close().expect("Failed to close connection");
+
+        let pending = PendingCommit::new(
+            commit_id,
+            bucket_id,
+            &bucket_path,
+            vec!["written".to_string()],
+        );
+        pending
+            .write(&buckets_dir)
+            .expect("Failed to write journal");
+
+        let recovered = recover_interrupted_commits(&buckets_dir).expect("Recovery failed");
+        assert!(recovered.is_empty());
+        assert!(storage_path.join("written").exists());
+        assert!(!pending.journal_path(&buckets_dir).exists());
+    }
+}
@@ -0,0 +1,516 @@
+use crate::utils::config::S3Config;
+use crate::utils::encryption::to_hex;
+use crate::utils::object_store::ObjectStore;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+/// Environment variables holding the credentials for S3 storage
+pub const ACCESS_KEY_ENV: &str = "AWS_ACCESS_KEY_ID";
+pub const SECRET_KEY_ENV: &str = "AWS_SECRET_ACCESS_KEY";
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+/// SHA-256 of an empty request body
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Objects in a bucket of an S3-compatible service, addressed path style as
+/// `<endpoint>/<bucket>/<prefix>/<hash>` so it also works with services such as MinIO.
+/// Requests are signed with AWS Signature Version 4.
+pub struct S3Store {
+    agent: ureq::Agent,
+    /// Scheme and authority of the endpoint
+    endpoint: String,
+    /// Authority of the endpoint, sent and signed as the `Host` header
+    host: String,
+    bucket: String,
+    region: String,
+    /// Prepended to every object key, empty or ending with a `/`
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3Store {
+    /// The store for the objects of the bucket at `bucket_key` in the repository, with
+    /// the credentials from the environment.
+    pub fn new(config: &S3Config, bucket_key: &str) -> io::Result<Self> {
+        match (std::env::var(ACCESS_KEY_ENV), std::env::var(SECRET_KEY_ENV)) {
+            (Ok(access_key), Ok(secret_key)) => {
+                Self::with_credentials(config, bucket_key, &access_key, &secret_key)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "S3 storage needs credentials in {} and {}",
+                    ACCESS_KEY_ENV, SECRET_KEY_ENV
+                ),
+            )),
+        }
+    }
+
+    pub fn with_credentials(
+        config: &S3Config,
+        bucket_key: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> io::Result<Self> {
+        let endpoint = config.endpoint.trim_end_matches('/');
+        let host = endpoint
+            .strip_prefix("https://")
+            .or_else(|| endpoint.strip_prefix("http://"))
+            .filter(|host| !host.is_empty() && !host.contains('/'))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "S3 endpoint {} must be an http or https URL without a path",
+                        config.endpoint
+                    ),
+                )
+            })?;
+        let mut prefix = [config.prefix.trim_matches('/'), bucket_key]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .build(),
+            endpoint: endpoint.to_string(),
+            host: host.to_string(),
+            bucket: config.bucket.clone(),
+            region: config.region.clone(),
+            prefix,
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+        })
+    }
+
+    fn object_path(&self, hash: &str) -> String {
+        format!("/{}/{}{}", self.bucket, self.prefix, hash)
+    }
+
+    /// A request for `path` with `query`, signed for a body with SHA-256 `payload_hash`.
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+        payload_hash: &str,
+    ) -> ureq::Request {
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let canonical_path = uri_encode(path, false);
+        let mut query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+            .collect::<Vec<_>>();
+        query.sort();
+        let canonical_query = query.join("&");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            canonical_path,
+            canonical_query,
+            self.host,
+            payload_hash,
+            amz_date,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", &amz_date[..8], self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            to_hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, &amz_date[..8], &self.region, "s3");
+        let signature = to_hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let mut url = format!("{}{}", self.endpoint, canonical_path);
+        if !canonical_query.is_empty() {
+            url.push('?');
+            url.push_str(&canonical_query);
+        }
+        self.agent
+            .request(method, &url)
+            .set("Host", &self.host)
+            .set("x-amz-date", &amz_date)
+            .set("x-amz-content-sha256", payload_hash)
+            .set(
+                "Authorization",
+                &format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key, scope, SIGNED_HEADERS, signature
+                ),
+            )
+    }
+
+    /// Sends a request, a missing object is `None`.
+    fn send(
+        &self,
+        request: ureq::Request,
+        body: Option<File>,
+    ) -> io::Result<Option<ureq::Response>> {
+        let result = match body {
+            Some(file) => request.send(file),
+            None => request.call(),
+        };
+        match result {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let code = xml_values(&body, "Code").into_iter().next();
+                Err(io::Error::other(format!(
+                    "S3 request to bucket {} failed with status {}{}",
+                    self.bucket,
+                    status,
+                    code.map(|code| format!(": {}", code)).unwrap_or_default()
+                )))
+            }
+            Err(e) => Err(io::Error::other(format!("S3 request failed: {}", e))),
+        }
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn put(&self, hash: &str, object_path: &Path) -> io::Result<()> {
+        let mut file = File::open(object_path)?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let payload_hash = to_hex(&hasher.finalize());
+        let request = self
+            .request("PUT", &self.object_path(hash), &[], &payload_hash)
+            .set("Content-Length", &size.to_string());
+        self.send(request, Some(file))?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("S3 bucket {} not found", self.bucket),
+            )
+        })?;
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Box<dyn Read>> {
+        let request = self.request("GET", &self.object_path(hash), &[], EMPTY_PAYLOAD_HASH);
+        match self.send(request, None)? {
+            Some(response) => Ok(Box::new(response.into_reader())),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("object {} not found in S3 bucket {}", hash, self.bucket),
+            )),
+        }
+    }
+
+    fn exists(&self, hash: &str) -> io::Result<bool> {
+        let request = self.request("HEAD", &self.object_path(hash), &[], EMPTY_PAYLOAD_HASH);
+        Ok(self.send(request, None)?.is_some())
+    }
+
+    /// Lists the keys under the prefix of the store, a page of up to 1000 keys at a time.
+    fn list(&self) -> io::Result<Vec<String>> {
+        let bucket_path = format!("/{}", self.bucket);
+        let mut hashes = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let request = self.request("GET", &bucket_path, &query, EMPTY_PAYLOAD_HASH);
+            let body = self
+                .send(request, None)?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("S3 bucket {} not found", self.bucket),
+                    )
+                })?
+                .into_string()?;
+
+            hashes.extend(
+                xml_values(&body, "Key")
+                    .into_iter()
+                    .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+                    .filter(|hash| !hash.contains('/')),
+            );
+            let truncated =
+                xml_values(&body, "IsTruncated").first().map(String::as_str) == Some("true");
+            token = xml_values(&body, "NextContinuationToken")
+                .into_iter()
+                .next();
+            if !truncated || token.is_none() {
+                break;
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    fn delete(&self, hash: &str) -> io::Result<()> {
+        let request = self.request("DELETE", &self.object_path(hash), &[], EMPTY_PAYLOAD_HASH);
+        self.send(request, None)?;
+        Ok(())
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The Signature Version 4 key for `date` (as `YYYYMMDD`), `region` and `service`
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Percent-encodes everything but unreserved characters, and `/` unless `encode_slash`.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The text of every `<tag>` element in an S3 XML response
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        values.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /// A stand-in for an S3-compatible service, keeping objects in memory. Listings
+    /// return two keys a page to exercise continuation tokens.
+    fn start_server() -> (String, Objects) {
+        let server = tiny_http::Server::http("127.0.0.1:0").expect("Failed to start server");
+        let address = server.server_addr().to_string();
+        let objects = Arc::new(Mutex::new(BTreeMap::<String, Vec<u8>>::new()));
+        let stored = Arc::clone(&objects);
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let signed = request.headers().iter().any(|header| {
+                    header.field.equiv("Authorization")
+                        && header
+                            .value
+                            .as_str()
+                            .starts_with("AWS4-HMAC-SHA256 Credential=test-key/")
+                });
+                let url = request.url().to_string();
+                let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+                let key = path.trim_start_matches("/assets/").to_string();
+                let mut objects = stored.lock().expect("Failed to lock objects");
+
+                let response = if !signed {
+                    tiny_http::Response::from_string("").with_status_code(403)
+                } else if query.contains("list-type=2") {
+                    let parameter = |name: &str| {
+                        query
+                            .split('&')
+                            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+                            .unwrap_or("")
+                            .replace("%2F", "/")
+                    };
+                    let prefix = parameter("prefix");
+                    let start = parameter("continuation-token").parse().unwrap_or(0);
+                    let keys = objects
+                        .keys()
+                        .filter(|key| key.starts_with(&prefix))
+                        .collect::<Vec<_>>();
+                    let page = keys.iter().skip(start).take(2);
+                    let mut xml = String::from("<ListBucketResult>");
+                    for key in page {
+                        xml.push_str(&format!("<Contents><Key>{}</Key></Contents>", key));
+                    }
+                    if start + 2 < keys.len() {
+                        xml.push_str(&format!(
+                            "<IsTruncated>true</IsTruncated>\
+                             <NextContinuationToken>{}</NextContinuationToken>",
+                            start + 2
+                        ));
+                    } else {
+                        xml.push_str("<IsTruncated>false</IsTruncated>");
+                    }
+                    xml.push_str("</ListBucketResult>");
+                    tiny_http::Response::from_string(xml)
+                } else {
+                    match request.method() {
+                        tiny_http::Method::Put => {
+                            let mut body = Vec::new();
+                            request
+                                .as_reader()
+                                .read_to_end(&mut body)
+                                .expect("Failed to read body");
+                            objects.insert(key, body);
+                            tiny_http::Response::from_string("")
+                        }
+                        tiny_http::Method::Get | tiny_http::Method::Head => {
+                            match objects.get(&key) {
+                                Some(body) => tiny_http::Response::from_data(body.clone()),
+                                None => tiny_http::Response::from_string("").with_status_code(404),
+                            }
+                        }
+                        tiny_http::Method::Delete => {
+                            objects.remove(&key);
+                            tiny_http::Response::from_string("").with_status_code(204)
+                        }
+                        _ => tiny_http::Response::from_string("").with_status_code(405),
+                    }
+                };
+                drop(objects);
+                let _ = request.respond(response);
+            }
+        });
+        (format!("http://{}", address), objects)
+    }
+
+    fn config(endpoint: &str) -> S3Config {
+        S3Config {
+            endpoint: endpoint.to_string(),
+            bucket: "assets".to_string(),
+            region: "us-east-1".to_string(),
+            prefix: "studio/".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_s3_store_roundtrip() {
+        let (endpoint, objects) = start_server();
+        let store =
+            S3Store::with_credentials(&config(&endpoint), "props/chairs", "test-key", "secret")
+                .expect("Failed to create store");
+
+        let dir = tempdir().expect("Failed to create temp dir");
+        let object_path = dir.path().join("object");
+        let hashes = ["a1", "b2", "c3"];
+        for hash in hashes {
+            fs::write(&object_path, format!("object {}", hash)).expect("Failed to write object");
+            store.put(hash, &object_path).expect("Failed to put object");
+        }
+        assert!(objects
+            .lock()
+            .expect("Failed to lock objects")
+            .contains_key("studio/props/chairs/a1"));
+
+        assert!(store.exists("b2").expect("Failed to look up object"));
+        assert!(!store.exists("d4").expect("Failed to look up object"));
+        let mut content = String::new();
+        store
+            .get("c3")
+            .expect("Failed to get object")
+            .read_to_string(&mut content)
+            .expect("Failed to read object");
+        assert_eq!(content, "object c3");
+        assert_eq!(
+            store.get("d4").err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        // Three keys take two pages
+        assert_eq!(store.list().expect("Failed to list"), hashes);
+        store.delete("b2").expect("Failed to delete object");
+        assert_eq!(store.list().expect("Failed to list"), vec!["a1", "c3"]);
+
+        // Requests are signed
+        let unsigned = S3Store::with_credentials(&config(&endpoint), "", "other", "secret")
+            .expect("Failed to create store");
+        assert!(unsigned.exists("a1").is_err());
+    }
+
+    #[test]
+    fn test_endpoint_validation() {
+        assert!(S3Store::with_credentials(&config("localhost:9000"), "", "key", "secret").is_err());
+        assert!(S3Store::with_credentials(
+            &config("http://localhost:9000/path"),
+            "",
+            "key",
+            "secret"
+        )
+        .is_err());
+        let store = S3Store::with_credentials(&config("https://s3.example.com/"), "", "key", "s")
+            .expect("Failed to create store");
+        assert_eq!(store.host, "s3.example.com");
+        assert_eq!(store.object_path("ab"), "/assets/studio/ab");
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // Example from the AWS Signature Version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            to_hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(
+            uri_encode("/assets/my props/a~b", false),
+            "/assets/my%20props/a~b"
+        );
+        assert_eq!(uri_encode("studio/", true), "studio%2F");
+    }
+
+    #[test]
+    fn test_xml_values() {
+        let xml = "<R><Key>a</Key><Key>b&amp;c</Key><IsTruncated>false</IsTruncated></R>";
+        assert_eq!(xml_values(xml, "Key"), vec!["a", "b&c"]);
+        assert_eq!(xml_values(xml, "IsTruncated"), vec!["false"]);
+        assert!(xml_values(xml, "Code").is_empty());
+    }
+}
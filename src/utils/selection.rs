@@ -0,0 +1,163 @@
+use crate::errors::BucketError;
+use crate::utils::utils::bucket_relative_path;
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::Path;
+
+/// A single path given on the command line.
+#[derive(Debug)]
+enum Selector {
+    /// A file, or a directory selecting every file below it
+    Path(String),
+    /// A glob pattern such as `textures/*.png` or `**/*.psd`
+    Glob(GlobMatcher),
+}
+
+/// Files of a bucket selected by paths, directories and glob patterns given on the
+/// command line, relative to the current directory.
+#[derive(Debug)]
+pub struct PathSelection {
+    selectors: Vec<(String, Selector)>,
+}
+
+impl PathSelection {
+    pub fn new(
+        bucket_path: &Path,
+        current_dir: &Path,
+        paths: &[String],
+    ) -> Result<Self, BucketError> {
+        let mut selectors = Vec::new();
+
+        for path in paths {
+            let selector = if is_glob(path) {
+                // Glob patterns are relative to the current directory, as paths are
+                let prefix = current_dir
+                    .canonicalize()?
+                    .strip_prefix(bucket_path.canonicalize()?)
+                    .map(Path::to_path_buf)
+                    .map_err(|_| BucketError::NotInBucket)?;
+                let pattern = prefix.join(path).to_string_lossy().to_string();
+                let glob = GlobBuilder::new(&pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| BucketError::InvalidData(format!("invalid pattern: {}", e)))?;
+                Selector::Glob(glob.compile_matcher())
+            } else {
+                Selector::Path(bucket_relative_path(bucket_path, current_dir, path)?)
+            };
+            selectors.push((path.clone(), selector));
+        }
+
+        Ok(Self { selectors })
+    }
+
+    /// Whether the file with bucket relative path `file` is selected.
+    pub fn matches(&self, file: &str) -> bool {
+        self.selectors
+            .iter()
+            .any(|(_, selector)| selector_matches(selector, file))
+    }
+
+    /// Returns the paths given on the command line that select none of `files`.
+    pub fn unmatched<'a>(&self, files: impl IntoIterator<Item = &'a str> + Clone) -> Vec<String> {
+        self.selectors
+            .iter()
+            .filter(|(_, selector)| {
+                !files
+                    .clone()
+                    .into_iter()
+                    .any(|file| selector_matches(selector, file))
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+fn selector_matches(selector: &Selector, file: &str) -> bool {
+    match selector {
+        Selector::Path(path) => Path::new(file).starts_with(path),
+        Selector::Glob(glob) => glob.is_match(file),
+    }
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn setup() -> (tempfile::TempDir, std::path::PathBuf) {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let bucket_path = temp_dir.path().join("art");
+        fs::create_dir_all(bucket_path.join("textures").join("hero"))
+            .expect("Failed to create dirs");
+        (temp_dir, bucket_path)
+    }
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn test_select_files_and_directories() {
+        let (_temp_dir, bucket_path) = setup();
+        let selection = PathSelection::new(
+            &bucket_path,
+            &bucket_path,
+            &paths(&["textures", "model.blend"]),
+        )
+        .expect("Failed to create selection");
+
+        assert!(selection.matches("model.blend"));
+        assert!(selection.matches("textures/hero/diffuse.png"));
+        assert!(!selection.matches("model.blend1"));
+        assert!(!selection.matches("textures_old/diffuse.png"));
+    }
+
+    #[test]
+    fn test_select_globs() {
+        let (_temp_dir, bucket_path) = setup();
+        let selection = PathSelection::new(&bucket_path, &bucket_path, &paths(&["*.png"]))
+            .expect("Failed to create selection");
+        assert!(selection.matches("logo.png"));
+        assert!(!selection.matches("textures/hero/diffuse.png"));
+
+        let selection = PathSelection::new(&bucket_path, &bucket_path, &paths(&["**/*.png"]))
+            .expect("Failed to create selection");
+        assert!(selection.matches("textures/hero/diffuse.png"));
+
+        // Relative to the current directory inside the bucket
+        let selection = PathSelection::new(
+            &bucket_path,
+            &bucket_path.join("textures"),
+            &paths(&["hero/*.png"]),
+        )
+        .expect("Failed to create selection");
+        assert!(selection.matches("textures/hero/diffuse.png"));
+        assert!(!selection.matches("hero/diffuse.png"));
+    }
+
+    #[test]
+    fn test_unmatched() {
+        let (_temp_dir, bucket_path) = setup();
+        let selection = PathSelection::new(
+            &bucket_path,
+            &bucket_path,
+            &paths(&["*.png", "model.blend"]),
+        )
+        .expect("Failed to create selection");
+        assert_eq!(
+            selection.unmatched(["logo.png", "notes.txt"]),
+            vec!["model.blend".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_select_outside_bucket() {
+        let (_temp_dir, bucket_path) = setup();
+        assert!(PathSelection::new(&bucket_path, &bucket_path, &paths(&["../other"])).is_err());
+    }
+}
@@ -1,6 +1,9 @@
+use crate::database::{database_path, open_metadata_store, read_database_type};
 use crate::errors::BucketError;
+use crate::metadata::MetadataStore;
+use crate::utils::ignore_rules::IgnoreRules;
+use crate::utils::security::validate_and_canonicalize_path;
 use blake3::{Hash, Hasher};
-use duckdb::Connection;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
@@ -24,6 +27,39 @@ pub(crate) fn find_files_excluding_top_level_b(dir: &Path) -> Vec<PathBuf> {
         .collect()
 }
 
+/// Lists the files of a bucket that can be committed, relative to the bucket root.
+///
+/// Skips the `.b` directory and every file matched by the ignore rules of the bucket.
+pub(crate) fn find_bucket_files(bucket_path: &Path) -> Result<Vec<PathBuf>, BucketError> {
+    let rules = IgnoreRules::for_bucket(bucket_path)?;
+    Ok(find_files_excluding_top_level_b(bucket_path)
+        .into_iter()
+        .filter(|file| !rules.is_ignored(&bucket_path.join(file), false))
+        .collect())
+}
+
+/// Converts a path given on the command line into the path of the file relative
+/// to the bucket root, as stored in the database.
+pub fn bucket_relative_path(
+    bucket_path: &Path,
+    current_dir: &Path,
+    file: &str,
+) -> Result<String, BucketError> {
+    let full_path = validate_and_canonicalize_path(&current_dir.join(file), Some(bucket_path))?;
+    let bucket_path = bucket_path.canonicalize()?;
+    let relative = full_path
+        .strip_prefix(&bucket_path)
+        .map_err(|_| BucketError::PathValidationError(format!("{} is not in the bucket", file)))?;
+
+    if relative.as_os_str().is_empty() || relative.starts_with(".b") {
+        return Err(BucketError::PathValidationError(format!(
+            "{} is not a file in the bucket",
+            file
+        )));
+    }
+    Ok(relative.to_string_lossy().to_string())
+}
+
 fn is_not_in_dir(entry: &DirEntry, root_dir: &Path, excluded_dir: &str) -> bool {
     let is_top_level_ex_dir = entry.depth() == 1 && entry.file_name() == excluded_dir;
 
@@ -91,39 +127,25 @@ pub fn find_bucket_repo(dir_path: &Path) -> Option<PathBuf> {
     }
 }
 
-pub fn connect_to_db() -> Result<Connection, BucketError> {
+/// Opens the metadata database of the repository the current directory is in, with
+/// the backend the repository was created with.
+pub fn connect_to_db() -> Result<Box<dyn MetadataStore>, BucketError> {
     let current_dir = env::current_dir()?;
 
-    let path = match find_directory_in_parents(&current_dir, ".buckets") {
-        Some(path) => path.join("buckets.db"),
-        None => return Err(BucketError::NotInRepo),
-    };
-
-    match Connection::open(path.as_path()) {
-        Ok(conn) => Ok(conn),
-        Err(e) => Err(BucketError::DuckDB(e)),
-    }
-}
-
-/// Helper to safely close a connection with proper error handling
-pub fn close_connection(connection: Connection) -> Result<(), BucketError> {
-    if let Err((_conn, e)) = connection.close() {
-        return Err(BucketError::from(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Failed to close database connection: {}", e),
-        )));
+    match find_directory_in_parents(&current_dir, ".buckets") {
+        Some(path) => open_metadata_store(&path),
+        None => Err(BucketError::NotInRepo),
     }
-    Ok(())
 }
 
 /// Execute a function with a shared database connection
 pub fn with_db_connection<F, R>(f: F) -> Result<R, BucketError>
 where
-    F: FnOnce(&Connection) -> Result<R, BucketError>,
+    F: FnOnce(&dyn MetadataStore) -> Result<R, BucketError>,
 {
     let connection = connect_to_db()?;
-    let result = f(&connection);
-    match close_connection(connection) {
+    let result = f(connection.as_ref());
+    match connection.close() {
         Ok(()) => result,
         Err(close_err) => {
             // If the original operation failed, return that error
@@ -268,6 +290,51 @@ mod tests {
         assert!(files.contains(&PathBuf::from("subdir/file3.txt")));
     }
 
+    #[test]
+    fn test_bucket_relative_path() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let bucket_path = temp_dir.path().join("art");
+        fs::create_dir_all(bucket_path.join("scenes")).expect("Failed to create dirs");
+        fs::write(bucket_path.join("scenes").join("shot.blend"), b"blend")
+            .expect("Failed to write file");
+
+        let relative = bucket_relative_path(&bucket_path, &bucket_path, "scenes/shot.blend")
+            .expect("Failed to resolve path");
+        assert_eq!(
+            relative,
+            Path::new("scenes").join("shot.blend").to_string_lossy()
+        );
+
+        let relative =
+            bucket_relative_path(&bucket_path, &bucket_path.join("scenes"), "shot.blend")
+                .expect("Failed to resolve path");
+        assert_eq!(
+            relative,
+            Path::new("scenes").join("shot.blend").to_string_lossy()
+        );
+
+        assert!(bucket_relative_path(&bucket_path, &bucket_path, "../outside.txt").is_err());
+    }
+
+    #[test]
+    fn test_find_bucket_files_skips_ignored() {
+        let temp_dir = tempdir().expect("failed to create temp dir");
+        let dir_path = temp_dir.path();
+
+        fs::create_dir_all(dir_path.join(".b")).expect("failed to create .b");
+        fs::create_dir_all(dir_path.join("cache")).expect("failed to create cache");
+        fs::write(dir_path.join(".bucketsignore"), "cache/\n").expect("failed to write ignore");
+        fs::write(dir_path.join("scene.blend"), b"blend").expect("failed to write file");
+        fs::write(dir_path.join("scene.blend1"), b"backup").expect("failed to write file");
+        fs::write(dir_path.join("cache").join("frame.exr"), b"exr").expect("failed to write file");
+
+        let files = find_bucket_files(dir_path).expect("failed to list files");
+
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&PathBuf::from("scene.blend")));
+        assert!(files.contains(&PathBuf::from(".bucketsignore")));
+    }
+
     #[test]
     fn test_hash_file() {
         let temp_dir = tempdir().expect("failed to create temp dir");
@@ -354,7 +421,7 @@ mod tests {
         let conn = result.expect("failed to connect to database");
 
         // Ensure we can execute a query
-        conn.execute("SELECT 1;", [])
+        conn.execute("SELECT 1;", &[])
             .expect("failed to execute query");
         conn.close().expect("failed to close connection");
     }
@@ -390,7 +457,7 @@ mod tests {
         env::set_current_dir(&temp_dir).expect("failed to change directory");
 
         let result = with_db_connection(|connection| {
-            connection.execute("SELECT 1;", [])?;
+            connection.execute("SELECT 1;", &[])?;
             Ok(42)
         });
 
@@ -667,13 +734,15 @@ pub fn get_db_path() -> Result<std::path::PathBuf, BucketError> {
     let current_dir = env::current_dir()?;
 
     match find_directory_in_parents(&current_dir, ".buckets") {
-        Some(path) => Ok(path.join("buckets.db")),
+        Some(path) => Ok(database_path(&path, read_database_type(&path)?)),
         None => Err(BucketError::NotInRepo),
     }
 }
 
 /// Create a database connection from a path (useful for reusing path lookups)
 #[allow(dead_code)]
-pub fn connect_to_db_with_path(db_path: &std::path::Path) -> Result<Connection, BucketError> {
-    Connection::open(db_path).map_err(BucketError::DuckDB)
+pub fn connect_to_db_with_path(
+    db_path: &std::path::Path,
+) -> Result<duckdb::Connection, BucketError> {
+    duckdb::Connection::open(db_path).map_err(BucketError::DuckDB)
 }
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use crate::{
     args::SharedArguments,
     data::bucket::{Bucket, BucketTrait},
+    database::{database_path, read_database_type, DatabaseType},
     errors::BucketError,
     utils::{checks, utils::find_bucket_repo},
     CURRENT_DIR,
@@ -15,9 +16,12 @@ pub struct World {
     // The root directory of the repository
     #[allow(dead_code)]
     pub repo_root: PathBuf,
-    // Path to the database file
+    // Path to the database file, or directory of an embedded server
     #[allow(dead_code)]
     pub repo_db_path: PathBuf,
+    // The database backend of the repository
+    #[allow(dead_code)]
+    pub database_type: DatabaseType,
     // The active bucket, None if no bucket is active
     #[allow(dead_code)]
     pub bucket: Option<Bucket>,
@@ -39,7 +43,8 @@ impl World {
             None => return Err(BucketError::NotInRepo),
         };
 
-        let repo_db_path = repo_root.join(".buckets").join("buckets.db");
+        let database_type = read_database_type(&repo_root)?;
+        let repo_db_path = database_path(&repo_root, database_type);
 
         let bucket = match Bucket::from_meta_data(&work_dir) {
             Ok(bucket) => Some(bucket),
@@ -52,6 +57,7 @@ impl World {
             work_dir,
             repo_root,
             repo_db_path,
+            database_type,
             bucket,
             verbose,
         })
@@ -0,0 +1,169 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use duckdb::Connection;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::collections::BTreeMap;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    /// Test archiving an earlier version of a bucket as tar.zst.
+    ///
+    /// # Commands
+    /// `$ buckets archive test_bucket@<id> -o first.tar.zst`
+    ///
+    /// # Expected output
+    /// The archive holds the manifest and the files of the first commit.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_archive_tar_zstd() {
+        let (repo_dir, first_commit) = setup();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("archive")
+            .arg(format!("test_bucket@{}", &first_commit[..8]))
+            .arg("-o")
+            .arg("first.tar.zst")
+            .assert()
+            .success()
+            .stdout(contains("Archived 2 files of test_bucket commit"));
+
+        let decoder = zstd::stream::Decoder::new(
+            File::open(repo_dir.join("first.tar.zst")).expect("Failed to open archive"),
+        )
+        .expect("Failed to create decoder");
+        let mut archive = tar::Archive::new(decoder);
+        let mut files = BTreeMap::new();
+        for entry in archive.entries().expect("Failed to read archive") {
+            let mut entry = entry.expect("Failed to read entry");
+            let mut content = String::new();
+            entry
+                .read_to_string(&mut content)
+                .expect("Failed to read entry");
+            files.insert(entry.path().expect("path").display().to_string(), content);
+        }
+
+        assert_eq!(files["model.blend"], "model v1");
+        assert_eq!(files["textures/wood.png"], "wood v1");
+        let manifest = &files["buckets-manifest.toml"];
+        assert!(manifest.contains(&format!("version = \"{}\"", first_commit)));
+        assert!(manifest.contains("message = \"first version\""));
+        assert!(manifest.contains(&blake3::hash(b"wood v1").to_string()));
+        // Nothing was checked out next to the archive
+        assert!(!repo_dir.join("model.blend").exists());
+    }
+
+    /// Test archiving the latest version of a bucket as zip.
+    ///
+    /// # Commands
+    /// `$ buckets archive test_bucket -o latest.zip`
+    ///
+    /// # Expected output
+    /// The archive holds the files of the last commit.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_archive_zip() {
+        let (repo_dir, _) = setup();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("archive")
+            .arg("test_bucket")
+            .arg("-o")
+            .arg("latest.zip")
+            .assert()
+            .success();
+
+        let mut archive = zip::ZipArchive::new(
+            File::open(repo_dir.join("latest.zip")).expect("Failed to open archive"),
+        )
+        .expect("Failed to read archive");
+        let mut content = String::new();
+        archive
+            .by_name("model.blend")
+            .expect("Missing file")
+            .read_to_string(&mut content)
+            .expect("Failed to read file");
+        assert_eq!(content, "model v2");
+        assert!(archive.by_name("buckets-manifest.toml").is_ok());
+    }
+
+    /// Test that unknown formats, buckets and versions are rejected.
+    #[test]
+    #[serial]
+    fn test_cli_archive_errors() {
+        let (repo_dir, _) = setup();
+
+        for (target, output, error) in [
+            ("test_bucket", "out.rar", "unsupported archive format"),
+            ("missing", "out.zip", "bucket missing"),
+            ("test_bucket@ffffffff", "out.zip", "commit ffffffff"),
+        ] {
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(repo_dir.as_path())
+                .arg("archive")
+                .arg(target)
+                .arg("-o")
+                .arg(output)
+                .assert()
+                .failure()
+                .stderr(contains(error));
+            assert!(!repo_dir.join(output).exists());
+        }
+    }
+
+    /// Creates a bucket with two commits and returns the id of the first one.
+    fn setup() -> (PathBuf, String) {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::create_dir_all(bucket_dir.join("textures")).expect("Failed to create directory");
+        for (version, message) in [("v1", "first version"), ("v2", "second version")] {
+            fs::write(bucket_dir.join("model.blend"), format!("model {}", version))
+                .expect("Failed to write file");
+            fs::write(
+                bucket_dir.join("textures").join("wood.png"),
+                format!("wood {}", version),
+            )
+            .expect("Failed to write file");
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(bucket_dir.as_path())
+                .arg("commit")
+                .arg(message)
+                .assert()
+                .success();
+        }
+
+        let connection = Connection::open(repo_dir.join(".buckets").join("buckets.db"))
+            .expect("Failed to open database");
+        let first_commit: String = connection
+            .query_row(
+                "SELECT CAST(id AS TEXT) FROM commits WHERE message = 'first version'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Failed to query commit");
+        (repo_dir, first_commit)
+    }
+}
@@ -0,0 +1,101 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::prelude::*;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Test the `check-ignore` command.
+    ///
+    /// # Commands
+    /// `$ buckets check-ignore scene.blend1 renders/frame.exr scene.blend`
+    ///
+    /// # Expected output
+    /// The ignore file and pattern matching each ignored path.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_check_ignore() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::write(repo_dir.join(".bucketsignore"), "renders/\n").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("check-ignore")
+            .arg("scene.blend1")
+            .arg("renders/frame.exr")
+            .arg("scene.blend")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "<built-in>:*.blend[0-9]    scene.blend1",
+            ))
+            .stdout(predicate::str::contains(
+                ".bucketsignore:renders/    renders/frame.exr",
+            ))
+            .stdout(predicate::str::contains("scene.blend is not ignored"));
+    }
+
+    /// Test that commit and status skip ignored files.
+    #[test]
+    #[serial]
+    fn test_cli_commit_skips_ignored_files() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::write(bucket_dir.join(".bucketsignore"), "*.log\n").expect("Failed to write file");
+        fs::write(bucket_dir.join("scene.blend"), b"scene").expect("Failed to write file");
+        fs::write(bucket_dir.join("scene.blend1"), b"backup").expect("Failed to write file");
+        fs::write(bucket_dir.join("render.log"), b"log").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("status")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("scene.blend\n"))
+            .stdout(predicate::str::contains("scene.blend1").not())
+            .stdout(predicate::str::contains("render.log").not());
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("scene")
+            .assert()
+            .success();
+
+        let storage = bucket_dir.join(".b").join("storage");
+        assert!(storage
+            .join(blake3::hash(b"scene").to_hex().as_str())
+            .exists());
+        assert!(!storage
+            .join(blake3::hash(b"backup").to_hex().as_str())
+            .exists());
+        assert!(!storage
+            .join(blake3::hash(b"log").to_hex().as_str())
+            .exists());
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        repo_dir
+    }
+}
@@ -4,11 +4,12 @@ mod common;
 mod tests {
     use crate::common::tests::get_test_dir;
     use duckdb::Connection;
+    use predicates::prelude::*;
     use predicates::str::contains;
     use serial_test::serial;
     use std::fs::File;
     use std::io::Write;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use uuid::{Uuid, Version};
     /// Test the `commit` command.
     ///
@@ -242,6 +243,396 @@ mod tests {
             .success();
     }
 
+    /// Test that an interrupted commit is cleaned up by the next command
+    #[test]
+    #[serial]
+    fn test_cli_commit_recovers_interrupted_commit() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let storage = bucket_dir.join(".b").join("storage");
+
+        // Simulate a commit that stored an object and crashed before the database insert
+        std::fs::write(storage.join("abandoned"), b"object").expect("Failed to write object");
+        std::fs::write(storage.join(".half.tmp"), b"obj").expect("Failed to write object");
+        let pending_dir = repo_dir.join(".buckets").join("pending");
+        std::fs::create_dir_all(&pending_dir).expect("Failed to create pending dir");
+        let commit_id = Uuid::new_v4();
+        std::fs::write(
+            pending_dir.join(commit_id.to_string()),
+            format!(
+                "commit_id = \"{}\"\nbucket_id = \"{}\"\nbucket_path = {:?}\nobjects = [\"abandoned\"]\n",
+                commit_id,
+                Uuid::new_v4(),
+                bucket_dir.to_string_lossy()
+            ),
+        )
+        .expect("Failed to write journal");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("status")
+            .assert()
+            .success()
+            .stderr(contains("Recovered interrupted commit"));
+
+        assert!(!storage.join("abandoned").exists());
+        assert!(!storage.join(".half.tmp").exists());
+        assert!(!pending_dir.join(commit_id.to_string()).exists());
+    }
+
+    /// Test that commit refuses to run while another process holds the repository lock,
+    /// while informational commands keep working
+    #[test]
+    #[serial]
+    fn test_cli_commit_repository_locked() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        std::fs::write(bucket_dir.join("test_file.txt"), b"test").expect("Failed to write file");
+
+        let lock_path = repo_dir.join(".buckets").join("lock");
+        std::fs::write(
+            &lock_path,
+            format!(
+                "pid = 4242\nhost = \"render-node-7\"\ncommand = \"commit\"\ncreated_at = \"{}\"\n",
+                chrono::Utc::now().to_rfc3339()
+            ),
+        )
+        .expect("Failed to write lock");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("locked commit")
+            .assert()
+            .failure()
+            .stderr(contains("Repository is locked"))
+            .stderr(contains("render-node-7"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("status")
+            .assert()
+            .success();
+
+        // Once the lock is released the commit goes through and releases it again
+        std::fs::remove_file(&lock_path).expect("Failed to remove lock");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("unlocked commit")
+            .assert()
+            .success();
+        assert!(!lock_path.exists());
+    }
+
+    /// Test committing only selected paths.
+    ///
+    /// # Commands
+    /// `$ buckets commit -m "textures" textures *.psd`
+    ///
+    /// # Expected output
+    /// Only the selected files are committed, the other changes stay uncommitted.
+    #[test]
+    #[serial]
+    fn test_cli_commit_selected_paths() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let textures_dir = bucket_dir.join("textures");
+        std::fs::create_dir_all(&textures_dir).expect("Failed to create directory");
+        std::fs::write(bucket_dir.join("model.blend"), b"model v1").expect("Failed to write file");
+        std::fs::write(textures_dir.join("wood.png"), b"wood v1").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("first version")
+            .assert()
+            .success();
+
+        std::fs::write(bucket_dir.join("model.blend"), b"model v2").expect("Failed to write file");
+        std::fs::write(textures_dir.join("wood.png"), b"wood v2").expect("Failed to write file");
+        std::fs::write(bucket_dir.join("concept.psd"), b"concept").expect("Failed to write file");
+        std::fs::write(bucket_dir.join("notes.txt"), b"notes").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("-m")
+            .arg("textures")
+            .arg("textures")
+            .arg("*.psd")
+            .assert()
+            .success();
+
+        let storage = bucket_dir.join(".b").join("storage");
+        assert!(storage
+            .join(blake3::hash(b"wood v2").to_hex().as_str())
+            .exists());
+        assert!(storage
+            .join(blake3::hash(b"concept").to_hex().as_str())
+            .exists());
+        assert!(!storage
+            .join(blake3::hash(b"model v2").to_hex().as_str())
+            .exists());
+        assert!(!storage
+            .join(blake3::hash(b"notes").to_hex().as_str())
+            .exists());
+
+        // The model is still modified and the notes are still new
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("status")
+            .assert()
+            .success()
+            .stdout(contains("modified:    model.blend"))
+            .stdout(contains("new:    notes.txt"))
+            .stdout(contains("committed:    concept.psd"));
+
+        // A path that matches nothing is an error
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("-m")
+            .arg("missing")
+            .arg("missing.png")
+            .assert()
+            .failure()
+            .stderr(contains("missing.png did not match any files"));
+    }
+
+    /// Test amending the last commit.
+    ///
+    /// # Commands
+    /// `$ buckets commit --amend -m "fixed message"`
+    ///
+    /// # Expected output
+    /// The history shows the amended commit only, including the new changes.
+    #[test]
+    #[serial]
+    fn test_cli_commit_amend() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        std::fs::write(bucket_dir.join("model.blend"), b"model v1").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("frist version")
+            .assert()
+            .success();
+
+        std::fs::write(bucket_dir.join("notes.txt"), b"notes").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("--amend")
+            .arg("-m")
+            .arg("first version")
+            .assert()
+            .success()
+            .stdout(contains("Amended commit"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("history")
+            .assert()
+            .success()
+            .stdout(contains("first version"))
+            .stdout(contains("frist version").not());
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("status")
+            .assert()
+            .success()
+            .stdout(contains("committed:    notes.txt"));
+
+        // The replaced commit is kept and the amend is logged
+        let db_path = repo_dir.join(".buckets").join("buckets.db");
+        let connection = Connection::open(db_path).expect("Failed to open database");
+        let abandoned: i64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM commits WHERE abandoned AND message = 'frist version'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Failed to query commits");
+        assert_eq!(abandoned, 1);
+        let operations: i64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM operations WHERE kind = 'amend'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Failed to query operations");
+        assert_eq!(operations, 1);
+    }
+
+    /// Test amending without any commit.
+    #[test]
+    #[serial]
+    fn test_cli_commit_amend_without_commit() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        std::fs::write(bucket_dir.join("model.blend"), b"model v1").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("--amend")
+            .assert()
+            .failure()
+            .stderr(contains("commit to amend"));
+    }
+
+    /// Test previewing a commit.
+    ///
+    /// # Commands
+    /// `$ buckets commit --dry-run "first version"`
+    ///
+    /// # Expected output
+    /// The files that would be committed are listed and nothing is stored.
+    #[test]
+    #[serial]
+    fn test_cli_commit_dry_run() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        std::fs::write(bucket_dir.join("model.blend"), b"model v1").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("--dry-run")
+            .arg("first version")
+            .assert()
+            .success()
+            .stdout(contains("new:    model.blend"))
+            .stdout(contains("Nothing was changed."));
+
+        let storage = bucket_dir.join(".b").join("storage");
+        assert!(!storage
+            .join(blake3::hash(b"model v1").to_hex().as_str())
+            .exists());
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("history")
+            .assert()
+            .success()
+            .stdout(contains("first version").not());
+    }
+
+    /// Test that the compression policy of the repository config is applied.
+    ///
+    /// # Commands
+    /// `$ buckets commit "first version"`
+    ///
+    /// # Expected output
+    /// PNG files and files below `store_below` are stored as is, other files are compressed.
+    /// Every file is restored with its original content.
+    #[test]
+    #[serial]
+    fn test_cli_commit_compression_policy() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let mut config = File::options()
+            .append(true)
+            .open(repo_dir.join(".buckets").join("config"))
+            .expect("Failed to open config");
+        writeln!(config, "\n[compression]\nstore_below = 16\n\n[[compression.rules]]\npattern = \"*.png\"\nstore = true")
+            .expect("Failed to write config");
+
+        let texture = "wood ".repeat(200);
+        let model = "model ".repeat(200);
+        std::fs::write(bucket_dir.join("wood.png"), &texture).expect("Failed to write file");
+        std::fs::write(bucket_dir.join("model.blend"), &model).expect("Failed to write file");
+        std::fs::write(bucket_dir.join("notes.txt"), b"tiny").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("first version")
+            .assert()
+            .success();
+
+        // Stored objects start with a 12 byte header
+        let storage = bucket_dir.join(".b").join("storage");
+        let object = |content: &[u8]| {
+            std::fs::read(storage.join(blake3::hash(content).to_hex().as_str()))
+                .expect("Failed to read object")
+        };
+        assert_eq!(&object(texture.as_bytes())[12..], texture.as_bytes());
+        assert_eq!(&object(b"tiny")[12..], b"tiny");
+        assert!(object(model.as_bytes()).len() < model.len());
+
+        std::fs::write(bucket_dir.join("wood.png"), b"changed").expect("Failed to write file");
+        std::fs::write(bucket_dir.join("model.blend"), b"changed").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("all")
+            .assert()
+            .success();
+        assert_eq!(
+            std::fs::read_to_string(bucket_dir.join("wood.png")).expect("Failed to read file"),
+            texture
+        );
+        assert_eq!(
+            std::fs::read_to_string(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            model
+        );
+    }
+
+    /// Test that a new version of an OBJ file is stored as a delta against the previous one.
+    #[test]
+    #[serial]
+    fn test_cli_commit_delta() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let first = (0..5000)
+            .map(|i| format!("v {}.0 {}.5 {}.25\n", i, i * 7 % 13, i * 3))
+            .collect::<String>();
+        let second = first.replacen("v 10.0", "v 10.5", 1);
+        std::fs::write(bucket_dir.join("mesh.obj"), &first).expect("Failed to write file");
+        commit(&bucket_dir, "first version");
+        std::fs::write(bucket_dir.join("mesh.obj"), &second).expect("Failed to write file");
+        commit(&bucket_dir, "second version");
+
+        // Codec 3 is a zstd delta, followed by its depth and the hash of its base
+        let storage = bucket_dir.join(".b").join("storage");
+        let object = |content: &str| {
+            std::fs::read(storage.join(blake3::hash(content.as_bytes()).to_hex().as_str()))
+                .expect("Failed to read object")
+        };
+        let delta = object(&second);
+        assert_eq!(object(&first)[11], 1);
+        assert_eq!(delta[11], 3);
+        assert_eq!(delta[12], 1);
+        assert_eq!(&delta[13..45], blake3::hash(first.as_bytes()).as_bytes());
+        assert!(delta.len() < object(&first).len() / 4);
+
+        std::fs::write(bucket_dir.join("mesh.obj"), b"changed").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("all")
+            .assert()
+            .success();
+        assert_eq!(
+            std::fs::read_to_string(bucket_dir.join("mesh.obj")).expect("Failed to read file"),
+            second
+        );
+    }
+
+    fn commit(bucket_dir: &Path, message: &str) {
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .arg("commit")
+            .arg(message)
+            .assert()
+            .success();
+    }
+
     fn setup() -> PathBuf {
         let temp_dir = get_test_dir();
         let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
@@ -0,0 +1,179 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Test migrating a repository to the backend it is on.
+    ///
+    /// # Commands
+    /// `$ buckets db migrate --to duckdb`
+    ///
+    /// # Expected output
+    /// The command fails and the repository is left as it was.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_db_migrate_to_same_backend() {
+        let repo_dir = setup();
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .args(["db", "migrate", "--to", "duckdb"])
+            .assert()
+            .failure()
+            .stderr(contains("the repository is on DuckDB already"));
+        assert_eq!(database_type(&repo_dir), "duckdb");
+        assert!(!repo_dir
+            .join(".buckets")
+            .join("buckets.db.migrating")
+            .exists());
+    }
+
+    /// Test migrating to an unknown backend.
+    ///
+    /// # Commands
+    /// `$ buckets db migrate --to oracle`
+    ///
+    /// # Expected output
+    /// The argument is rejected.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_db_migrate_to_unknown_backend() {
+        let repo_dir = setup();
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .args(["db", "migrate", "--to", "oracle"])
+            .assert()
+            .failure()
+            .stderr(contains("Invalid database type"));
+        assert_eq!(database_type(&repo_dir), "duckdb");
+    }
+
+    /// Test migrating to PostgreSQL in a build without it.
+    ///
+    /// # Commands
+    /// `$ buckets db migrate --to postgresql`
+    ///
+    /// `$ buckets history`
+    ///
+    /// # Expected output
+    /// The migration fails, and the repository stays on DuckDB with its history.
+    ///
+    #[test]
+    #[serial]
+    #[cfg(not(feature = "postgres"))]
+    fn test_cli_db_migrate_without_postgres() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        commit_file(&bucket_dir, "first version");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .args(["db", "migrate", "--to", "postgresql"])
+            .assert()
+            .failure()
+            .stderr(contains("PostgreSQL support not compiled in"));
+        assert_eq!(database_type(&repo_dir), "duckdb");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("history")
+            .assert()
+            .success()
+            .stdout(contains("first version"));
+    }
+
+    /// Test moving a repository from DuckDB to SQLite and back.
+    ///
+    /// # Commands
+    /// `$ buckets commit "first version"`
+    ///
+    /// `$ buckets db migrate --to sqlite`
+    ///
+    /// `$ buckets commit "second version"`
+    ///
+    /// `$ buckets db migrate --to duckdb`
+    ///
+    /// # Expected output
+    /// Both migrations print the copied rows, and the history has both commits afterwards.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_db_migrate_sqlite_round_trip() {
+        let repo_dir = setup();
+        let buckets_dir = repo_dir.join(".buckets");
+        let bucket_dir = repo_dir.join("test_bucket");
+        commit_file(&bucket_dir, "first version");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .args(["db", "migrate", "--to", "sqlite"])
+            .assert()
+            .success()
+            .stdout(contains("       1 commits"))
+            .stdout(contains("Migrated the repository from DuckDB to SQLite"));
+        assert_eq!(database_type(&repo_dir), "sqlite");
+        assert!(buckets_dir.join("buckets.sqlite").is_file());
+        assert!(!buckets_dir.join("buckets.sqlite.migrating").exists());
+        // The old database stays where it was
+        assert!(buckets_dir.join("buckets.db").is_file());
+
+        commit_file(&bucket_dir, "second version");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .args(["db", "migrate", "--to", "duckdb"])
+            .assert()
+            .success()
+            .stdout(contains("       2 commits"));
+        assert_eq!(database_type(&repo_dir), "duckdb");
+        assert!(!buckets_dir.join("buckets.db.migrating").exists());
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("history")
+            .assert()
+            .success()
+            .stdout(contains("first version"))
+            .stdout(contains("second version"));
+    }
+
+    fn commit_file(bucket_dir: &Path, message: &str) {
+        fs::write(bucket_dir.join("scene.blend"), message).expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .args(["commit", message])
+            .assert()
+            .success();
+    }
+
+    fn database_type(repo_dir: &Path) -> String {
+        fs::read_to_string(repo_dir.join(".buckets").join("database_type"))
+            .expect("Failed to read database type")
+            .trim()
+            .to_string()
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+        repo_dir
+    }
+}
@@ -0,0 +1,248 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use duckdb::Connection;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Test restoring an earlier version next to the working files.
+    ///
+    /// # Commands
+    /// `$ buckets restore textures --commit <id> --to ../old`
+    ///
+    /// # Expected output
+    /// The files of the first commit are written below `../old`, the working files
+    /// keep their current content.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_restore_to() {
+        let (repo_dir, first_commit) = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("restore")
+            .arg("textures")
+            .arg("--commit")
+            .arg(&first_commit[..8])
+            .arg("--to")
+            .arg("../old")
+            .assert()
+            .success()
+            .stdout(contains("restored:    textures/wood.png"));
+
+        let old_dir = repo_dir.join("old");
+        assert_eq!(
+            fs::read(old_dir.join("textures").join("wood.png")).expect("Failed to read file"),
+            b"wood v1"
+        );
+        assert!(!old_dir.join("model.blend").exists());
+        assert_eq!(
+            fs::read(bucket_dir.join("textures").join("wood.png")).expect("Failed to read file"),
+            b"wood v2"
+        );
+
+        // Without --commit the last commit is used
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("restore")
+            .arg("model.blend")
+            .arg("--to")
+            .arg("../latest")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read(repo_dir.join("latest").join("model.blend")).expect("Failed to read file"),
+            b"model v2"
+        );
+    }
+
+    /// Test restoring an earlier version into the working files.
+    ///
+    /// # Commands
+    /// `$ buckets restore textures --commit <id> --to .`
+    ///
+    /// `$ buckets restore textures --commit _ --to ../old`
+    ///
+    /// # Expected output
+    /// Both are refused and the working files keep their current content.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_restore_to_refuses_working_files() {
+        let (repo_dir, first_commit) = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        for destination in [".", "textures/../new", "../test_bucket"] {
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(bucket_dir.as_path())
+                .arg("restore")
+                .arg("textures")
+                .arg("--commit")
+                .arg(&first_commit[..8])
+                .arg("--to")
+                .arg(destination)
+                .assert()
+                .failure()
+                .stderr(contains("use revert to restore working files"));
+        }
+        assert_eq!(
+            fs::read(bucket_dir.join("textures").join("wood.png")).expect("Failed to read file"),
+            b"wood v2"
+        );
+        assert!(!bucket_dir.join("new").exists());
+
+        // LIKE wildcards are not commit ids
+        for commit in ["_", "%", ""] {
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(bucket_dir.as_path())
+                .arg("restore")
+                .arg("textures")
+                .arg("--commit")
+                .arg(commit)
+                .arg("--to")
+                .arg("../old")
+                .assert()
+                .failure()
+                .stderr(contains("is not a commit id"));
+        }
+        assert!(!repo_dir.join("old").exists());
+    }
+
+    /// Test exporting a whole commit.
+    ///
+    /// # Commands
+    /// `$ buckets export <id> export`
+    ///
+    /// # Expected output
+    /// Every file of the commit is written to the directory.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_export() {
+        let (repo_dir, first_commit) = setup();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("export")
+            .arg(&first_commit)
+            .arg("export")
+            .assert()
+            .success()
+            .stdout(contains("Exported 2 files of test_bucket commit"));
+
+        let export_dir = repo_dir.join("export");
+        assert_eq!(
+            fs::read(export_dir.join("model.blend")).expect("Failed to read file"),
+            b"model v1"
+        );
+        assert_eq!(
+            fs::read(export_dir.join("textures").join("wood.png")).expect("Failed to read file"),
+            b"wood v1"
+        );
+
+        // An export never writes into a directory that has files
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("export")
+            .arg(&first_commit)
+            .arg("export")
+            .assert()
+            .failure()
+            .stderr(contains("is not an empty directory"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("export")
+            .arg("ffffffff")
+            .arg("missing")
+            .assert()
+            .failure()
+            .stderr(contains("commit ffffffff"));
+    }
+
+    /// Test exporting into a bucket.
+    ///
+    /// # Commands
+    /// `$ buckets export <id> export`
+    ///
+    /// # Expected output
+    /// The export is refused and nothing is written to the bucket.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_export_refuses_buckets() {
+        let (repo_dir, first_commit) = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        for (dir, destination) in [
+            (&bucket_dir, "export"),
+            (&repo_dir, "test_bucket/export"),
+            (&repo_dir, "elsewhere/../test_bucket/textures/export"),
+        ] {
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(dir.as_path())
+                .arg("export")
+                .arg(&first_commit)
+                .arg(destination)
+                .assert()
+                .failure()
+                .stderr(contains("export to a directory outside the buckets"));
+        }
+        assert!(!bucket_dir.join("export").exists());
+        assert!(!bucket_dir.join("textures").join("export").exists());
+    }
+
+    /// Creates a bucket with two commits and returns the id of the first one.
+    fn setup() -> (PathBuf, String) {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::create_dir_all(bucket_dir.join("textures")).expect("Failed to create directory");
+        for (version, message) in [("v1", "first version"), ("v2", "second version")] {
+            fs::write(bucket_dir.join("model.blend"), format!("model {}", version))
+                .expect("Failed to write file");
+            fs::write(
+                bucket_dir.join("textures").join("wood.png"),
+                format!("wood {}", version),
+            )
+            .expect("Failed to write file");
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(bucket_dir.as_path())
+                .arg("commit")
+                .arg(message)
+                .assert()
+                .success();
+        }
+
+        let connection = Connection::open(repo_dir.join(".buckets").join("buckets.db"))
+            .expect("Failed to open database");
+        let first_commit: String = connection
+            .query_row(
+                "SELECT CAST(id AS TEXT) FROM commits WHERE message = 'first version'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Failed to query commit");
+        (repo_dir, first_commit)
+    }
+}
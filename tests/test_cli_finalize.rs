@@ -3,6 +3,8 @@ mod common;
 #[cfg(test)]
 mod tests {
     use crate::common::tests::get_test_dir;
+    use predicates::prelude::*;
+    use predicates::str::contains;
     use serial_test::serial;
 
     /// Test the `finalize` command.
@@ -22,4 +24,26 @@ mod tests {
             .assert()
             .success();
     }
+
+    /// Test the dry run of the `finalize` command.
+    ///
+    /// # Commands
+    /// `$ buckets finalize --dry-run`
+    ///
+    /// # Expected output
+    /// `Dry run: finalize would change nothing. Nothing was changed.`
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_finalize_dry_run() {
+        let temp_dir = get_test_dir();
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(temp_dir.as_path())
+            .arg("finalize")
+            .arg("--dry-run")
+            .assert()
+            .success()
+            .stdout(contains("Dry run: finalize would change nothing"))
+            .stdout(contains("finalize command").not());
+    }
 }
@@ -0,0 +1,92 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Test the `fsck` command.
+    ///
+    /// # Commands
+    /// `$ buckets fsck`
+    ///
+    /// # Expected output
+    /// A summary of the checked objects, failure when an object is damaged.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_fsck() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        fs::write(bucket_dir.join("test_file.txt"), b"test").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("test message")
+            .assert()
+            .success();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("fsck")
+            .assert()
+            .success()
+            .stdout(contains("Checked 1 objects, found 0 problems."));
+    }
+
+    /// Test `fsck` on a repository with a corrupted and a missing object.
+    #[test]
+    #[serial]
+    fn test_cli_fsck_corrupt_and_missing() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        fs::write(bucket_dir.join("a.txt"), b"first").expect("Failed to write file");
+        fs::write(bucket_dir.join("b.txt"), b"second").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("test message")
+            .assert()
+            .success();
+
+        let storage = bucket_dir.join(".b").join("storage");
+        let corrupt = blake3::hash(b"first").to_hex().to_string();
+        let missing = blake3::hash(b"second").to_hex().to_string();
+        fs::write(storage.join(&corrupt), b"bit rot").expect("Failed to corrupt object");
+        fs::remove_file(storage.join(&missing)).expect("Failed to remove object");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("fsck")
+            .assert()
+            .failure()
+            .stdout(contains(format!("corrupt:    test_bucket/{}", corrupt)))
+            .stdout(contains(format!("missing:    test_bucket/{}", missing)))
+            .stderr(contains("Integrity check failed"));
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        repo_dir
+    }
+}
@@ -76,6 +76,53 @@ mod acceptance_tests {
             .stdout(predicate::str::contains("test commit message 2"));
     }
 
+    /// Test that commits record the configured author and that `log` shows it.
+    #[test]
+    #[serial]
+    fn test_cli_history_shows_author() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        // Author configured in the repository config
+        let config_path = repo_dir.join(".buckets").join("config");
+        let mut config = std::fs::read_to_string(&config_path).expect("Failed to read config");
+        config.push_str("\n[user]\nname = \"Repo User\"\nemail = \"repo@example.com\"\n");
+        std::fs::write(&config_path, config).expect("Failed to write config");
+
+        create_test_file(&bucket_dir, "test_file.txt", "test content");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(&bucket_dir)
+            .env_remove("BUCKETS_USER_NAME")
+            .env_remove("BUCKETS_USER_EMAIL")
+            .arg("commit")
+            .arg("first")
+            .assert()
+            .success();
+
+        // The environment overrides the repository config
+        create_test_file(&bucket_dir, "test_file.txt", "changed content");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(&bucket_dir)
+            .env("BUCKETS_USER_NAME", "Env User")
+            .env_remove("BUCKETS_USER_EMAIL")
+            .arg("commit")
+            .arg("second")
+            .assert()
+            .success();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(&bucket_dir)
+            .arg("log")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "Author: Repo User <repo@example.com>",
+            ))
+            .stdout(predicate::str::contains(
+                "Author: Env User <repo@example.com>",
+            ));
+    }
+
     fn create_test_file(dir: &std::path::Path, filename: &str, content: &str) {
         let file_path = dir.join(filename);
         let mut file = File::create(&file_path).expect("Failed to create file");
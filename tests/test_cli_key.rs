@@ -0,0 +1,440 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Test encrypting a repository with a key file and rotating to a new key file.
+    ///
+    /// # Commands
+    /// `$ buckets key init --key-file old.key`
+    /// `$ buckets key rotate --key-file new.key`
+    ///
+    /// # Expected output
+    /// Existing and new objects are encrypted, and can be restored with the new key
+    /// after the old key file is gone.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_key_file_init_and_rotate() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let content = "confidential ".repeat(100);
+        fs::write(bucket_dir.join("early.txt"), &content).expect("Failed to write file");
+        commit(&bucket_dir, "before encryption");
+
+        let old_key = repo_dir.parent().expect("no parent").join("old.key");
+        fs::write(&old_key, b"old key material").expect("Failed to write key file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("key")
+            .arg("init")
+            .arg("--key-file")
+            .arg(&old_key)
+            .assert()
+            .success()
+            .stdout(contains("Encrypted 1 objects with key"));
+        assert!(repo_dir.join(".buckets").join("keys.toml").exists());
+
+        let later = "classified ".repeat(100);
+        fs::write(bucket_dir.join("later.txt"), &later).expect("Failed to write file");
+        commit(&bucket_dir, "after encryption");
+
+        // Codec 4 is an encrypted object, the content does not appear in storage
+        for text in [&content, &later] {
+            let object = fs::read(object_path(&bucket_dir, text)).expect("Failed to read object");
+            assert_eq!(object[11], 4);
+            assert!(!object
+                .windows(12)
+                .any(|window| window == &text.as_bytes()[..12]));
+        }
+
+        let new_key = repo_dir.parent().expect("no parent").join("new.key");
+        fs::write(&new_key, b"new key material").expect("Failed to write key file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("key")
+            .arg("rotate")
+            .arg("--key-file")
+            .arg(&new_key)
+            .assert()
+            .success()
+            .stdout(contains("Re-encrypted 2 objects with key"));
+        fs::remove_file(&old_key).expect("Failed to remove old key");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("fsck")
+            .assert()
+            .success()
+            .stdout(contains("found 0 problems"));
+
+        fs::write(bucket_dir.join("early.txt"), b"changed").expect("Failed to write file");
+        revert_all(&bucket_dir).success();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("early.txt")).expect("Failed to read file"),
+            content
+        );
+    }
+
+    /// Test resuming a rotation that stopped after re-encrypting an object in the bucket
+    /// storage, before writing it back to the remote store.
+    ///
+    /// # Commands
+    /// `$ buckets key rotate --key-file new.key`
+    ///
+    /// # Expected output
+    /// The resumed rotation writes the object back to the remote store, which can be read
+    /// once the old key file is gone.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_key_resume_rotation_publishes_objects() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let nas_dir = repo_dir.parent().expect("no parent").join("nas");
+        use_directory_store(&repo_dir, &nas_dir);
+
+        let old_key = repo_dir.parent().expect("no parent").join("old.key");
+        fs::write(&old_key, b"old key material").expect("Failed to write key file");
+        key(&bucket_dir, "init", &old_key);
+        let content = "confidential ".repeat(100);
+        fs::write(bucket_dir.join("asset.txt"), &content).expect("Failed to write file");
+        commit(&bucket_dir, "secret");
+
+        let keys_path = repo_dir.join(".buckets").join("keys.toml");
+        let old_ring = fs::read_to_string(&keys_path).expect("Failed to read keys");
+        let hash = blake3::hash(content.as_bytes()).to_hex().to_string();
+        let remote_object = nas_dir.join("test_bucket").join(&hash);
+        let old_object = fs::read(&remote_object).expect("Failed to read object");
+
+        let new_key = repo_dir.parent().expect("no parent").join("new.key");
+        fs::write(&new_key, b"new key material").expect("Failed to write key file");
+        key(&bucket_dir, "rotate", &new_key);
+
+        // As left by a rotation that stopped before writing the object back
+        let new_ring = fs::read_to_string(&keys_path).expect("Failed to read keys");
+        let old_entry = &old_ring[old_ring.find("[[key]]").expect("no keys")..];
+        fs::write(&keys_path, format!("{}\n{}", new_ring, old_entry))
+            .expect("Failed to write keys");
+        fs::write(&remote_object, &old_object).expect("Failed to write object");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("key")
+            .arg("rotate")
+            .assert()
+            .success()
+            .stdout(contains("Resuming the rotation"));
+        fs::remove_file(&old_key).expect("Failed to remove old key");
+        assert_eq!(
+            fs::read(&remote_object).expect("Failed to read object"),
+            fs::read(object_path(&bucket_dir, &content)).expect("Failed to read object")
+        );
+
+        // Restored from the remote store
+        fs::remove_file(object_path(&bucket_dir, &content)).expect("Failed to remove object");
+        fs::write(bucket_dir.join("asset.txt"), b"changed").expect("Failed to write file");
+        revert_all(&bucket_dir).success();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("asset.txt")).expect("Failed to read file"),
+            content
+        );
+    }
+
+    /// Test rotating the key of a bucket with packed objects and objects committed before
+    /// a remote store was configured.
+    ///
+    /// # Commands
+    /// `$ buckets storage pack`
+    /// `$ buckets key rotate --key-file new.key`
+    ///
+    /// # Expected output
+    /// Packed, loose and remote objects are re-encrypted, objects kept in the bucket are not
+    /// written to the remote store, and every file is restored after the old key file is gone.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_key_rotate_local_and_packed_objects() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let old_key = repo_dir.parent().expect("no parent").join("old.key");
+        fs::write(&old_key, b"old key material").expect("Failed to write key file");
+        key(&bucket_dir, "init", &old_key);
+
+        let files = [
+            ("packed.txt", "packed ".repeat(100)),
+            ("local.txt", "local ".repeat(100)),
+            ("remote.txt", "remote ".repeat(100)),
+        ];
+        fs::write(bucket_dir.join(files[0].0), &files[0].1).expect("Failed to write file");
+        commit(&bucket_dir, "packed");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("storage")
+            .arg("pack")
+            .assert()
+            .success();
+        fs::write(bucket_dir.join(files[1].0), &files[1].1).expect("Failed to write file");
+        commit(&bucket_dir, "local");
+
+        let nas_dir = repo_dir.parent().expect("no parent").join("nas");
+        use_directory_store(&repo_dir, &nas_dir);
+        fs::write(bucket_dir.join(files[2].0), &files[2].1).expect("Failed to write file");
+        commit(&bucket_dir, "remote");
+
+        let new_key = repo_dir.parent().expect("no parent").join("new.key");
+        fs::write(&new_key, b"new key material").expect("Failed to write key file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("key")
+            .arg("rotate")
+            .arg("--key-file")
+            .arg(&new_key)
+            .assert()
+            .success()
+            .stdout(contains("Re-encrypted 3 objects"));
+        fs::remove_file(&old_key).expect("Failed to remove old key");
+
+        let remote_dir = nas_dir.join("test_bucket");
+        let remote_object =
+            |content: &str| remote_dir.join(blake3::hash(content.as_bytes()).to_hex().as_str());
+        assert!(!remote_object(&files[0].1).exists());
+        assert!(!remote_object(&files[1].1).exists());
+        assert!(remote_object(&files[2].1).exists());
+        assert!(object_path(&bucket_dir, &files[0].1).exists());
+
+        // The remote object is fetched again
+        fs::remove_file(object_path(&bucket_dir, &files[2].1)).expect("Failed to remove object");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("fsck")
+            .assert()
+            .success();
+        for (name, _) in &files {
+            fs::write(bucket_dir.join(name), b"changed").expect("Failed to write file");
+        }
+        revert_all(&bucket_dir).success();
+        for (name, content) in &files {
+            assert_eq!(
+                &fs::read_to_string(bucket_dir.join(name)).expect("Failed to read file"),
+                content
+            );
+        }
+    }
+
+    /// Test that dictionaries, trained on committed files, are encrypted with the objects.
+    ///
+    /// # Commands
+    /// `$ buckets storage train-dict --min-samples 8 --size 1024`
+    /// `$ buckets key init --key-file old.key`
+    /// `$ buckets key rotate --key-file new.key`
+    ///
+    /// # Expected output
+    /// The dictionary no longer contains committed text once the repository is encrypted,
+    /// and objects compressed with it are restored after the old key file is gone.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_key_encrypts_dictionaries() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        for i in 0..40 {
+            fs::write(bucket_dir.join(format!("m{}.json", i)), material(i))
+                .expect("Failed to write file");
+        }
+        commit(&bucket_dir, "materials");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("storage")
+            .arg("train-dict")
+            .arg("--min-samples")
+            .arg("8")
+            .arg("--size")
+            .arg("1024")
+            .assert()
+            .success();
+        let content = material(100);
+        fs::write(bucket_dir.join("new.json"), &content).expect("Failed to write file");
+        commit(&bucket_dir, "new material");
+
+        let dictionary = fs::read_dir(repo_dir.join(".buckets").join("dictionaries"))
+            .expect("Failed to list dictionaries")
+            .map(|entry| entry.expect("Failed to read entry").path())
+            .find(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "dict")
+            })
+            .expect("no dictionary");
+        let contains_text = |path: &Path| {
+            fs::read(path)
+                .expect("Failed to read dictionary")
+                .windows(15)
+                .any(|window| window == b"principled_bsdf")
+        };
+        assert!(contains_text(&dictionary));
+
+        let old_key = repo_dir.parent().expect("no parent").join("old.key");
+        fs::write(&old_key, b"old key material").expect("Failed to write key file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("key")
+            .arg("init")
+            .arg("--key-file")
+            .arg(&old_key)
+            .assert()
+            .success()
+            .stdout(contains("Encrypted 1 dictionaries with key"));
+        assert!(!contains_text(&dictionary));
+
+        let new_key = repo_dir.parent().expect("no parent").join("new.key");
+        fs::write(&new_key, b"new key material").expect("Failed to write key file");
+        key(&bucket_dir, "rotate", &new_key);
+        fs::remove_file(&old_key).expect("Failed to remove old key");
+        assert!(!contains_text(&dictionary));
+
+        fs::write(bucket_dir.join("new.json"), b"changed").expect("Failed to write file");
+        revert_all(&bucket_dir).success();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("new.json")).expect("Failed to read file"),
+            content
+        );
+    }
+
+    /// Test a repository encrypted with a passphrase, which is needed to restore files.
+    #[test]
+    #[serial]
+    fn test_cli_key_passphrase() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("BUCKETS_PASSPHRASE", "correct horse")
+            .arg("key")
+            .arg("init")
+            .assert()
+            .success();
+
+        let content = "under embargo ".repeat(100);
+        fs::write(bucket_dir.join("asset.txt"), &content).expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("BUCKETS_PASSPHRASE", "correct horse")
+            .arg("commit")
+            .arg("secret")
+            .assert()
+            .success();
+
+        fs::write(bucket_dir.join("asset.txt"), b"changed").expect("Failed to write file");
+        revert_all(&bucket_dir).failure();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("asset.txt")).expect("Failed to read file"),
+            "changed"
+        );
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("BUCKETS_PASSPHRASE", "correct horse")
+            .env("BUCKETS_NEW_PASSPHRASE", "battery staple")
+            .arg("key")
+            .arg("rotate")
+            .assert()
+            .success()
+            .stdout(contains("Re-encrypted 1 objects"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("BUCKETS_PASSPHRASE", "battery staple")
+            .arg("revert")
+            .arg("all")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("asset.txt")).expect("Failed to read file"),
+            content
+        );
+    }
+
+    fn material(i: usize) -> String {
+        format!(
+            r#"{{
+    "name": "material_{i}",
+    "shader": "principled_bsdf",
+    "base_color": [0.{i}, 0.5, 0.25, 1.0],
+    "roughness": 0.{i},
+    "metallic": {metallic}
+}}"#,
+            i = i,
+            metallic = i.is_multiple_of(2)
+        )
+    }
+
+    fn object_path(bucket_dir: &Path, content: &str) -> PathBuf {
+        bucket_dir
+            .join(".b")
+            .join("storage")
+            .join(blake3::hash(content.as_bytes()).to_hex().as_str())
+    }
+
+    fn revert_all(bucket_dir: &Path) -> assert_cmd::assert::Assert {
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .env_remove("BUCKETS_PASSPHRASE")
+            .arg("revert")
+            .arg("all")
+            .assert()
+    }
+
+    fn key(bucket_dir: &Path, command: &str, key_file: &Path) {
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .arg("key")
+            .arg(command)
+            .arg("--key-file")
+            .arg(key_file)
+            .assert()
+            .success();
+    }
+
+    fn use_directory_store(repo_dir: &Path, store_dir: &Path) {
+        let config_path = repo_dir.join(".buckets").join("config");
+        let mut config = fs::read_to_string(&config_path).expect("Failed to read config");
+        config.push_str(&format!(
+            "\n[storage]\nbackend = \"directory\"\npath = {:?}\n",
+            store_dir.to_string_lossy()
+        ));
+        fs::write(&config_path, config).expect("Failed to write config");
+    }
+
+    fn commit(bucket_dir: &Path, message: &str) {
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .arg("commit")
+            .arg(message)
+            .assert()
+            .success();
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+        repo_dir
+    }
+}
@@ -3,7 +3,9 @@ mod common;
 #[cfg(test)]
 mod tests {
     use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
     use serial_test::serial;
+    use std::fs;
 
     /// Test the `list` command.
     ///
@@ -11,6 +13,7 @@ mod tests {
     /// `$ buckets list`
     ///
     /// # Expected output
+    /// Every bucket with the date and author of its latest commit.
     ///
     #[test]
     #[serial]
@@ -18,8 +21,39 @@ mod tests {
         let temp_dir = get_test_dir();
         let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
         cmd.current_dir(temp_dir.as_path())
-            .arg("list")
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let repo_dir = temp_dir.join("test_repo");
+        for bucket in ["concept_art", "models"] {
+            let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+            cmd.current_dir(repo_dir.as_path())
+                .arg("create")
+                .arg(bucket)
+                .assert()
+                .success();
+        }
+
+        let bucket_dir = repo_dir.join("models");
+        fs::write(bucket_dir.join("hero.blend"), b"blend").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("BUCKETS_USER_NAME", "Alice")
+            .env("BUCKETS_USER_EMAIL", "alice@example.com")
+            .arg("commit")
+            .arg("hero model")
             .assert()
             .success();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("list")
+            .assert()
+            .success()
+            .stdout(contains("concept_art"))
+            .stdout(contains("no commits"))
+            .stdout(contains("by Alice <alice@example.com>"));
     }
 }
@@ -0,0 +1,187 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Test the `lock`, `locks` and `unlock` commands.
+    ///
+    /// # Commands
+    /// `$ buckets lock scene.blend`
+    /// `$ buckets locks`
+    /// `$ buckets unlock scene.blend`
+    ///
+    /// # Expected output
+    /// The lock is listed with its owner until it is released.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_lock_and_unlock() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "alice")
+            .arg("lock")
+            .arg("scene.blend")
+            .assert()
+            .success()
+            .stdout(contains("Locked scene.blend for alice"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("locks")
+            .assert()
+            .success()
+            .stdout(contains("test_bucket/scene.blend    alice"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "alice")
+            .arg("unlock")
+            .arg("scene.blend")
+            .assert()
+            .success()
+            .stdout(contains("Unlocked scene.blend"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("locks")
+            .assert()
+            .success()
+            .stdout(contains("No files are locked."));
+    }
+
+    /// Test that a file locked by one user cannot be committed or unlocked by another.
+    #[test]
+    #[serial]
+    fn test_cli_lock_blocks_other_users() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "alice")
+            .arg("lock")
+            .arg("scene.blend")
+            .assert()
+            .success();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "alice")
+            .arg("lock")
+            .arg("scene.blend")
+            .assert()
+            .success();
+
+        // Another user can neither take the lock nor commit the file
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "bob")
+            .arg("lock")
+            .arg("scene.blend")
+            .assert()
+            .failure()
+            .stderr(contains("File is locked: scene.blend (locked by alice"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "bob")
+            .arg("commit")
+            .arg("bob's changes")
+            .assert()
+            .failure()
+            .stderr(contains("File is locked: scene.blend (locked by alice"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "bob")
+            .arg("unlock")
+            .arg("scene.blend")
+            .assert()
+            .failure()
+            .stderr(contains("use --force"));
+
+        // The lock owner can commit
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "alice")
+            .arg("commit")
+            .arg("alice's changes")
+            .assert()
+            .success();
+
+        // Only an administrator can break the lock
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "bob")
+            .arg("unlock")
+            .arg("--force")
+            .arg("scene.blend")
+            .assert()
+            .failure()
+            .stderr(contains("only the admins"));
+
+        let config_path = repo_dir.join(".buckets").join("config");
+        let config = fs::read_to_string(&config_path).expect("Failed to read config");
+        fs::write(
+            &config_path,
+            format!("{}\n[locks]\nadmins = [\"bob\"]\n", config),
+        )
+        .expect("Failed to write config");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .env("USER", "bob")
+            .arg("unlock")
+            .arg("--force")
+            .arg("scene.blend")
+            .assert()
+            .success()
+            .stdout(contains("Unlocked scene.blend (was locked by alice)"));
+    }
+
+    /// Test locking a file that does not exist.
+    #[test]
+    #[serial]
+    fn test_cli_lock_missing_file() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("lock")
+            .arg("missing.blend")
+            .assert()
+            .failure()
+            .stderr(contains("File not found missing.blend"));
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        fs::write(repo_dir.join("test_bucket").join("scene.blend"), b"blend")
+            .expect("Failed to write file");
+        repo_dir
+    }
+}
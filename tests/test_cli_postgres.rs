@@ -0,0 +1,281 @@
+//! Repositories on PostgreSQL. The tests use the server in `BUCKETS_TEST_DATABASE_URL`, or start
+//! an embedded server standing in for it when the variable is not set:
+//!
+//! `$ BUCKETS_TEST_DATABASE_URL=postgres://buckets@db.studio.lan/test cargo test --features postgres`
+#![cfg(feature = "postgres")]
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use postgresql_embedded::PostgreSQL;
+    use predicates::prelude::*;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// The server the tests run against
+    struct TestServer {
+        url: String,
+        // Stopped when the test is done
+        _embedded: Option<(PostgreSQL, tokio::runtime::Runtime)>,
+    }
+
+    fn test_server() -> TestServer {
+        if let Ok(url) = std::env::var("BUCKETS_TEST_DATABASE_URL") {
+            return TestServer {
+                url,
+                _embedded: None,
+            };
+        }
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+        let mut server = PostgreSQL::default();
+        runtime
+            .block_on(async {
+                server.setup().await?;
+                server.start().await
+            })
+            .expect("Failed to start PostgreSQL");
+        TestServer {
+            url: server.settings().url("postgres"),
+            _embedded: Some((server, runtime)),
+        }
+    }
+
+    /// A repository name no earlier run has used on a shared server
+    fn unique_repo_name() -> String {
+        format!("repo_{}", &uuid::Uuid::new_v4().simple().to_string()[..8])
+    }
+
+    fn buckets(dir: &Path, args: &[&str]) -> assert_cmd::assert::Assert {
+        assert_cmd::Command::cargo_bin("buckets")
+            .expect("failed to run command")
+            .current_dir(dir)
+            .args(args)
+            .assert()
+    }
+
+    /// Creates bucket `test_bucket` in `repo_dir` and commits a file to it.
+    fn commit_file(repo_dir: &Path, message: &str) -> PathBuf {
+        buckets(repo_dir, &["create", "test_bucket"]).success();
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::write(bucket_dir.join("scene.blend"), message).expect("Failed to write file");
+        buckets(&bucket_dir, &["commit", message]).success();
+        bucket_dir
+    }
+
+    /// Test a repository on an external server.
+    ///
+    /// # Commands
+    /// `$ buckets init <repo> --database-url <url>`
+    ///
+    /// `$ buckets create test_bucket`
+    ///
+    /// `$ buckets commit "first version"`
+    ///
+    /// `$ buckets history`
+    ///
+    /// # Expected output
+    /// The `[database]` table of `.buckets/config` has the URL and schema `buckets_<repo>`.
+    ///
+    /// The tables are in that schema on the server, and the commit is in the history.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_external_database() {
+        let server = test_server();
+        let temp_dir = get_test_dir();
+        let repo_name = unique_repo_name();
+        buckets(
+            &temp_dir,
+            &["init", &repo_name, "--database-url", &server.url],
+        )
+        .success();
+
+        let repo_dir = temp_dir.join(&repo_name);
+        let buckets_dir = repo_dir.join(".buckets");
+        let database_type =
+            fs::read_to_string(buckets_dir.join("database_type")).expect("Failed to read type");
+        assert_eq!(database_type.trim(), "postgresql");
+        assert!(!buckets_dir.join("postgres_data").exists());
+        let config = fs::read_to_string(buckets_dir.join("config")).expect("Failed to read config");
+        let schema = format!("buckets_{}", repo_name);
+        assert!(config.contains("[database]"));
+        assert!(config.contains(&format!("schema = \"{}\"", schema)));
+
+        let bucket_dir = commit_file(&repo_dir, "first version");
+        buckets(&bucket_dir, &["history"])
+            .success()
+            .stdout(predicate::str::contains("first version"));
+
+        let mut client =
+            postgres::Client::connect(&server.url, postgres::NoTls).expect("Failed to connect");
+        let commits: i64 = client
+            .query_one(&format!("SELECT count(*) FROM \"{}\".commits", schema), &[])
+            .expect("Failed to count commits")
+            .get(0);
+        assert_eq!(commits, 1);
+    }
+
+    /// Test that repositories on the same server keep their metadata apart.
+    ///
+    /// # Commands
+    /// `$ buckets init <repo a> --database-url <url>`
+    ///
+    /// `$ buckets init <repo b> --database-url <url> --database-schema <schema b>`
+    ///
+    /// `$ buckets history` in each
+    ///
+    /// # Expected output
+    /// Each history only has the commit of its own repository. Another `init` in a schema that
+    /// holds a repository fails.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_repositories_share_server() {
+        let server = test_server();
+        let temp_dir = get_test_dir();
+        let repo_a = unique_repo_name();
+        let repo_b = unique_repo_name();
+        let schema_b = format!("studio_{}", repo_b);
+        buckets(&temp_dir, &["init", &repo_a, "--database-url", &server.url]).success();
+        buckets(
+            &temp_dir,
+            &[
+                "init",
+                &repo_b,
+                "--database-url",
+                &server.url,
+                "--database-schema",
+                &schema_b,
+            ],
+        )
+        .success();
+
+        let bucket_a = commit_file(&temp_dir.join(&repo_a), "version of a");
+        let bucket_b = commit_file(&temp_dir.join(&repo_b), "version of b");
+        buckets(&bucket_a, &["history"])
+            .success()
+            .stdout(predicate::str::contains("version of a"))
+            .stdout(predicate::str::contains("version of b").not());
+        buckets(&bucket_b, &["history"])
+            .success()
+            .stdout(predicate::str::contains("version of b"))
+            .stdout(predicate::str::contains("version of a").not());
+
+        let repo_c = unique_repo_name();
+        buckets(
+            &temp_dir,
+            &[
+                "init",
+                &repo_c,
+                "--database-url",
+                &server.url,
+                "--database-schema",
+                &schema_b,
+            ],
+        )
+        .failure();
+    }
+
+    /// Test moving a repository from DuckDB to an external server and back.
+    ///
+    /// # Commands
+    /// `$ buckets init <repo>`
+    ///
+    /// `$ buckets commit "first version"`
+    ///
+    /// `$ buckets db migrate --to postgresql --database-url <url>`
+    ///
+    /// `$ buckets commit "second version"`
+    ///
+    /// `$ buckets db migrate --to duckdb`
+    ///
+    /// # Expected output
+    /// Both migrations print the copied rows, and the history has both commits afterwards.
+    /// The `[database]` table is only in `.buckets/config` while the repository is on the
+    /// server.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_migrate_round_trip() {
+        let server = test_server();
+        let temp_dir = get_test_dir();
+        let repo_name = unique_repo_name();
+        buckets(&temp_dir, &["init", &repo_name]).success();
+        let repo_dir = temp_dir.join(&repo_name);
+        let buckets_dir = repo_dir.join(".buckets");
+        let bucket_dir = commit_file(&repo_dir, "first version");
+
+        buckets(
+            &repo_dir,
+            &[
+                "db",
+                "migrate",
+                "--to",
+                "postgresql",
+                "--database-url",
+                &server.url,
+            ],
+        )
+        .success()
+        .stdout(predicate::str::contains("       1 commits"))
+        .stdout(predicate::str::contains(format!(
+            "to PostgreSQL schema buckets_{}",
+            repo_name
+        )));
+        let database_type =
+            fs::read_to_string(buckets_dir.join("database_type")).expect("Failed to read type");
+        assert_eq!(database_type.trim(), "postgresql");
+        let config = fs::read_to_string(buckets_dir.join("config")).expect("Failed to read config");
+        assert!(config.contains("[database]"));
+
+        fs::write(bucket_dir.join("scene.blend"), "second version").expect("Failed to write file");
+        buckets(&bucket_dir, &["commit", "second version"]).success();
+
+        buckets(&repo_dir, &["db", "migrate", "--to", "duckdb"])
+            .success()
+            .stdout(predicate::str::contains("       2 commits"));
+        let database_type =
+            fs::read_to_string(buckets_dir.join("database_type")).expect("Failed to read type");
+        assert_eq!(database_type.trim(), "duckdb");
+        let config = fs::read_to_string(buckets_dir.join("config")).expect("Failed to read config");
+        assert!(!config.contains("[database]"));
+        assert!(!buckets_dir.join("buckets.db.migrating").exists());
+
+        buckets(&bucket_dir, &["history"])
+            .success()
+            .stdout(predicate::str::contains("first version"))
+            .stdout(predicate::str::contains("second version"));
+    }
+
+    /// Test a repository on its own embedded server.
+    ///
+    /// # Commands
+    /// `$ buckets init <repo> --database postgresql`
+    ///
+    /// `$ buckets create test_bucket`
+    ///
+    /// `$ buckets commit "first version"`
+    ///
+    /// `$ buckets history`
+    ///
+    /// # Expected output
+    /// The server data is in `.buckets/postgres_data`, and the commit is in the history.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_embedded_database() {
+        let temp_dir = get_test_dir();
+        let repo_name = unique_repo_name();
+        buckets(&temp_dir, &["init", &repo_name, "--database", "postgresql"]).success();
+
+        let repo_dir = temp_dir.join(&repo_name);
+        assert!(repo_dir.join(".buckets").join("postgres_data").is_dir());
+
+        let bucket_dir = commit_file(&repo_dir, "first version");
+        buckets(&bucket_dir, &["history"])
+            .success()
+            .stdout(predicate::str::contains("first version"));
+    }
+}
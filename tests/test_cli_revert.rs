@@ -2,7 +2,9 @@ mod common;
 
 #[cfg(test)]
 mod tests {
+    use predicates::str::contains;
     use serial_test::serial;
+    use std::fs;
     use std::{fs::File, io::Write};
 
     use tempfile::tempdir;
@@ -51,4 +53,157 @@ mod tests {
             .assert()
             .success();
     }
+
+    /// Test that `revert` refuses to overwrite a file from a damaged object.
+    #[test]
+    #[serial]
+    fn test_cli_revert_corrupt_object() {
+        let temp_dir = tempdir().expect("invalid temp dir").keep();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("invalid command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("invalid command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        let bucket_dir = repo_dir.join("test_bucket");
+        let file_path = bucket_dir.join("test_file.txt");
+        let mut file = File::create(&file_path).expect("invalid file");
+        file.write_all(b"test").expect("invalid write");
+        let mut cmd3 = assert_cmd::Command::cargo_bin("buckets").expect("invalid command");
+        cmd3.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("test message")
+            .assert()
+            .success();
+
+        // Damage the stored object and change the working file
+        let object = bucket_dir
+            .join(".b")
+            .join("storage")
+            .join(blake3::hash(b"test").to_hex().as_str());
+        std::fs::write(&object, b"bit rot").expect("invalid write");
+        std::fs::write(&file_path, b"new work").expect("invalid write");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("test_file.txt")
+            .assert()
+            .failure();
+
+        let content = std::fs::read(&file_path).expect("invalid read");
+        assert_eq!(content, b"new work");
+    }
+
+    /// Test reverting directories, glob patterns and all files.
+    ///
+    /// # Commands
+    /// `$ buckets revert textures *.psd`
+    /// `$ buckets revert all --remove-new`
+    ///
+    /// # Expected output
+    /// Every matching file is restored and new files are only deleted with `--remove-new`.
+    #[test]
+    #[serial]
+    fn test_cli_revert_paths_and_all() {
+        let temp_dir = tempdir().expect("invalid temp dir").keep();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("invalid command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("invalid command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        let bucket_dir = repo_dir.join("test_bucket");
+        let textures_dir = bucket_dir.join("textures");
+        fs::create_dir_all(&textures_dir).expect("invalid directory");
+        fs::write(textures_dir.join("wood.png"), b"wood").expect("invalid write");
+        fs::write(bucket_dir.join("poster.psd"), b"poster").expect("invalid write");
+        fs::write(bucket_dir.join("model.blend"), b"model").expect("invalid write");
+        let mut cmd3 = assert_cmd::Command::cargo_bin("buckets").expect("invalid command");
+        cmd3.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("test message")
+            .assert()
+            .success();
+
+        fs::write(textures_dir.join("wood.png"), b"wood v2").expect("invalid write");
+        fs::write(textures_dir.join("stone.png"), b"stone").expect("invalid write");
+        fs::remove_file(bucket_dir.join("poster.psd")).expect("invalid remove");
+        fs::write(bucket_dir.join("model.blend"), b"model v2").expect("invalid write");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("textures")
+            .arg("*.psd")
+            .assert()
+            .success()
+            .stdout(contains("restored:    textures/wood.png"))
+            .stdout(contains("restored:    poster.psd"))
+            .stdout(contains("kept new:    textures/stone.png"));
+
+        assert_eq!(
+            fs::read(textures_dir.join("wood.png")).expect("invalid read"),
+            b"wood"
+        );
+        assert_eq!(
+            fs::read(bucket_dir.join("poster.psd")).expect("invalid read"),
+            b"poster"
+        );
+        assert!(textures_dir.join("stone.png").exists());
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("invalid read"),
+            b"model v2"
+        );
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("all")
+            .arg("--remove-new")
+            .assert()
+            .success()
+            .stdout(contains("restored:    model.blend"))
+            .stdout(contains("removed:    textures/stone.png"));
+
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("invalid read"),
+            b"model"
+        );
+        assert!(!textures_dir.join("stone.png").exists());
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("all")
+            .assert()
+            .success()
+            .stdout(contains("No changes to revert."));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("*.jpg")
+            .assert()
+            .failure()
+            .stderr(contains("*.jpg did not match any files"));
+    }
 }
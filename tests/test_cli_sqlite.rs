@@ -0,0 +1,131 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Test a repository with its metadata in SQLite.
+    ///
+    /// # Commands
+    /// `$ buckets init test_repo --database sqlite`
+    ///
+    /// `$ buckets create test_bucket`
+    ///
+    /// `$ buckets commit "first version"`
+    ///
+    /// `$ buckets commit "second version"`
+    ///
+    /// `$ buckets history`
+    ///
+    /// # Expected output
+    /// The metadata is in `.buckets/buckets.sqlite`, and the history has both commits with the
+    /// latest first.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_sqlite_repository() {
+        let repo_dir = setup();
+        let buckets_dir = repo_dir.join(".buckets");
+        let database_type =
+            fs::read_to_string(buckets_dir.join("database_type")).expect("Failed to read type");
+        assert_eq!(database_type.trim(), "sqlite");
+        assert!(buckets_dir.join("buckets.sqlite").is_file());
+        assert!(!buckets_dir.join("buckets.db").exists());
+
+        let bucket_dir = repo_dir.join("test_bucket");
+        commit_file(&bucket_dir, "first version");
+        commit_file(&bucket_dir, "second version");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let output = cmd
+            .current_dir(bucket_dir.as_path())
+            .arg("history")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let history = String::from_utf8(output).expect("History is not UTF-8");
+        let first = history
+            .find("first version")
+            .expect("First commit is missing");
+        let second = history
+            .find("second version")
+            .expect("Second commit is missing");
+        assert!(second < first);
+    }
+
+    /// Test undoing a revert in a SQLite repository.
+    ///
+    /// # Commands
+    /// `$ buckets revert scene.blend`
+    ///
+    /// `$ buckets undo`
+    ///
+    /// `$ buckets oplog`
+    ///
+    /// # Expected output
+    /// The changed content is back, and the operation log lists the undone revert.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_sqlite_undo() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        commit_file(&bucket_dir, "first version");
+        fs::write(bucket_dir.join("scene.blend"), "changed").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .args(["revert", "scene.blend"])
+            .assert()
+            .success();
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("scene.blend")).expect("Failed to read file"),
+            "changed"
+        );
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("oplog")
+            .assert()
+            .success()
+            .stdout(contains("revert"));
+    }
+
+    fn commit_file(bucket_dir: &Path, message: &str) {
+        fs::write(bucket_dir.join("scene.blend"), message).expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .args(["commit", message])
+            .assert()
+            .success();
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .args(["init", "test_repo", "--database", "sqlite"])
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+        repo_dir
+    }
+}
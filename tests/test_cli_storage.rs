@@ -0,0 +1,345 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Test training a dictionary and storing new files with it.
+    ///
+    /// # Commands
+    /// `$ buckets storage train-dict --min-samples 8 --size 1024`
+    ///
+    /// # Expected output
+    /// A dictionary is trained for `.json`, new JSON objects record it in their header
+    /// and are restored with it.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_storage_train_dict() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let materials = bucket_dir.join("materials");
+        fs::create_dir_all(&materials).expect("Failed to create directory");
+        for i in 0..40 {
+            fs::write(materials.join(format!("m{}.json", i)), material(i))
+                .expect("Failed to write file");
+        }
+        fs::write(bucket_dir.join("model.blend"), b"model").expect("Failed to write file");
+        commit(&bucket_dir, "materials");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("storage")
+            .arg("train-dict")
+            .arg("--min-samples")
+            .arg("8")
+            .arg("--size")
+            .arg("1024")
+            .assert()
+            .success()
+            .stdout(contains("Trained dictionary"))
+            .stdout(contains("for .json from 40 files"));
+        assert!(repo_dir
+            .join(".buckets")
+            .join("dictionaries")
+            .join("index.toml")
+            .exists());
+
+        let content = material(100);
+        fs::write(materials.join("new.json"), &content).expect("Failed to write file");
+        commit(&bucket_dir, "new material");
+
+        // Codec 2 is zstd with a dictionary
+        let object = fs::read(
+            bucket_dir
+                .join(".b")
+                .join("storage")
+                .join(blake3::hash(content.as_bytes()).to_hex().as_str()),
+        )
+        .expect("Failed to read object");
+        assert_eq!(object[11], 2);
+
+        fs::write(materials.join("new.json"), b"changed").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("materials/new.json")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(materials.join("new.json")).expect("Failed to read file"),
+            content
+        );
+    }
+
+    /// Test that file types with too few files get no dictionary.
+    #[test]
+    #[serial]
+    fn test_cli_storage_train_dict_too_few_samples() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::write(bucket_dir.join("a.json"), material(1)).expect("Failed to write file");
+        commit(&bucket_dir, "one material");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("storage")
+            .arg("train-dict")
+            .assert()
+            .success()
+            .stdout(contains(
+                "No file type has enough small files to train a dictionary.",
+            ));
+    }
+
+    /// Test packing loose objects and reading them back from the pack.
+    ///
+    /// # Commands
+    /// `$ buckets storage pack`
+    ///
+    /// # Expected output
+    /// Loose objects are moved into a pack, files are restored from it and `fsck` finds
+    /// no problems. Objects of later commits are written loose.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_storage_pack() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let materials = bucket_dir.join("materials");
+        fs::create_dir_all(&materials).expect("Failed to create directory");
+        for i in 0..20 {
+            fs::write(materials.join(format!("m{}.json", i)), material(i))
+                .expect("Failed to write file");
+        }
+        commit(&bucket_dir, "materials");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("storage")
+            .arg("pack")
+            .assert()
+            .success()
+            .stdout(contains("Packed 20 objects of test_bucket into pack-"));
+
+        let storage = bucket_dir.join(".b").join("storage");
+        let object =
+            |content: &str| storage.join(blake3::hash(content.as_bytes()).to_hex().as_str());
+        assert!(!object(&material(3)).exists());
+        assert!(storage.join("packs").is_dir());
+
+        fs::write(materials.join("m3.json"), b"changed").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("materials/m3.json")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(materials.join("m3.json")).expect("Failed to read file"),
+            material(3)
+        );
+
+        fs::write(materials.join("new.json"), material(100)).expect("Failed to write file");
+        commit(&bucket_dir, "new material");
+        assert!(object(&material(100)).exists());
+
+        // The revert kept the changed m3.json as a snapshot in the operation log
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("fsck")
+            .assert()
+            .success()
+            .stdout(contains("Checked 22 objects, found 0 problems."));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("storage")
+            .arg("pack")
+            .assert()
+            .success()
+            .stdout(contains("Packed 2 objects of test_bucket"));
+        assert!(!object(&material(100)).exists());
+    }
+
+    /// Test keeping the objects of a repository in a directory outside of it.
+    ///
+    /// # Expected output
+    /// Committed objects are in `<path>/<bucket>/`, the bucket storage keeps a copy of
+    /// the current tree, and files are restored, deltas included.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_storage_directory_backend() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let nas_dir = repo_dir.parent().expect("no parent").join("nas");
+        let config_path = repo_dir.join(".buckets").join("config");
+        let mut config = fs::read_to_string(&config_path).expect("Failed to read config");
+        config.push_str(&format!(
+            "\n[storage]\nbackend = \"directory\"\npath = {:?}\n",
+            nas_dir.to_string_lossy()
+        ));
+        fs::write(&config_path, config).expect("Failed to write config");
+
+        let model = "v 1.0 2.0 3.0\n".repeat(500);
+        fs::write(bucket_dir.join("model.obj"), &model).expect("Failed to write file");
+        commit(&bucket_dir, "model");
+        let changed = format!("{}v 4.0 5.0 6.0\n", model);
+        fs::write(bucket_dir.join("model.obj"), &changed).expect("Failed to write file");
+        commit(&bucket_dir, "changed model");
+
+        for content in [&model, &changed] {
+            let hash = blake3::hash(content.as_bytes()).to_hex().to_string();
+            assert!(nas_dir.join("test_bucket").join(&hash).is_file());
+            // The first version is the base of the delta of the current version
+            assert!(bucket_dir.join(".b").join("storage").join(&hash).is_file());
+        }
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("fsck")
+            .assert()
+            .success()
+            .stdout(contains("Checked 2 objects, found 0 problems."));
+
+        fs::write(bucket_dir.join("model.obj"), b"broken").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("model.obj")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(bucket_dir.join("model.obj")).expect("Failed to read file"),
+            changed
+        );
+    }
+
+    /// Test fetching objects of older commits from the remote store.
+    ///
+    /// # Commands
+    /// `$ buckets prefetch <commit>`
+    /// `$ buckets export <commit> <dir>`
+    ///
+    /// # Expected output
+    /// With `[cache] max_size = 0` only the objects of the current tree stay in the bucket
+    /// storage. Prefetch fetches the objects of an older commit, and an export fetches them
+    /// when they are not cached.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_prefetch_and_lazy_fetch() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        let storage_dir = bucket_dir.join(".b").join("storage");
+        let nas_dir = repo_dir.parent().expect("no parent").join("nas");
+        let config_path = repo_dir.join(".buckets").join("config");
+        let mut config = fs::read_to_string(&config_path).expect("Failed to read config");
+        config.push_str(&format!(
+            "\n[storage]\nbackend = \"directory\"\npath = {:?}\n\n[cache]\nmax_size = 0\n",
+            nas_dir.to_string_lossy()
+        ));
+        fs::write(&config_path, config).expect("Failed to write config");
+
+        let mut hashes = Vec::new();
+        for version in ["texture v1", "texture v2", "texture v3"] {
+            fs::write(bucket_dir.join("texture.png"), version).expect("Failed to write file");
+            commit(&bucket_dir, version);
+            hashes.push(blake3::hash(version.as_bytes()).to_hex().to_string());
+        }
+        for hash in &hashes {
+            assert!(nas_dir.join("test_bucket").join(hash).is_file());
+        }
+        assert!(!storage_dir.join(&hashes[0]).exists());
+        assert!(!storage_dir.join(&hashes[1]).exists());
+        assert!(storage_dir.join(&hashes[2]).is_file());
+
+        let connection = duckdb::Connection::open(repo_dir.join(".buckets").join("buckets.db"))
+            .expect("Failed to open database");
+        let first_commit: String = connection
+            .query_row(
+                "SELECT CAST(id AS TEXT) FROM commits WHERE message = 'texture v1'",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Failed to query commit");
+        drop(connection);
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("prefetch")
+            .arg(&first_commit)
+            .assert()
+            .success()
+            .stdout(contains("Fetched 1 objects"));
+        assert!(storage_dir.join(&hashes[0]).is_file());
+
+        // Committing trims the cache again, the export fetches what it needs
+        fs::write(bucket_dir.join("texture.png"), "texture v4").expect("Failed to write file");
+        commit(&bucket_dir, "texture v4");
+        assert!(!storage_dir.join(&hashes[0]).exists());
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(repo_dir.as_path())
+            .arg("export")
+            .arg(&first_commit)
+            .arg("export")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(repo_dir.join("export").join("texture.png"))
+                .expect("Failed to read file"),
+            "texture v1"
+        );
+    }
+
+    fn material(i: usize) -> String {
+        format!(
+            r#"{{
+    "name": "material_{i}",
+    "shader": "principled_bsdf",
+    "base_color": [0.{i}, 0.5, 0.25, 1.0],
+    "roughness": 0.{i},
+    "metallic": {metallic},
+    "textures": {{
+        "albedo": "textures/material_{i}_albedo.png",
+        "normal": "textures/material_{i}_normal.png"
+    }}
+}}"#,
+            i = i,
+            metallic = i.is_multiple_of(2)
+        )
+    }
+
+    fn commit(bucket_dir: &Path, message: &str) {
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir)
+            .arg("commit")
+            .arg(message)
+            .assert()
+            .success();
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+        repo_dir
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::prelude::*;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Test the `uncommit` command.
+    ///
+    /// # Commands
+    /// `$ buckets uncommit`
+    ///
+    /// # Expected output
+    /// The last commit is removed from the history and its changes show up in
+    /// the status again, while the working files are untouched.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_uncommit() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        fs::write(bucket_dir.join("model.blend"), b"model v1").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("first version")
+            .assert()
+            .success();
+
+        fs::write(bucket_dir.join("model.blend"), b"model v2").expect("Failed to write file");
+        fs::write(bucket_dir.join("wrong.psd"), b"wrong").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("second version")
+            .assert()
+            .success();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("uncommit")
+            .assert()
+            .success()
+            .stdout(contains("Uncommitted"))
+            .stdout(contains("\"second version\""))
+            .stdout(contains("Head is now"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("history")
+            .assert()
+            .success()
+            .stdout(contains("first version"))
+            .stdout(contains("second version").not());
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("status")
+            .assert()
+            .success()
+            .stdout(contains("modified:    model.blend"))
+            .stdout(contains("new:    wrong.psd"));
+
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"model v2"
+        );
+    }
+
+    /// Test `uncommit` in a bucket without commits.
+    #[test]
+    #[serial]
+    fn test_cli_uncommit_without_commits() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("uncommit")
+            .assert()
+            .success()
+            .stdout(contains("Bucket test_bucket has no commits."));
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+        repo_dir
+    }
+}
@@ -0,0 +1,224 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::tests::get_test_dir;
+    use predicates::prelude::*;
+    use predicates::str::contains;
+    use serial_test::serial;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Test undoing a revert.
+    ///
+    /// # Commands
+    /// `$ buckets revert model.blend`
+    /// `$ buckets undo`
+    /// `$ buckets oplog`
+    ///
+    /// # Expected output
+    /// The changes discarded by the revert are back and the log shows both operations.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_undo_revert() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        fs::write(bucket_dir.join("model.blend"), b"work in progress")
+            .expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("model.blend")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"model v1"
+        );
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .success()
+            .stdout(contains("restored:    model.blend"));
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"work in progress"
+        );
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("oplog")
+            .assert()
+            .success()
+            .stdout(contains("undo    undo revert model.blend"))
+            .stdout(contains("revert    revert model.blend"))
+            .stdout(contains("(undone)"));
+
+        // The revert was undone already
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .success()
+            .stdout(contains("Nothing to undo."));
+    }
+
+    /// Test that an undo that fails to restore a file can be run again.
+    ///
+    /// # Commands
+    /// `$ buckets revert model.blend`
+    /// `$ buckets undo` (with the snapshot missing from storage)
+    /// `$ buckets undo`
+    ///
+    /// # Expected output
+    /// The first undo fails without being logged, the second one restores the file.
+    ///
+    #[test]
+    #[serial]
+    fn test_cli_undo_retry_after_failure() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        fs::write(bucket_dir.join("model.blend"), b"work in progress")
+            .expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("model.blend")
+            .assert()
+            .success();
+
+        let object_path = bucket_dir
+            .join(".b")
+            .join("storage")
+            .join(blake3::hash(b"work in progress").to_hex().as_str());
+        let moved_path = bucket_dir.join("snapshot");
+        fs::rename(&object_path, &moved_path).expect("Failed to move object");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .failure();
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("oplog")
+            .assert()
+            .success()
+            .stdout(contains("undo revert").not())
+            .stdout(contains("(undone)").not());
+
+        fs::rename(&moved_path, &object_path).expect("Failed to move object");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .success()
+            .stdout(contains("restored:    model.blend"));
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"work in progress"
+        );
+    }
+
+    /// Test that a dry run of `revert` and `rollback` leaves the working files alone.
+    #[test]
+    #[serial]
+    fn test_cli_dry_run_keeps_files() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::write(bucket_dir.join("model.blend"), b"model v2").expect("Failed to write file");
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("revert")
+            .arg("--dry-run")
+            .arg("model.blend")
+            .assert()
+            .success()
+            .stdout(contains("restore:    model.blend"));
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("rollback")
+            .arg("--dry-run")
+            .assert()
+            .success()
+            .stdout(contains("restore:    model.blend"));
+
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"model v2"
+        );
+
+        // Nothing was logged, so there is nothing to undo
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .success()
+            .stdout(contains("Nothing to undo."));
+    }
+
+    /// Test undoing a rollback of all files.
+    #[test]
+    #[serial]
+    fn test_cli_undo_rollback() {
+        let repo_dir = setup();
+        let bucket_dir = repo_dir.join("test_bucket");
+
+        fs::write(bucket_dir.join("model.blend"), b"model v2").expect("Failed to write file");
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("rollback")
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"model v1"
+        );
+
+        let mut cmd = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd.current_dir(bucket_dir.as_path())
+            .arg("undo")
+            .assert()
+            .success()
+            .stdout(contains("Undoing rollback 1 files"));
+        assert_eq!(
+            fs::read(bucket_dir.join("model.blend")).expect("Failed to read file"),
+            b"model v2"
+        );
+    }
+
+    fn setup() -> PathBuf {
+        let temp_dir = get_test_dir();
+        let mut cmd1 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd1.current_dir(temp_dir.as_path())
+            .arg("init")
+            .arg("test_repo")
+            .assert()
+            .success();
+
+        let mut cmd2 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        let repo_dir = temp_dir.as_path().join("test_repo");
+        cmd2.current_dir(repo_dir.as_path())
+            .arg("create")
+            .arg("test_bucket")
+            .assert()
+            .success();
+
+        let bucket_dir = repo_dir.join("test_bucket");
+        fs::write(bucket_dir.join("model.blend"), b"model v1").expect("Failed to write file");
+        let mut cmd3 = assert_cmd::Command::cargo_bin("buckets").expect("failed to run command");
+        cmd3.current_dir(bucket_dir.as_path())
+            .arg("commit")
+            .arg("first version")
+            .assert()
+            .success();
+        repo_dir
+    }
+}